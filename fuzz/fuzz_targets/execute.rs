@@ -0,0 +1,13 @@
+#![no_main]
+
+use chip8::emulator::fuzz_step;
+use libfuzzer_sys::fuzz_target;
+
+// The first two bytes pick the starting PC, the rest is loaded as memory from 0x000.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let pc = u16::from_be_bytes([data[0], data[1]]);
+    let _ = fuzz_step(&data[2..], pc);
+});
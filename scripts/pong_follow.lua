@@ -0,0 +1,20 @@
+-- Plays the left paddle of Pong (1 player) by following the ball.
+-- Run with: cargo run --features lua -- --script scripts/pong_follow.lua PONG
+--
+-- The ROM keeps the ball position in V6/V7 and the left paddle's top row in VB.
+-- The paddle is 6 pixels high and moves with keys 1 (up) and 4 (down).
+
+local BALL_Y = 0x7
+local PADDLE_Y = 0xB
+local PADDLE_HEIGHT = 6
+
+function on_load(emu)
+  print("pong_follow: playing the left paddle")
+end
+
+function on_frame(emu)
+  local ball = emu:reg(BALL_Y)
+  local center = emu:reg(PADDLE_Y) + PADDLE_HEIGHT // 2
+  emu:key(0x1, ball < center - 1)
+  emu:key(0x4, ball > center + 1)
+end
@@ -0,0 +1,129 @@
+// Records the beeper to a WAV file: a square wave while the sound timer runs and
+// silence otherwise, for as much wall-clock time as the recording lasts. Samples are
+// generated and written on the frontend thread, once per host frame.
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::fs::File;
+use std::io::BufWriter;
+
+const SAMPLE_RATE: u32 = 44100;
+const TONE: f64 = 440.0;
+const VOLUME: i16 = i16::MAX / 4;
+
+pub struct AudioRecorder {
+    writer: WavWriter<BufWriter<File>>,
+    // Position within the current square wave period, 0..1.
+    phase: f64,
+    // Fraction of a sample owed from previous frames.
+    carry: f64,
+}
+
+impl AudioRecorder {
+    pub fn create(path: &str) -> Result<Self, hound::Error> {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        Ok(AudioRecorder {
+            writer: WavWriter::create(path, spec)?,
+            phase: 0.0,
+            carry: 0.0,
+        })
+    }
+
+    // Appends `seconds` of audio, beeping if `sound` is set.
+    pub fn record(&mut self, seconds: f64, sound: bool) -> Result<(), hound::Error> {
+        let samples = seconds * SAMPLE_RATE as f64 + self.carry;
+        self.carry = samples.fract();
+        for _ in 0..samples as u64 {
+            let sample = match sound {
+                true if self.phase < 0.5 => VOLUME,
+                true => -VOLUME,
+                false => 0,
+            };
+            self.writer.write_sample(sample)?;
+            self.phase = (self.phase + TONE / SAMPLE_RATE as f64).fract();
+        }
+        Ok(())
+    }
+
+    // Writes the final header. Dropping the recorder does too, but ignores errors.
+    pub fn finish(self) -> Result<(), hound::Error> {
+        self.writer.finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::emulator::Chip8;
+    use hound::WavReader;
+    use std::{env, fs};
+
+    // 735 samples at 44.1kHz.
+    const FRAME: f64 = 1.0 / 60.0;
+
+    fn wav_path(name: &str) -> String {
+        let path = env::temp_dir().join(format!("chip8-audio-{}-{}.wav", name, std::process::id()));
+        path.to_string_lossy().into_owned()
+    }
+
+    fn samples(path: &str) -> Vec<i16> {
+        let samples = WavReader::open(path)
+            .unwrap()
+            .into_samples()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let _ = fs::remove_file(path);
+        samples
+    }
+
+    #[test]
+    fn recordings_last_as_long_as_the_time_given() {
+        let path = wav_path("length");
+        let mut recorder = AudioRecorder::create(&path).unwrap();
+        recorder.record(0.25, false).unwrap();
+        recorder.record(0.5, true).unwrap();
+        recorder.record(0.25, false).unwrap();
+        recorder.finish().unwrap();
+        let samples = samples(&path);
+        assert_eq!(samples.len(), SAMPLE_RATE as usize);
+        let beep = 11025..33075;
+        assert!(samples[beep.clone()].iter().all(|&s| s.abs() == VOLUME));
+        assert!(samples[..beep.start].iter().all(|&s| s == 0));
+        assert!(samples[beep.end..].iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn partial_samples_carry_over_between_frames() {
+        let path = wav_path("carry");
+        let mut recorder = AudioRecorder::create(&path).unwrap();
+        // Half a sample each time.
+        for _ in 0..5 {
+            recorder.record(0.5 / SAMPLE_RATE as f64, false).unwrap();
+        }
+        recorder.finish().unwrap();
+        assert_eq!(samples(&path).len(), 2);
+    }
+
+    #[test]
+    fn the_beep_follows_the_sound_timer() {
+        let path = wav_path("timer");
+        let mut e = Chip8::new();
+        // ST = 6 in the second frame, heard for the 5 frames it stays above 0 after it.
+        e.load(&[0x60, 0x06, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+        let mut recorder = AudioRecorder::create(&path).unwrap();
+        for _ in 0..10 {
+            e.run_frame().unwrap();
+            recorder.record(FRAME, e.is_sound_active()).unwrap();
+        }
+        recorder.finish().unwrap();
+        let samples = samples(&path);
+        assert_eq!(samples.len(), 7350);
+        let loud: Vec<usize> = (0..10)
+            .filter(|f| samples[f * 735..(f + 1) * 735].iter().any(|&s| s != 0))
+            .collect();
+        assert_eq!(loud, [1, 2, 3, 4, 5]);
+    }
+}
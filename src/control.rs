@@ -0,0 +1,301 @@
+// Remote control over TCP, for bots, dashboards and tests written in anything that
+// can open a socket. Clients send one JSON command per line and get one JSON
+// response per line back:
+//
+//   {"cmd":"key","key":5,"down":true}      {"ok":true}
+//   {"cmd":"read_mem","addr":512,"len":2}  {"ok":true,"data":[96,0]}
+//
+// Everything is non-blocking and polled from the frontend's frame loop, so commands
+// run between frames on the thread that owns the machine.
+use serde_json::{json, Value};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+// Most frames one step command runs, a minute of emulation. The frontend answers
+// nothing else until they're done, so longer requests are refused.
+pub const MAX_STEP_FRAMES: usize = 3600;
+// Most bytes kept for a client either way. Past it, a line still without its newline
+// gets an error and the client is dropped, as is one that leaves its responses unread.
+const MAX_BUFFER: usize = 64 * 1024;
+
+struct Client {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    // Responses the socket hasn't taken yet.
+    outgoing: Vec<u8>,
+}
+
+pub struct ControlServer {
+    listener: TcpListener,
+    clients: Vec<Client>,
+}
+
+// A command received from client number `client`, to answer with `ControlServer::reply`.
+pub struct Request {
+    pub client: usize,
+    pub command: Result<Value, String>,
+}
+
+impl ControlServer {
+    // Only listens on localhost.
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(ControlServer {
+            listener,
+            clients: Vec::new(),
+        })
+    }
+
+    // Accepts new clients and returns the complete lines they sent since the last poll.
+    pub fn poll(&mut self) -> Vec<Request> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) if stream.set_nonblocking(true).is_ok() => {
+                    self.clients.push(Client {
+                        stream,
+                        buffer: Vec::new(),
+                        outgoing: Vec::new(),
+                    })
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    eprintln!("Control connection failed: {}", e);
+                    break;
+                }
+            }
+        }
+
+        let mut requests = Vec::new();
+        self.clients
+            .retain_mut(|c| write_pending(c) && read_available(c));
+        for (n, client) in self.clients.iter_mut().enumerate() {
+            while let Some(end) = client.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = client.buffer.drain(..=end).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                requests.push(Request {
+                    client: n,
+                    command: serde_json::from_slice(&line).map_err(|e| e.to_string()),
+                });
+            }
+        }
+        requests
+    }
+
+    pub fn reply(&mut self, client: usize, response: Value) {
+        let Some(c) = self.clients.get_mut(client) else {
+            return;
+        };
+        queue(c, &response);
+        // Dropped on the next poll, indexes stay put until then.
+        if !write_pending(c) {
+            let _ = c.stream.shutdown(std::net::Shutdown::Both);
+        }
+    }
+}
+
+fn queue(client: &mut Client, response: &Value) {
+    client
+        .outgoing
+        .extend_from_slice(response.to_string().as_bytes());
+    client.outgoing.push(b'\n');
+}
+
+// Writes as much of the queued responses as the socket takes now, the rest waits for
+// the next poll. Returns false once the client is gone or too far behind.
+fn write_pending(client: &mut Client) -> bool {
+    while !client.outgoing.is_empty() {
+        match client.stream.write(&client.outgoing) {
+            Ok(0) => return false,
+            Ok(n) => {
+                client.outgoing.drain(..n);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(_) => return false,
+        }
+    }
+    client.outgoing.len() <= MAX_BUFFER
+}
+
+// Returns false once the client has disconnected or sent too long a line.
+fn read_available(client: &mut Client) -> bool {
+    let mut chunk = [0; 1024];
+    loop {
+        if client.buffer.len() >= MAX_BUFFER {
+            // Complete lines are taken out on this poll, the rest can wait for the next.
+            if client.buffer.contains(&b'\n') {
+                return true;
+            }
+            queue(
+                client,
+                &error(format!("lines are limited to {} bytes", MAX_BUFFER)),
+            );
+            write_pending(client);
+            return false;
+        }
+        match client.stream.read(&mut chunk) {
+            Ok(0) => return false,
+            Ok(n) => client.buffer.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(_) => return false,
+        }
+    }
+}
+
+pub fn ok(fields: Value) -> Value {
+    let mut response = json!({ "ok": true });
+    if let (Some(response), Value::Object(fields)) = (response.as_object_mut(), fields) {
+        response.extend(fields);
+    }
+    response
+}
+
+pub fn error(message: impl Into<String>) -> Value {
+    json!({ "ok": false, "error": message.into() })
+}
+
+// The frames a step command asks for, 1 when it doesn't say.
+pub fn step_frames(command: &Value) -> Result<usize, String> {
+    let n = &command["n"];
+    if n.is_null() {
+        return Ok(1);
+    }
+    n.as_u64()
+        .and_then(|n| usize::try_from(n).ok())
+        .filter(|&n| n <= MAX_STEP_FRAMES)
+        .ok_or_else(|| format!("step expects n from 0 to {}", MAX_STEP_FRAMES))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn step_counts_are_bounded() {
+        assert_eq!(step_frames(&json!({ "cmd": "step" })), Ok(1));
+        assert_eq!(step_frames(&json!({ "cmd": "step", "n": 10 })), Ok(10));
+        let max = json!({ "cmd": "step", "n": MAX_STEP_FRAMES });
+        assert_eq!(step_frames(&max), Ok(MAX_STEP_FRAMES));
+        let too_many = json!({ "cmd": "step", "n": MAX_STEP_FRAMES + 1 });
+        assert_eq!(
+            step_frames(&too_many),
+            Err(String::from("step expects n from 0 to 3600"))
+        );
+        assert!(step_frames(&json!({ "cmd": "step", "n": -1 })).is_err());
+        assert!(step_frames(&json!({ "cmd": "step", "n": "10" })).is_err());
+    }
+
+    #[test]
+    fn responses_include_their_fields() {
+        assert_eq!(
+            ok(json!({ "frames": 2 })),
+            json!({ "ok": true, "frames": 2 })
+        );
+        assert_eq!(error("no"), json!({ "ok": false, "error": "no" }));
+    }
+
+    #[test]
+    fn commands_arrive_a_line_at_a_time_and_get_answers() {
+        let mut server = ControlServer::bind(0).unwrap();
+        let port = server.listener.local_addr().unwrap().port();
+        let mut client = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        client
+            .write_all(b"{\"cmd\":\"step\",\"n\":2}\n\nnot json\n{\"cmd\":")
+            .unwrap();
+
+        let start = Instant::now();
+        let requests = loop {
+            let requests = server.poll();
+            if requests.len() >= 2 {
+                break requests;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "no requests");
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].command, Ok(json!({ "cmd": "step", "n": 2 })));
+        assert!(requests[1].command.is_err());
+
+        server.reply(requests[0].client, ok(json!({ "frames": 2 })));
+        let mut line = String::new();
+        BufReader::new(&client).read_line(&mut line).unwrap();
+        assert!(line.ends_with('\n'));
+        let response: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(response, json!({ "ok": true, "frames": 2 }));
+    }
+
+    // Polls `server` until it has `clients` connected clients, or up to 5 seconds.
+    fn poll_until(server: &mut ControlServer, clients: usize) -> Vec<Request> {
+        let start = Instant::now();
+        loop {
+            let requests = server.poll();
+            if server.clients.len() == clients {
+                return requests;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "clients don't match"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn overlong_lines_get_an_error_and_a_disconnect() {
+        let mut server = ControlServer::bind(0).unwrap();
+        let port = server.listener.local_addr().unwrap().port();
+        let mut client = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        poll_until(&mut server, 1);
+        let writer = {
+            let mut client = client.try_clone().unwrap();
+            thread::spawn(move || {
+                let _ = client.write_all(&vec![b' '; MAX_BUFFER]);
+            })
+        };
+        assert!(poll_until(&mut server, 0).is_empty());
+        writer.join().unwrap();
+
+        let mut response = String::new();
+        let _ = client.read_to_string(&mut response);
+        let line = response.lines().next().unwrap();
+        let response: Value = serde_json::from_str(line).unwrap();
+        assert_eq!(response, error("lines are limited to 65536 bytes"));
+    }
+
+    #[test]
+    fn replies_queue_up_in_order() {
+        let mut server = ControlServer::bind(0).unwrap();
+        let port = server.listener.local_addr().unwrap().port();
+        let client = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        poll_until(&mut server, 1);
+        // Larger than the socket takes at once on some systems, never an error.
+        let data = "x".repeat(MAX_BUFFER / 8);
+        for n in 0..4 {
+            server.reply(0, ok(json!({ "n": n, "data": data })));
+        }
+        let reader = thread::spawn(move || {
+            let lines: Vec<Value> = BufReader::new(client)
+                .lines()
+                .take(4)
+                .map(|l| serde_json::from_str(&l.unwrap()).unwrap())
+                .collect();
+            lines
+        });
+        // Polling writes whatever the socket didn't take the first time.
+        while !reader.is_finished() {
+            server.poll();
+            thread::sleep(Duration::from_millis(1));
+        }
+        let lines = reader.join().unwrap();
+        let order: Vec<_> = lines.iter().map(|l| l["n"].as_u64().unwrap()).collect();
+        assert_eq!(order, [0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,119 @@
+// Optional CRT look: faint scanlines and a slight glow, applied as a shader while the
+// render target holding the display is drawn to the window.
+use macroquad::prelude::*;
+
+const VERTEX: &str = "#version 100
+attribute vec3 position;
+attribute vec2 texcoord;
+attribute vec4 color0;
+
+varying lowp vec2 uv;
+varying lowp vec4 color;
+
+uniform mat4 Model;
+uniform mat4 Projection;
+
+void main() {
+    gl_Position = Projection * Model * vec4(position, 1);
+    color = color0 / 255.0;
+    uv = texcoord;
+}
+";
+
+const FRAGMENT: &str = "#version 100
+precision mediump float;
+
+varying vec4 color;
+varying vec2 uv;
+
+uniform sampler2D Texture;
+// Size of the window in pixels.
+uniform vec2 Resolution;
+
+void main() {
+    vec2 texel = 2.0 / Resolution;
+    vec3 base = texture2D(Texture, uv).rgb;
+    vec3 glow = texture2D(Texture, uv + vec2(texel.x, 0.0)).rgb
+        + texture2D(Texture, uv - vec2(texel.x, 0.0)).rgb
+        + texture2D(Texture, uv + vec2(0.0, texel.y)).rgb
+        + texture2D(Texture, uv - vec2(0.0, texel.y)).rgb;
+    float scanline = 0.85 + 0.15 * sin(uv.y * Resolution.y * 3.14159);
+    gl_FragColor = vec4((base + glow * 0.06) * scanline * color.rgb, 1.0);
+}
+";
+
+pub struct Crt {
+    pub enabled: bool,
+    // `None` if the shader failed to build, which leaves the effect off.
+    material: Option<Material>,
+}
+
+impl Crt {
+    pub fn new(enabled: bool) -> Self {
+        let material = load_material(
+            ShaderSource::Glsl {
+                vertex: VERTEX,
+                fragment: FRAGMENT,
+            },
+            MaterialParams {
+                uniforms: vec![UniformDesc::new("Resolution", UniformType::Float2)],
+                ..Default::default()
+            },
+        );
+        let material = match material {
+            Ok(m) => Some(m),
+            Err(e) => {
+                eprintln!("The CRT effect is unavailable: {}", e);
+                None
+            }
+        };
+        Crt {
+            enabled: enabled && material.is_some(),
+            material,
+        }
+    }
+
+    // Returns whether the effect is now on, which it can't be without the shader.
+    pub fn toggle(&mut self) -> bool {
+        self.enabled = !self.enabled && self.material.is_some();
+        self.enabled
+    }
+
+    // Draws `texture` over the whole window, through the shader when enabled.
+    pub fn draw(&self, texture: &Texture2D) {
+        let material = self.material.as_ref().filter(|_| self.enabled);
+        if let Some(m) = material {
+            m.set_uniform("Resolution", (screen_width(), screen_height()));
+            gl_use_material(m);
+        }
+        draw_texture_ex(
+            texture,
+            0.0,
+            0.0,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(vec2(screen_width(), screen_height())),
+                ..Default::default()
+            },
+        );
+        if material.is_some() {
+            gl_use_default_material();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_effect_stays_off_without_the_shader() {
+        let mut crt = Crt {
+            enabled: false,
+            material: None,
+        };
+        assert!(!crt.toggle());
+        assert!(!crt.toggle());
+        assert!(!crt.enabled);
+    }
+}
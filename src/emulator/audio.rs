@@ -0,0 +1,76 @@
+// The beeper. The sound timer only says whether the tone should be on, an audio
+// backend makes it heard. `Beeper`, with the `audio` feature, plays it through
+// macroquad, other frontends plug in their own.
+use super::Chip8;
+use crate::prelude::*;
+
+pub trait AudioBackend {
+    // Called after every frame with whether the sound timer is running. Backends
+    // start or stop their tone when this changes.
+    fn set_playing(&mut self, playing: bool);
+}
+
+impl Chip8 {
+    // Plays the beeper through `backend` from the next frame on, `None` for silence.
+    pub fn set_audio_backend(&mut self, backend: Option<Box<dyn AudioBackend>>) {
+        if let Some(old) = self.audio.as_mut() {
+            old.set_playing(false);
+        }
+        self.audio = backend;
+    }
+
+    pub(super) fn update_audio(&mut self) {
+        let playing = self.is_sound_active();
+        if let Some(audio) = self.audio.as_mut() {
+            audio.set_playing(playing);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::machine;
+    use super::*;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    // Logs every call it gets.
+    struct Log(Rc<RefCell<Vec<bool>>>);
+
+    impl AudioBackend for Log {
+        fn set_playing(&mut self, playing: bool) {
+            self.0.borrow_mut().push(playing);
+        }
+    }
+
+    fn log(e: &mut Chip8) -> Rc<RefCell<Vec<bool>>> {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        e.set_audio_backend(Some(Box::new(Log(calls.clone()))));
+        calls
+    }
+
+    #[test]
+    fn backends_play_while_the_sound_timer_runs() {
+        // ST = 3 in the second frame, which already counts it down once.
+        let mut e = machine(&[0x6003, 0xF018, 0x1204]);
+        let calls = log(&mut e);
+        for _ in 0..6 {
+            e.run_frame().unwrap();
+        }
+        assert_eq!(*calls.borrow(), [false, true, true, false, false, false]);
+    }
+
+    #[test]
+    fn replacing_a_backend_silences_the_old_one() {
+        let mut e = machine(&[0x600A, 0xF018, 0x1204]);
+        let old = log(&mut e);
+        e.run_frame().unwrap();
+        e.run_frame().unwrap();
+        let new = log(&mut e);
+        assert_eq!(*old.borrow(), [false, true, false]);
+        e.run_frame().unwrap();
+        assert_eq!(*new.borrow(), [true]);
+        e.set_audio_backend(None);
+        assert_eq!(new.borrow().last(), Some(&false));
+    }
+}
@@ -0,0 +1,84 @@
+// The default `AudioBackend`: a square wave looped through macroquad's audio while the
+// sound timer runs. Needs the `audio` feature, and on Linux ALSA.
+use super::AudioBackend;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use macroquad::audio::{load_sound_from_bytes, play_sound, stop_sound, PlaySoundParams, Sound};
+use std::io::Cursor;
+
+const SAMPLE_RATE: u32 = 44100;
+// Samples per period, a whole number so the one second loop has no seam: 441Hz.
+const PERIOD: u32 = 100;
+const VOLUME: f32 = 0.25;
+
+pub struct Beeper {
+    sound: Sound,
+    playing: bool,
+}
+
+impl Beeper {
+    pub async fn new() -> Result<Self, macroquad::Error> {
+        let sound = load_sound_from_bytes(&square_wave()).await?;
+        Ok(Beeper {
+            sound,
+            playing: false,
+        })
+    }
+}
+
+impl AudioBackend for Beeper {
+    fn set_playing(&mut self, playing: bool) {
+        if playing == self.playing {
+            return;
+        }
+        self.playing = playing;
+        if playing {
+            let params = PlaySoundParams {
+                looped: true,
+                volume: VOLUME,
+            };
+            play_sound(&self.sound, params);
+        } else {
+            stop_sound(&self.sound);
+        }
+    }
+}
+
+// One second of the tone as a WAV file.
+fn square_wave() -> Vec<u8> {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut wav = Cursor::new(Vec::new());
+    // Writing to memory doesn't fail.
+    let mut writer = WavWriter::new(&mut wav, spec).expect("in memory");
+    for n in 0..SAMPLE_RATE {
+        let high = n % PERIOD < PERIOD / 2;
+        let sample = if high { i16::MAX } else { -i16::MAX };
+        writer.write_sample(sample).expect("in memory");
+    }
+    writer.finalize().expect("in memory");
+    wav.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hound::WavReader;
+
+    #[test]
+    fn the_tone_is_one_second_of_square_wave() {
+        let wav = square_wave();
+        let mut reader = WavReader::new(Cursor::new(wav)).unwrap();
+        assert_eq!(reader.spec().sample_rate, SAMPLE_RATE);
+        let samples: Vec<i16> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(samples.len(), SAMPLE_RATE as usize);
+        assert_eq!(samples[..2], [i16::MAX, i16::MAX]);
+        assert_eq!(samples[PERIOD as usize / 2], -i16::MAX);
+        assert_eq!(samples[PERIOD as usize], i16::MAX);
+        // Ends on a low half, so the loop starts a fresh period.
+        assert_eq!(samples.last(), Some(&-i16::MAX));
+    }
+}
@@ -0,0 +1,233 @@
+// Host keys for the 16 keypad keys, saved and loaded as text with one `key = name`
+// line per keypad key, e.g. `5 = W`. Names are macroquad's KeyCode names. Keys a file
+// leaves out keep their current binding and `#` starts a comment.
+use super::{keycode_from_hex, Chip8, Chip8Error, InputSource};
+use macroquad::input::{is_key_down, is_key_pressed, KeyCode};
+
+// Every key that can be bound.
+const BINDABLE: &[KeyCode] = &[
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+    KeyCode::Kp0,
+    KeyCode::Kp1,
+    KeyCode::Kp2,
+    KeyCode::Kp3,
+    KeyCode::Kp4,
+    KeyCode::Kp5,
+    KeyCode::Kp6,
+    KeyCode::Kp7,
+    KeyCode::Kp8,
+    KeyCode::Kp9,
+    KeyCode::KpDecimal,
+    KeyCode::KpDivide,
+    KeyCode::KpMultiply,
+    KeyCode::KpEnter,
+    KeyCode::Space,
+    KeyCode::Apostrophe,
+    KeyCode::Comma,
+    KeyCode::Period,
+    KeyCode::Slash,
+    KeyCode::Semicolon,
+    KeyCode::LeftBracket,
+    KeyCode::RightBracket,
+    KeyCode::Backslash,
+    KeyCode::GraveAccent,
+    KeyCode::Tab,
+    KeyCode::Up,
+    KeyCode::Down,
+    KeyCode::Left,
+    KeyCode::Right,
+    KeyCode::LeftShift,
+    KeyCode::RightShift,
+    KeyCode::LeftControl,
+    KeyCode::RightControl,
+    KeyCode::LeftAlt,
+    KeyCode::RightAlt,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyBindings {
+    pub keys: [KeyCode; 16],
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            keys: std::array::from_fn(|n| keycode_from_hex(n as u8)),
+        }
+    }
+}
+
+fn key_name(code: KeyCode) -> String {
+    format!("{:?}", code)
+}
+
+impl KeyBindings {
+    // Keys held now or pressed since the last frame, see `poll_host_keys`.
+    pub fn poll(&self) -> [bool; 16] {
+        self.keys
+            .map(|code| is_key_down(code) || is_key_pressed(code))
+    }
+
+    // The keypad key bound to `code`.
+    pub fn key_for(&self, code: KeyCode) -> Option<u8> {
+        self.keys.iter().position(|&k| k == code).map(|n| n as u8)
+    }
+
+    // Returns the bindings with the file's lines applied. The first bad line is an
+    // error and nothing is applied.
+    pub fn parse(&self, source: &str) -> Result<Self, Chip8Error> {
+        let mut bindings = *self;
+        for (n, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad = |why: &str| {
+                Chip8Error::InvalidBindings(format!("line {}: {}: {}", n + 1, why, line))
+            };
+            let (key, name) = line
+                .split_once('=')
+                .ok_or_else(|| bad("expected key = name"))?;
+            let key = u8::from_str_radix(key.trim(), 16)
+                .ok()
+                .filter(|&k| k < 16)
+                .ok_or_else(|| bad("keypad keys are 0 to F"))?;
+            let code = BINDABLE
+                .iter()
+                .copied()
+                .find(|&c| key_name(c).eq_ignore_ascii_case(name.trim()))
+                .ok_or_else(|| bad("unknown key"))?;
+            bindings.keys[key as usize] = code;
+        }
+        Ok(bindings)
+    }
+}
+
+impl InputSource for KeyBindings {
+    fn poll(&mut self) -> [bool; 16] {
+        KeyBindings::poll(self)
+    }
+}
+
+impl std::fmt::Display for KeyBindings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (n, &code) in self.keys.iter().enumerate() {
+            writeln!(f, "{:X} = {}", n, key_name(code))?;
+        }
+        Ok(())
+    }
+}
+
+impl Chip8 {
+    pub fn bindings(&self) -> &KeyBindings {
+        &self.bindings
+    }
+
+    pub fn set_bindings(&mut self, bindings: KeyBindings) {
+        self.bindings = bindings;
+    }
+
+    pub fn export_bindings(&self) -> String {
+        self.bindings.to_string()
+    }
+
+    // Applies a file written by `export_bindings`, or a hand written one. Invalid files
+    // leave the bindings as they were.
+    pub fn import_bindings(&mut self, source: &str) -> Result<(), Chip8Error> {
+        self.bindings = self.bindings.parse(source)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom() -> KeyBindings {
+        let mut bindings = KeyBindings::default();
+        bindings.keys[0x5] = KeyCode::Up;
+        bindings.keys[0x8] = KeyCode::Down;
+        bindings.keys[0xA] = KeyCode::Space;
+        bindings.keys[0xF] = KeyCode::Kp9;
+        bindings
+    }
+
+    #[test]
+    fn exported_bindings_import_unchanged() {
+        let mut e = Chip8::new();
+        e.set_bindings(custom());
+        let text = e.export_bindings();
+        assert_eq!(text.lines().count(), 16);
+        assert!(text.contains("5 = Up\n"));
+        assert!(text.contains("A = Space\n"));
+
+        let mut other = Chip8::new();
+        other.import_bindings(&text).unwrap();
+        assert_eq!(other.bindings(), &custom());
+        assert_eq!(other.bindings().key_for(KeyCode::Kp9), Some(0xF));
+    }
+
+    #[test]
+    fn files_may_leave_keys_out_and_comment() {
+        let bindings = KeyBindings::default()
+            .parse("# arrows\n\n5 = up  # jump\nf=kp9\n")
+            .unwrap();
+        assert_eq!(bindings.keys[0x5], KeyCode::Up);
+        assert_eq!(bindings.keys[0xF], KeyCode::Kp9);
+        assert_eq!(bindings.keys[0x0], KeyBindings::default().keys[0x0]);
+    }
+
+    #[test]
+    fn bad_lines_are_errors_that_change_nothing() {
+        let cases = [
+            ("5 = Up\n5 W", "line 2: expected key = name: 5 W"),
+            ("10 = W", "line 1: keypad keys are 0 to F: 10 = W"),
+            ("G = W", "line 1: keypad keys are 0 to F: G = W"),
+            ("5 = Up\n\n6 = Escape", "line 3: unknown key: 6 = Escape"),
+        ];
+        for (source, why) in cases {
+            let mut e = Chip8::new();
+            e.set_bindings(custom());
+            match e.import_bindings(source) {
+                Err(Chip8Error::InvalidBindings(message)) => assert_eq!(message, why),
+                other => panic!("{:?} for {:?}", other, source),
+            }
+            assert_eq!(e.bindings(), &custom());
+        }
+    }
+}
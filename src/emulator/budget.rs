@@ -0,0 +1,96 @@
+// Bounded runs for CI and fuzzing, which must finish even if a ROM never does.
+use super::{Chip8, Chip8Error, StopReason};
+
+// Why `run_with_budget` returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunStatus {
+    BudgetExhausted,
+    Exited,
+    ReachedEnd,
+    // Parked in a loop at this address, with idle detection on.
+    Idle(u16),
+    Stopped(StopReason),
+}
+
+impl Chip8 {
+    // Runs frames until `max_cycles` instructions have executed or the program halts,
+    // whichever comes first. The keypad is left as the caller set it and the last frame
+    // is cut short at the budget, without its timer tick.
+    pub fn run_with_budget(&mut self, max_cycles: u64) -> Result<RunStatus, Chip8Error> {
+        self.budget = Some(max_cycles);
+        let status = self.run_budgeted();
+        self.budget = None;
+        status
+    }
+
+    fn run_budgeted(&mut self) -> Result<RunStatus, Chip8Error> {
+        loop {
+            if let Some(reason) = self.stop_reason() {
+                return Ok(RunStatus::Stopped(reason));
+            }
+            if self.has_exited() {
+                return Ok(RunStatus::Exited);
+            }
+            if self.reached_end() {
+                return Ok(RunStatus::ReachedEnd);
+            }
+            if let Some(pc) = self.idle_pc() {
+                return Ok(RunStatus::Idle(pc));
+            }
+            if self.budget == Some(0) {
+                return Ok(RunStatus::BudgetExhausted);
+            }
+            self.run_frame()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::machine;
+    use super::*;
+
+    #[test]
+    fn infinite_loops_stop_at_the_budget() {
+        let mut e = machine(&[0x7001, 0x1200]);
+        e.set_cycles_per_frame(10);
+        assert_eq!(e.run_with_budget(25).unwrap(), RunStatus::BudgetExhausted);
+        // Cut short halfway through the third frame.
+        assert_eq!(e.stats().instructions, 25);
+        assert_eq!(e.registers()[0], 13);
+        // Each run gets a budget of its own, and plain runs none at all.
+        assert_eq!(e.run_with_budget(5).unwrap(), RunStatus::BudgetExhausted);
+        assert_eq!(e.stats().instructions, 30);
+        e.run_frame().unwrap();
+        assert_eq!(e.stats().instructions, 40);
+        assert_eq!(e.run_with_budget(0).unwrap(), RunStatus::BudgetExhausted);
+        assert_eq!(e.stats().instructions, 40);
+    }
+
+    #[test]
+    fn halting_programs_return_before_the_budget() {
+        let mut e = machine(&[0x7001, 0x00FD]);
+        assert_eq!(e.run_with_budget(100).unwrap(), RunStatus::Exited);
+        assert_eq!(e.stats().instructions, 2);
+
+        let mut e = machine(&[0x7001, 0x7001, 0x1204]);
+        e.add_breakpoint(0x202);
+        assert_eq!(
+            e.run_with_budget(100).unwrap(),
+            RunStatus::Stopped(StopReason::Breakpoint { pc: 0x202 })
+        );
+
+        let mut e = machine(&[0x1200]);
+        e.set_idle_detection(Some(3));
+        assert_eq!(e.run_with_budget(100).unwrap(), RunStatus::Idle(0x200));
+    }
+
+    #[test]
+    fn errors_end_the_run() {
+        let mut e = machine(&[0x7001, 0x00EE]);
+        assert!(matches!(
+            e.run_with_budget(100),
+            Err(Chip8Error::StackUnderflow { pc: 0x202 })
+        ));
+    }
+}
@@ -0,0 +1,207 @@
+// Configures a machine before it exists, for frontends that need more than `new`'s
+// defaults. `Chip8::builder().speed(700).quirks(quirks).build()`. Settings left out
+// keep the defaults, and everything set here can still be changed afterwards through
+// the usual setters, apart from the memory size and start address.
+use super::{megachip, Chip8, Chip8Error, Font, QuirkConfig, Timing, Variant, CUSTOM_FONT};
+use crate::prelude::*;
+
+#[derive(Clone, Debug, Default)]
+pub struct Chip8Builder {
+    variant: Variant,
+    memory_size: Option<usize>,
+    start_pc: Option<u16>,
+    ips: Option<u32>,
+    timing: Timing,
+    // The variant's own otherwise, see `Chip8::set_variant`.
+    quirks: Option<QuirkConfig>,
+    font: Option<Font>,
+    seed: Option<u64>,
+}
+
+impl Chip8Builder {
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    // Bytes of memory, the variant's own size otherwise.
+    pub fn memory_size(mut self, size: usize) -> Self {
+        self.memory_size = Some(size);
+        self
+    }
+
+    // Where `load` puts programs and execution starts, 0x200 otherwise. The ETI 660
+    // ran its programs from 0x600.
+    pub fn start_pc(mut self, addr: u16) -> Self {
+        self.start_pc = Some(addr);
+        self
+    }
+
+    // Instructions per second, see `Chip8::set_ips`.
+    pub fn speed(mut self, ips: u32) -> Self {
+        self.ips = Some(ips);
+        self
+    }
+
+    pub fn timing(mut self, timing: Timing) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    pub fn quirks(mut self, quirks: QuirkConfig) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    // Used for Fx29 instead of the built-in font, registered as `CUSTOM_FONT`.
+    pub fn font(mut self, font: Font) -> Self {
+        self.font = Some(font);
+        self
+    }
+
+    // Makes Cxkk reproducible, see `Chip8::seed_rng`.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    // Fails if the start address leaves no room for a program in memory.
+    pub fn build(self) -> Result<Chip8, Chip8Error> {
+        let mut e = Chip8::new();
+        e.set_variant(self.variant);
+        if let Some(size) = self.memory_size {
+            if !(0x200..=megachip::MEMORY_SIZE).contains(&size) {
+                return Err(Chip8Error::InvalidConfig(format!(
+                    "memory size {:#X} isn't between 0x200 and {:#X}",
+                    size,
+                    megachip::MEMORY_SIZE
+                )));
+            }
+            e.memory = vec![0; size];
+            e.reset_heatmap();
+        }
+        if let Some(addr) = self.start_pc {
+            if addr as usize + 2 > e.memory.len() {
+                return Err(Chip8Error::InvalidConfig(format!(
+                    "start address {:#05X} is past the end of memory",
+                    addr
+                )));
+            }
+            e.start_pc = addr;
+            e.pc = addr;
+        }
+        e.set_timing(self.timing);
+        if let Some(ips) = self.ips {
+            e.set_ips(ips);
+        }
+        if let Some(quirks) = self.quirks {
+            e.set_quirks(quirks);
+        }
+        if let Some(font) = self.font {
+            e.register_font(CUSTOM_FONT, font);
+            e.set_font(CUSTOM_FONT)?;
+        }
+        if let Some(seed) = self.seed {
+            e.seed_rng(seed);
+        }
+        Ok(e)
+    }
+}
+
+impl Chip8 {
+    pub fn builder() -> Chip8Builder {
+        Chip8Builder::default()
+    }
+
+    // Where programs are loaded and start, see `Chip8Builder::start_pc`.
+    pub fn start_pc(&self) -> u16 {
+        self.start_pc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{rom, steps};
+    use super::super::{KeyIndexPolicy, LowMemoryProtection, VfReset};
+    use super::*;
+
+    #[test]
+    fn an_empty_builder_matches_new() {
+        let (built, new) = (Chip8::builder().build().unwrap(), Chip8::new());
+        assert_eq!(built.start_pc(), 0x200);
+        assert_eq!(built.pc(), new.pc());
+        assert_eq!(built.memory().len(), new.memory().len());
+        assert_eq!(built.cycles_per_frame(), new.cycles_per_frame());
+        assert_eq!(built.quirks(), new.quirks());
+        assert_eq!(built.state_hash(), new.state_hash());
+        // Without quirks, the variant's own.
+        let mega = Chip8::builder().variant(Variant::MegaChip).build().unwrap();
+        assert_eq!(mega.quirks().low_memory, LowMemoryProtection::Off);
+    }
+
+    #[test]
+    fn programs_load_and_restart_at_the_start_address() {
+        let mut e = Chip8::builder().start_pc(0x600).build().unwrap();
+        e.load(&rom(&[0x6007, 0x1602])).unwrap();
+        assert_eq!(e.pc(), 0x600);
+        assert_eq!(e.memory()[0x600..0x602], [0x60, 0x07]);
+        steps(&mut e, 3);
+        assert_eq!((e.pc(), e.registers()[0]), (0x602, 7));
+        e.reset().unwrap();
+        assert_eq!(e.pc(), 0x600);
+    }
+
+    #[test]
+    fn memory_size_sets_how_large_a_rom_fits() {
+        let big = vec![0; 0x2000 - 0x200];
+        assert!(Chip8::new().load(&big).is_err());
+        let mut e = Chip8::builder().memory_size(0x2000).build().unwrap();
+        assert_eq!(e.memory().len(), 0x2000);
+        e.load(&big).unwrap();
+    }
+
+    #[test]
+    fn impossible_layouts_are_rejected() {
+        for builder in [
+            Chip8::builder().memory_size(0x1FF),
+            Chip8::builder().memory_size(megachip::MEMORY_SIZE + 1),
+            Chip8::builder().start_pc(0xFFF),
+            Chip8::builder().memory_size(0x800).start_pc(0x800),
+        ] {
+            assert!(matches!(builder.build(), Err(Chip8Error::InvalidConfig(_))));
+        }
+    }
+
+    #[test]
+    fn speed_quirks_font_and_seed_are_applied() {
+        let quirks = QuirkConfig {
+            key_index: KeyIndexPolicy::Mask,
+            vf_reset: VfReset::Keep,
+            ..QuirkConfig::default()
+        };
+        let font = Font::new(vec![0xAA; 16 * 5]).unwrap();
+        let build = || {
+            Chip8::builder()
+                .speed(600)
+                .quirks(quirks)
+                .font(font.clone())
+                .seed(9)
+                .build()
+                .unwrap()
+        };
+        let mut e = build();
+        assert_eq!(e.cycles_per_frame(), 10);
+        assert_eq!(e.quirks(), &quirks);
+        assert_eq!(e.font().data(), font.data());
+        // Eight random bytes, the same from every machine built with the seed.
+        let program = rom(&[
+            0xC0FF, 0xC1FF, 0xC2FF, 0xC3FF, 0xC4FF, 0xC5FF, 0xC6FF, 0xC7FF,
+        ]);
+        let mut again = build();
+        for e in [&mut e, &mut again] {
+            e.load(&program).unwrap();
+            steps(e, 8);
+        }
+        assert_eq!(e.registers(), again.registers());
+    }
+}
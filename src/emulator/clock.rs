@@ -0,0 +1,405 @@
+// Fixed-timestep pacing for the 60Hz emulation frame, independent of the host's frame rate.
+//
+// When the host stalls (window drags, resume from sleep) the owed time is not run all at
+// once: at most `max_speedup` frames are emulated per host frame until the backlog is
+// gone, and anything owed beyond `max_backlog` seconds is dropped. `last_dropped` says
+// how many frames the last host frame dropped, for the frontend to report.
+use rand::Rng;
+
+pub struct FrameClock {
+    frame_time: f64,
+    max_speedup: f64,
+    max_backlog: f64,
+    accumulator: f64,
+    last_dropped: u64,
+    stats: TimingStats,
+    skew: Option<ClockSkew>,
+    skew_pos: usize,
+    // Time supplied by `advance_time`, when the host's frame times are ignored.
+    manual: Option<f64>,
+}
+
+// Distorts the host frame times the clock is fed, to reproduce timing bugs seen on
+// slower or faster machines. Durations are in seconds.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClockSkew {
+    // Adds a random amount between -d and d to every frame time.
+    Jitter(f64),
+    // Replaces the frame times with these, repeating from the start when they run out.
+    Sequence(Vec<f64>),
+}
+
+// Host frame durations and how well emulation kept up with them, in seconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TimingStats {
+    pub host_frames: u64,
+    pub min_frame: f64,
+    pub max_frame: f64,
+    pub real_time: f64,
+    pub emulated_time: f64,
+    // Frames owed past `max_backlog` and never emulated.
+    pub dropped_frames: u64,
+    // Frames emulated on top of one per host frame, to catch up.
+    pub extra_frames: u64,
+}
+
+impl TimingStats {
+    pub fn mean_frame(&self) -> f64 {
+        if self.host_frames == 0 {
+            return 0.0;
+        }
+        self.real_time / self.host_frames as f64
+    }
+
+    // Emulated over real time, 1.0 when emulation runs at exactly full speed.
+    pub fn speed_ratio(&self) -> f64 {
+        if self.real_time == 0.0 {
+            return 0.0;
+        }
+        self.emulated_time / self.real_time
+    }
+}
+
+impl FrameClock {
+    pub fn new(frame_time: f64) -> Self {
+        FrameClock {
+            frame_time,
+            max_speedup: 4.0,
+            max_backlog: 0.5,
+            accumulator: 0.0,
+            last_dropped: 0,
+            stats: TimingStats::default(),
+            skew: None,
+            skew_pos: 0,
+            manual: None,
+        }
+    }
+
+    pub fn with_catch_up(mut self, max_speedup: f64, max_backlog: f64) -> Self {
+        self.max_speedup = max_speedup;
+        self.max_backlog = max_backlog;
+        self
+    }
+
+    pub fn set_skew(&mut self, skew: Option<ClockSkew>) {
+        self.skew = skew;
+        self.skew_pos = 0;
+    }
+
+    // With a manual clock `advance` ignores the host's frame times and only runs the
+    // frames owed for time given to `advance_time`, all of them, with no catch-up limit
+    // or dropped backlog. Makes timer behavior exactly repeatable, e.g. for tests.
+    pub fn set_manual(&mut self, manual: bool) {
+        self.manual = manual.then_some(0.0);
+    }
+
+    pub fn is_manual(&self) -> bool {
+        self.manual.is_some()
+    }
+
+    // Time for the next `advance` to account for, under a manual clock.
+    pub fn advance_time(&mut self, seconds: f64) {
+        if let Some(pending) = self.manual.as_mut() {
+            *pending += seconds.max(0.0);
+        }
+    }
+
+    fn skewed(&mut self, elapsed: f64) -> f64 {
+        let skewed = match &self.skew {
+            None => elapsed,
+            Some(ClockSkew::Jitter(d)) if *d > 0.0 => {
+                elapsed + rand::thread_rng().gen_range(-d..=*d)
+            }
+            Some(ClockSkew::Jitter(_)) => elapsed,
+            Some(ClockSkew::Sequence(times)) if times.is_empty() => elapsed,
+            Some(ClockSkew::Sequence(times)) => {
+                let t = times[self.skew_pos % times.len()];
+                self.skew_pos += 1;
+                t
+            }
+        };
+        skewed.max(0.0)
+    }
+
+    // Feeds `elapsed` seconds of host time and returns how many frames to emulate now.
+    pub fn advance(&mut self, elapsed: f64) -> u32 {
+        if let Some(pending) = self.manual.as_mut() {
+            let elapsed = std::mem::take(pending);
+            self.accumulator += elapsed;
+            let ticks = (self.accumulator / self.frame_time).floor() as u32;
+            self.accumulator -= ticks as f64 * self.frame_time;
+            self.stats.host_frames += 1;
+            self.stats.real_time += elapsed;
+            self.stats.emulated_time += ticks as f64 * self.frame_time;
+            return ticks;
+        }
+        let elapsed = self.skewed(elapsed);
+        let stats = &mut self.stats;
+        if stats.host_frames == 0 || elapsed < stats.min_frame {
+            stats.min_frame = elapsed;
+        }
+        stats.max_frame = stats.max_frame.max(elapsed);
+        stats.host_frames += 1;
+        stats.real_time += elapsed;
+
+        self.accumulator += elapsed;
+        self.last_dropped = 0;
+        if self.accumulator > self.max_backlog {
+            self.last_dropped = ((self.accumulator - self.max_backlog) / self.frame_time) as u64;
+            self.stats.dropped_frames += self.last_dropped;
+            self.accumulator = self.max_backlog;
+        }
+
+        let owed = (self.accumulator / self.frame_time).floor() as u32;
+        // Per host frame, however long that frame took, so a stall's own length doesn't
+        // let it run its whole backlog at once.
+        let budget = (self.max_speedup.floor() as u32).max(1);
+        let ticks = owed.min(budget);
+        self.accumulator -= ticks as f64 * self.frame_time;
+        self.stats.emulated_time += ticks as f64 * self.frame_time;
+        self.stats.extra_frames += ticks.saturating_sub(1) as u64;
+        ticks
+    }
+
+    // Frames the last `advance` dropped for being owed past `max_backlog`.
+    pub fn last_dropped(&self) -> u64 {
+        self.last_dropped
+    }
+
+    pub fn timing_stats(&self) -> TimingStats {
+        self.stats
+    }
+
+    pub fn reset_timing_stats(&mut self) {
+        self.stats = TimingStats::default();
+    }
+
+    // Whether whole frames are still owed after the last `advance`.
+    pub fn is_behind(&self) -> bool {
+        self.accumulator >= self.frame_time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{machine, steps};
+    use super::super::Chip8;
+    use super::*;
+
+    // A power of two, so the accumulator stays exact.
+    const FRAME: f64 = 1.0 / 64.0;
+
+    fn clock() -> FrameClock {
+        FrameClock::new(FRAME).with_catch_up(4.0, 0.5)
+    }
+
+    #[test]
+    fn steady_host_frames_run_one_frame_each() {
+        let mut clock = clock();
+        assert!((0..100).all(|_| clock.advance(FRAME) == 1));
+        // A host running at twice the rate runs a frame every other host frame.
+        let ticks: Vec<_> = (0..6).map(|_| clock.advance(FRAME / 2.0)).collect();
+        assert_eq!(ticks, [0, 1, 0, 1, 0, 1]);
+        let stats = clock.timing_stats();
+        assert_eq!((stats.dropped_frames, stats.extra_frames), (0, 0));
+        assert!(!clock.is_behind());
+    }
+
+    #[test]
+    fn a_stall_is_caught_up_over_the_following_frames() {
+        let mut clock = clock();
+        // 16 frames owed, then one more every host frame.
+        let ticks: Vec<_> = [16.0 * FRAME, FRAME, FRAME, FRAME, FRAME, FRAME]
+            .into_iter()
+            .map(|elapsed| clock.advance(elapsed))
+            .collect();
+        assert_eq!(ticks, [4, 4, 4, 4, 4, 1]);
+        assert!(!clock.is_behind());
+        let stats = clock.timing_stats();
+        assert_eq!(stats.emulated_time, stats.real_time);
+        assert_eq!(stats.extra_frames, 15);
+        assert_eq!(stats.dropped_frames, 0);
+    }
+
+    #[test]
+    fn catch_up_rate_does_not_grow_with_the_stall() {
+        for stall in [8.0 * FRAME, 16.0 * FRAME, 0.5] {
+            let mut clock = clock();
+            assert_eq!(clock.advance(stall), 4);
+            assert!(clock.is_behind());
+        }
+        let mut clock = FrameClock::new(FRAME).with_catch_up(2.0, 0.5);
+        assert_eq!(clock.advance(0.25), 2);
+        // Below 1x the clock still runs a frame per host frame.
+        let mut clock = FrameClock::new(FRAME).with_catch_up(0.5, 0.5);
+        assert_eq!(clock.advance(0.25), 1);
+    }
+
+    #[test]
+    fn backlog_past_the_limit_is_dropped() {
+        let mut clock = clock();
+        let mut ticks = clock.advance(1.0);
+        // Half a second is kept, the other half dropped.
+        assert_eq!(clock.last_dropped(), 32);
+        assert_eq!(clock.timing_stats().dropped_frames, 32);
+        while clock.is_behind() {
+            ticks += clock.advance(0.0);
+        }
+        assert_eq!(ticks, 32);
+        assert_eq!(clock.last_dropped(), 0);
+        assert_eq!(clock.timing_stats().dropped_frames, 32);
+    }
+
+    #[test]
+    fn stats_summarize_the_host_frames() {
+        let mut clock = clock();
+        assert_eq!(
+            (
+                clock.timing_stats().mean_frame(),
+                clock.timing_stats().speed_ratio()
+            ),
+            (0.0, 0.0)
+        );
+        for elapsed in [FRAME, 3.0 * FRAME, FRAME, FRAME / 2.0, FRAME / 2.0] {
+            clock.advance(elapsed);
+        }
+        let stats = clock.timing_stats();
+        assert_eq!(stats.host_frames, 5);
+        assert_eq!(
+            (stats.min_frame, stats.max_frame),
+            (FRAME / 2.0, 3.0 * FRAME)
+        );
+        assert_eq!(stats.mean_frame(), 6.0 * FRAME / 5.0);
+        assert_eq!(stats.speed_ratio(), 1.0);
+        assert_eq!(stats.extra_frames, 2);
+        clock.reset_timing_stats();
+        assert_eq!(clock.timing_stats(), TimingStats::default());
+    }
+
+    #[test]
+    fn dropped_frames_pull_the_speed_ratio_down() {
+        let mut clock = FrameClock::new(FRAME).with_catch_up(4.0, 2.0 * FRAME);
+        assert_eq!(clock.advance(8.0 * FRAME), 2);
+        let stats = clock.timing_stats();
+        assert_eq!(stats.dropped_frames, 6);
+        assert_eq!(stats.speed_ratio(), 0.25);
+    }
+
+    #[test]
+    fn skew_sequences_replace_the_host_frame_times() {
+        let mut clock = clock();
+        clock.set_skew(Some(ClockSkew::Sequence(vec![2.0 * FRAME, 0.0, FRAME])));
+        // The host's own times are ignored, the sequence repeats.
+        let ticks: Vec<_> = (0..6).map(|_| clock.advance(100.0)).collect();
+        assert_eq!(ticks, [2, 0, 1, 2, 0, 1]);
+        assert_eq!(clock.timing_stats().real_time, 6.0 * FRAME);
+        // Empty sequences change nothing.
+        clock.set_skew(Some(ClockSkew::Sequence(Vec::new())));
+        assert_eq!(clock.advance(FRAME), 1);
+    }
+
+    #[test]
+    fn skewed_frame_times_drive_the_timers() {
+        // DT starts at 60, then each host frame runs the frames its skewed time is owed.
+        let mut e = machine(&[0x603C, 0xF015, 0x1204]);
+        steps(&mut e, 2);
+        let mut clock = clock();
+        clock.set_skew(Some(ClockSkew::Sequence(vec![
+            FRAME,
+            3.0 * FRAME,
+            FRAME / 2.0,
+        ])));
+        let mut delays = Vec::new();
+        for _ in 0..6 {
+            for _ in 0..clock.advance(FRAME) {
+                e.run_frame().unwrap();
+            }
+            delays.push(e.timers().delay);
+        }
+        assert_eq!(delays, [59, 56, 56, 55, 52, 51]);
+    }
+
+    #[test]
+    fn jitter_stays_within_its_range() {
+        let mut clock = clock();
+        clock.set_skew(Some(ClockSkew::Jitter(FRAME / 4.0)));
+        for _ in 0..200 {
+            clock.advance(FRAME);
+        }
+        let stats = clock.timing_stats();
+        assert!(stats.min_frame >= FRAME * 0.75 && stats.max_frame <= FRAME * 1.25);
+        assert!(stats.min_frame < stats.max_frame);
+        // No jitter is no skew.
+        clock.set_skew(Some(ClockSkew::Jitter(0.0)));
+        clock.reset_timing_stats();
+        clock.advance(FRAME);
+        assert_eq!(clock.timing_stats().max_frame, FRAME);
+    }
+
+    #[test]
+    fn manual_clock_runs_exactly_the_time_given() {
+        let mut clock = clock();
+        clock.set_manual(true);
+        assert_eq!(clock.advance(1.0), 0);
+        clock.advance_time(40.0 * FRAME);
+        assert_eq!(clock.advance(0.0), 40);
+        assert_eq!(clock.timing_stats().dropped_frames, 0);
+    }
+
+    // A machine that loads DT with 60 and then loops, ready for the clock to drive.
+    fn timed_machine() -> Chip8 {
+        let mut e = machine(&[0x603C, 0xF015, 0x1204]);
+        steps(&mut e, 2);
+        e
+    }
+
+    fn run_owed(clock: &mut FrameClock, e: &mut Chip8) {
+        for _ in 0..clock.advance(1.0) {
+            e.run_frame().unwrap();
+        }
+    }
+
+    #[test]
+    fn manual_clock_drives_exact_timer_decrements() {
+        let mut e = timed_machine();
+        let mut clock = clock();
+        clock.set_manual(true);
+        // Host frames alone run nothing.
+        run_owed(&mut clock, &mut e);
+        assert_eq!(e.timers().delay, 60);
+        clock.advance_time(3.0 * FRAME);
+        run_owed(&mut clock, &mut e);
+        assert_eq!(e.timers().delay, 57);
+        // Part frames carry over, and negative time counts for nothing.
+        clock.advance_time(FRAME / 2.0);
+        clock.advance_time(-FRAME);
+        run_owed(&mut clock, &mut e);
+        assert_eq!(e.timers().delay, 57);
+        clock.advance_time(FRAME / 2.0);
+        run_owed(&mut clock, &mut e);
+        assert_eq!(e.timers().delay, 56);
+    }
+
+    #[test]
+    fn manual_clock_frames_follow_the_speed_multiplier() {
+        let mut e = timed_machine();
+        e.set_speed_multiplier(0.5);
+        let mut clock = clock();
+        clock.set_manual(true);
+        clock.advance_time(6.0 * FRAME);
+        run_owed(&mut clock, &mut e);
+        assert_eq!(e.timers().delay, 57);
+    }
+
+    #[test]
+    fn turning_the_manual_clock_off_follows_host_time_again() {
+        let mut clock = clock();
+        clock.set_manual(true);
+        assert!(clock.is_manual());
+        clock.advance_time(FRAME);
+        clock.set_manual(false);
+        assert!(!clock.is_manual());
+        // The pending time is dropped with it.
+        assert_eq!(clock.advance(2.0 * FRAME), 2);
+    }
+}
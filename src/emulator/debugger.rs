@@ -0,0 +1,341 @@
+use super::{Chip8, Chip8Error, Instruction, StepInfo};
+use crate::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisterWatch {
+    pub register: usize,
+    // `None` matches any write, `Some(v)` only a write of exactly `v`.
+    pub value: Option<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    RegisterWatch { register: usize, value: u8, pc: u16 },
+    // Paused from a script, see `Script`.
+    Script { pc: u16 },
+    // About to execute the instruction at a breakpoint.
+    Breakpoint { pc: u16 },
+    // Stopped by the embedder with `halt`.
+    Halted { pc: u16 },
+}
+
+#[derive(Default)]
+pub(super) struct Debugger {
+    watches: Vec<RegisterWatch>,
+    breakpoints: Vec<u16>,
+    // The breakpoint execution just continued from, which mustn't stop it again.
+    resume_pc: Option<u16>,
+    pub(super) stop: Option<StopReason>,
+}
+
+impl Chip8 {
+    pub fn add_register_watch(&mut self, x: usize, value: Option<u8>) {
+        self.debugger
+            .watches
+            .push(RegisterWatch { register: x, value });
+    }
+
+    pub fn clear_register_watches(&mut self) {
+        self.debugger.watches.clear();
+    }
+
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.debugger.stop
+    }
+
+    pub fn continue_execution(&mut self) {
+        if let Some(StopReason::Breakpoint { pc }) = self.debugger.stop {
+            self.debugger.resume_pc = Some(pc);
+        }
+        self.debugger.stop = None;
+    }
+
+    // Stops fetching and executing until `resume`, e.g. once a program reaches a known
+    // end state. Frames still run for the host, which keeps drawing and polling input.
+    pub fn halt(&mut self) {
+        self.debugger.stop = Some(StopReason::Halted { pc: self.pc });
+    }
+
+    // Undoes `halt`, leaving other stops alone.
+    pub fn resume(&mut self) {
+        if self.is_halted() {
+            self.debugger.stop = None;
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        matches!(self.debugger.stop, Some(StopReason::Halted { .. }))
+    }
+
+    // Stops execution before the instruction at `addr` runs.
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        if !self.debugger.breakpoints.contains(&addr) {
+            self.debugger.breakpoints.push(addr);
+        }
+    }
+
+    // Whether there was a breakpoint at `addr`.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        let before = self.debugger.breakpoints.len();
+        self.debugger.breakpoints.retain(|&a| a != addr);
+        self.debugger.breakpoints.len() != before
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.debugger.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> &[u16] {
+        &self.debugger.breakpoints
+    }
+
+    // Runs one instruction, even when stopped or at a breakpoint.
+    pub fn step_instruction(&mut self) -> Result<Option<StepInfo>, Chip8Error> {
+        self.debugger.stop = None;
+        self.debugger.resume_pc = Some(self.pc);
+        self.step()
+    }
+
+    // Runs one whole frame, its instructions and a timer tick, even when stopped. A
+    // breakpoint or watch later in the frame still stops it there, as in `run_frame`.
+    pub fn step_frame(&mut self) -> Result<(), Chip8Error> {
+        self.debugger.stop = None;
+        self.debugger.resume_pc = Some(self.pc);
+        self.run_frame()
+    }
+
+    // True if execution should stop before the instruction at `pc`.
+    pub(super) fn check_breakpoint(&mut self, pc: u16) -> bool {
+        if self.debugger.resume_pc.take() == Some(pc) {
+            return false;
+        }
+        if !self.debugger.breakpoints.contains(&pc) {
+            return false;
+        }
+        self.debugger.stop = Some(StopReason::Breakpoint { pc });
+        true
+    }
+
+    pub(super) fn check_register_watches(&mut self, pc: u16, ins: &Instruction) {
+        // Fx0A only writes once a key was taken, which moves on from it.
+        if matches!(ins, Instruction::LdVxK(_)) && self.pc == pc {
+            return;
+        }
+        for watch in &self.debugger.watches {
+            if !ins.writes_register(watch.register) {
+                continue;
+            }
+            let value = self.registers.v[watch.register];
+            if watch.value.is_none_or(|v| v == value) {
+                self.debugger.stop = Some(StopReason::RegisterWatch {
+                    register: watch.register,
+                    value,
+                    pc,
+                });
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{machine, steps};
+    use super::*;
+
+    #[test]
+    fn watch_stops_on_a_write_of_its_value() {
+        let mut e = machine(&[0x6201, 0x6203, 0x6207, 0x6209]);
+        e.add_register_watch(2, Some(7));
+        steps(&mut e, 2);
+        assert_eq!(e.stop_reason(), None);
+        steps(&mut e, 1);
+        let stop = StopReason::RegisterWatch {
+            register: 2,
+            value: 7,
+            pc: 0x204,
+        };
+        assert_eq!(e.stop_reason(), Some(stop));
+        // Stopped, so nothing more runs until it continues.
+        assert_eq!(e.step().unwrap(), None);
+        assert_eq!(e.pc(), 0x206);
+        e.continue_execution();
+        steps(&mut e, 1);
+        assert_eq!(e.registers()[2], 9);
+        assert_eq!(e.stop_reason(), None);
+    }
+
+    #[test]
+    fn watch_ignores_other_registers_and_values() {
+        let mut e = machine(&[0x6307, 0x6206, 0x7201]);
+        e.add_register_watch(2, Some(8));
+        steps(&mut e, 3);
+        assert_eq!(e.stop_reason(), None);
+        assert_eq!(e.registers()[2], 7);
+    }
+
+    #[test]
+    fn any_value_watch_stops_on_every_write() {
+        let mut e = machine(&[0x6500, 0x6205]);
+        e.add_register_watch(2, None);
+        steps(&mut e, 1);
+        assert_eq!(e.stop_reason(), None);
+        steps(&mut e, 1);
+        assert!(matches!(
+            e.stop_reason(),
+            Some(StopReason::RegisterWatch {
+                register: 2,
+                value: 5,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn flag_writes_hit_vf_watches() {
+        // 8xy4 with a carry sets VF.
+        let mut e = machine(&[0x60FF, 0x6101, 0x8014]);
+        e.add_register_watch(0xF, Some(1));
+        steps(&mut e, 3);
+        assert!(matches!(
+            e.stop_reason(),
+            Some(StopReason::RegisterWatch {
+                register: 0xF,
+                value: 1,
+                pc: 0x204
+            })
+        ));
+    }
+
+    #[test]
+    fn key_wait_only_hits_once_a_key_is_taken() {
+        let mut e = machine(&[0xF20A, 0x00FD]);
+        e.add_register_watch(2, None);
+        e.add_register_watch(2, Some(0));
+        // Waiting writes nothing, whatever V2 already holds.
+        steps(&mut e, 5);
+        assert_eq!(e.stop_reason(), None);
+        assert_eq!(e.pc(), 0x200);
+        e.inject_key(0xA, true);
+        e.keyboard.update([false; 16]);
+        steps(&mut e, 1);
+        assert_eq!(e.pc(), 0x202);
+        assert!(matches!(
+            e.stop_reason(),
+            Some(StopReason::RegisterWatch {
+                register: 2,
+                value: 0xA,
+                pc: 0x200
+            })
+        ));
+    }
+
+    #[test]
+    fn cleared_watches_no_longer_stop() {
+        let mut e = machine(&[0x6207]);
+        e.add_register_watch(2, Some(7));
+        e.clear_register_watches();
+        steps(&mut e, 1);
+        assert_eq!(e.stop_reason(), None);
+    }
+
+    #[test]
+    fn breakpoint_stops_before_its_address_and_continues_past_it() {
+        let mut e = machine(&[0x6001, 0x6002, 0x6003]);
+        e.add_breakpoint(0x202);
+        steps(&mut e, 3);
+        assert_eq!(e.stop_reason(), Some(StopReason::Breakpoint { pc: 0x202 }));
+        assert_eq!(e.registers()[0], 1);
+        e.continue_execution();
+        steps(&mut e, 2);
+        assert_eq!(e.registers()[0], 3);
+        assert_eq!(e.stop_reason(), None);
+    }
+
+    #[test]
+    fn breakpoint_elsewhere_never_stops() {
+        let mut e = machine(&[0x6001, 0x6002]);
+        e.add_breakpoint(0x300);
+        steps(&mut e, 2);
+        assert_eq!(e.stop_reason(), None);
+        assert!(e.remove_breakpoint(0x300));
+        assert!(!e.remove_breakpoint(0x300));
+    }
+
+    #[test]
+    fn halted_machines_stay_put_until_resumed() {
+        let mut e = machine(&[0x7001, 0x7001, 0x1200]);
+        steps(&mut e, 1);
+        e.halt();
+        assert!(e.is_halted());
+        assert_eq!(e.stop_reason(), Some(StopReason::Halted { pc: 0x202 }));
+        for _ in 0..5 {
+            e.run_frame().unwrap();
+        }
+        assert_eq!(e.step().unwrap(), None);
+        assert_eq!((e.pc(), e.registers()[0]), (0x202, 1));
+        e.resume();
+        assert!(!e.is_halted());
+        steps(&mut e, 2);
+        assert_eq!((e.pc(), e.registers()[0]), (0x200, 2));
+    }
+
+    #[test]
+    fn halted_machines_still_poll_input() {
+        let mut e = machine(&[0x1200]);
+        let polls = std::rc::Rc::new(core::cell::Cell::new(0));
+        let counter = polls.clone();
+        e.set_input_source(Some(Box::new(move || {
+            counter.set(counter.get() + 1);
+            [false; 16]
+        })));
+        e.halt();
+        for _ in 0..3 {
+            e.tick().unwrap();
+        }
+        assert_eq!(polls.get(), 3);
+        assert_eq!(e.stats().instructions, 0);
+    }
+
+    #[test]
+    fn step_frame_runs_one_frame_of_instructions_and_one_tick() {
+        // V0 = 50, DT = V0, then count in V1 forever.
+        let mut e = machine(&[0x6032, 0xF015, 0x7101, 0x1204]);
+        steps(&mut e, 2);
+        e.set_cycles_per_frame(7);
+        let (before, delay) = (e.stats().instructions, e.timers().delay);
+        e.step_frame().unwrap();
+        assert_eq!(e.stats().instructions - before, 7);
+        assert_eq!(e.timers().delay, delay - 1);
+        e.step_frame().unwrap();
+        assert_eq!(e.stats().instructions - before, 14);
+        assert_eq!(e.timers().delay, delay - 2);
+    }
+
+    #[test]
+    fn step_frame_runs_even_when_stopped() {
+        let mut e = machine(&[0x7001, 0x1200]);
+        e.set_cycles_per_frame(4);
+        e.halt();
+        e.step_frame().unwrap();
+        assert_eq!(e.stats().instructions, 4);
+        assert_eq!(e.stop_reason(), None);
+        // Past a breakpoint it just sat on, but stopping at the next one.
+        e.add_breakpoint(0x200);
+        e.run_frame().unwrap();
+        assert_eq!(e.stop_reason(), Some(StopReason::Breakpoint { pc: 0x200 }));
+        e.step_frame().unwrap();
+        assert_eq!(e.stop_reason(), Some(StopReason::Breakpoint { pc: 0x200 }));
+        assert_eq!(e.registers()[0], 3);
+    }
+
+    #[test]
+    fn resume_leaves_other_stops_alone() {
+        let mut e = machine(&[0x7001, 0x7001]);
+        e.add_breakpoint(0x202);
+        steps(&mut e, 2);
+        assert!(!e.is_halted());
+        e.resume();
+        assert_eq!(e.stop_reason(), Some(StopReason::Breakpoint { pc: 0x202 }));
+    }
+}
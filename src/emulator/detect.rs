@@ -0,0 +1,142 @@
+// Guesses which variant a ROM was written for, from its file extension and the
+// instructions it contains. Every even offset is decoded, so data that happens to
+// look like a marker opcode can fool it.
+use super::Variant;
+use crate::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Detection {
+    pub variant: Variant,
+    // What the guess was based on, for logging.
+    pub reason: String,
+}
+
+fn is_megachip(op: u16) -> bool {
+    op == 0x0011
+}
+
+fn is_schip(op: u16) -> bool {
+    matches!(op, 0x00FB | 0x00FC | 0x00FE | 0x00FF | 0x00C0..=0x00CF)
+        || (op & 0xF00F == 0xD000)
+        || matches!(op & 0xF0FF, 0xF030 | 0xF075 | 0xF085)
+}
+
+fn is_xochip(op: u16) -> bool {
+    op == 0xF000 || matches!(op & 0xF00F, 0x5002 | 0x5003) || matches!(op & 0xF0FF, 0xF001 | 0xF03A)
+}
+
+fn find(rom: &[u8], marker: fn(u16) -> bool) -> Option<(usize, u16)> {
+    rom.chunks_exact(2)
+        .enumerate()
+        .map(|(n, w)| (0x200 + 2 * n, u16::from_be_bytes([w[0], w[1]])))
+        .find(|&(_, op)| marker(op))
+}
+
+pub fn detect_variant(rom: &[u8], file_name: &str) -> Detection {
+    let name = file_name.rsplit(['/', '\\']).next().unwrap_or(file_name);
+    let extension = name
+        .rsplit_once('.')
+        .filter(|(stem, _)| !stem.is_empty())
+        .map(|(_, e)| e.to_ascii_lowercase());
+    let found = |variant, what: &str| Detection {
+        variant,
+        reason: what.to_string(),
+    };
+    let unsupported = |family: &str, why: String| Detection {
+        variant: Variant::Chip8,
+        reason: format!("{}, {} isn't supported so running as chip8", why, family),
+    };
+
+    if extension.as_deref() == Some("mc8") {
+        return found(Variant::MegaChip, "the .mc8 extension");
+    }
+    if let Some((addr, op)) = find(rom, is_megachip) {
+        return found(
+            Variant::MegaChip,
+            &format!("the mega mode switch {:04X} at {:#05X}", op, addr),
+        );
+    }
+    if rom.starts_with(&[0x12, 0x60]) {
+        return found(Variant::Hires, "the opening 1260 jump of hires programs");
+    }
+    match extension.as_deref() {
+        Some("xo8") => return unsupported("XO-CHIP", String::from("the .xo8 extension")),
+        Some("sc8") => return unsupported("SCHIP", String::from("the .sc8 extension")),
+        _ => {}
+    }
+    if let Some((addr, op)) = find(rom, is_xochip) {
+        let why = format!("XO-CHIP instruction {:04X} at {:#05X}", op, addr);
+        return unsupported("XO-CHIP", why);
+    }
+    if let Some((addr, op)) = find(rom, is_schip) {
+        let why = format!("SCHIP instruction {:04X} at {:#05X}", op, addr);
+        return unsupported("SCHIP", why);
+    }
+    found(Variant::Chip8, "no variant specific instructions")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::rom;
+    use super::*;
+
+    fn variant(program: &[u16], file_name: &str) -> Variant {
+        detect_variant(&rom(program), file_name).variant
+    }
+
+    #[test]
+    fn plain_programs_are_chip8() {
+        let guess = detect_variant(&rom(&[0x6001, 0xD015, 0x1204]), "pong.ch8");
+        assert_eq!(guess.variant, Variant::Chip8);
+        assert_eq!(guess.reason, "no variant specific instructions");
+    }
+
+    #[test]
+    fn marker_opcodes_pick_the_variant() {
+        let guess = detect_variant(&rom(&[0x6001, 0x0011, 0x1204]), "demo.ch8");
+        assert_eq!(guess.variant, Variant::MegaChip);
+        assert_eq!(guess.reason, "the mega mode switch 0011 at 0x202");
+        assert_eq!(variant(&[0x1260, 0x0000], "maze.ch8"), Variant::Hires);
+        // Only at the start of the program.
+        assert_eq!(variant(&[0x6001, 0x1260], "maze.ch8"), Variant::Chip8);
+    }
+
+    #[test]
+    fn schip_and_xochip_markers_are_named_but_run_as_chip8() {
+        for op in [0x00FF, 0x00C4, 0xD120, 0xF330, 0xF585] {
+            let guess = detect_variant(&rom(&[0x6001, op]), "game.ch8");
+            assert_eq!(guess.variant, Variant::Chip8);
+            let why = format!("SCHIP instruction {:04X} at 0x202", op);
+            assert!(guess.reason.starts_with(&why), "{}", guess.reason);
+        }
+        for op in [0xF000, 0x5122, 0x5123, 0xF201, 0xF03A] {
+            let guess = detect_variant(&rom(&[op]), "game.ch8");
+            assert!(
+                guess.reason.starts_with("XO-CHIP instruction"),
+                "{}",
+                guess.reason
+            );
+        }
+        // XO-CHIP wins over SCHIP markers that come first.
+        let guess = detect_variant(&rom(&[0x00FF, 0xF000]), "game.ch8");
+        assert!(guess
+            .reason
+            .starts_with("XO-CHIP instruction F000 at 0x202"));
+    }
+
+    #[test]
+    fn extensions_are_a_hint() {
+        assert_eq!(variant(&[0x6001], "games/DEMO.MC8"), Variant::MegaChip);
+        let guess = detect_variant(&rom(&[0x6001]), "car.sc8");
+        assert_eq!(
+            guess.reason,
+            "the .sc8 extension, SCHIP isn't supported so running as chip8"
+        );
+        assert!(detect_variant(&rom(&[0x6001]), "octo.xo8")
+            .reason
+            .contains("XO-CHIP"));
+        // Neither a directory's dots nor a hidden file's name count.
+        assert_eq!(variant(&[0x6001], "roms.mc8/pong"), Variant::Chip8);
+        assert_eq!(variant(&[0x6001], ".mc8"), Variant::Chip8);
+    }
+}
@@ -0,0 +1,94 @@
+// Where a frontend shows the display. `Chip8::present` hands the screen to a backend,
+// which reads the pixels with `Screen::pixels` and, to match the built-in drawing,
+// the palette, `brightness` for persistence and `grid`. The macroquad window is one
+// backend, `MacroquadDisplay`, others can be SDL, a terminal or nothing at all.
+use super::{Chip8, Screen};
+
+pub trait DisplayBackend {
+    fn present(&mut self, screen: &Screen);
+}
+
+impl Chip8 {
+    // Shows the display on `backend`. Under MegaChip's mega mode the picture is in
+    // `mega_frame` instead.
+    pub fn present(&self, backend: &mut dyn DisplayBackend) {
+        backend.present(&self.screen);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::machine;
+    use super::*;
+
+    // Remembers the lit pixels of every screen it was given.
+    #[derive(Default)]
+    struct Recorder(Vec<usize>);
+
+    impl DisplayBackend for Recorder {
+        fn present(&mut self, screen: &Screen) {
+            self.0.push(screen.lit_pixels().count());
+        }
+    }
+
+    // Draws the font's "0", 14 pixels, then loops.
+    fn drawing_machine() -> Chip8 {
+        let mut e = machine(&[0xA000, 0xD005, 0x1204]);
+        e.set_cycles_per_frame(3);
+        e.set_input_source(Some(Box::new(|| [false; 16])));
+        e
+    }
+
+    #[test]
+    fn present_after_tick_shows_that_frame() {
+        let mut e = drawing_machine();
+        let mut recorder = Recorder::default();
+        e.present(&mut recorder);
+        e.tick().unwrap();
+        e.present(&mut recorder);
+        assert_eq!(recorder.0, [0, 14]);
+    }
+
+    // Draws like a terminal frontend, a '#' for every lit pixel.
+    #[derive(Default)]
+    struct Text(Vec<String>);
+
+    impl DisplayBackend for Text {
+        fn present(&mut self, screen: &Screen) {
+            self.0 = screen
+                .pixels()
+                .chunks(screen.cols())
+                .map(|row| row.iter().map(|&p| if p { '#' } else { '.' }).collect())
+                .collect();
+        }
+    }
+
+    #[test]
+    fn backends_see_the_pixels_at_the_current_resolution() {
+        let mut e = drawing_machine();
+        e.tick().unwrap();
+        let mut text = Text::default();
+        e.present(&mut text);
+        assert_eq!(text.0.len(), 32);
+        assert_eq!(&text.0[0][..6], "####..");
+        assert_eq!(&text.0[1][..6], "#..#..");
+
+        let mut e = machine(&[0x00FF, 0xA000, 0xD005]);
+        e.set_cycles_per_frame(3);
+        e.set_input_source(Some(Box::new(|| [false; 16])));
+        e.tick().unwrap();
+        e.present(&mut text);
+        assert_eq!((text.0.len(), text.0[0].len()), (64, 128));
+        assert_eq!(&text.0[4][..6], "####..");
+    }
+
+    #[test]
+    fn tick_runs_without_presenting() {
+        let mut e = drawing_machine();
+        for _ in 0..10 {
+            e.tick().unwrap();
+        }
+        assert_eq!(e.screen().lit_pixels().count(), 14);
+        assert_eq!(e.pc(), 0x204);
+    }
+}
@@ -0,0 +1,329 @@
+// Diagnostic dumps for bug reports. With a dump target set, an emulation error or an
+// internal panic during `step` first writes the machine state there, then carries on
+// as it would have. `state_json` is the full machine state in a readable form instead,
+// for diffing two runs.
+use super::{Chip8, Chip8Error, Instruction, StepInfo};
+use serde_json::{json, Value};
+use std::any::Any;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Write as _;
+use std::panic::{self, AssertUnwindSafe};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DumpTarget {
+    Stderr,
+    // Appended to, so dumps from several runs pile up.
+    File(String),
+}
+
+// How `state_summary` writes register values and addresses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RegisterFormat {
+    #[default]
+    Hex,
+    Decimal,
+    Binary,
+}
+
+impl RegisterFormat {
+    fn byte(self, value: u8) -> String {
+        match self {
+            RegisterFormat::Hex => format!("{:02X}", value),
+            RegisterFormat::Decimal => format!("{:3}", value),
+            RegisterFormat::Binary => format!("{:08b}", value),
+        }
+    }
+
+    fn address(self, value: u32) -> String {
+        match self {
+            RegisterFormat::Hex => format!("{:#05X}", value),
+            RegisterFormat::Decimal => value.to_string(),
+            RegisterFormat::Binary => format!("{:#b}", value),
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "unknown panic"
+    }
+}
+
+impl Chip8 {
+    pub fn set_crash_dump(&mut self, target: Option<DumpTarget>) {
+        self.crash_dump = target;
+    }
+
+    // PC, I, timers, the V registers eight to a line and the stack.
+    pub fn state_summary(&self, format: RegisterFormat) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "PC {}  I {}  DT {}  ST {}",
+            format.address(self.pc as u32),
+            format.address(self.registers.i),
+            self.timers.delay,
+            self.timers.sound
+        );
+        for (n, regs) in self.registers.v.chunks(8).enumerate() {
+            let line: Vec<_> = regs
+                .iter()
+                .enumerate()
+                .map(|(x, &v)| format!("V{:X} {}", n * 8 + x, format.byte(v)))
+                .collect();
+            let _ = writeln!(out, "{}", line.join("  "));
+        }
+        let stack: Vec<_> = self
+            .stack
+            .iter()
+            .map(|&a| format.address(a as u32))
+            .collect();
+        let _ = writeln!(out, "Stack [{}]", stack.join(", "));
+        out
+    }
+
+    // The state summary, screen checksum and the recent instructions.
+    pub fn diagnostic_dump(&self, cause: &str) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "Chip8 diagnostic dump");
+        let _ = writeln!(out, "Cause: {}", cause);
+        out.push_str(&self.state_summary(RegisterFormat::Hex));
+        let _ = writeln!(out, "Screen hash {:#018X}", self.screen.hash());
+        let _ = writeln!(out, "Recent instructions:");
+        for &(pc, opcode) in &self.history {
+            let _ = match Instruction::decode(opcode) {
+                Some(ins) => writeln!(out, "  {:#05X}  {:04X}  {}", pc, opcode, ins),
+                None => writeln!(out, "  {:#05X}  {:04X}  ???", pc, opcode),
+            };
+        }
+        out
+    }
+
+    // Registers, timers, stack, quirks, counters, the display as rows of `#` and `.`
+    // and memory as hex, 16 bytes a line. Deterministic runs give identical dumps.
+    pub fn state_json(&self) -> Value {
+        let width = format!("{:X}", self.memory.len().saturating_sub(1)).len();
+        let memory: Vec<_> = self
+            .memory
+            .chunks(16)
+            .enumerate()
+            .map(|(n, line)| {
+                let hex: String = line.iter().map(|b| format!("{:02X}", b)).collect();
+                format!("{:0width$X}: {}", n * 16, hex, width = width)
+            })
+            .collect();
+        let text = self.screen.to_text();
+        json!({
+            "variant": self.variant.name(),
+            "pc": format!("{:#05X}", self.pc),
+            "i": format!("{:#05X}", self.registers.i),
+            "v": self.registers.v.iter().map(|v| format!("{:02X}", v)).collect::<Vec<_>>(),
+            "delay_timer": self.timers.delay,
+            "sound_timer": self.timers.sound,
+            "stack": self.stack.iter().map(|a| format!("{:#05X}", a)).collect::<Vec<_>>(),
+            "quirks": {
+                "sprite_read": format!("{:?}", self.quirks.sprite_read),
+                "key_index": format!("{:?}", self.quirks.key_index),
+                "low_memory": format!("{:?}", self.quirks.low_memory),
+                "vf_reset": format!("{:?}", self.quirks.vf_reset),
+            },
+            "timing": format!("{:?}", self.timing),
+            "instructions": self.stats.instructions,
+            "frames": self.stats.frames,
+            "exited": self.exited,
+            "screen": {
+                "width": self.screen.cols,
+                "height": self.screen.rows,
+                "rows": text.lines().collect::<Vec<_>>(),
+            },
+            "memory": memory,
+        })
+    }
+
+    fn write_crash_dump(&self, cause: &str) {
+        let dump = self.diagnostic_dump(cause);
+        match &self.crash_dump {
+            Some(DumpTarget::Stderr) => eprint!("{}", dump),
+            Some(DumpTarget::File(path)) => {
+                let result = File::options()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut f| writeln!(f, "{}", dump));
+                if let Err(e) = result {
+                    eprintln!("Error writing the crash dump to {}: {}\n{}", path, e, dump);
+                }
+            }
+            None => {}
+        }
+    }
+
+    pub(super) fn step_with_dump(&mut self) -> Result<Option<StepInfo>, Chip8Error> {
+        match panic::catch_unwind(AssertUnwindSafe(|| self.execute_step())) {
+            Ok(Err(e)) => {
+                self.write_crash_dump(&e.to_string());
+                Err(e)
+            }
+            Ok(ok) => ok,
+            Err(payload) => {
+                self.write_crash_dump(&format!("panic: {}", panic_message(&*payload)));
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{machine, steps};
+    use super::*;
+    use std::{env, fs};
+
+    fn dump_path(name: &str) -> String {
+        let path = env::temp_dir().join(format!("chip8-dump-{}-{}.txt", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn errors_write_a_populated_dump() {
+        let path = dump_path("underflow");
+        let mut e = machine(&[0x6A12, 0xA300, 0x00EE]);
+        e.set_crash_dump(Some(DumpTarget::File(path.clone())));
+        steps(&mut e, 2);
+        assert!(matches!(
+            e.step(),
+            Err(Chip8Error::StackUnderflow { pc: 0x204 })
+        ));
+        let dump = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(
+            dump.starts_with("Chip8 diagnostic dump\nCause: Return with an empty stack at 0x204\n")
+        );
+        assert!(dump.contains("PC 0x204  I 0x300"));
+        assert!(dump.contains("VA 12"));
+        assert!(dump.contains(&format!("Screen hash {:#018X}", e.screen().hash())));
+        assert!(dump.contains("  0x202  A300  "));
+    }
+
+    #[test]
+    fn nothing_is_written_without_an_error_or_a_target() {
+        let path = dump_path("quiet");
+        let mut e = machine(&[0x6A12, 0x00EE]);
+        e.set_crash_dump(Some(DumpTarget::File(path.clone())));
+        steps(&mut e, 1);
+        assert!(fs::metadata(&path).is_err());
+        e.set_crash_dump(None);
+        assert!(e.step().is_err());
+        assert!(fs::metadata(&path).is_err());
+    }
+
+    #[test]
+    fn summaries_follow_the_register_format() {
+        let mut e = machine(&[0x6A0C, 0xA300, 0x2208, 0x0000, 0x1208]);
+        steps(&mut e, 3);
+        let hex = e.state_summary(RegisterFormat::Hex);
+        assert!(hex.starts_with("PC 0x208  I 0x300  DT 0  ST 0\n"));
+        assert!(hex.contains("V8 00  V9 00  VA 0C"));
+        assert!(hex.ends_with("Stack [0x204]\n"));
+        let decimal = e.state_summary(RegisterFormat::Decimal);
+        assert!(decimal.starts_with("PC 520  I 768"));
+        assert!(decimal.contains("VA  12"));
+        let binary = e.state_summary(RegisterFormat::Binary);
+        assert!(binary.contains("VA 00001100"));
+    }
+
+    #[test]
+    fn v0_of_255_follows_the_register_format() {
+        let mut e = machine(&[0x60FF, 0x2206, 0x0000, 0xA123]);
+        steps(&mut e, 3);
+        let summary = |format| e.state_summary(format);
+        assert!(summary(RegisterFormat::Hex).contains("V0 FF  V1 00"));
+        assert!(summary(RegisterFormat::Decimal).contains("V0 255  V1   0"));
+        assert!(summary(RegisterFormat::Binary).contains("V0 11111111  V1 00000000"));
+        // Addresses follow it too.
+        assert_eq!(
+            summary(RegisterFormat::Decimal).lines().next(),
+            Some("PC 520  I 291  DT 0  ST 0")
+        );
+        assert!(summary(RegisterFormat::Decimal).ends_with("Stack [514]\n"));
+        assert!(summary(RegisterFormat::Binary).starts_with("PC 0b1000001000  I 0b100100011"));
+        assert_eq!(RegisterFormat::default(), RegisterFormat::Hex);
+    }
+
+    #[test]
+    fn state_json_describes_the_machine() {
+        let mut e = machine(&[0x6A12, 0xA300, 0xD005]);
+        steps(&mut e, 3);
+        let state = e.state_json();
+        assert_eq!(state["pc"], "0x206");
+        assert_eq!(state["i"], "0x300");
+        assert_eq!(state["v"][0xA], "12");
+        assert_eq!(state["instructions"], 3);
+        assert_eq!(state["screen"]["width"], 64);
+        assert_eq!(
+            state["memory"][0x20],
+            "200: 6A12A300D00500000000000000000000"
+        );
+    }
+
+    #[test]
+    fn state_json_has_every_field() {
+        let mut e = machine(&[0x2206, 0x0000, 0x0000, 0xF015]);
+        steps(&mut e, 1);
+        let state = e.state_json();
+        let keys: Vec<_> = state
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "delay_timer",
+                "exited",
+                "frames",
+                "i",
+                "instructions",
+                "memory",
+                "pc",
+                "quirks",
+                "screen",
+                "sound_timer",
+                "stack",
+                "timing",
+                "v",
+                "variant",
+            ]
+        );
+        assert_eq!(state["stack"], json!(["0x200"]));
+        assert_eq!(state["v"].as_array().unwrap().len(), 16);
+        assert_eq!(state["screen"]["rows"].as_array().unwrap().len(), 32);
+        assert_eq!(state["screen"]["rows"][0], ".".repeat(64));
+        // 4KB in 16 byte lines.
+        assert_eq!(state["memory"].as_array().unwrap().len(), 256);
+        assert_eq!(state["quirks"].as_object().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn identical_runs_give_identical_dumps() {
+        let run = || {
+            let mut e = machine(&[0xA20A, 0xC0FF, 0xD015, 0x7001, 0x1202]);
+            e.seed_rng(3);
+            steps(&mut e, 40);
+            e.state_json()
+        };
+        let state = run();
+        assert_eq!(state, run());
+        let mut other = machine(&[0xA20A, 0xC0FF, 0xD015, 0x7001, 0x1202]);
+        other.seed_rng(4);
+        steps(&mut other, 40);
+        assert_ne!(state, other.state_json());
+    }
+}
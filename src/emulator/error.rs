@@ -0,0 +1,195 @@
+use crate::prelude::*;
+use core::{error, fmt};
+#[cfg(feature = "std")]
+use std::io;
+
+// Everything loading or running a program can fail with. A bad ROM never panics, the
+// step that hits the problem returns it, see `fuzz_step`.
+#[derive(Debug)]
+pub enum Chip8Error {
+    RomNotFound(String),
+    // `reload` on a machine whose program didn't come from a file.
+    NoRomFile,
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    RomTooLarge {
+        size: usize,
+        max: usize,
+    },
+    EmptyRom,
+    InvalidOpcode {
+        pc: u16,
+        opcode: u16,
+    },
+    StackUnderflow {
+        pc: u16,
+    },
+    StackOverflow {
+        pc: u16,
+    },
+    MemoryOutOfBounds {
+        pc: u16,
+        addr: usize,
+    },
+    ProtectedWrite {
+        pc: u16,
+        addr: usize,
+    },
+    MisalignedJump {
+        pc: u16,
+        target: u16,
+    },
+    Script(String),
+    InvalidState(String),
+    InvalidBindings(String),
+    InvalidFont(String),
+    InvalidConfig(String),
+}
+
+// The name frontends outside this crate tend to look for.
+pub type EmulatorError = Chip8Error;
+
+impl Chip8Error {
+    // The CHIP-8 address of the faulting instruction, for runtime errors.
+    pub fn address(&self) -> Option<u16> {
+        match *self {
+            Chip8Error::InvalidOpcode { pc, .. }
+            | Chip8Error::StackUnderflow { pc }
+            | Chip8Error::StackOverflow { pc }
+            | Chip8Error::MemoryOutOfBounds { pc, .. }
+            | Chip8Error::ProtectedWrite { pc, .. }
+            | Chip8Error::MisalignedJump { pc, .. } => Some(pc),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::RomNotFound(path) => write!(f, "ROM not found: {}", path),
+            Chip8Error::NoRomFile => write!(f, "No ROM file to reload"),
+            #[cfg(feature = "std")]
+            Chip8Error::Io(e) => write!(f, "Error reading the ROM: {}", e),
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "ROM too large: {} bytes, max {}", size, max)
+            }
+            Chip8Error::EmptyRom => write!(f, "The ROM is empty"),
+            Chip8Error::InvalidOpcode { pc, opcode } => {
+                write!(f, "Invalid opcode {:04X} at {:#05X}", opcode, pc)
+            }
+            Chip8Error::StackUnderflow { pc } => {
+                write!(f, "Return with an empty stack at {:#05X}", pc)
+            }
+            Chip8Error::StackOverflow { pc } => {
+                write!(f, "Call with a full stack at {:#05X}", pc)
+            }
+            Chip8Error::MemoryOutOfBounds { pc, addr } => {
+                write!(
+                    f,
+                    "Memory access out of bounds ({:#06X}) at {:#05X}",
+                    addr, pc
+                )
+            }
+            Chip8Error::ProtectedWrite { pc, addr } => {
+                write!(f, "Write to protected address {:#05X} at {:#05X}", addr, pc)
+            }
+            Chip8Error::MisalignedJump { pc, target } => {
+                write!(f, "Jump to odd address {:#05X} at {:#05X}", target, pc)
+            }
+            Chip8Error::Script(message) => write!(f, "Script error: {}", message),
+            Chip8Error::InvalidState(why) => write!(f, "Invalid save state: {}", why),
+            Chip8Error::InvalidBindings(why) => write!(f, "Invalid key bindings: {}", why),
+            Chip8Error::InvalidFont(why) => write!(f, "Invalid font: {}", why),
+            Chip8Error::InvalidConfig(why) => write!(f, "Invalid configuration: {}", why),
+        }
+    }
+}
+
+impl error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            Chip8Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for Chip8Error {
+    fn from(e: io::Error) -> Self {
+        Chip8Error::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{machine, steps};
+    use super::*;
+
+    // The error `program` fails with after running it all and then `extra` more steps,
+    // checking it stopped at the faulting instruction.
+    fn fails_with(program: &[u16], extra: usize) -> EmulatorError {
+        let mut e = machine(program);
+        steps(&mut e, program.len() - 1 + extra);
+        let pc = e.pc();
+        let err = e.run_frame().unwrap_err();
+        assert_eq!(err.address(), Some(pc));
+        assert_eq!(e.pc(), pc);
+        err
+    }
+
+    #[test]
+    fn bad_programs_return_errors_instead_of_panicking() {
+        assert!(matches!(
+            fails_with(&[0x6000, 0xF0FF], 0),
+            Chip8Error::InvalidOpcode {
+                pc: 0x202,
+                opcode: 0xF0FF
+            }
+        ));
+        assert!(matches!(
+            fails_with(&[0x6000, 0x00EE], 0),
+            Chip8Error::StackUnderflow { pc: 0x202 }
+        ));
+        // Calls itself until the 16 deep stack is full.
+        assert!(matches!(
+            fails_with(&[0x2200], 16),
+            Chip8Error::StackOverflow { pc: 0x200 }
+        ));
+        assert!(matches!(
+            fails_with(&[0x1FFF], 1),
+            Chip8Error::MemoryOutOfBounds { pc: 0xFFF, .. }
+        ));
+    }
+
+    #[test]
+    fn errors_say_what_went_wrong_and_where() {
+        let cases = [
+            (
+                Chip8Error::InvalidOpcode {
+                    pc: 0x20A,
+                    opcode: 0xF0FF,
+                },
+                "Invalid opcode F0FF at 0x20A",
+            ),
+            (
+                Chip8Error::StackUnderflow { pc: 0x202 },
+                "Return with an empty stack at 0x202",
+            ),
+            (
+                Chip8Error::MemoryOutOfBounds {
+                    pc: 0xFFF,
+                    addr: 0x1000,
+                },
+                "Memory access out of bounds (0x1000) at 0xFFF",
+            ),
+            (Chip8Error::EmptyRom, "The ROM is empty"),
+        ];
+        for (err, text) in cases {
+            assert_eq!(err.to_string(), text);
+        }
+        assert_eq!(Chip8Error::EmptyRom.address(), None);
+    }
+}
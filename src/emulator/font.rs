@@ -0,0 +1,249 @@
+// Hex digit fonts. Fx29 points I at a digit's glyph in the active font, which `load`
+// copies to the font address. Fonts other than the built-in one can be registered by
+// name and switched between, even while a program runs.
+use super::{Chip8, Chip8Error};
+use crate::prelude::*;
+
+pub const STANDARD_FONT: &str = "standard";
+// What a font given on the command line or to `Chip8Builder::font` is registered as.
+pub const CUSTOM_FONT: &str = "custom";
+
+const STANDARD_GLYPHS: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, 0x20, 0x60, 0x20, 0x20, 0x70, 0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0,
+    0x10, 0xF0, 0x10, 0xF0, 0x90, 0x90, 0xF0, 0x10, 0x10, 0xF0, 0x80, 0xF0, 0x10, 0xF0, 0xF0, 0x80,
+    0xF0, 0x90, 0xF0, 0xF0, 0x10, 0x20, 0x40, 0x40, 0xF0, 0x90, 0xF0, 0x90, 0xF0, 0xF0, 0x90, 0xF0,
+    0x10, 0xF0, 0xF0, 0x90, 0xF0, 0x90, 0x90, 0xE0, 0x90, 0xE0, 0x90, 0xE0, 0xF0, 0x80, 0x80, 0x80,
+    0xF0, 0xE0, 0x90, 0x90, 0x90, 0xE0, 0xF0, 0x80, 0xF0, 0x80, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0x80,
+];
+
+// Sixteen glyphs, 0 to F, each `glyph_len` rows of 8 pixels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Font {
+    glyph_len: usize,
+    data: Vec<u8>,
+}
+
+impl Font {
+    // `data` holds the sixteen glyphs back to back, so its length sets their height.
+    pub fn new(data: Vec<u8>) -> Result<Self, Chip8Error> {
+        if data.is_empty() || !data.len().is_multiple_of(16) {
+            return Err(Chip8Error::InvalidFont(format!(
+                "{} bytes don't make 16 glyphs",
+                data.len()
+            )));
+        }
+        // It has to fit below 0x200 to stay clear of programs.
+        if data.len() > 0x200 {
+            return Err(Chip8Error::InvalidFont(String::from(
+                "glyphs taller than 32 rows",
+            )));
+        }
+        Ok(Font {
+            glyph_len: data.len() / 16,
+            data,
+        })
+    }
+
+    pub fn standard() -> Self {
+        Font {
+            glyph_len: 5,
+            data: STANDARD_GLYPHS.to_vec(),
+        }
+    }
+
+    pub fn glyph_len(&self) -> usize {
+        self.glyph_len
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Chip8 {
+    // Adds or replaces the font called `name`. Replacing the active one takes effect
+    // straight away.
+    pub fn register_font(&mut self, name: &str, font: Font) {
+        match self.fonts.iter_mut().find(|(n, _)| n == name) {
+            Some((_, f)) => *f = font,
+            None => self.fonts.push((name.to_string(), font)),
+        }
+        if self.font_name() == name {
+            self.set_font_base(self.font_base);
+            self.write_font();
+        }
+    }
+
+    // Switches to a registered font and copies it to the font address, so sprites
+    // already drawn keep the old glyphs and later Fx29s use the new ones.
+    pub fn set_font(&mut self, name: &str) -> Result<(), Chip8Error> {
+        let Some(n) = self.fonts.iter().position(|(n, _)| n == name) else {
+            return Err(Chip8Error::InvalidFont(format!("no font called {}", name)));
+        };
+        self.active_font = n;
+        self.set_font_base(self.font_base);
+        self.write_font();
+        Ok(())
+    }
+
+    pub fn font_name(&self) -> &str {
+        &self.fonts[self.active_font].0
+    }
+
+    pub fn font(&self) -> &Font {
+        &self.fonts[self.active_font].1
+    }
+
+    // Call before `load`. Some interpreters keep the font at 0x50 rather than 0, which
+    // programs reading the font bytes directly can depend on. The font has to fit
+    // below 0x200, so larger addresses are clamped.
+    pub fn set_font_base(&mut self, addr: u16) {
+        let max = 0x200 - self.font().data.len();
+        self.font_base = addr.min(max as u16);
+    }
+
+    pub fn font_base(&self) -> u16 {
+        self.font_base
+    }
+
+    // Where Fx29 points I for `digit`.
+    pub(super) fn glyph_address(&self, digit: u8) -> u32 {
+        self.font_base as u32 + digit as u32 * self.font().glyph_len as u32
+    }
+
+    pub(super) fn write_font(&mut self) {
+        let base = self.font_base as usize;
+        let font = &self.fonts[self.active_font].1;
+        self.memory[base..base + font.data.len()].copy_from_slice(&font.data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{rom, steps};
+    use super::*;
+
+    // A machine with its font at `base` that points I at the glyph for V0 = 0xA and
+    // draws it at (0, 0).
+    fn font_at(base: u16) -> Chip8 {
+        let mut e = Chip8::new();
+        e.set_font_base(base);
+        e.load(&rom(&[0x600A, 0xF029, 0xD115])).unwrap();
+        e
+    }
+
+    #[test]
+    fn fx29_points_into_the_font_wherever_it_is() {
+        let e = font_at(0);
+        assert_eq!(e.font_base(), 0);
+        assert_eq!(e.memory()[..5], [0xF0, 0x90, 0x90, 0x90, 0xF0]);
+
+        let mut e = font_at(0x50);
+        assert_eq!(e.memory()[0x50..0x55], [0xF0, 0x90, 0x90, 0x90, 0xF0]);
+        assert!(e.memory()[..0x50].iter().all(|&b| b == 0));
+        steps(&mut e, 2);
+        assert_eq!(e.index(), 0x50 + 0xA * 5);
+        // The glyph drawn is the A.
+        steps(&mut e, 1);
+        let text = e.screen().to_text();
+        let rows: Vec<_> = text.lines().take(5).map(|l| &l[..4]).collect();
+        assert_eq!(rows, ["####", "#..#", "####", "#..#", "#..#"]);
+    }
+
+    #[test]
+    fn font_bases_past_the_interpreter_area_are_clamped() {
+        let mut e = font_at(0x1FF);
+        assert_eq!(e.font_base(), 0x200 - 80);
+        steps(&mut e, 2);
+        assert_eq!(e.index(), 0x1B0 + 0xA * 5);
+        // The program is untouched.
+        assert_eq!(e.memory()[0x200..0x202], [0x60, 0x0A]);
+    }
+
+    #[test]
+    fn reset_keeps_the_font_where_it_was() {
+        let mut e = font_at(0x50);
+        e.reset().unwrap();
+        assert_eq!(e.font_base(), 0x50);
+        assert_eq!(e.memory()[0x50], 0xF0);
+    }
+
+    // Glyph n is n + 1 rows of a single pixel in column n % 8, padded to `rows` rows.
+    fn stripes(rows: usize) -> Font {
+        let data = (0..16)
+            .flat_map(|n| (0..rows).map(move |r| if r <= n { 0x80 >> (n % 8) } else { 0 }))
+            .collect();
+        Font::new(data).unwrap()
+    }
+
+    // Runs Fx29 and Dxyn for `digit` at (0, 0), drawing `rows` rows.
+    fn draw_digit(e: &mut Chip8, digit: u8, rows: u8) {
+        e.load(&rom(&[
+            0x6000 | digit as u16,
+            0x6100,
+            0xF029,
+            0xD110 | rows as u16,
+        ]))
+        .unwrap();
+        steps(e, 4);
+    }
+
+    #[test]
+    fn registered_fonts_are_drawn_once_active() {
+        let mut e = Chip8::new();
+        assert_eq!(e.font_name(), STANDARD_FONT);
+        e.register_font("stripes", stripes(5));
+        // Registering alone doesn't switch.
+        assert_eq!(e.font(), &Font::standard());
+        e.set_font("stripes").unwrap();
+        assert_eq!(e.font_name(), "stripes");
+        draw_digit(&mut e, 2, 5);
+        assert_eq!(e.index(), 10);
+        let lit: Vec<_> = e.screen().lit_pixels().collect();
+        assert_eq!(lit, [(0, 2), (1, 2), (2, 2)]);
+    }
+
+    #[test]
+    fn fx29_steps_by_the_glyph_height() {
+        let mut e = Chip8::new();
+        e.set_font_base(0x50);
+        e.register_font("tall", stripes(10));
+        e.set_font("tall").unwrap();
+        draw_digit(&mut e, 9, 10);
+        assert_eq!(e.index(), 0x50 + 9 * 10);
+        let lit: Vec<_> = e.screen().lit_pixels().collect();
+        assert_eq!(lit, (0..10).map(|r| (r, 1)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn switching_fonts_rewrites_the_font_area() {
+        let mut e = Chip8::new();
+        e.register_font("stripes", stripes(5));
+        e.set_font("stripes").unwrap();
+        assert_eq!(e.memory()[..5], [0x80, 0, 0, 0, 0]);
+        // Replacing the active font takes effect straight away.
+        e.register_font("stripes", Font::standard());
+        assert_eq!(e.memory()[..5], [0xF0, 0x90, 0x90, 0x90, 0xF0]);
+        e.set_font(STANDARD_FONT).unwrap();
+        assert!(matches!(
+            e.set_font("missing"),
+            Err(Chip8Error::InvalidFont(why)) if why == "no font called missing"
+        ));
+        assert_eq!(e.font_name(), STANDARD_FONT);
+    }
+
+    #[test]
+    fn fonts_must_be_sixteen_glyphs_below_0x200() {
+        for len in [0, 15, 81] {
+            assert!(Font::new(vec![0; len]).is_err(), "{}", len);
+        }
+        assert!(Font::new(vec![0; 0x210]).is_err());
+        assert_eq!(Font::new(vec![0; 160]).unwrap().glyph_len(), 10);
+        // Taller fonts leave less room for the base address.
+        let mut e = Chip8::new();
+        e.register_font("tall", stripes(10));
+        e.set_font("tall").unwrap();
+        e.set_font_base(0x1FF);
+        assert_eq!(e.font_base(), 0x200 - 160);
+    }
+}
@@ -0,0 +1,236 @@
+// The macroquad frontend: drawing the display to the window and reading the host
+// keyboard. Everything else in `emulator` runs without a window, so building without
+// the `macroquad` feature leaves a core that can be stepped, inspected and rendered
+// with `Screen::render_to_rgba` from tests, servers or other frontends.
+use super::{Chip8, Chip8Error, DisplayBackend, InputSource, KeyBindings, Palette, Screen};
+use macroquad::input::KeyCode;
+use macroquad::prelude::*;
+
+pub fn keycode_from_hex(x: u8) -> KeyCode {
+    match x {
+        0 => KeyCode::Key0,
+        1 => KeyCode::Key1,
+        2 => KeyCode::Key2,
+        3 => KeyCode::Key3,
+        4 => KeyCode::Key4,
+        5 => KeyCode::Key5,
+        6 => KeyCode::Key6,
+        7 => KeyCode::Key7,
+        8 => KeyCode::Key8,
+        9 => KeyCode::Key9,
+        10 => KeyCode::A,
+        11 => KeyCode::B,
+        12 => KeyCode::C,
+        13 => KeyCode::D,
+        14 => KeyCode::E,
+        15 => KeyCode::F,
+        _ => KeyCode::Z,
+    }
+}
+
+// The keypad key `code` is bound to under `bindings`. With the default bindings this
+// is the inverse of `keycode_from_hex`.
+pub fn hex_from_keycode(code: KeyCode, bindings: &KeyBindings) -> Option<u8> {
+    bindings.key_for(code)
+}
+
+// Keys held now or pressed since the last frame, so a tap shorter than a frame
+// still registers. See `KeyBindings::poll`.
+pub fn poll_host_keys(bindings: &KeyBindings) -> [bool; 16] {
+    bindings.poll()
+}
+
+fn color([r, g, b, a]: [u8; 4]) -> Color {
+    Color::from_rgba(r, g, b, a)
+}
+
+impl Palette {
+    pub fn colors(self) -> [Color; 4] {
+        self.rgba().map(color)
+    }
+
+    pub fn background(self) -> Color {
+        self.colors()[0]
+    }
+
+    pub fn foreground(self) -> Color {
+        self.colors()[1]
+    }
+}
+
+impl Screen {
+    // Top left corner that centers the display inside a `width` x `height` window.
+    fn origin(&self, width: f32, height: f32) -> (f32, f32) {
+        (
+            ((width - (self.cols * self.pixel_w) as f32) / 2.0).max(0.0),
+            ((height - (self.rows * self.pixel_h) as f32) / 2.0).max(0.0),
+        )
+    }
+
+    pub fn pixel_rect(&self, row: usize, col: usize, origin: (f32, f32)) -> Rect {
+        Rect::new(
+            origin.0 + (col * self.pixel_w) as f32,
+            origin.1 + (row * self.pixel_h) as f32,
+            self.pixel_w as f32,
+            self.pixel_h as f32,
+        )
+    }
+
+    // Draws the lit pixels only, the caller clears to `palette().background()`.
+    // Under persistence recently cleared pixels are drawn too, fading out.
+    pub fn draw(&self) {
+        let color = self.palette.foreground();
+        let origin = self.origin(screen_width(), screen_height());
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                let alpha = self.brightness(row, col);
+                if alpha > 0.0 {
+                    let r = self.pixel_rect(row, col, origin);
+                    draw_rectangle(r.x, r.y, r.w, r.h, Color { a: alpha, ..color })
+                }
+            }
+        }
+        if self.grid {
+            self.draw_grid(origin, Color { a: 0.15, ..color });
+        }
+    }
+
+    fn draw_grid(&self, origin: (f32, f32), color: Color) {
+        let (width, height) = (
+            (self.cols * self.pixel_w) as f32,
+            (self.rows * self.pixel_h) as f32,
+        );
+        for col in 0..=self.cols {
+            let x = origin.0 + (col * self.pixel_w) as f32;
+            draw_line(x, origin.1, x, origin.1 + height, 1.0, color);
+        }
+        for row in 0..=self.rows {
+            let y = origin.1 + (row * self.pixel_h) as f32;
+            draw_line(origin.0, y, origin.0 + width, y, 1.0, color);
+        }
+    }
+}
+
+// Draws into the macroquad window, centered, as `Screen::draw` does.
+pub struct MacroquadDisplay;
+
+impl DisplayBackend for MacroquadDisplay {
+    fn present(&mut self, screen: &Screen) {
+        screen.draw();
+    }
+}
+
+impl Chip8 {
+    // Emulates a frame and then draws its result, so the picture is never a frame behind.
+    pub fn run(&mut self) -> Result<(), Chip8Error> {
+        self.tick()?;
+        self.render();
+        Ok(())
+    }
+
+    pub fn render(&self) {
+        match self.mega_frame() {
+            Some((rgba, alpha)) => draw_mega_frame(rgba, alpha),
+            None => self.present(&mut MacroquadDisplay),
+        }
+    }
+
+    // The host keyboard through the key bindings, for `tick` when no input source is set.
+    pub(super) fn host_input(&self) -> Option<Box<dyn InputSource>> {
+        Some(Box::new(self.bindings))
+    }
+}
+
+// The MegaChip display, scaled to fit the window.
+fn draw_mega_frame(rgba: &[u8], alpha: u8) {
+    use super::megachip::{HEIGHT, WIDTH};
+    let texture = Texture2D::from_rgba8(WIDTH as u16, HEIGHT as u16, rgba);
+    texture.set_filter(FilterMode::Nearest);
+    let scale = (screen_width() / WIDTH as f32).min(screen_height() / HEIGHT as f32);
+    let size = vec2(WIDTH as f32 * scale, HEIGHT as f32 * scale);
+    draw_texture_ex(
+        &texture,
+        (screen_width() - size.x) / 2.0,
+        (screen_height() - size.y) / 2.0,
+        Color::from_rgba(0xFF, 0xFF, 0xFF, alpha),
+        DrawTextureParams {
+            dest_size: Some(size),
+            ..Default::default()
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(pixel_w: usize, pixel_h: usize) -> Screen {
+        let mut screen = Screen::new();
+        screen.set_pixel_size(pixel_w, pixel_h);
+        screen
+    }
+
+    #[test]
+    fn keycodes_round_trip_under_the_default_bindings() {
+        let bindings = KeyBindings::default();
+        for k in 0..16 {
+            assert_eq!(hex_from_keycode(keycode_from_hex(k), &bindings), Some(k));
+        }
+        assert_eq!(hex_from_keycode(KeyCode::Q, &bindings), None);
+    }
+
+    #[test]
+    fn keycodes_follow_remapped_bindings() {
+        let bindings = KeyBindings::default().parse("5 = W\n").unwrap();
+        assert_eq!(hex_from_keycode(KeyCode::W, &bindings), Some(5));
+        assert_eq!(hex_from_keycode(KeyCode::Key5, &bindings), None);
+    }
+
+    #[test]
+    fn pixel_rects_use_both_pixel_sizes() {
+        let screen = screen(10, 20);
+        assert_eq!(
+            screen.pixel_rect(0, 0, (0.0, 0.0)),
+            Rect::new(0.0, 0.0, 10.0, 20.0)
+        );
+        assert_eq!(
+            screen.pixel_rect(1, 2, (5.0, 7.0)),
+            Rect::new(25.0, 27.0, 10.0, 20.0)
+        );
+        assert_eq!(
+            screen.pixel_rect(31, 63, (0.0, 0.0)),
+            Rect::new(630.0, 620.0, 10.0, 20.0)
+        );
+    }
+
+    #[test]
+    fn display_is_centered_by_its_scaled_size() {
+        // 640 x 640 with these sizes, although the display is twice as wide as tall.
+        let screen = screen(10, 20);
+        assert_eq!(screen.origin(800.0, 700.0), (80.0, 30.0));
+        // Larger than the window, it starts at the corner.
+        assert_eq!(screen.origin(600.0, 600.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn equal_pixel_sizes_stay_square() {
+        let screen = screen(24, 24);
+        let r = screen.pixel_rect(3, 4, (0.0, 0.0));
+        assert_eq!((r.w, r.h), (24.0, 24.0));
+        assert_eq!((r.x, r.y), (96.0, 72.0));
+    }
+
+    #[test]
+    fn palettes_convert_to_macroquad_colors() {
+        let palette = Palette::GameBoy;
+        assert_eq!(
+            palette.background(),
+            Color::from_rgba(0x0F, 0x38, 0x0F, 0xFF)
+        );
+        assert_eq!(
+            palette.foreground(),
+            Color::from_rgba(0x9B, 0xBC, 0x0F, 0xFF)
+        );
+        assert_eq!(palette.colors().len(), 4);
+    }
+}
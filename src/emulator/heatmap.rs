@@ -0,0 +1,128 @@
+use super::Chip8;
+use crate::prelude::*;
+
+// Per address counts of instruction fetches, data reads and writes. Only kept while
+// enabled, it costs three counters per byte of memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessHeatmap {
+    pub fetches: Vec<u32>,
+    pub reads: Vec<u32>,
+    pub writes: Vec<u32>,
+}
+
+impl AccessHeatmap {
+    fn new(size: usize) -> Self {
+        AccessHeatmap {
+            fetches: vec![0; size],
+            reads: vec![0; size],
+            writes: vec![0; size],
+        }
+    }
+}
+
+fn bump(counts: &mut [u32], addr: usize) {
+    if let Some(c) = counts.get_mut(addr) {
+        *c = c.saturating_add(1);
+    }
+}
+
+impl Chip8 {
+    pub fn set_heatmap_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.heatmap = None;
+        } else if self.heatmap.is_none() {
+            self.heatmap = Some(Box::new(AccessHeatmap::new(self.memory.len())));
+        }
+    }
+
+    pub fn heatmap(&self) -> Option<&AccessHeatmap> {
+        self.heatmap.as_deref()
+    }
+
+    // The counts as [reads, writes, fetches], indexed by address.
+    pub fn access_heatmap(&self) -> Option<[&[u32]; 3]> {
+        self.heatmap
+            .as_deref()
+            .map(|h| [&h.reads[..], &h.writes[..], &h.fetches[..]])
+    }
+
+    pub fn reset_heatmap(&mut self) {
+        if let Some(h) = self.heatmap.as_mut() {
+            **h = AccessHeatmap::new(self.memory.len());
+        }
+    }
+
+    pub(super) fn note_fetch(&mut self, pc: u16) {
+        if let Some(h) = self.heatmap.as_mut() {
+            bump(&mut h.fetches, pc as usize);
+            bump(&mut h.fetches, pc as usize + 1);
+        }
+    }
+
+    pub(super) fn note_reads(&mut self, addr: usize, len: usize) {
+        if let Some(h) = self.heatmap.as_mut() {
+            (addr..addr + len).for_each(|a| bump(&mut h.reads, a));
+        }
+    }
+
+    pub(super) fn note_writes(&mut self, addr: usize, len: usize) {
+        if let Some(h) = self.heatmap.as_mut() {
+            (addr..addr + len).for_each(|a| bump(&mut h.writes, a));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{machine, steps};
+
+    #[test]
+    fn counters_are_off_by_default() {
+        let mut e = machine(&[0x6001]);
+        steps(&mut e, 1);
+        assert!(e.heatmap().is_none() && e.access_heatmap().is_none());
+    }
+
+    #[test]
+    fn fetches_reads_and_writes_are_counted_per_address() {
+        // Stores V0..V1 at 0x300, draws them as 2 rows and reads them back.
+        let mut e = machine(&[0xA300, 0xF155, 0xA300, 0xD002, 0xF165, 0x1200]);
+        e.set_heatmap_enabled(true);
+        // Back round to the first instruction.
+        steps(&mut e, 7);
+        let h = e.heatmap().unwrap();
+        assert_eq!(h.fetches[0x200..0x202], [2, 2]);
+        assert_eq!(h.fetches[0x20A..0x20C], [1, 1]);
+        assert_eq!(h.writes[0x300..0x303], [1, 1, 0]);
+        // Once by the draw and once by Fx65.
+        assert_eq!(h.reads[0x300..0x303], [2, 2, 0]);
+        assert_eq!(h.reads[0x200], 0);
+        let [reads, writes, fetches] = e.access_heatmap().unwrap();
+        assert_eq!((reads[0x300], writes[0x300], fetches[0x200]), (2, 1, 2));
+    }
+
+    #[test]
+    fn reset_zeroes_the_counts_and_disabling_drops_them() {
+        let mut e = machine(&[0x1200]);
+        e.set_heatmap_enabled(true);
+        steps(&mut e, 3);
+        assert_eq!(e.heatmap().unwrap().fetches[0x200], 3);
+        e.reset_heatmap();
+        assert!(e.heatmap().unwrap().fetches.iter().all(|&c| c == 0));
+        e.set_heatmap_enabled(false);
+        assert!(e.heatmap().is_none());
+    }
+
+    #[test]
+    fn loop_bodies_are_the_most_fetched() {
+        // Counts V0 up to 50, then spins at 0x208.
+        let mut e = machine(&[0x6000, 0x7001, 0x3032, 0x1202, 0x1208]);
+        e.set_heatmap_enabled(true);
+        steps(&mut e, 160);
+        let [_, _, fetches] = e.access_heatmap().unwrap();
+        let per_instruction: Vec<u32> = (0x200..0x20A).step_by(2).map(|a| fetches[a]).collect();
+        assert_eq!(per_instruction, [1, 50, 50, 49, 10]);
+        // Both bytes of each opcode.
+        assert_eq!(fetches[0x203], 50);
+    }
+}
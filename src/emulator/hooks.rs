@@ -0,0 +1,435 @@
+use super::Chip8;
+use crate::prelude::*;
+
+pub type PcHook = Box<dyn FnMut(&mut Chip8)>;
+pub type InstructionHook = Box<dyn FnMut(u16, u16)>;
+pub type OverflowHook = Box<dyn FnMut(u16, u16)>;
+pub type DeepStackHook = Box<dyn FnMut(u16, usize)>;
+pub type MisalignedJumpHook = Box<dyn FnMut(u16, u16)>;
+pub type DrawHook = Box<dyn FnMut(u16, u16, bool)>;
+pub type SoundHook = Box<dyn FnMut()>;
+pub type KeyWaitHook = Box<dyn FnMut(u16, usize)>;
+
+#[derive(Default)]
+pub(super) struct Hooks {
+    pc: Vec<(u16, PcHook)>,
+    instruction: Vec<InstructionHook>,
+    overflow: Vec<OverflowHook>,
+    deep_stack: Vec<(usize, DeepStackHook)>,
+    misaligned_jump: Vec<MisalignedJumpHook>,
+    draw: Vec<DrawHook>,
+    sound_start: Vec<SoundHook>,
+    sound_stop: Vec<SoundHook>,
+    // Whether the sound timer was running when last checked, for the sound hooks.
+    sound_on: bool,
+    key_wait: Vec<KeyWaitHook>,
+    // The Fx0A currently waiting, so its hooks run once per wait.
+    pub(super) waiting_at: Option<u16>,
+}
+
+impl Chip8 {
+    // Runs `hook` whenever PC reaches `addr`, before that instruction executes.
+    pub fn on_pc(&mut self, addr: u16, hook: PcHook) {
+        self.hooks.pc.push((addr, hook));
+    }
+
+    pub fn clear_pc_hooks(&mut self) {
+        self.hooks.pc.clear();
+    }
+
+    pub(super) fn run_pc_hooks(&mut self) {
+        if self.hooks.pc.is_empty() {
+            return;
+        }
+        let pc = self.pc;
+        // Hooks get the whole machine, so they are moved out while they run.
+        let mut hooks = core::mem::take(&mut self.hooks.pc);
+        for (addr, hook) in hooks.iter_mut() {
+            if *addr == pc {
+                hook(self);
+            }
+        }
+        hooks.append(&mut self.hooks.pc);
+        self.hooks.pc = hooks;
+    }
+
+    // Runs `hook` with (pc, opcode) for every fetched instruction, before it executes.
+    pub fn on_instruction(&mut self, hook: InstructionHook) {
+        self.hooks.instruction.push(hook);
+    }
+
+    pub fn clear_instruction_hooks(&mut self) {
+        self.hooks.instruction.clear();
+    }
+
+    pub(super) fn run_instruction_hooks(&mut self, pc: u16, opcode: u16) {
+        for hook in self.hooks.instruction.iter_mut() {
+            hook(pc, opcode);
+        }
+    }
+
+    // Runs `hook` with (pc, opcode) whenever a 7xkk or 8xy4 wraps past 255. Execution
+    // is unaffected, this is for catching overflows a program didn't mean.
+    pub fn on_overflow(&mut self, hook: OverflowHook) {
+        self.hooks.overflow.push(hook);
+    }
+
+    pub fn clear_overflow_hooks(&mut self) {
+        self.hooks.overflow.clear();
+    }
+
+    pub(super) fn run_overflow_hooks(&mut self, opcode: u16) {
+        let pc = self.pc;
+        for hook in self.hooks.overflow.iter_mut() {
+            hook(pc, opcode);
+        }
+    }
+
+    // Runs `hook` with (pc, depth) when a 2nnn takes the stack past `threshold`
+    // entries, as early warning of runaway recursion. It runs again each time the
+    // depth climbs back past it.
+    pub fn on_deep_stack(&mut self, threshold: usize, hook: DeepStackHook) {
+        self.hooks.deep_stack.push((threshold, hook));
+    }
+
+    pub fn clear_deep_stack_hooks(&mut self) {
+        self.hooks.deep_stack.clear();
+    }
+
+    pub(super) fn run_deep_stack_hooks(&mut self) {
+        let (pc, depth) = (self.pc, self.stack.len());
+        for (threshold, hook) in self.hooks.deep_stack.iter_mut() {
+            if depth == *threshold + 1 {
+                hook(pc, depth);
+            }
+        }
+    }
+
+    // Runs `hook` with (pc, target) when a 1nnn, 2nnn or Bnnn goes to an odd address,
+    // unless `set_misaligned_jump_error` makes that an error instead.
+    pub fn on_misaligned_jump(&mut self, hook: MisalignedJumpHook) {
+        self.hooks.misaligned_jump.push(hook);
+    }
+
+    pub fn clear_misaligned_jump_hooks(&mut self) {
+        self.hooks.misaligned_jump.clear();
+    }
+
+    pub(super) fn run_misaligned_jump_hooks(&mut self, target: u16) {
+        let pc = self.pc;
+        for hook in self.hooks.misaligned_jump.iter_mut() {
+            hook(pc, target);
+        }
+    }
+
+    // Runs `hook` with (pc, opcode, collided) after every Dxyn.
+    pub fn on_draw(&mut self, hook: DrawHook) {
+        self.hooks.draw.push(hook);
+    }
+
+    pub fn clear_draw_hooks(&mut self) {
+        self.hooks.draw.clear();
+    }
+
+    pub(super) fn run_draw_hooks(&mut self, pc: u16, opcode: u16) {
+        let collided = self.registers.v[0xF] != 0;
+        for hook in self.hooks.draw.iter_mut() {
+            hook(pc, opcode, collided);
+        }
+    }
+
+    // Runs `hook` when the sound timer starts running, after the Fx18 that set it.
+    pub fn on_sound_start(&mut self, hook: SoundHook) {
+        self.hooks.sound_start.push(hook);
+    }
+
+    // Runs `hook` when the sound timer stops, usually as it counts down to 0 at the
+    // end of a frame.
+    pub fn on_sound_stop(&mut self, hook: SoundHook) {
+        self.hooks.sound_stop.push(hook);
+    }
+
+    pub fn clear_sound_hooks(&mut self) {
+        self.hooks.sound_start.clear();
+        self.hooks.sound_stop.clear();
+    }
+
+    pub(super) fn check_sound_hooks(&mut self) {
+        let on = self.is_sound_active();
+        if on == self.hooks.sound_on {
+            return;
+        }
+        self.hooks.sound_on = on;
+        let hooks = match on {
+            true => &mut self.hooks.sound_start,
+            false => &mut self.hooks.sound_stop,
+        };
+        for hook in hooks.iter_mut() {
+            hook();
+        }
+    }
+
+    // Runs `hook` with (pc, x) when an Fx0A starts waiting for a key. It runs once per
+    // wait, not for every instruction spent waiting.
+    pub fn on_key_wait(&mut self, hook: KeyWaitHook) {
+        self.hooks.key_wait.push(hook);
+    }
+
+    pub fn clear_key_wait_hooks(&mut self) {
+        self.hooks.key_wait.clear();
+    }
+
+    pub(super) fn run_key_wait_hooks(&mut self, x: usize) {
+        let pc = self.pc;
+        if self.hooks.waiting_at == Some(pc) {
+            return;
+        }
+        self.hooks.waiting_at = Some(pc);
+        for hook in self.hooks.key_wait.iter_mut() {
+            hook(pc, x);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{machine, rom, steps};
+    use super::super::Chip8Error;
+    use alloc::rc::Rc;
+    use core::cell::{Cell, RefCell};
+
+    // 0x202 and 0x204 each add 1 to V0, then 0x206 jumps back to 0x202.
+    const LOOP: [u16; 4] = [0x6000, 0x7001, 0x7001, 0x1202];
+
+    // The (pc, target) pairs the misaligned jump hooks saw after `n` steps of `program`.
+    fn misaligned_jumps(program: &[u16], n: usize) -> Vec<(u16, u16)> {
+        let mut e = machine(program);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        e.on_misaligned_jump(Box::new(move |pc, target| {
+            log.borrow_mut().push((pc, target))
+        }));
+        steps(&mut e, n);
+        seen.take()
+    }
+
+    #[test]
+    fn misaligned_jump_hooks_see_odd_targets_only() {
+        assert_eq!(misaligned_jumps(&[0x1203], 1), [(0x200, 0x203)]);
+        assert_eq!(misaligned_jumps(&[0x2205], 1), [(0x200, 0x205)]);
+        assert_eq!(misaligned_jumps(&[0x6001, 0xB204], 2), [(0x202, 0x205)]);
+        assert_eq!(misaligned_jumps(&[0x1202, 0x6001, 0xB205], 3), []);
+    }
+
+    #[test]
+    fn odd_jumps_still_go_through_without_the_error() {
+        let mut e = machine(&[0x1203]);
+        steps(&mut e, 1);
+        assert_eq!(e.pc(), 0x203);
+    }
+
+    #[test]
+    fn odd_jumps_can_be_an_error() {
+        let count = Rc::new(Cell::new(0));
+        for (program, pc) in [
+            (&[0x1203][..], 0x200),
+            (&[0x2205][..], 0x200),
+            (&[0x6001, 0xB204][..], 0x202),
+        ] {
+            let mut e = machine(program);
+            let counter = count.clone();
+            e.on_misaligned_jump(Box::new(move |_, _| counter.set(counter.get() + 1)));
+            e.set_misaligned_jump_error(true);
+            steps(&mut e, program.len() - 1);
+            let err = e.step().unwrap_err();
+            assert!(matches!(err, Chip8Error::MisalignedJump { pc: p, .. } if p == pc));
+            assert_eq!(e.pc(), pc);
+        }
+        assert_eq!(count.get(), 0);
+        // Even targets are fine either way.
+        let mut e = machine(&[0x1202, 0x2206, 0x00EE]);
+        e.set_misaligned_jump_error(true);
+        steps(&mut e, 3);
+    }
+
+    #[test]
+    fn draw_hooks_see_every_draw_and_its_collision() {
+        // The same sprite twice, the second erasing the first.
+        let mut e = machine(&[0xA000, 0xD005, 0xD005, 0x6001]);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        e.on_draw(Box::new(move |pc, op, hit| {
+            log.borrow_mut().push((pc, op, hit))
+        }));
+        steps(&mut e, 4);
+        assert_eq!(
+            *seen.borrow(),
+            [(0x202, 0xD005, false), (0x204, 0xD005, true)]
+        );
+        e.clear_draw_hooks();
+        e.load(&rom(&[0xD005])).unwrap();
+        steps(&mut e, 1);
+        assert_eq!(seen.borrow().len(), 2);
+    }
+
+    #[test]
+    fn sound_hooks_run_as_the_timer_starts_and_stops() {
+        // ST = 2, then loop.
+        let mut e = machine(&[0x6002, 0xF018, 0x1204]);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let (start, stop) = (events.clone(), events.clone());
+        e.on_sound_start(Box::new(move || start.borrow_mut().push("start")));
+        e.on_sound_stop(Box::new(move || stop.borrow_mut().push("stop")));
+        steps(&mut e, 2);
+        assert_eq!(*events.borrow(), ["start"]);
+        e.run_frame().unwrap();
+        assert_eq!(*events.borrow(), ["start"]);
+        e.run_frame().unwrap();
+        e.run_frame().unwrap();
+        assert_eq!(*events.borrow(), ["start", "stop"]);
+        e.clear_sound_hooks();
+        e.load(&rom(&[0x6002, 0xF018])).unwrap();
+        steps(&mut e, 2);
+        assert!(e.is_sound_active());
+        assert_eq!(events.borrow().len(), 2);
+    }
+
+    #[test]
+    fn key_wait_hooks_run_once_per_wait() {
+        let mut e = machine(&[0xF30A, 0xF40A]);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        e.on_key_wait(Box::new(move |pc, x| log.borrow_mut().push((pc, x))));
+        steps(&mut e, 5);
+        assert_eq!(*seen.borrow(), [(0x200, 3)]);
+        e.inject_key(1, true);
+        e.keyboard.update([false; 16]);
+        steps(&mut e, 1);
+        e.inject_key(1, false);
+        e.keyboard.update([false; 16]);
+        steps(&mut e, 3);
+        assert_eq!(*seen.borrow(), [(0x200, 3), (0x202, 4)]);
+    }
+
+    #[test]
+    fn pc_hook_runs_each_time_its_address_is_reached() {
+        let mut e = machine(&LOOP);
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        e.on_pc(0x204, Box::new(move |_| counter.set(counter.get() + 1)));
+        steps(&mut e, 2);
+        assert_eq!(count.get(), 0);
+        steps(&mut e, 1);
+        assert_eq!(count.get(), 1);
+        steps(&mut e, 4);
+        assert_eq!(count.get(), 2);
+    }
+
+    #[test]
+    fn pc_hook_runs_before_the_instruction_and_can_change_the_machine() {
+        let mut e = machine(&LOOP);
+        let seen = Rc::new(Cell::new(None));
+        let at = seen.clone();
+        e.on_pc(
+            0x204,
+            Box::new(move |e| {
+                at.set(Some((e.pc(), e.registers()[0])));
+                e.registers.v[0] = 0x10;
+            }),
+        );
+        steps(&mut e, 3);
+        assert_eq!(seen.get(), Some((0x204, 1)));
+        assert_eq!(e.registers()[0], 0x11);
+    }
+
+    #[test]
+    fn cleared_pc_hooks_no_longer_run() {
+        let mut e = machine(&LOOP);
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        e.on_pc(0x204, Box::new(move |_| counter.set(counter.get() + 1)));
+        e.clear_pc_hooks();
+        steps(&mut e, 7);
+        assert_eq!(count.get(), 0);
+    }
+
+    // Runs `program` with an overflow hook, returning the (pc, opcode) pairs it saw.
+    fn overflows(program: &[u16], n: usize) -> Vec<(u16, u16)> {
+        let mut e = machine(program);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        e.on_overflow(Box::new(move |pc, opcode| {
+            log.borrow_mut().push((pc, opcode))
+        }));
+        steps(&mut e, n);
+        let seen = seen.borrow().clone();
+        seen
+    }
+
+    #[test]
+    fn adding_past_255_runs_the_overflow_hook() {
+        assert_eq!(overflows(&[0x60FF, 0x7001], 2), [(0x202, 0x7001)]);
+        assert_eq!(overflows(&[0x6380, 0x6580, 0x8354], 3), [(0x204, 0x8354)]);
+    }
+
+    #[test]
+    fn sums_up_to_255_are_not_overflows() {
+        assert!(overflows(&[0x60FE, 0x7001, 0x6101, 0x6000, 0x8014], 5).is_empty());
+        // Other carrying instructions aren't watched.
+        assert!(overflows(&[0x6001, 0x6102, 0x8015], 3).is_empty());
+    }
+
+    #[test]
+    fn overflows_still_wrap_and_set_the_flag() {
+        let mut e = machine(&[0x60FF, 0x7002, 0x61FF, 0x8014]);
+        e.on_overflow(Box::new(|_, _| {}));
+        // 7xkk wraps and leaves VF alone.
+        steps(&mut e, 2);
+        assert_eq!((e.registers()[0], e.registers()[0xF]), (0x01, 0));
+        // 8xy4 wraps and carries.
+        steps(&mut e, 2);
+        assert_eq!((e.registers()[0], e.registers()[0xF]), (0x00, 1));
+    }
+
+    #[test]
+    fn cleared_overflow_hooks_no_longer_run() {
+        let mut e = machine(&[0x60FF, 0x7001]);
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        e.on_overflow(Box::new(move |_, _| counter.set(counter.get() + 1)));
+        e.clear_overflow_hooks();
+        steps(&mut e, 2);
+        assert_eq!(count.get(), 0);
+    }
+
+    #[test]
+    fn deep_stack_hook_runs_when_calls_pass_the_threshold() {
+        // Calls itself until the stack overflows.
+        let mut e = machine(&[0x2200]);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        e.on_deep_stack(
+            12,
+            Box::new(move |pc, depth| log.borrow_mut().push((pc, depth))),
+        );
+        steps(&mut e, 12);
+        assert!(seen.borrow().is_empty());
+        steps(&mut e, 3);
+        assert_eq!(*seen.borrow(), [(0x200, 13)]);
+    }
+
+    #[test]
+    fn deep_stack_hook_runs_again_after_returning_below_it() {
+        // Calls 0x206 twice, which goes one deeper.
+        let mut e = machine(&[0x2206, 0x2206, 0x1204, 0x220A, 0x00EE, 0x00EE]);
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        e.on_deep_stack(1, Box::new(move |_, _| counter.set(counter.get() + 1)));
+        steps(&mut e, 8);
+        assert_eq!(count.get(), 2);
+        e.clear_deep_stack_hooks();
+        e.reset().unwrap();
+        steps(&mut e, 8);
+        assert_eq!(count.get(), 2);
+    }
+}
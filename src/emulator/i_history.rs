@@ -0,0 +1,79 @@
+// Opt-in record of writes to I, for following how a program walks its sprite and
+// data tables.
+use super::Chip8;
+use alloc::collections::VecDeque;
+
+impl Chip8 {
+    // Keeps the last `len` (pc, I) pairs, one per instruction that set I. `None` turns
+    // recording off and drops the history.
+    pub fn set_i_history(&mut self, len: Option<usize>) {
+        self.i_history = len.map(|len| (VecDeque::with_capacity(len), len));
+    }
+
+    // Oldest first, `None` unless recording.
+    pub fn i_history(&self) -> Option<&VecDeque<(u16, u32)>> {
+        self.i_history.as_ref().map(|(history, _)| history)
+    }
+
+    pub(super) fn set_i(&mut self, value: u32) {
+        self.registers.i = value;
+        if let Some((history, len)) = self.i_history.as_mut() {
+            if history.len() == *len {
+                history.pop_front();
+            }
+            if *len > 0 {
+                history.push_back((self.pc, value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{machine, steps};
+
+    #[test]
+    fn annn_writes_are_recorded_with_their_pc() {
+        let mut e = machine(&[0xA300, 0x6001, 0xA310, 0xA320]);
+        assert!(e.i_history().is_none());
+        e.set_i_history(Some(8));
+        steps(&mut e, 4);
+        let history: Vec<_> = e.i_history().unwrap().iter().copied().collect();
+        assert_eq!(history, [(0x200, 0x300), (0x204, 0x310), (0x206, 0x320)]);
+    }
+
+    #[test]
+    fn every_instruction_that_moves_i_is_recorded() {
+        let mut e = machine(&[0x6004, 0xF029, 0xF01E, 0xA300, 0xF155]);
+        e.set_i_history(Some(8));
+        steps(&mut e, 5);
+        let history: Vec<_> = e.i_history().unwrap().iter().copied().collect();
+        // Fx55 leaves I past the registers it stored.
+        assert_eq!(
+            history,
+            [(0x202, 20), (0x204, 24), (0x206, 0x300), (0x208, 0x302)]
+        );
+    }
+
+    #[test]
+    fn the_history_keeps_the_latest_writes() {
+        let mut e = machine(&[0xA300, 0xA301, 0xA302, 0xA303]);
+        e.set_i_history(Some(2));
+        steps(&mut e, 4);
+        let history: Vec<_> = e.i_history().unwrap().iter().copied().collect();
+        assert_eq!(history, [(0x204, 0x302), (0x206, 0x303)]);
+        e.set_i_history(None);
+        assert!(e.i_history().is_none());
+    }
+
+    #[test]
+    fn loading_a_program_clears_the_history() {
+        let mut e = machine(&[0xA300]);
+        e.set_i_history(Some(4));
+        steps(&mut e, 1);
+        e.load(&[0xA3, 0x10]).unwrap();
+        assert!(e.i_history().unwrap().is_empty());
+        steps(&mut e, 1);
+        assert_eq!(e.i_history().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,161 @@
+// Detects programs that have finished and now spin in place, usually on a 1nnn that
+// jumps to itself. The machine counts as idle once the same PC has been fetched a
+// number of times in a row with nothing changed in between: registers, I, stack,
+// timers, memory, display and input polls. Polling the keypad counts as a change,
+// so loops waiting for a key are never idle.
+use super::{Chip8, Instruction};
+
+// Longest loop, in instructions, that is recognised.
+const MAX_LOOP_LEN: u32 = 8;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+struct Snapshot {
+    v: [u8; 16],
+    i: u32,
+    sp: usize,
+    delay: u8,
+    sound: u8,
+    activity: u64,
+}
+
+#[derive(Default)]
+pub(super) struct IdleDetector {
+    threshold: u32,
+    anchor_pc: u16,
+    anchor: Snapshot,
+    // Fetches since the last one at `anchor_pc`.
+    since: u32,
+    repeats: u32,
+    // Bumped by instructions that change memory or the display or read the keypad.
+    activity: u64,
+}
+
+impl Chip8 {
+    // Marks the machine idle after `iterations` unchanged passes over the same loop.
+    // `None` turns detection off.
+    pub fn set_idle_detection(&mut self, iterations: Option<u32>) {
+        self.idle = iterations.map(|threshold| IdleDetector {
+            threshold,
+            ..Default::default()
+        });
+    }
+
+    // The PC the program is idling at, once it is.
+    pub fn idle_pc(&self) -> Option<u16> {
+        self.idle
+            .as_ref()
+            .filter(|d| d.repeats >= d.threshold)
+            .map(|d| d.anchor_pc)
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle_pc().is_some()
+    }
+
+    pub(super) fn reset_idle(&mut self) {
+        if let Some(d) = self.idle.as_mut() {
+            *d = IdleDetector {
+                threshold: d.threshold,
+                ..Default::default()
+            };
+        }
+    }
+
+    pub(super) fn check_idle(&mut self, pc: u16) {
+        let Some(d) = self.idle.as_mut() else {
+            return;
+        };
+        let now = Snapshot {
+            v: self.registers.v,
+            i: self.registers.i,
+            sp: self.stack.len(),
+            delay: self.timers.delay,
+            sound: self.timers.sound,
+            activity: d.activity,
+        };
+        if pc == d.anchor_pc && now == d.anchor {
+            d.repeats = d.repeats.saturating_add(1);
+            d.since = 0;
+        } else if pc == d.anchor_pc || d.since >= MAX_LOOP_LEN {
+            d.anchor_pc = pc;
+            d.anchor = now;
+            d.repeats = 0;
+            d.since = 0;
+        } else {
+            d.since += 1;
+        }
+    }
+
+    pub(super) fn note_idle_activity(&mut self, ins: &Instruction) {
+        if let Some(d) = self.idle.as_mut() {
+            if ins.touches_display()
+                || matches!(
+                    ins,
+                    Instruction::Skp(_)
+                        | Instruction::Sknp(_)
+                        | Instruction::LdVxK(_)
+                        | Instruction::LdB(_)
+                        | Instruction::LdIVx(_)
+                )
+            {
+                d.activity += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{machine, steps};
+    use super::MAX_LOOP_LEN;
+
+    const THRESHOLD: u32 = 10;
+
+    // Runs `program` for `n` instructions with idle detection on, returning the idle PC.
+    fn idle_after(program: &[u16], n: usize) -> Option<u16> {
+        let mut e = machine(program);
+        e.set_idle_detection(Some(THRESHOLD));
+        steps(&mut e, n);
+        e.idle_pc()
+    }
+
+    #[test]
+    fn a_jump_to_itself_goes_idle_after_the_threshold() {
+        let program = [0x6001, 0x1202];
+        // Up to MAX_LOOP_LEN fetches pass before a loop is picked up at all.
+        let n = (MAX_LOOP_LEN + THRESHOLD) as usize;
+        assert_eq!(idle_after(&program, n), None);
+        assert_eq!(idle_after(&program, n + 1), Some(0x202));
+    }
+
+    #[test]
+    fn short_loops_that_change_nothing_go_idle() {
+        assert!(idle_after(&[0x6001, 0x1200], 100).is_some());
+        assert!(idle_after(&[0x2204, 0x1200, 0x00EE], 100).is_some());
+    }
+
+    #[test]
+    fn loops_that_change_state_stay_busy() {
+        // Counting in a register, and drawing.
+        assert_eq!(idle_after(&[0x7001, 0x1200], 100), None);
+        assert_eq!(idle_after(&[0xD001, 0x1200], 100), None);
+    }
+
+    #[test]
+    fn key_wait_loops_stay_busy() {
+        assert_eq!(idle_after(&[0xE09E, 0x1200], 100), None);
+        assert_eq!(idle_after(&[0xE0A1, 0x1200, 0x1200], 100), None);
+    }
+
+    #[test]
+    fn detection_is_off_by_default_and_cleared_on_reset() {
+        let mut e = machine(&[0x1200]);
+        steps(&mut e, 100);
+        assert!(!e.is_idle());
+        e.set_idle_detection(Some(THRESHOLD));
+        steps(&mut e, 100);
+        assert!(e.is_idle());
+        e.reset().unwrap();
+        assert!(!e.is_idle());
+    }
+}
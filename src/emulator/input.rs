@@ -0,0 +1,117 @@
+// Where the keypad comes from. `tick` polls the input source once a frame, or before
+// every instruction under `InputPolling::PerCycle`, so gamepads, network players or
+// scripted input can drive the keypad instead of the host keyboard. Closures returning
+// the 16 keys work as sources too.
+use super::{Chip8, Chip8Error, InputPolling};
+use crate::prelude::*;
+
+pub trait InputSource {
+    // The keys held down, indexed by keypad key.
+    fn poll(&mut self) -> [bool; 16];
+}
+
+impl<F: FnMut() -> [bool; 16]> InputSource for F {
+    fn poll(&mut self) -> [bool; 16] {
+        self()
+    }
+}
+
+impl Chip8 {
+    // Replaces the host keyboard as what `tick` reads the keypad from. `None` goes back
+    // to the host keyboard, or without the macroquad frontend to leaving the keypad
+    // to `keyboard` and `inject_key`.
+    pub fn set_input_source(&mut self, source: Option<Box<dyn InputSource>>) {
+        self.input_source = source;
+    }
+
+    // One 60Hz emulation frame: poll input, execute and update the timers.
+    pub fn tick(&mut self) -> Result<(), Chip8Error> {
+        let custom = self.input_source.is_some();
+        let Some(mut input) = self.input_source.take().or_else(|| self.host_input()) else {
+            return self.run_frame();
+        };
+        self.keyboard.update(input.poll());
+        if self.input_polling == InputPolling::PerFrame || self.key_sampler.is_some() {
+            let result = self.run_frame();
+            if custom {
+                self.input_source = Some(input);
+            }
+            return result;
+        }
+        self.key_sampler = Some((input, 1));
+        let result = self.run_frame();
+        if let Some((input, _)) = self.key_sampler.take().filter(|_| custom) {
+            self.input_source = Some(input);
+        }
+        result
+    }
+
+    #[cfg(not(feature = "macroquad"))]
+    fn host_input(&self) -> Option<Box<dyn InputSource>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::machine;
+    use super::*;
+
+    // Plays back one keypad state per poll, then holds nothing.
+    struct Scripted(Vec<[bool; 16]>);
+
+    impl InputSource for Scripted {
+        fn poll(&mut self) -> [bool; 16] {
+            if self.0.is_empty() {
+                return [false; 16];
+            }
+            self.0.remove(0)
+        }
+    }
+
+    fn held(key: usize) -> [bool; 16] {
+        core::array::from_fn(|k| k == key)
+    }
+
+    // Counts the frames key 5 is held in V1, a frame being one pass of the loop.
+    const COUNT_5: [u16; 4] = [0x6005, 0xE0A1, 0x7101, 0x1202];
+
+    #[test]
+    fn tick_reads_the_keypad_from_the_source() {
+        let mut e = machine(&COUNT_5);
+        e.set_cycles_per_frame(3);
+        e.set_input_source(Some(Box::new(Scripted(vec![
+            held(0),
+            held(5),
+            held(5),
+            held(4),
+        ]))));
+        e.tick().unwrap();
+        assert!(!e.keyboard.keymap[5]);
+        e.tick().unwrap();
+        assert!(e.keyboard.keymap[5]);
+        e.tick().unwrap();
+        e.tick().unwrap();
+        assert!(e.keyboard.keymap[4]);
+        assert_eq!(e.registers()[1], 2);
+    }
+
+    #[test]
+    fn sources_are_polled_once_a_frame_and_can_be_replaced() {
+        let mut e = machine(&COUNT_5);
+        let mut polls = 0;
+        e.set_input_source(Some(Box::new(move || {
+            polls += 1;
+            held(if polls > 2 { 5 } else { 0 })
+        })));
+        e.tick().unwrap();
+        e.tick().unwrap();
+        assert!(!e.keyboard.keymap[5]);
+        e.tick().unwrap();
+        assert!(e.keyboard.keymap[5]);
+        e.set_input_source(Some(Box::new(|| held(0xF))));
+        e.tick().unwrap();
+        assert!(e.keyboard.keymap[0xF]);
+        assert!(!e.keyboard.keymap[5]);
+    }
+}
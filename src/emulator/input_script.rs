@@ -0,0 +1,267 @@
+// Hand-written input for automated runs, as opposed to recorded input. Commands are
+// separated by newlines or `;`, and `#` starts a comment:
+//
+//   wait 120; press 5; wait 10; release 5
+//   assert_pixel 12 20 on
+//   assert_hash 0xDEADBEEF
+//
+// `wait` runs that many frames, keys are hex digits, pixels are column then row and
+// hashes are `Screen::hash` values.
+use super::{Chip8, Chip8Error};
+use crate::prelude::*;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::fs;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Command {
+    Wait(u64),
+    Press(u8),
+    Release(u8),
+    AssertPixel { x: usize, y: usize, on: bool },
+    AssertHash(u64),
+}
+
+// A failed assertion, with the frame it was checked before.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssertionFailure {
+    pub frame: u64,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssertionFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Assertion failed at frame {} (line {}): {}",
+            self.frame, self.line, self.message
+        )
+    }
+}
+
+pub struct InputScript {
+    // Commands along with the line they came from.
+    commands: Vec<(usize, Command)>,
+    next: usize,
+    // Frames left before the next command runs.
+    wait: u64,
+    frame: u64,
+}
+
+fn parse_number<T: TryFrom<u64>>(word: Option<&str>, what: &str) -> Result<T, String> {
+    let word = word.ok_or_else(|| format!("missing {}", what))?;
+    let value = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => word.parse(),
+    };
+    value
+        .ok()
+        .and_then(|v| T::try_from(v).ok())
+        .ok_or_else(|| format!("invalid {} {}", what, word))
+}
+
+fn parse_key(word: Option<&str>) -> Result<u8, String> {
+    let word = word.ok_or("missing key")?;
+    match u8::from_str_radix(word, 16) {
+        Ok(key) if key < 16 => Ok(key),
+        _ => Err(format!("invalid key {}, expected 0-F", word)),
+    }
+}
+
+fn parse_command(text: &str) -> Result<Command, String> {
+    let mut words = text.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    let command = match name {
+        "wait" => Command::Wait(parse_number(words.next(), "frame count")?),
+        "press" => Command::Press(parse_key(words.next())?),
+        "release" => Command::Release(parse_key(words.next())?),
+        "assert_pixel" => Command::AssertPixel {
+            x: parse_number(words.next(), "column")?,
+            y: parse_number(words.next(), "row")?,
+            on: match words.next() {
+                Some("on") => true,
+                Some("off") => false,
+                _ => return Err(String::from("assert_pixel expects on or off")),
+            },
+        },
+        "assert_hash" => Command::AssertHash(parse_number(words.next(), "hash")?),
+        _ => return Err(format!("unknown command {}", name)),
+    };
+    match words.next() {
+        Some(extra) => Err(format!("unexpected {} after {}", extra, name)),
+        None => Ok(command),
+    }
+}
+
+impl InputScript {
+    pub fn parse(source: &str) -> Result<Self, Chip8Error> {
+        let mut commands = Vec::new();
+        for (n, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            for text in line.split(';').filter(|t| !t.trim().is_empty()) {
+                let command = parse_command(text)
+                    .map_err(|e| Chip8Error::Script(format!("line {}: {}", n + 1, e)))?;
+                commands.push((n + 1, command));
+            }
+        }
+        Ok(InputScript {
+            commands,
+            next: 0,
+            wait: 0,
+            frame: 0,
+        })
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &str) -> Result<Self, Chip8Error> {
+        let source =
+            fs::read_to_string(path).map_err(|e| Chip8Error::Script(format!("{}: {}", path, e)))?;
+        Self::parse(&source).map_err(|e| match e {
+            Chip8Error::Script(message) => Chip8Error::Script(format!("{}: {}", path, message)),
+            e => e,
+        })
+    }
+
+    // Frames run so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.wait == 0 && self.next == self.commands.len()
+    }
+
+    // Runs the commands due before the next frame: everything up to the next `wait`.
+    // Keys are set on `e`'s keypad directly, so this is meant for frames run with
+    // `Chip8::run_frame` rather than `tick`.
+    pub fn run_due(&mut self, e: &mut Chip8) -> Result<(), AssertionFailure> {
+        while self.wait == 0 && self.next < self.commands.len() {
+            let (line, command) = self.commands[self.next];
+            self.next += 1;
+            let fail = |message: String| AssertionFailure {
+                frame: self.frame,
+                line,
+                message,
+            };
+            match command {
+                Command::Wait(frames) => self.wait = frames,
+                Command::Press(key) => e.keyboard.keymap[key as usize] = true,
+                Command::Release(key) => e.keyboard.keymap[key as usize] = false,
+                Command::AssertPixel { x, y, on } => {
+                    let screen = e.screen();
+                    if x >= screen.cols() || y >= screen.rows() {
+                        return Err(fail(format!("pixel ({}, {}) is off screen", x, y)));
+                    }
+                    if screen.get(y, x) != on {
+                        return Err(fail(format!(
+                            "pixel ({}, {}) is {}",
+                            x,
+                            y,
+                            if on { "off" } else { "on" }
+                        )));
+                    }
+                }
+                Command::AssertHash(hash) => {
+                    let actual = e.screen().hash();
+                    if actual != hash {
+                        return Err(fail(format!(
+                            "screen hash is {:#X}, expected {:#X}",
+                            actual, hash
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Meant to be called once after every emulated frame.
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+        self.wait = self.wait.saturating_sub(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::machine;
+    use super::*;
+
+    // Draws the "0" glyph at (5, 5) once key 5 is held, one instruction a frame.
+    const WAIT_FOR_5: [u16; 5] = [0x6005, 0xE09E, 0x1202, 0xD005, 0x1208];
+
+    // Runs `source` against `WAIT_FOR_5` until it's done, returning the frames run.
+    fn run(source: &str) -> Result<u64, AssertionFailure> {
+        let mut e = machine(&WAIT_FOR_5);
+        let mut script = InputScript::parse(source).unwrap();
+        loop {
+            script.run_due(&mut e)?;
+            if script.is_done() {
+                return Ok(script.frame());
+            }
+            e.run_frame().unwrap();
+            script.end_frame();
+        }
+    }
+
+    fn parse_error(source: &str) -> String {
+        match InputScript::parse(source) {
+            Err(Chip8Error::Script(message)) => message,
+            _ => panic!("{:?} parsed", source),
+        }
+    }
+
+    #[test]
+    fn scripts_press_keys_and_check_the_screen() {
+        let source = "wait 3; assert_pixel 5 5 off; press 5 # start\nwait 3\nassert_pixel 5 5 on";
+        assert_eq!(run(source), Ok(6));
+    }
+
+    #[test]
+    fn failures_report_the_frame_and_line() {
+        let failure = run("wait 2\n\nassert_pixel 5 5 on").unwrap_err();
+        assert_eq!((failure.frame, failure.line), (2, 3));
+        assert_eq!(
+            failure.to_string(),
+            "Assertion failed at frame 2 (line 3): pixel (5, 5) is off"
+        );
+        let failure = run("assert_pixel 64 0 off").unwrap_err();
+        assert_eq!(failure.message, "pixel (64, 0) is off screen");
+    }
+
+    #[test]
+    fn hashes_are_compared_with_the_screen() {
+        let blank = Chip8::new().screen().hash();
+        assert_eq!(run(&format!("wait 1; assert_hash {:#X}", blank)), Ok(1));
+        let failure = run("press 5; wait 4; assert_hash 0x1").unwrap_err();
+        assert!(failure.message.ends_with("expected 0x1"));
+    }
+
+    #[test]
+    fn released_keys_stop_being_held() {
+        let mut e = machine(&WAIT_FOR_5);
+        let mut script = InputScript::parse("press 5; release 5").unwrap();
+        script.run_due(&mut e).unwrap();
+        assert!(script.is_done());
+        assert!(!e.keyboard.keymap[5]);
+    }
+
+    #[test]
+    fn bad_lines_are_rejected_with_their_number() {
+        assert_eq!(
+            parse_error("wait 1; jump 3"),
+            "line 1: unknown command jump"
+        );
+        assert_eq!(
+            parse_error("\npress 10"),
+            "line 2: invalid key 10, expected 0-F"
+        );
+        assert_eq!(parse_error("wait 1 2"), "line 1: unexpected 2 after wait");
+        assert_eq!(parse_error("wait x"), "line 1: invalid frame count x");
+        assert_eq!(
+            parse_error("assert_pixel 1 2 maybe"),
+            "line 1: assert_pixel expects on or off"
+        );
+    }
+}
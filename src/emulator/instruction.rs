@@ -0,0 +1,506 @@
+use crate::prelude::*;
+use core::fmt;
+
+// An opcode the interpreter implements, for listing capabilities.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    pub pattern: &'static str,
+    pub mnemonic: &'static str,
+    pub description: &'static str,
+}
+
+// Pattern, an opcode matching it and what it does. The mnemonics come from decoding
+// the sample, so they always agree with the disassembler. MegaChip's extra opcodes
+// aren't decoded here and aren't listed.
+const OPCODES: &[(&str, u16, &str)] = &[
+    ("0nnn", 0x0123, "machine code routine at nnn, skipped"),
+    ("00Cn", 0x00C1, "scroll the display down n rows"),
+    ("00E0", 0x00E0, "clear the display"),
+    ("00EE", 0x00EE, "return from a subroutine"),
+    ("00FB", 0x00FB, "scroll the display right 4 pixels"),
+    ("00FC", 0x00FC, "scroll the display left 4 pixels"),
+    ("00FD", 0x00FD, "exit the interpreter"),
+    ("00FE", 0x00FE, "low resolution"),
+    ("00FF", 0x00FF, "high resolution, 128x64"),
+    ("1nnn", 0x1200, "jump to nnn"),
+    ("2nnn", 0x2200, "call the subroutine at nnn"),
+    ("3xkk", 0x3000, "skip if Vx == kk"),
+    ("4xkk", 0x4000, "skip if Vx != kk"),
+    ("5xy0", 0x5000, "skip if Vx == Vy"),
+    ("6xkk", 0x6000, "Vx = kk"),
+    ("7xkk", 0x7000, "Vx += kk, VF untouched"),
+    ("8xy0", 0x8000, "Vx = Vy"),
+    ("8xy1", 0x8001, "Vx |= Vy, VF = 0"),
+    ("8xy2", 0x8002, "Vx &= Vy, VF = 0"),
+    ("8xy3", 0x8003, "Vx ^= Vy, VF = 0"),
+    ("8xy4", 0x8004, "Vx += Vy, VF = carry"),
+    ("8xy5", 0x8005, "Vx -= Vy, VF = no borrow"),
+    ("8xy6", 0x8006, "Vx >>= 1, VF = shifted out bit"),
+    ("8xy7", 0x8007, "Vx = Vy - Vx, VF = no borrow"),
+    ("8xyE", 0x800E, "Vx <<= 1, VF = shifted out bit"),
+    ("9xy0", 0x9000, "skip if Vx != Vy"),
+    ("Annn", 0xA000, "I = nnn"),
+    ("Bnnn", 0xB000, "jump to nnn + V0"),
+    ("Cxkk", 0xC000, "Vx = random byte & kk"),
+    (
+        "Dxyn",
+        0xD001,
+        "draw n sprite rows from I at (Vx, Vy), VF = collision",
+    ),
+    ("Ex9E", 0xE09E, "skip if key Vx is down"),
+    ("ExA1", 0xE0A1, "skip if key Vx is up"),
+    ("Fx07", 0xF007, "Vx = delay timer"),
+    ("Fx0A", 0xF00A, "wait for a key and store it in Vx"),
+    ("Fx15", 0xF015, "delay timer = Vx"),
+    ("Fx18", 0xF018, "sound timer = Vx"),
+    ("Fx1E", 0xF01E, "I += Vx"),
+    ("Fx29", 0xF029, "I = font sprite for digit Vx"),
+    ("Fx33", 0xF033, "store Vx as three BCD digits at I"),
+    ("Fx55", 0xF055, "store V0 to Vx at I, I += x + 1"),
+    ("Fx65", 0xF065, "load V0 to Vx from I, I += x + 1"),
+];
+
+pub fn supported_opcodes() -> Vec<OpcodeInfo> {
+    OPCODES
+        .iter()
+        .map(|&(pattern, sample, description)| OpcodeInfo {
+            pattern,
+            mnemonic: Instruction::decode(sample)
+                .expect("sample opcodes decode")
+                .mnemonic(),
+            description,
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    // A VIP machine code call, which can't run here.
+    Sys(u16),
+    Cls,
+    Ret,
+    ScrollDown(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    Lores,
+    Hires,
+    Jp(u16),
+    Call(u16),
+    SeByte(usize, u8),
+    SneByte(usize, u8),
+    SeReg(usize, usize),
+    LdByte(usize, u8),
+    AddByte(usize, u8),
+    LdReg(usize, usize),
+    Or(usize, usize),
+    And(usize, usize),
+    Xor(usize, usize),
+    AddReg(usize, usize),
+    Sub(usize, usize),
+    Shr(usize, usize),
+    Subn(usize, usize),
+    Shl(usize, usize),
+    SneReg(usize, usize),
+    LdI(u16),
+    JpV0(u16),
+    Rnd(usize, u8),
+    Drw(usize, usize, u8),
+    Skp(usize),
+    Sknp(usize),
+    LdVxDt(usize),
+    LdVxK(usize),
+    LdDtVx(usize),
+    LdStVx(usize),
+    AddI(usize),
+    LdF(usize),
+    LdB(usize),
+    LdIVx(usize),
+    LdVxI(usize),
+}
+
+impl Instruction {
+    pub fn decode(ins: u16) -> Option<Instruction> {
+        let x = ((ins & 0x0F00) >> 8) as usize;
+        let y = ((ins & 0x00F0) >> 4) as usize;
+        let nnn = ins & 0x0FFF;
+        let kk = (ins & 0x00FF) as u8;
+        let n = (ins & 0x000F) as u8;
+
+        // One jump on the top nibble, then on whichever byte tells the group apart.
+        let decoded = match ins >> 12 {
+            // The exact SCHIP patterns first, anything else is a machine code call.
+            0x0 => match ins {
+                0x00C0..=0x00CF => Instruction::ScrollDown(n),
+                0x00E0 => Instruction::Cls,
+                0x00EE => Instruction::Ret,
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::Lores,
+                0x00FF => Instruction::Hires,
+                _ => Instruction::Sys(nnn),
+            },
+            0x1 => Instruction::Jp(nnn),
+            0x2 => Instruction::Call(nnn),
+            0x3 => Instruction::SeByte(x, kk),
+            0x4 => Instruction::SneByte(x, kk),
+            0x5 => Instruction::SeReg(x, y),
+            0x6 => Instruction::LdByte(x, kk),
+            0x7 => Instruction::AddByte(x, kk),
+            0x8 => match n {
+                0x0 => Instruction::LdReg(x, y),
+                0x1 => Instruction::Or(x, y),
+                0x2 => Instruction::And(x, y),
+                0x3 => Instruction::Xor(x, y),
+                0x4 => Instruction::AddReg(x, y),
+                0x5 => Instruction::Sub(x, y),
+                0x6 => Instruction::Shr(x, y),
+                0x7 => Instruction::Subn(x, y),
+                0xE => Instruction::Shl(x, y),
+                _ => return None,
+            },
+            0x9 => Instruction::SneReg(x, y),
+            0xA => Instruction::LdI(nnn),
+            0xB => Instruction::JpV0(nnn),
+            0xC => Instruction::Rnd(x, kk),
+            0xD => Instruction::Drw(x, y, n),
+            0xE => match n {
+                0xE => Instruction::Skp(x),
+                0x1 => Instruction::Sknp(x),
+                _ => return None,
+            },
+            _ => match kk {
+                0x07 => Instruction::LdVxDt(x),
+                0x0A => Instruction::LdVxK(x),
+                0x15 => Instruction::LdDtVx(x),
+                0x18 => Instruction::LdStVx(x),
+                0x1E => Instruction::AddI(x),
+                0x20..=0x2F => Instruction::LdF(x),
+                0x30..=0x3F => Instruction::LdB(x),
+                0x50..=0x5F => Instruction::LdIVx(x),
+                0x60..=0x6F => Instruction::LdVxI(x),
+                _ => return None,
+            },
+        };
+        Some(decoded)
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Sys(_) => "SYS",
+            Instruction::Cls => "CLS",
+            Instruction::Ret => "RET",
+            Instruction::ScrollDown(_) => "SCD",
+            Instruction::ScrollRight => "SCR",
+            Instruction::ScrollLeft => "SCL",
+            Instruction::Exit => "EXIT",
+            Instruction::Lores => "LOW",
+            Instruction::Hires => "HIGH",
+            Instruction::Jp(_) | Instruction::JpV0(_) => "JP",
+            Instruction::Call(_) => "CALL",
+            Instruction::SeByte(..) | Instruction::SeReg(..) => "SE",
+            Instruction::SneByte(..) | Instruction::SneReg(..) => "SNE",
+            Instruction::AddByte(..) | Instruction::AddReg(..) | Instruction::AddI(_) => "ADD",
+            Instruction::Or(..) => "OR",
+            Instruction::And(..) => "AND",
+            Instruction::Xor(..) => "XOR",
+            Instruction::Sub(..) => "SUB",
+            Instruction::Shr(..) => "SHR",
+            Instruction::Subn(..) => "SUBN",
+            Instruction::Shl(..) => "SHL",
+            Instruction::Rnd(..) => "RND",
+            Instruction::Drw(..) => "DRW",
+            Instruction::Skp(_) => "SKP",
+            Instruction::Sknp(_) => "SKNP",
+            Instruction::LdByte(..)
+            | Instruction::LdReg(..)
+            | Instruction::LdI(_)
+            | Instruction::LdVxDt(_)
+            | Instruction::LdVxK(_)
+            | Instruction::LdDtVx(_)
+            | Instruction::LdStVx(_)
+            | Instruction::LdF(_)
+            | Instruction::LdB(_)
+            | Instruction::LdIVx(_)
+            | Instruction::LdVxI(_) => "LD",
+        }
+    }
+
+    // Whether pc moves on to the next instruction afterwards, rather than jumping.
+    // Calls count, as their return comes back to the next instruction.
+    pub fn falls_through(&self) -> bool {
+        !matches!(
+            self,
+            Instruction::Jp(_) | Instruction::JpV0(_) | Instruction::Ret | Instruction::Exit
+        )
+    }
+
+    // Whether this instruction can skip the one after it.
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SeByte(..)
+                | Instruction::SneByte(..)
+                | Instruction::SeReg(..)
+                | Instruction::SneReg(..)
+                | Instruction::Skp(_)
+                | Instruction::Sknp(_)
+        )
+    }
+
+    // Whether this instruction changes the display.
+    pub fn touches_display(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Cls
+                | Instruction::Drw(..)
+                | Instruction::ScrollDown(_)
+                | Instruction::ScrollRight
+                | Instruction::ScrollLeft
+                | Instruction::Lores
+                | Instruction::Hires
+        )
+    }
+
+    // Whether executing this instruction stores into `Vreg`, including VF flag updates.
+    // Fx0A only stores once a key is pressed, see `check_register_watches`.
+    pub fn writes_register(&self, reg: usize) -> bool {
+        match *self {
+            Instruction::LdByte(x, _)
+            | Instruction::AddByte(x, _)
+            | Instruction::LdReg(x, _)
+            | Instruction::Rnd(x, _)
+            | Instruction::LdVxDt(x)
+            | Instruction::LdVxK(x) => reg == x,
+            Instruction::Or(x, _)
+            | Instruction::And(x, _)
+            | Instruction::Xor(x, _)
+            | Instruction::AddReg(x, _)
+            | Instruction::Sub(x, _)
+            | Instruction::Shr(x, _)
+            | Instruction::Subn(x, _)
+            | Instruction::Shl(x, _) => reg == x || reg == 0xF,
+            Instruction::Drw(_, _, _) => reg == 0xF,
+            Instruction::LdVxI(x) => reg <= x,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Sys(nnn) => write!(f, "SYS {:#05X}", nnn),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Lores => write!(f, "LOW"),
+            Instruction::Hires => write!(f, "HIGH"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Jp(nnn) => write!(f, "JP {:#05X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL {:#05X}", nnn),
+            Instruction::SeByte(x, kk) => write!(f, "SE V{:X}, {:#04X}", x, kk),
+            Instruction::SneByte(x, kk) => write!(f, "SNE V{:X}, {:#04X}", x, kk),
+            Instruction::SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LdByte(x, kk) => write!(f, "LD V{:X}, {:#04X}", x, kk),
+            Instruction::AddByte(x, kk) => write!(f, "ADD V{:X}, {:#04X}", x, kk),
+            Instruction::LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(nnn) => write!(f, "LD I, {:#05X}", nnn),
+            Instruction::JpV0(nnn) => write!(f, "JP V0, {:#05X}", nnn),
+            Instruction::Rnd(x, kk) => write!(f, "RND V{:X}, {:#04X}", x, kk),
+            Instruction::Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp(x) => write!(f, "SKP V{:X}", x),
+            Instruction::Sknp(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdF(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LdB(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::machine;
+    use super::*;
+
+    #[test]
+    fn operands_come_from_their_nibbles() {
+        let cases = [
+            (0x0ABC, Instruction::Sys(0xABC)),
+            (0x00C7, Instruction::ScrollDown(7)),
+            (0x1ABC, Instruction::Jp(0xABC)),
+            (0x2ABC, Instruction::Call(0xABC)),
+            (0x3A5C, Instruction::SeByte(0xA, 0x5C)),
+            (0x5AB0, Instruction::SeReg(0xA, 0xB)),
+            (0x7F01, Instruction::AddByte(0xF, 0x01)),
+            (0x8AB4, Instruction::AddReg(0xA, 0xB)),
+            (0x8ABE, Instruction::Shl(0xA, 0xB)),
+            (0x9AB0, Instruction::SneReg(0xA, 0xB)),
+            (0xBABC, Instruction::JpV0(0xABC)),
+            (0xC3F0, Instruction::Rnd(3, 0xF0)),
+            (0xD12F, Instruction::Drw(1, 2, 0xF)),
+            (0xE59E, Instruction::Skp(5)),
+            (0xE5A1, Instruction::Sknp(5)),
+            (0xF70A, Instruction::LdVxK(7)),
+            (0xF733, Instruction::LdB(7)),
+            (0xFF65, Instruction::LdVxI(0xF)),
+        ];
+        for (opcode, expected) in cases {
+            assert_eq!(
+                Instruction::decode(opcode),
+                Some(expected),
+                "{:04X}",
+                opcode
+            );
+        }
+    }
+
+    #[test]
+    fn schip_00xx_opcodes_decode_before_the_catch_all() {
+        let cases = [
+            (0x00C0, Instruction::ScrollDown(0)),
+            (0x00CF, Instruction::ScrollDown(0xF)),
+            (0x00E0, Instruction::Cls),
+            (0x00EE, Instruction::Ret),
+            (0x00FB, Instruction::ScrollRight),
+            (0x00FC, Instruction::ScrollLeft),
+            (0x00FD, Instruction::Exit),
+            (0x00FE, Instruction::Lores),
+            (0x00FF, Instruction::Hires),
+            // Near misses are machine code calls, never a clear.
+            (0x00E1, Instruction::Sys(0x0E1)),
+            (0x00BF, Instruction::Sys(0x0BF)),
+            (0x00D0, Instruction::Sys(0x0D0)),
+            (0x0000, Instruction::Sys(0x000)),
+            (0x01E0, Instruction::Sys(0x1E0)),
+        ];
+        for (opcode, expected) in cases {
+            assert_eq!(
+                Instruction::decode(opcode),
+                Some(expected),
+                "{:04X}",
+                opcode
+            );
+        }
+    }
+
+    #[test]
+    fn unassigned_opcodes_do_not_decode() {
+        for opcode in [
+            0x8008, 0x800D, 0x800F, 0xE000, 0xE09F, 0xF000, 0xF0FF, 0xF01F,
+        ] {
+            assert_eq!(Instruction::decode(opcode), None, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn disassembly_starts_with_the_mnemonic() {
+        for opcode in 0..=u16::MAX {
+            if let Some(ins) = Instruction::decode(opcode) {
+                let text = ins.to_string();
+                assert_eq!(text.split(' ').next(), Some(ins.mnemonic()), "{}", text);
+            }
+        }
+    }
+
+    // Whether `opcode` fits `pattern`, where lowercase letters stand for any nibble.
+    fn fits(pattern: &str, opcode: u16) -> bool {
+        pattern.chars().enumerate().all(|(n, c)| {
+            let nibble = (opcode >> (12 - 4 * n)) & 0xF;
+            c.is_ascii_lowercase() || c.to_digit(16) == Some(nibble as u32)
+        })
+    }
+
+    #[test]
+    fn core_opcodes_are_listed_with_their_mnemonics() {
+        let listed = supported_opcodes();
+        for (pattern, mnemonic) in [
+            ("00E0", "CLS"),
+            ("00EE", "RET"),
+            ("1nnn", "JP"),
+            ("8xy4", "ADD"),
+            ("8xyE", "SHL"),
+            ("Dxyn", "DRW"),
+            ("Fx0A", "LD"),
+            ("Fx33", "LD"),
+        ] {
+            let info = listed.iter().find(|o| o.pattern == pattern).unwrap();
+            assert_eq!(info.mnemonic, mnemonic, "{}", pattern);
+            assert!(!info.description.is_empty());
+        }
+    }
+
+    #[test]
+    fn every_kind_of_instruction_is_listed() {
+        for &(pattern, sample, _) in OPCODES {
+            assert!(fits(pattern, sample), "{} {:04X}", pattern, sample);
+        }
+        // Each kind of instruction the decoder produces has a listed opcode.
+        let listed: Vec<_> = OPCODES
+            .iter()
+            .filter_map(|&(_, sample, _)| Instruction::decode(sample))
+            .map(|ins| core::mem::discriminant(&ins))
+            .collect();
+        for opcode in 0..=u16::MAX {
+            if let Some(ins) = Instruction::decode(opcode) {
+                let kind = core::mem::discriminant(&ins);
+                assert!(listed.contains(&kind), "{:04X} isn't listed", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn listed_opcodes_all_decode() {
+        let listed = supported_opcodes();
+        assert_eq!(listed.len(), OPCODES.len());
+        assert!(listed
+            .iter()
+            .any(|o| o.pattern == "Dxyn" && o.mnemonic == "DRW"));
+    }
+
+    // Stepping decodes and dispatches once, `execute_instruction` on the raw opcode
+    // must end in the same state for every listed instruction.
+    #[test]
+    fn stepping_matches_executing_the_opcode() {
+        for &(pattern, opcode, _) in OPCODES {
+            let setup = || {
+                let mut e = machine(&[opcode]);
+                for (n, v) in e.registers.v.iter_mut().enumerate() {
+                    *v = n as u8 * 17;
+                }
+                e.registers.i = 0x300;
+                e.stack.push(0x400);
+                e.seed_rng(1);
+                e
+            };
+            let (mut stepped, mut executed) = (setup(), setup());
+            let stepped_result = stepped.step().map(|_| ());
+            let executed_result = executed.execute_instruction(opcode);
+            assert_eq!(
+                stepped_result.is_ok(),
+                executed_result.is_ok(),
+                "{}",
+                pattern
+            );
+            assert_eq!(stepped.state_hash(), executed.state_hash(), "{}", pattern);
+        }
+    }
+}
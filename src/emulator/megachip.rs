@@ -0,0 +1,379 @@
+// MEGA-CHIP8 extension, only active under `Variant::MegaChip`. Mega mode (0011)
+// switches to a 256x192 display of 32 bit colors with a 256 entry palette. Sprites
+// are byte-per-pixel palette indices blitted with transparency instead of XORed, and
+// the picture only updates when the program issues 00E0.
+use super::{Chip8, Chip8Error};
+use crate::prelude::*;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 192;
+// I is 24 bits wide, so is the address space.
+pub const MEMORY_SIZE: usize = 0x100_0000;
+
+// How 080n combines a sprite pixel with what's already on screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Alpha25,
+    Alpha50,
+    Alpha75,
+    Add,
+    Multiply,
+}
+
+// A digitized sound started by 060n, for an audio backend to play. Samples are
+// unsigned 8 bit, stored after a 6 byte header at `addr`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DigitizedSound {
+    pub addr: usize,
+    pub sample_rate: u16,
+    pub len: usize,
+    pub looping: bool,
+}
+
+pub(super) struct MegaChip {
+    enabled: bool,
+    // ARGB. Entry 0 is transparent in sprites.
+    palette: [[u8; 4]; 256],
+    sprite_w: usize,
+    sprite_h: usize,
+    alpha: u8,
+    blend: BlendMode,
+    collision_color: u8,
+    // Drawn into by Dxyn and shown on the next 00E0, as RGBA.
+    back: Vec<[u8; 4]>,
+    // Palette index last drawn at each pixel of `back`, for collisions.
+    indices: Vec<u8>,
+    front: Vec<u8>,
+    sound: Option<DigitizedSound>,
+}
+
+impl MegaChip {
+    pub(super) fn new() -> Self {
+        MegaChip {
+            enabled: false,
+            palette: [[0xFF, 0, 0, 0]; 256],
+            sprite_w: 0,
+            sprite_h: 0,
+            alpha: 0xFF,
+            blend: BlendMode::default(),
+            collision_color: 0,
+            back: vec![[0, 0, 0, 0xFF]; WIDTH * HEIGHT],
+            indices: vec![0; WIDTH * HEIGHT],
+            front: vec![0; WIDTH * HEIGHT * 4],
+            sound: None,
+        }
+    }
+
+    fn blend(&self, dst: [u8; 4], src: [u8; 4]) -> [u8; 4] {
+        let mix = |d: u8, s: u8, a: u16| ((s as u16 * a + d as u16 * (4 - a)) / 4) as u8;
+        let mut out = [0, 0, 0, 0xFF];
+        for c in 0..3 {
+            out[c] = match self.blend {
+                BlendMode::Normal => src[c],
+                BlendMode::Alpha25 => mix(dst[c], src[c], 1),
+                BlendMode::Alpha50 => mix(dst[c], src[c], 2),
+                BlendMode::Alpha75 => mix(dst[c], src[c], 3),
+                BlendMode::Add => dst[c].saturating_add(src[c]),
+                BlendMode::Multiply => (dst[c] as u16 * src[c] as u16 / 255) as u8,
+            };
+        }
+        out
+    }
+
+    fn present(&mut self) {
+        for (out, p) in self.front.chunks_exact_mut(4).zip(&self.back) {
+            out.copy_from_slice(p);
+        }
+        self.back.fill([0, 0, 0, 0xFF]);
+        self.indices.fill(0);
+    }
+
+    fn scroll_up(&mut self, n: usize) {
+        let n = n.min(HEIGHT) * WIDTH;
+        self.back.copy_within(n.., 0);
+        self.indices.copy_within(n.., 0);
+        let len = self.back.len();
+        self.back[len - n..].fill([0, 0, 0, 0xFF]);
+        self.indices[len - n..].fill(0);
+    }
+}
+
+impl Chip8 {
+    pub fn is_mega_mode(&self) -> bool {
+        self.megachip.as_ref().is_some_and(|m| m.enabled)
+    }
+
+    // The digitized sound currently playing, if any.
+    pub fn digitized_sound(&self) -> Option<DigitizedSound> {
+        self.megachip.as_ref().and_then(|m| m.sound)
+    }
+
+    // The mega mode display as RGBA, 256x192, and the alpha it's drawn with. `None`
+    // outside mega mode, where `screen` holds the picture.
+    pub fn mega_frame(&self) -> Option<(&[u8], u8)> {
+        match &self.megachip {
+            Some(m) if m.enabled => Some((&m.front, m.alpha)),
+            _ => None,
+        }
+    }
+
+    // Executes `opcode` if it is a MegaChip instruction, or one that behaves
+    // differently in mega mode. Returns whether it did.
+    pub(super) fn execute_megachip(&mut self, opcode: u16) -> Result<bool, Chip8Error> {
+        let Some(mut m) = self.megachip.take() else {
+            return Ok(false);
+        };
+        let result = self.execute_mega(&mut m, opcode);
+        self.megachip = Some(m);
+        result
+    }
+
+    fn execute_mega(&mut self, m: &mut MegaChip, opcode: u16) -> Result<bool, Chip8Error> {
+        let nn = (opcode & 0xFF) as usize;
+        match opcode {
+            0x0010 => m.enabled = false,
+            0x0011 => {
+                m.enabled = true;
+                m.back.fill([0, 0, 0, 0xFF]);
+                m.indices.fill(0);
+            }
+            _ if !m.enabled => return Ok(false),
+            0x0100..=0x01FF => {
+                self.check_next_pc(self.pc, 4)?;
+                let low = self.peek_opcode_at(self.pc.wrapping_add(2)) as u32;
+                self.note_fetch(self.pc.wrapping_add(2));
+                self.set_i((nn as u32) << 16 | low);
+                self.pc += 2;
+            }
+            0x0200..=0x02FF => {
+                let i = self.registers.i as usize;
+                self.check_i_range(nn * 4)?;
+                self.note_reads(i, nn * 4);
+                for (c, argb) in self.memory[i..i + nn * 4].chunks_exact(4).enumerate() {
+                    m.palette[c + 1] = [argb[0], argb[1], argb[2], argb[3]];
+                }
+            }
+            0x0300..=0x03FF => m.sprite_w = if nn == 0 { 256 } else { nn },
+            0x0400..=0x04FF => m.sprite_h = if nn == 0 { 256 } else { nn },
+            0x0500..=0x05FF => m.alpha = nn as u8,
+            0x0600..=0x06FF => {
+                let i = self.registers.i as usize;
+                self.check_i_range(6)?;
+                self.note_reads(i, 6);
+                let header = &self.memory[i..i + 6];
+                m.sound = Some(DigitizedSound {
+                    addr: i + 6,
+                    sample_rate: u16::from_be_bytes([header[0], header[1]]),
+                    len: (header[2] as usize) << 16
+                        | (header[3] as usize) << 8
+                        | header[4] as usize,
+                    looping: opcode & 0xF == 0,
+                });
+            }
+            0x0700 => m.sound = None,
+            0x0800..=0x08FF => {
+                m.blend = match opcode & 0xF {
+                    1 => BlendMode::Alpha25,
+                    2 => BlendMode::Alpha50,
+                    3 => BlendMode::Alpha75,
+                    4 => BlendMode::Add,
+                    5 => BlendMode::Multiply,
+                    _ => BlendMode::Normal,
+                }
+            }
+            0x0900..=0x09FF => m.collision_color = nn as u8,
+            0x00B0..=0x00BF => m.scroll_up((opcode & 0xF) as usize),
+            0x00E0 => m.present(),
+            0xD000..=0xDFFF => self.draw_mega_sprite(m, opcode)?,
+            _ => return Ok(false),
+        }
+        self.check_next_pc(self.pc, 2)?;
+        self.pc += 2;
+        Ok(true)
+    }
+
+    fn draw_mega_sprite(&mut self, m: &mut MegaChip, opcode: u16) -> Result<(), Chip8Error> {
+        let x0 = self.registers.v[((opcode >> 8) & 0xF) as usize] as usize;
+        let y0 = self.registers.v[((opcode >> 4) & 0xF) as usize] as usize;
+        let (w, h) = (m.sprite_w, m.sprite_h);
+        let i = self.registers.i as usize;
+        self.check_i_range(w * h)?;
+        self.note_reads(i, w * h);
+
+        self.registers.v[0xF] = 0;
+        for row in 0..h {
+            let y = y0 + row;
+            if y >= HEIGHT {
+                break;
+            }
+            for col in 0..w {
+                let x = x0 + col;
+                if x >= WIDTH {
+                    break;
+                }
+                let index = self.memory[i + row * w + col];
+                if index == 0 {
+                    continue;
+                }
+                let p = y * WIDTH + x;
+                if m.indices[p] != 0 && m.indices[p] == m.collision_color {
+                    self.registers.v[0xF] = 1;
+                }
+                let [a, r, g, b] = m.palette[index as usize];
+                m.back[p] = m.blend(m.back[p], [r, g, b, a]);
+                m.indices[p] = index;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{rom, steps};
+    use super::super::Variant;
+    use super::*;
+
+    // A MegaChip machine running `program` with two palette entries at 0x300, a dark
+    // red and a dark blue, and a 2x1 sprite of each followed by a transparent pixel at
+    // 0x310 and 0x312.
+    fn mega(program: &[u16]) -> Chip8 {
+        let mut e = Chip8::new();
+        e.set_variant(Variant::MegaChip);
+        e.load(&rom(program)).unwrap();
+        e.memory[0x300..0x308].copy_from_slice(&[0xFF, 0x80, 0, 0, 0xFF, 0, 0, 0x80]);
+        e.memory[0x310..0x314].copy_from_slice(&[1, 0, 2, 0]);
+        e
+    }
+
+    // Mega mode on, the palette loaded and 2x1 sprites, leaving I at the red sprite.
+    const SETUP: [u16; 5] = [0x0011, 0xA300, 0x0202, 0x0302, 0x0401];
+
+    fn pixel(e: &Chip8, x: usize, y: usize) -> [u8; 4] {
+        let (front, _) = e.mega_frame().unwrap();
+        let p = (y * WIDTH + x) * 4;
+        front[p..p + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn mega_mode_switches_on_and_off() {
+        let mut e = mega(&[0x0011, 0x0010]);
+        assert!(!e.is_mega_mode() && e.mega_frame().is_none());
+        steps(&mut e, 1);
+        assert!(e.is_mega_mode());
+        assert_eq!(e.mega_frame().unwrap().1, 0xFF);
+        steps(&mut e, 1);
+        assert!(!e.is_mega_mode() && e.mega_frame().is_none());
+    }
+
+    #[test]
+    fn classic_machines_have_no_mega_mode() {
+        let mut e = Chip8::new();
+        e.load(&rom(&[0x0011])).unwrap();
+        let _ = e.step();
+        assert!(!e.is_mega_mode());
+    }
+
+    #[test]
+    fn long_loads_take_a_24_bit_address() {
+        let mut e = mega(&[0x0011, 0x0112, 0x3456]);
+        steps(&mut e, 2);
+        assert_eq!(e.registers.i, 0x12_3456);
+        assert_eq!(e.pc(), 0x206);
+    }
+
+    #[test]
+    fn sprites_show_on_the_next_clear_with_index_0_transparent() {
+        let mut e = mega(&[&SETUP[..], &[0xA310, 0xD000, 0x00E0]].concat());
+        steps(&mut e, 7);
+        assert_eq!(pixel(&e, 0, 0), [0; 4]);
+        steps(&mut e, 1);
+        assert_eq!(pixel(&e, 0, 0), [0x80, 0, 0, 0xFF]);
+        assert_eq!(pixel(&e, 1, 0), [0, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn collisions_only_count_the_collision_color() {
+        let mut e = mega(&[&SETUP[..], &[0xA310, 0xD000, 0xD000, 0x0901, 0xD000]].concat());
+        steps(&mut e, 8);
+        assert_eq!(e.registers.v[0xF], 0);
+        steps(&mut e, 2);
+        assert_eq!(e.registers.v[0xF], 1);
+    }
+
+    #[test]
+    fn blend_modes_mix_with_the_back_buffer() {
+        let program = [0xA310, 0xD000, 0x0802, 0xA312, 0xD000, 0x00E0];
+        let mut e = mega(&[&SETUP[..], &program].concat());
+        steps(&mut e, 11);
+        assert_eq!(pixel(&e, 0, 0), [0x40, 0, 0x40, 0xFF]);
+    }
+
+    #[test]
+    fn scrolling_moves_the_back_buffer_up() {
+        let program = [0xA310, 0x6102, 0xD010, 0x00B1, 0x00E0];
+        let mut e = mega(&[&SETUP[..], &program].concat());
+        steps(&mut e, 10);
+        assert_eq!(pixel(&e, 0, 1), [0x80, 0, 0, 0xFF]);
+        assert_eq!(pixel(&e, 0, 2), [0, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn mega_instructions_show_in_the_heatmap() {
+        let mut e = mega(&[&SETUP[..3], &[0x0112, 0x3456]].concat());
+        e.set_heatmap_enabled(true);
+        steps(&mut e, 4);
+        let [reads, _, fetches] = e.access_heatmap().unwrap();
+        assert!(reads[0x300..0x308].iter().all(|&c| c == 1));
+        assert_eq!(reads[0x308], 0);
+        // The second half of the long load.
+        assert_eq!(fetches[0x208..0x20A], [1, 1]);
+    }
+
+    #[test]
+    fn digitized_sound_reads_its_header() {
+        let mut e = mega(&[0x0011, 0xA300, 0x0600, 0x0601, 0x0700]);
+        e.memory[0x300..0x306].copy_from_slice(&[0x1F, 0x40, 0, 0x01, 0x10, 0]);
+        steps(&mut e, 3);
+        let sound = DigitizedSound {
+            addr: 0x306,
+            sample_rate: 8000,
+            len: 0x110,
+            looping: true,
+        };
+        assert_eq!(e.digitized_sound(), Some(sound));
+        steps(&mut e, 1);
+        assert!(!e.digitized_sound().unwrap().looping);
+        steps(&mut e, 1);
+        assert_eq!(e.digitized_sound(), None);
+    }
+
+    #[test]
+    fn running_off_the_end_of_pcs_range_is_an_error() {
+        // Mega mode switched off over and over up to 0xFFFE, which has no next address.
+        let mut e = mega(&[0x0010]);
+        e.memory[0x202..0x10000].copy_from_slice(&[0x00, 0x10].repeat(0x7EFF));
+        let err = loop {
+            if let Err(err) = e.step() {
+                break err;
+            }
+        };
+        assert!(matches!(
+            err,
+            Chip8Error::MemoryOutOfBounds { pc: 0xFFFE, .. }
+        ));
+        assert_eq!(e.pc(), 0xFFFE);
+        // The 4-byte long load needs the two after it as well.
+        let mut e = mega(&[0x0011]);
+        e.memory[0xFFFC..0x10000].copy_from_slice(&[0x01, 0x12, 0x34, 0x56]);
+        steps(&mut e, 1);
+        e.pc = 0xFFFC;
+        assert!(matches!(
+            e.step(),
+            Err(Chip8Error::MemoryOutOfBounds { pc: 0xFFFC, .. })
+        ));
+        assert_eq!(e.registers.i, 0);
+    }
+}
@@ -0,0 +1,71 @@
+const fn rgb(hex: u32) -> [u8; 4] {
+    [(hex >> 16) as u8, (hex >> 8) as u8, hex as u8, 255]
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Palette {
+    #[default]
+    Classic,
+    GameBoy,
+    Amber,
+    BlueOnBlack,
+}
+
+impl Palette {
+    pub const ALL: [Palette; 4] = [
+        Palette::Classic,
+        Palette::GameBoy,
+        Palette::Amber,
+        Palette::BlueOnBlack,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Palette::Classic => "classic",
+            Palette::GameBoy => "gameboy",
+            Palette::Amber => "amber",
+            Palette::BlueOnBlack => "blue",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+
+    // Background, first plane, second plane and both planes as RGBA, in the order
+    // XO-CHIP's four color mode indexes them. Plain CHIP-8 only uses the first two.
+    pub fn rgba(self) -> [[u8; 4]; 4] {
+        match self {
+            Palette::Classic => [rgb(0x000000), rgb(0xFFFFFF), rgb(0xAAAAAA), rgb(0x555555)],
+            Palette::GameBoy => [rgb(0x0F380F), rgb(0x9BBC0F), rgb(0x8BAC0F), rgb(0x306230)],
+            Palette::Amber => [rgb(0x140C00), rgb(0xFFB000), rgb(0xCC7A00), rgb(0x7A4A00)],
+            Palette::BlueOnBlack => [rgb(0x000000), rgb(0x3A8DFF), rgb(0x1C5FC0), rgb(0x9CC8FF)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Chip8;
+    use super::*;
+
+    #[test]
+    fn set_palette_reaches_the_screen() {
+        let mut e = Chip8::new();
+        assert_eq!(e.screen().palette(), Palette::Classic);
+        e.set_palette(Palette::GameBoy);
+        assert_eq!(e.screen().palette(), Palette::GameBoy);
+        let [background, foreground, ..] = e.screen().palette().rgba();
+        assert_eq!(background, [0x0F, 0x38, 0x0F, 0xFF]);
+        assert_eq!(foreground, [0x9B, 0xBC, 0x0F, 0xFF]);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for palette in Palette::ALL {
+            assert_eq!(Palette::from_name(palette.name()), Some(palette));
+        }
+        assert_eq!(Palette::from_name("blue"), Some(Palette::BlueOnBlack));
+        assert_eq!(Palette::from_name("Classic"), None);
+    }
+}
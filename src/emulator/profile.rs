@@ -0,0 +1,232 @@
+// Address level profiler: counts how often each PC executes and reports the hottest
+// code, with consecutive hot addresses folded into ranges so loops show up whole.
+use super::{Chip8, Instruction};
+use crate::prelude::*;
+use core::fmt;
+#[cfg(feature = "std")]
+use serde_json::{json, Value};
+
+// Addresses under this share of all executed instructions are left out of reports.
+const HOT_PERCENT: f64 = 1.0;
+
+pub(super) struct Profiler {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HotInstruction {
+    pub addr: u16,
+    pub opcode: u16,
+    pub count: u64,
+}
+
+// A run of consecutive hot instructions, usually a loop body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HotRange {
+    pub start: u16,
+    pub end: u16,
+    pub count: u64,
+    pub percent: f64,
+    pub instructions: Vec<HotInstruction>,
+}
+
+// Hot ranges, hottest first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProfileReport {
+    pub total: u64,
+    pub ranges: Vec<HotRange>,
+}
+
+fn disassemble(opcode: u16) -> String {
+    Instruction::decode(opcode).map_or(String::from("???"), |ins| ins.to_string())
+}
+
+impl ProfileReport {
+    #[cfg(feature = "std")]
+    pub fn to_json(&self) -> Value {
+        let ranges: Vec<_> = self
+            .ranges
+            .iter()
+            .map(|r| {
+                let instructions: Vec<_> = r
+                    .instructions
+                    .iter()
+                    .map(|i| {
+                        json!({
+                            "addr": i.addr,
+                            "opcode": format!("{:04X}", i.opcode),
+                            "disassembly": disassemble(i.opcode),
+                            "count": i.count,
+                        })
+                    })
+                    .collect();
+                json!({
+                    "start": r.start,
+                    "end": r.end,
+                    "count": r.count,
+                    "percent": r.percent,
+                    "instructions": instructions,
+                })
+            })
+            .collect();
+        json!({ "total": self.total, "ranges": ranges })
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} instructions executed", self.total)?;
+        for r in &self.ranges {
+            writeln!(
+                f,
+                "{:#05X}-{:#05X}  {:5.1}%  {}",
+                r.start, r.end, r.percent, r.count
+            )?;
+            for i in &r.instructions {
+                writeln!(
+                    f,
+                    "    {:#05X}  {:04X}  {:<16} {}",
+                    i.addr,
+                    i.opcode,
+                    disassemble(i.opcode),
+                    i.count
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Chip8 {
+    // Costs 512K of counters while enabled. Disabling drops the counts.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        if !enabled {
+            self.profiler = None;
+        } else if self.profiler.is_none() {
+            self.profiler = Some(Box::new(Profiler {
+                counts: vec![0; 1 << 16],
+                total: 0,
+            }));
+        }
+    }
+
+    pub(super) fn count_execution(&mut self, pc: u16) {
+        if let Some(p) = self.profiler.as_mut() {
+            p.counts[pc as usize] += 1;
+            p.total += 1;
+        }
+    }
+
+    // The `top` hottest ranges, `None` unless profiling.
+    pub fn profile_report(&self, top: usize) -> Option<ProfileReport> {
+        let p = self.profiler.as_ref()?;
+        let percent = |count: u64| count as f64 * 100.0 / p.total.max(1) as f64;
+        let mut ranges: Vec<HotRange> = Vec::new();
+        for (addr, &count) in p.counts.iter().enumerate() {
+            if count == 0 || percent(count) < HOT_PERCENT {
+                continue;
+            }
+            let addr = addr as u16;
+            let hot = HotInstruction {
+                addr,
+                opcode: self.peek_opcode_at(addr),
+                count,
+            };
+            match ranges.last_mut() {
+                Some(r) if r.end.wrapping_add(2) == addr => {
+                    r.end = addr;
+                    r.count += count;
+                    r.instructions.push(hot);
+                }
+                _ => ranges.push(HotRange {
+                    start: addr,
+                    end: addr,
+                    count,
+                    percent: 0.0,
+                    instructions: vec![hot],
+                }),
+            }
+        }
+        for r in ranges.iter_mut() {
+            r.percent = percent(r.count);
+        }
+        ranges.sort_by_key(|r| core::cmp::Reverse(r.count));
+        ranges.truncate(top);
+        Some(ProfileReport {
+            total: p.total,
+            ranges,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{machine, steps};
+    use super::*;
+
+    // Counts V0 to 10 in a first loop, then spins in a second one for `n` passes.
+    // The jump between them runs once, keeping the loops apart.
+    fn two_loops(n: usize) -> Chip8 {
+        let program = [
+            0x6000, 0x7001, 0x300A, 0x1202, 0x120A, 0x7101, 0x7201, 0x120A,
+        ];
+        let mut e = machine(&program);
+        e.set_profiling(true);
+        steps(&mut e, 31 + 3 * n);
+        e
+    }
+
+    #[test]
+    fn profiling_is_off_by_default() {
+        let mut e = machine(&[0x1200]);
+        steps(&mut e, 10);
+        assert_eq!(e.profile_report(10), None);
+    }
+
+    #[test]
+    fn hot_loops_are_ranked_by_executions() {
+        let report = two_loops(100).profile_report(10).unwrap();
+        assert_eq!(report.total, 331);
+        let ranges: Vec<_> = report
+            .ranges
+            .iter()
+            .map(|r| (r.start, r.end, r.count))
+            .collect();
+        // 0x200 and 0x208 run once, under 1% of the total.
+        assert_eq!(ranges, [(0x20A, 0x20E, 300), (0x202, 0x206, 29)]);
+        let inner = &report.ranges[1].instructions;
+        let counts: Vec<_> = inner.iter().map(|i| (i.addr, i.opcode, i.count)).collect();
+        assert_eq!(
+            counts,
+            [(0x202, 0x7001, 10), (0x204, 0x300A, 10), (0x206, 0x1202, 9)]
+        );
+        assert_eq!(two_loops(100).profile_report(1).unwrap().ranges.len(), 1);
+    }
+
+    #[test]
+    fn reports_show_percentages_and_disassembly() {
+        let report = two_loops(100).profile_report(10).unwrap();
+        assert_eq!(report.ranges[0].percent, 300.0 * 100.0 / 331.0);
+        let text = report.to_string();
+        assert!(text.starts_with("331 instructions executed\n"));
+        assert!(text.contains("0x20A-0x20E   90.6%  300\n"));
+        let jump = format!("    0x20E  120A  {:<16} 100\n", disassemble(0x120A));
+        assert!(text.contains(&jump));
+        #[cfg(feature = "std")]
+        {
+            let json = report.to_json();
+            assert_eq!(json["total"], 331);
+            assert_eq!(json["ranges"][0]["instructions"][2]["opcode"], "120A");
+        }
+    }
+
+    #[test]
+    fn disabling_drops_the_counts() {
+        let mut e = two_loops(10);
+        e.set_profiling(false);
+        assert_eq!(e.profile_report(10), None);
+        e.set_profiling(true);
+        assert_eq!(e.profile_report(10).unwrap().total, 0);
+    }
+}
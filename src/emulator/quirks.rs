@@ -0,0 +1,72 @@
+use super::{Chip8, KeyIndexPolicy, LowMemoryProtection, SpriteReadPolicy, VfReset};
+use core::fmt;
+
+// Behaviors that differ between interpreters and that ROMs may depend on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QuirkConfig {
+    pub sprite_read: SpriteReadPolicy,
+    pub key_index: KeyIndexPolicy,
+    pub low_memory: LowMemoryProtection,
+    pub vf_reset: VfReset,
+}
+
+impl fmt::Display for QuirkConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "sprite_read={:?} key_index={:?} low_memory={:?} vf_reset={:?}",
+            self.sprite_read, self.key_index, self.low_memory, self.vf_reset
+        )
+    }
+}
+
+impl Chip8 {
+    pub fn quirks(&self) -> &QuirkConfig {
+        &self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: QuirkConfig) {
+        self.quirks = quirks;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_names_every_flag() {
+        let text = QuirkConfig::default().to_string();
+        for name in [
+            "sprite_read=Wrap",
+            "key_index=NotPressed",
+            "low_memory=Error",
+            "vf_reset=Reset",
+        ] {
+            assert!(text.contains(name), "{} missing from {}", name, text);
+        }
+    }
+
+    #[test]
+    fn quirks_reflect_the_setters() {
+        let mut e = Chip8::new();
+        assert_eq!(e.quirks(), &QuirkConfig::default());
+        e.set_sprite_read_policy(SpriteReadPolicy::Clamp);
+        e.set_key_index_policy(KeyIndexPolicy::Mask);
+        e.set_protect_low_memory(LowMemoryProtection::Off);
+        e.set_vf_reset(VfReset::Keep);
+        let quirks = QuirkConfig {
+            sprite_read: SpriteReadPolicy::Clamp,
+            key_index: KeyIndexPolicy::Mask,
+            low_memory: LowMemoryProtection::Off,
+            vf_reset: VfReset::Keep,
+        };
+        assert_eq!(e.quirks(), &quirks);
+        assert_eq!(
+            quirks.to_string(),
+            "sprite_read=Clamp key_index=Mask low_memory=Off vf_reset=Keep"
+        );
+        e.set_quirks(QuirkConfig::default());
+        assert_eq!(e.quirks(), &QuirkConfig::default());
+    }
+}
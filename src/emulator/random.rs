@@ -0,0 +1,124 @@
+// Where Cxkk's random bytes come from. By default the thread's generator, or without
+// std one seeded with 0. Tests and replays seed it or plug in their own, so a run
+// draws the same numbers every time.
+use super::Chip8;
+use crate::prelude::*;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+}
+
+// Any `rand` generator, e.g. a seeded `StdRng` or `ChaCha8Rng`.
+impl<R: RngCore> RandomSource for R {
+    fn next_byte(&mut self) -> u8 {
+        self.next_u32() as u8
+    }
+}
+
+impl Chip8 {
+    // Cxkk draws from `source` from now on, `None` goes back to the default.
+    pub fn set_random_source(&mut self, source: Option<Box<dyn RandomSource>>) {
+        self.rng = source;
+    }
+
+    // Cxkk draws from a generator seeded with `seed` from now on, instead of the
+    // shared unseeded one.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.set_random_source(Some(Box::new(StdRng::seed_from_u64(seed))));
+    }
+
+    pub(super) fn random_byte(&mut self) -> u8 {
+        match self.rng.as_mut() {
+            Some(rng) => rng.next_byte(),
+            #[cfg(feature = "std")]
+            None => rand::thread_rng().next_byte(),
+            #[cfg(not(feature = "std"))]
+            None => {
+                self.seed_rng(0);
+                self.random_byte()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{rom, steps};
+    use super::*;
+
+    // Hands out 0x00, 0x11, 0x22, ...
+    struct Counting(u8);
+
+    impl RandomSource for Counting {
+        fn next_byte(&mut self) -> u8 {
+            let byte = self.0;
+            self.0 = self.0.wrapping_add(0x11);
+            byte
+        }
+    }
+
+    // V0 to V7 after eight Cx draws masked with `kk`.
+    fn draws(e: &mut Chip8, kk: u8) -> [u8; 8] {
+        let program: Vec<u16> = (0..8).map(|x| 0xC000 | x << 8 | kk as u16).collect();
+        e.load(&rom(&program)).unwrap();
+        steps(e, 8);
+        e.registers()[..8].try_into().unwrap()
+    }
+
+    #[test]
+    fn cxkk_masks_whatever_the_source_gives() {
+        let mut e = Chip8::new();
+        e.set_random_source(Some(Box::new(Counting(0))));
+        assert_eq!(
+            draws(&mut e, 0xFF),
+            [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]
+        );
+        assert_eq!(
+            draws(&mut e, 0x0F),
+            [0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F]
+        );
+    }
+
+    #[test]
+    fn seeds_repeat_their_draws() {
+        let with_seed = |seed| {
+            let mut e = Chip8::new();
+            e.seed_rng(seed);
+            draws(&mut e, 0xFF)
+        };
+        assert_eq!(with_seed(42), with_seed(42));
+        assert_ne!(with_seed(42), with_seed(43));
+        // The same as plugging in the seeded generator directly.
+        let mut e = Chip8::new();
+        e.set_random_source(Some(Box::new(StdRng::seed_from_u64(42))));
+        assert_eq!(draws(&mut e, 0xFF), with_seed(42));
+    }
+
+    #[test]
+    fn reseeding_starts_the_sequence_over() {
+        let mut e = Chip8::new();
+        e.seed_rng(7);
+        let first = draws(&mut e, 0xFF);
+        assert_ne!(draws(&mut e, 0xFF), first);
+        e.seed_rng(7);
+        assert_eq!(draws(&mut e, 0xFF), first);
+    }
+
+    #[test]
+    fn no_source_goes_back_to_the_default() {
+        let mut e = Chip8::new();
+        e.set_random_source(Some(Box::new(Counting(0))));
+        e.set_random_source(None);
+        let bytes = draws(&mut e, 0xFF);
+        // The thread generator, or without std seed 0.
+        #[cfg(not(feature = "std"))]
+        assert_eq!(bytes, {
+            e.seed_rng(0);
+            draws(&mut e, 0xFF)
+        });
+        #[cfg(feature = "std")]
+        assert_ne!(bytes, [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+    }
+}
@@ -0,0 +1,331 @@
+// Recorded keypad input, for replaying a run exactly. Along with a seeded random
+// number generator this makes a run reproducible from the ROM, the log and the seed.
+// The keypad is logged once per frame, so runs to record must not set a key sampler.
+// The text format has one `frame keys` line per change of the keypad, keys being a
+// hex mask with bit n for key n, and ends with the number of frames run:
+//
+//   seed 1234
+//   0 0000
+//   120 0020
+//   130 0000
+//   end 300
+use super::{Chip8, Chip8Error};
+use crate::prelude::*;
+use core::fmt;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InputLog {
+    pub seed: Option<u64>,
+    // (frame, keypad) whenever the keypad changed, in frame order.
+    changes: Vec<(u64, [bool; 16])>,
+    frames: u64,
+}
+
+// What a replay ended with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReplayOutcome {
+    pub frames: u64,
+    pub hash: u64,
+    pub matches: bool,
+}
+
+fn mask(keys: &[bool; 16]) -> u16 {
+    keys.iter()
+        .enumerate()
+        .fold(0, |m, (n, &down)| m | (down as u16) << n)
+}
+
+impl InputLog {
+    pub fn new(seed: Option<u64>) -> Self {
+        InputLog {
+            seed,
+            ..Default::default()
+        }
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Notes the keypad a frame ran with. Frames are recorded in order, one call each.
+    pub fn record(&mut self, keys: [bool; 16]) {
+        if self.changes.last().map(|(_, k)| k) != Some(&keys) {
+            self.changes.push((self.frames, keys));
+        }
+        self.frames += 1;
+    }
+
+    // The keypad at `frame`.
+    pub fn keys_at(&self, frame: u64) -> [bool; 16] {
+        let n = self.changes.partition_point(|&(f, _)| f <= frame);
+        n.checked_sub(1).map_or([false; 16], |n| self.changes[n].1)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, Chip8Error> {
+        let mut log = InputLog::default();
+        let mut ended = false;
+        for (n, line) in source.lines().enumerate() {
+            let bad = || Chip8Error::Script(format!("input log line {}: {}", n + 1, line));
+            let mut words = line.split_whitespace();
+            let (Some(first), Some(second), None) = (words.next(), words.next(), words.next())
+            else {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                return Err(bad());
+            };
+            match first {
+                "seed" => log.seed = Some(second.parse().map_err(|_| bad())?),
+                "end" => {
+                    log.frames = second.parse().map_err(|_| bad())?;
+                    ended = true;
+                }
+                _ => {
+                    let frame: u64 = first.parse().map_err(|_| bad())?;
+                    let keys = u16::from_str_radix(second, 16).map_err(|_| bad())?;
+                    if log.changes.last().is_some_and(|&(f, _)| f >= frame) {
+                        return Err(bad());
+                    }
+                    log.changes
+                        .push((frame, core::array::from_fn(|k| keys & 1 << k != 0)));
+                }
+            }
+        }
+        if !ended {
+            return Err(Chip8Error::Script(String::from(
+                "input log has no end line",
+            )));
+        }
+        Ok(log)
+    }
+}
+
+impl fmt::Display for InputLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(seed) = self.seed {
+            writeln!(f, "seed {}", seed)?;
+        }
+        for (frame, keys) in &self.changes {
+            writeln!(f, "{} {:04X}", frame, mask(keys))?;
+        }
+        writeln!(f, "end {}", self.frames)
+    }
+}
+
+// Runs `rom_a` and `rom_b` side by side on machines set up by `configure`, with no
+// input and Cxkk seeded with `seed`, for up to `frames` frames. Returns the first frame
+// after which their registers, timers, stack or display differ, `None` if they never
+// do. Memory isn't compared since the ROMs already differ there, a difference only
+// counts once it affects what the program does. Runs that fail the same way in the
+// same frame are equal.
+pub fn first_divergence(
+    rom_a: &[u8],
+    rom_b: &[u8],
+    seed: u64,
+    frames: u64,
+    configure: impl Fn(&mut Chip8),
+) -> Result<Option<u64>, Chip8Error> {
+    let boot = |rom: &[u8]| {
+        let mut e = Chip8::new();
+        configure(&mut e);
+        e.load(rom)?;
+        e.seed_rng(seed);
+        Ok::<_, Chip8Error>(e)
+    };
+    let (mut a, mut b) = (boot(rom_a)?, boot(rom_b)?);
+    for frame in 0..frames {
+        match (a.run_frame(), b.run_frame()) {
+            (Ok(()), Ok(())) => {}
+            (Err(x), Err(y)) if x.to_string() == y.to_string() => return Ok(None),
+            _ => return Ok(Some(frame)),
+        }
+        if a.behavior_hash() != b.behavior_hash() {
+            return Ok(Some(frame));
+        }
+        if a.has_exited() && b.has_exited() {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+// Whether the two ROMs behave the same for `frames` frames, see `first_divergence`.
+pub fn behaviorally_equal(
+    rom_a: &[u8],
+    rom_b: &[u8],
+    seed: u64,
+    frames: u64,
+) -> Result<bool, Chip8Error> {
+    first_divergence(rom_a, rom_b, seed, frames, |_| {}).map(|d| d.is_none())
+}
+
+impl Chip8 {
+    // Loads `rom` into this (configured) machine and replays `input` under `seed`,
+    // for the logged number of frames but at most `max_frames`, stopping early if the
+    // program exits. Then compares the final `state_hash` with `expected_hash`.
+    pub fn verify_replay(
+        &mut self,
+        rom: &[u8],
+        input: &InputLog,
+        seed: u64,
+        expected_hash: u64,
+        max_frames: u64,
+    ) -> Result<ReplayOutcome, Chip8Error> {
+        self.load(rom)?;
+        self.seed_rng(seed);
+        let mut frames = 0;
+        while frames < input.frames().min(max_frames) && !self.has_exited() {
+            self.keyboard.keymap = input.keys_at(frames);
+            self.run_frame()?;
+            frames += 1;
+        }
+        let hash = self.state_hash();
+        Ok(ReplayOutcome {
+            frames,
+            hash,
+            matches: hash == expected_hash,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::rom;
+    use super::*;
+
+    // Draws random numbers until key 5 is held, then exits.
+    const UNTIL_5: [u16; 5] = [0xC0FF, 0x6105, 0xE19E, 0x1200, 0x00FD];
+    const LOG: &str = "seed 7\n0 0000\n30 0020\nend 100\n";
+
+    fn replay(seed: u64, expected: u64) -> ReplayOutcome {
+        let input = InputLog::parse(LOG).unwrap();
+        Chip8::new()
+            .verify_replay(&rom(&UNTIL_5), &input, seed, expected, 1000)
+            .unwrap()
+    }
+
+    fn keys(down: &[usize]) -> [bool; 16] {
+        core::array::from_fn(|k| down.contains(&k))
+    }
+
+    // Counts in V1 forever, with a data byte after the loop it never reads.
+    const COUNTER: [u16; 4] = [0x6001, 0x7101, 0x1202, 0xAB00];
+
+    fn patched(at: usize, op: u16) -> Vec<u8> {
+        let mut program = COUNTER;
+        program[at] = op;
+        rom(&program)
+    }
+
+    #[test]
+    fn unread_data_patches_behave_the_same() {
+        let b = patched(3, 0xCD00);
+        assert!(behaviorally_equal(&rom(&COUNTER), &b, 1, 100).unwrap());
+        assert_eq!(
+            first_divergence(&rom(&COUNTER), &b, 1, 100, |_| {}).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn code_patches_diverge_at_the_first_frame_they_change() {
+        // Frame 0 sets V0, frame 1 is the first add.
+        let b = patched(1, 0x7102);
+        assert!(!behaviorally_equal(&rom(&COUNTER), &b, 1, 100).unwrap());
+        assert_eq!(
+            first_divergence(&rom(&COUNTER), &b, 1, 100, |_| {}).unwrap(),
+            Some(1)
+        );
+        // Configured with more instructions a frame, it shows up in frame 0.
+        let fast = |e: &mut Chip8| e.set_cycles_per_frame(4);
+        assert_eq!(
+            first_divergence(&rom(&COUNTER), &b, 1, 100, fast).unwrap(),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn random_draws_follow_the_seed() {
+        let a = rom(&[0xC0FF, 0x1200]);
+        for seed in [0, 1, 99] {
+            assert!(behaviorally_equal(&a, &a, seed, 50).unwrap());
+        }
+    }
+
+    #[test]
+    fn failing_or_exiting_the_same_way_is_equal() {
+        let a = rom(&[0x6001, 0x00FD]);
+        let b = rom(&[0x6001, 0x00FD, 0x1234]);
+        assert!(behaviorally_equal(&a, &b, 0, 10).unwrap());
+        let a = rom(&[0x6001, 0xF0FF]);
+        let b = rom(&[0x6001, 0xF0FF, 0x1234]);
+        assert!(behaviorally_equal(&a, &b, 0, 10).unwrap());
+        // But not failing where the other doesn't.
+        let b = rom(&[0x6001, 0x1202]);
+        assert_eq!(first_divergence(&a, &b, 0, 10, |_| {}).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn logs_keep_only_the_changes() {
+        let mut log = InputLog::new(Some(3));
+        for keys in [keys(&[]), keys(&[]), keys(&[5]), keys(&[5, 0xA]), keys(&[])] {
+            log.record(keys);
+        }
+        assert_eq!(log.frames(), 5);
+        assert_eq!(
+            log.to_string(),
+            "seed 3\n0 0000\n2 0020\n3 0420\n4 0000\nend 5\n"
+        );
+        assert_eq!(log.keys_at(1), keys(&[]));
+        assert_eq!(log.keys_at(3), keys(&[5, 0xA]));
+        assert_eq!(InputLog::parse(&log.to_string()).unwrap(), log);
+    }
+
+    #[test]
+    fn malformed_logs_are_rejected() {
+        for source in [
+            "0 0000\n",
+            "0 0000\n0 0001\nend 2\n",
+            "seed x\nend 1\n",
+            "0 0000 1\nend 1\n",
+        ] {
+            assert!(InputLog::parse(source).is_err(), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn replays_reach_the_same_state() {
+        let first = replay(7, 0);
+        assert!(!first.matches);
+        // Exited after key 5 went down at frame 30.
+        assert!(first.frames > 30 && first.frames < 100);
+        let again = replay(7, first.hash);
+        assert!(again.matches);
+        assert_eq!(again.frames, first.frames);
+        // Pinned, so a change to what a replay ends in shows up here.
+        assert_eq!(again.hash, 0xF500_3741_E288_5B31);
+        assert!(!replay(8, first.hash).matches);
+    }
+
+    #[test]
+    fn replays_stop_at_the_frame_cap() {
+        let input = InputLog::parse(LOG).unwrap();
+        let outcome = Chip8::new()
+            .verify_replay(&rom(&UNTIL_5), &input, 7, 0, 10)
+            .unwrap();
+        assert_eq!(outcome.frames, 10);
+    }
+
+    #[test]
+    fn divergence_is_found_in_behavior_not_bytes() {
+        let a = rom(&[0x6001, 0x1202, 0x1234]);
+        let unused = rom(&[0x6001, 0x1202, 0x5678]);
+        let differs = rom(&[0x6001, 0x6102, 0x1204]);
+        assert_eq!(first_divergence(&a, &a, 1, 20, |_| {}).unwrap(), None);
+        assert!(behaviorally_equal(&a, &unused, 1, 20).unwrap());
+        assert_eq!(
+            first_divergence(&a, &differs, 1, 20, |_| {}).unwrap(),
+            Some(1)
+        );
+    }
+}
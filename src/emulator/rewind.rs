@@ -0,0 +1,145 @@
+// Opt-in ring of recent save states, for stepping back out of a death or past a bug.
+// Each state holds all of memory: 4KB for most programs, but 16MB under MegaChip.
+use super::Chip8;
+use crate::prelude::*;
+use alloc::collections::VecDeque;
+
+pub(super) struct Rewind {
+    states: VecDeque<Vec<u8>>,
+    depth: usize,
+    interval: u32,
+    // Frames until the next state is taken.
+    countdown: u32,
+}
+
+impl Chip8 {
+    // Takes a save state every `interval` frames, keeping the last `depth`, so the
+    // buffer reaches back `depth * interval` frames. A depth of 0 turns it off and
+    // drops the states.
+    pub fn set_rewind(&mut self, depth: usize, interval: u32) {
+        self.rewind = (depth > 0).then(|| Rewind {
+            states: VecDeque::with_capacity(depth),
+            depth,
+            interval: interval.max(1),
+            countdown: 0,
+        });
+    }
+
+    // States held, each `interval` frames further back.
+    pub fn rewind_len(&self) -> usize {
+        self.rewind.as_ref().map_or(0, |r| r.states.len())
+    }
+
+    // Restores the most recent state and drops it, so calling this once per host frame
+    // while a key is held runs the program backwards. False once there are none left.
+    pub fn rewind(&mut self) -> bool {
+        let Some(state) = self.rewind.as_mut().and_then(|r| r.states.pop_back()) else {
+            return false;
+        };
+        // Taken from this machine, so it always loads.
+        let _ = self.load_state(&state);
+        if let Some(r) = self.rewind.as_mut() {
+            r.countdown = r.interval;
+        }
+        true
+    }
+
+    pub(super) fn clear_rewind(&mut self) {
+        if let Some(r) = self.rewind.as_mut() {
+            r.states.clear();
+            r.countdown = 0;
+        }
+    }
+
+    // Called at the end of every frame.
+    pub(super) fn record_rewind(&mut self) {
+        let Some(r) = self.rewind.as_mut() else {
+            return;
+        };
+        if r.countdown > 0 {
+            r.countdown -= 1;
+            return;
+        }
+        r.countdown = r.interval - 1;
+        if r.states.len() == r.depth {
+            r.states.pop_front();
+        }
+        let state = self.save_state();
+        if let Some(r) = self.rewind.as_mut() {
+            r.states.push_back(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::machine;
+    use super::*;
+
+    // Adds 1 to V0 every frame, so V0 counts the frames run.
+    fn counter(depth: usize, interval: u32) -> Chip8 {
+        let mut e = machine(&[0x7001, 0x1200]);
+        e.set_cycles_per_frame(2);
+        e.set_rewind(depth, interval);
+        e
+    }
+
+    fn frames(e: &mut Chip8, n: usize) {
+        for _ in 0..n {
+            e.run_frame().unwrap();
+        }
+    }
+
+    #[test]
+    fn rewinding_is_off_by_default() {
+        let mut e = machine(&[0x7001, 0x1200]);
+        frames(&mut e, 10);
+        assert_eq!(e.rewind_len(), 0);
+        assert!(!e.rewind());
+    }
+
+    #[test]
+    fn rewind_walks_back_through_the_newest_states() {
+        // States after frames 1, 3, 5, 7 and 9, keeping the last three.
+        let mut e = counter(3, 2);
+        frames(&mut e, 10);
+        assert_eq!(e.rewind_len(), 3);
+        let mut seen = Vec::new();
+        while e.rewind() {
+            seen.push(e.registers()[0]);
+        }
+        assert_eq!(seen, [9, 7, 5]);
+        assert_eq!(e.rewind_len(), 0);
+        // Where the last rewind left it.
+        assert_eq!(e.registers()[0], 5);
+    }
+
+    #[test]
+    fn recording_restarts_an_interval_after_a_rewind() {
+        let mut e = counter(4, 3);
+        frames(&mut e, 7);
+        assert!(e.rewind());
+        assert_eq!(e.registers()[0], 7);
+        assert_eq!(e.rewind_len(), 2);
+        frames(&mut e, 3);
+        assert_eq!(e.rewind_len(), 2);
+        frames(&mut e, 1);
+        assert_eq!(e.rewind_len(), 3);
+        assert!(e.rewind());
+        assert_eq!(e.registers()[0], 11);
+    }
+
+    #[test]
+    fn resets_and_a_depth_of_0_drop_the_states() {
+        let mut e = counter(5, 1);
+        frames(&mut e, 3);
+        assert_eq!(e.rewind_len(), 3);
+        e.reset().unwrap();
+        assert_eq!(e.rewind_len(), 0);
+        frames(&mut e, 2);
+        assert_eq!(e.rewind_len(), 2);
+        e.set_rewind(0, 1);
+        assert_eq!(e.rewind_len(), 0);
+        assert!(!e.rewind());
+    }
+}
@@ -0,0 +1,236 @@
+use super::{Chip8, Chip8Error, StopReason};
+use mlua::{Function, Lua, UserData, UserDataMethods};
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+
+// Lua globals a script can define.
+const ON_LOAD: &str = "on_load";
+const ON_FRAME: &str = "on_frame";
+const ON_INSTRUCTION: &str = "on_instruction";
+
+struct State {
+    lua: Lua,
+    // Callbacks that raised an error. They are reported once and not called again.
+    failed: RefCell<Vec<&'static str>>,
+}
+
+impl State {
+    fn callback(&self, name: &'static str) -> Option<Function<'_>> {
+        if self.failed.borrow().contains(&name) {
+            return None;
+        }
+        self.lua.globals().get(name).ok()
+    }
+
+    fn check(&self, name: &'static str, result: mlua::Result<()>) {
+        if let Err(e) = result {
+            eprintln!("Script error in {}, disabling it: {}", name, e);
+            self.failed.borrow_mut().push(name);
+        }
+    }
+
+    fn call_with_emu(&self, name: &'static str, e: &mut Chip8) {
+        if let Some(f) = self.callback(name) {
+            let result = self.lua.scope(|scope| {
+                let emu = scope.create_userdata_ref_mut(e)?;
+                f.call::<_, ()>(emu)
+            });
+            self.check(name, result);
+        }
+    }
+}
+
+// A Lua script driving the machine through `on_load(emu)`, `on_frame(emu)` and
+// `on_instruction(pc, opcode)`.
+pub struct Script {
+    state: Rc<State>,
+}
+
+impl Script {
+    pub fn load(path: &str) -> Result<Self, Chip8Error> {
+        let source =
+            fs::read_to_string(path).map_err(|e| Chip8Error::Script(format!("{}: {}", path, e)))?;
+        Self::from_source(&source, path)
+    }
+
+    // A script given as source rather than a file, `name` standing in for the path
+    // in error messages.
+    pub fn from_source(source: &str, name: &str) -> Result<Self, Chip8Error> {
+        let lua = Lua::new();
+        lua.load(source)
+            .set_name(name)
+            .exec()
+            .map_err(|e| Chip8Error::Script(e.to_string()))?;
+        Ok(Script {
+            state: Rc::new(State {
+                lua,
+                failed: RefCell::new(Vec::new()),
+            }),
+        })
+    }
+
+    // Registers the script's `on_instruction` with `e` and runs its `on_load`.
+    pub fn attach(&self, e: &mut Chip8) {
+        if self.state.callback(ON_INSTRUCTION).is_some() {
+            let state = Rc::clone(&self.state);
+            e.on_instruction(Box::new(move |pc, opcode| {
+                if let Some(f) = state.callback(ON_INSTRUCTION) {
+                    let result = f.call::<_, ()>((pc, opcode));
+                    state.check(ON_INSTRUCTION, result);
+                }
+            }));
+        }
+        self.state.call_with_emu(ON_LOAD, e);
+    }
+
+    // Meant to be called once after every emulated frame.
+    pub fn on_frame(&self, e: &mut Chip8) {
+        self.state.call_with_emu(ON_FRAME, e);
+    }
+}
+
+fn index(value: usize, len: usize, what: &str) -> mlua::Result<usize> {
+    if value < len {
+        Ok(value)
+    } else {
+        Err(mlua::Error::RuntimeError(format!(
+            "{} {:#X} out of range",
+            what, value
+        )))
+    }
+}
+
+impl UserData for Chip8 {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("reg", |_, e, x: usize| {
+            Ok(e.registers.v[index(x, 16, "register")?])
+        });
+        methods.add_method_mut("set_reg", |_, e, (x, value): (usize, u8)| {
+            e.registers.v[index(x, 16, "register")?] = value;
+            Ok(())
+        });
+        methods.add_method("i", |_, e, ()| Ok(e.registers.i));
+        methods.add_method_mut("set_i", |_, e, value: u32| {
+            e.registers.i = value;
+            Ok(())
+        });
+        methods.add_method("pc", |_, e, ()| Ok(e.pc));
+        methods.add_method("peek", |_, e, addr: usize| {
+            Ok(e.memory[index(addr, e.memory.len(), "address")?])
+        });
+        methods.add_method_mut("poke", |_, e, (addr, value): (usize, u8)| {
+            let addr = index(addr, e.memory.len(), "address")?;
+            e.memory[addr] = value;
+            Ok(())
+        });
+        methods.add_method_mut("key", |_, e, (key, down): (u8, bool)| {
+            e.inject_key(key, down);
+            Ok(())
+        });
+        methods.add_method_mut("pause", |_, e, ()| {
+            e.debugger.stop = Some(StopReason::Script { pc: e.pc });
+            Ok(())
+        });
+        methods.add_method_mut("resume", |_, e, ()| {
+            e.continue_execution();
+            Ok(())
+        });
+        methods.add_method("pixel", |_, e, (x, y): (usize, usize)| {
+            Ok(e.screen.get(y, x))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{machine, steps};
+    use super::*;
+
+    // V0 counts up by one every instruction.
+    const COUNTER: [u16; 2] = [0x7001, 0x1200];
+
+    fn attached(source: &str) -> (Script, Chip8) {
+        let script = Script::from_source(source, "test.lua").unwrap();
+        let mut e = machine(&COUNTER);
+        script.attach(&mut e);
+        (script, e)
+    }
+
+    #[test]
+    fn on_load_can_patch_memory_and_registers() {
+        let (_, e) = attached(
+            "function on_load(emu) emu:poke(0x201, 5) emu:set_reg(3, emu:peek(0x200)) end",
+        );
+        assert_eq!(e.memory()[0x201], 5);
+        assert_eq!(e.registers()[3], 0x70);
+    }
+
+    #[test]
+    fn on_frame_sees_the_machine_after_each_frame() {
+        let (script, mut e) = attached(
+            "frames = 0
+             function on_frame(emu)
+               frames = frames + 1
+               emu:set_reg(1, emu:reg(0))
+               emu:key(0xA, frames % 2 == 1)
+             end",
+        );
+        steps(&mut e, 3);
+        script.on_frame(&mut e);
+        assert_eq!(e.registers()[1], 2);
+        // Injected keys show up once the keypad is next updated.
+        e.keyboard.update([false; 16]);
+        assert!(e.keyboard.keymap[0xA]);
+        script.on_frame(&mut e);
+        e.keyboard.update([false; 16]);
+        assert!(!e.keyboard.keymap[0xA]);
+    }
+
+    #[test]
+    fn on_instruction_gets_every_fetch() {
+        let (script, mut e) = attached(
+            "seen = {}
+             function on_instruction(pc, opcode) seen[#seen + 1] = pc end
+             function on_frame(emu) emu:set_reg(2, #seen) end",
+        );
+        steps(&mut e, 4);
+        script.on_frame(&mut e);
+        assert_eq!(e.registers()[2], 4);
+    }
+
+    #[test]
+    fn pause_stops_the_machine_until_resumed() {
+        let (script, mut e) = attached("function on_frame(emu) emu:pause() end");
+        script.on_frame(&mut e);
+        assert!(e.step().unwrap().is_none());
+        assert_eq!(e.pc(), 0x200);
+        e.continue_execution();
+        steps(&mut e, 1);
+        assert_eq!(e.registers()[0], 1);
+    }
+
+    #[test]
+    fn failing_callbacks_are_disabled_not_fatal() {
+        let (script, mut e) = attached(
+            "calls = 0
+             function on_frame(emu)
+               calls = calls + 1
+               emu:set_reg(5, calls)
+               emu:poke(0x10000, 1)
+             end",
+        );
+        script.on_frame(&mut e);
+        script.on_frame(&mut e);
+        // It ran once, up to the out of range poke.
+        assert_eq!(e.registers()[5], 1);
+    }
+
+    #[test]
+    fn syntax_errors_fail_to_load() {
+        assert!(matches!(
+            Script::from_source("function (", "broken.lua"),
+            Err(Chip8Error::Script(_))
+        ));
+    }
+}
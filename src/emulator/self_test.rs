@@ -0,0 +1,324 @@
+// A built-in check of the interpreter: small programs, one per group of opcodes, run
+// on a default machine and compared with what they should leave behind. Catches a
+// broken build or a quirk change that alters the default behavior.
+use super::Chip8;
+use crate::prelude::*;
+use core::fmt;
+
+// Steps a case may take before it counts as hung.
+const MAX_STEPS: usize = 1000;
+
+type Check = fn(&Chip8) -> Result<(), String>;
+
+struct Case {
+    category: &'static str,
+    opcodes: &'static str,
+    program: &'static [u16],
+    // Keypad keys held while it runs.
+    keys: &'static [u8],
+    // Expected V registers when it exits.
+    v: &'static [(usize, u8)],
+    check: Option<Check>,
+}
+
+const CASES: &[Case] = &[
+    Case {
+        category: "loads and adds",
+        opcodes: "6xkk 7xkk 8xy0",
+        program: &[0x6A12, 0x7A01, 0x8BA0, 0x00FD],
+        keys: &[],
+        v: &[(0xA, 0x13), (0xB, 0x13)],
+        check: None,
+    },
+    Case {
+        category: "logic",
+        opcodes: "8xy1 8xy2 8xy3",
+        program: &[
+            0x6F01, 0x60F0, 0x610F, 0x8011, 0x62F0, 0x630F, 0x8232, 0x64FF, 0x650F, 0x8453, 0x00FD,
+        ],
+        keys: &[],
+        v: &[(0x0, 0xFF), (0x2, 0x00), (0x4, 0xF0), (0xF, 0)],
+        check: None,
+    },
+    Case {
+        category: "carry and borrow",
+        opcodes: "8xy4 8xy5 8xy7",
+        program: &[
+            0x60FF, 0x6101, 0x8014, 0x82F0, 0x6305, 0x6406, 0x8345, 0x85F0, 0x6605, 0x6703, 0x8677,
+            0x6806, 0x6905, 0x8895, 0x00FD,
+        ],
+        keys: &[],
+        v: &[
+            (0x0, 0x00),
+            (0x2, 1),
+            (0x3, 0xFF),
+            (0x5, 0),
+            (0x6, 0xFE),
+            (0x8, 1),
+            (0xF, 1),
+        ],
+        check: None,
+    },
+    Case {
+        category: "shifts",
+        opcodes: "8xy6 8xyE",
+        program: &[0x6105, 0x8106, 0x8AF0, 0x6281, 0x820E, 0x00FD],
+        keys: &[],
+        v: &[(0x1, 0x02), (0xA, 1), (0x2, 0x02), (0xF, 1)],
+        check: None,
+    },
+    Case {
+        category: "skips",
+        opcodes: "3xkk 4xkk 5xy0 9xy0",
+        program: &[
+            0x6005, 0x3005, 0x6101, 0x4005, 0x6201, 0x6305, 0x5030, 0x6401, 0x9030, 0x6501, 0x00FD,
+        ],
+        keys: &[],
+        v: &[(0x1, 0), (0x2, 1), (0x4, 0), (0x5, 1)],
+        check: None,
+    },
+    Case {
+        category: "jumps and calls",
+        opcodes: "1nnn 2nnn 00EE Bnnn",
+        program: &[
+            0x2208, 0x6A01, 0x120C, 0x6B01, 0x6C01, 0x00EE, 0x6002, 0xB212, 0x6D01, 0x6D02, 0x00FD,
+        ],
+        keys: &[],
+        v: &[(0xA, 1), (0xB, 0), (0xC, 1), (0xD, 0)],
+        check: Some(|e| match e.stack.len() {
+            0 => Ok(()),
+            n => Err(format!("{} return addresses left on the stack", n)),
+        }),
+    },
+    Case {
+        category: "memory",
+        opcodes: "Annn Fx1E Fx33 Fx55 Fx65",
+        program: &[
+            0xA300, 0x6A7B, 0xFA33, 0xF265, 0x6C04, 0xFC1E, 0x60AA, 0x61BB, 0xF155, 0x00FD,
+        ],
+        keys: &[],
+        v: &[(0x2, 3)],
+        check: Some(|e| {
+            let (bcd, stored) = (&e.memory[0x300..0x303], &e.memory[0x307..0x309]);
+            if bcd != [1, 2, 3] {
+                return Err(format!("BCD of 123 stored as {:?}", bcd));
+            }
+            if stored != [0xAA, 0xBB] {
+                return Err(format!("Fx55 stored {:02X?}", stored));
+            }
+            match e.registers.i {
+                0x309 => Ok(()),
+                i => Err(format!("I is {:#05X}, expected 0x309", i)),
+            }
+        }),
+    },
+    Case {
+        category: "timers",
+        opcodes: "Fx07 Fx15 Fx18",
+        program: &[0x600A, 0xF015, 0xF107, 0xF018, 0x00FD],
+        keys: &[],
+        v: &[(0x1, 0x0A)],
+        check: Some(|e| match e.timers.sound {
+            0x0A => Ok(()),
+            st => Err(format!("ST is {}, expected 10", st)),
+        }),
+    },
+    Case {
+        category: "random",
+        opcodes: "Cxkk",
+        program: &[0x60FF, 0xC000, 0x00FD],
+        keys: &[],
+        v: &[(0x0, 0)],
+        check: None,
+    },
+    Case {
+        category: "keypad",
+        opcodes: "Ex9E ExA1 Fx0A",
+        program: &[
+            0x6005, 0xE09E, 0x6101, 0xE0A1, 0x6201, 0x6006, 0xE0A1, 0x6301, 0xF40A, 0x00FD,
+        ],
+        keys: &[5],
+        v: &[(0x1, 0), (0x2, 1), (0x3, 0), (0x4, 5)],
+        check: None,
+    },
+    Case {
+        category: "drawing",
+        opcodes: "Fx29 Dxyn",
+        program: &[
+            0x6000, 0xF029, 0xD005, 0x8AF0, 0xD005, 0x8BF0, 0xD005, 0x00FD,
+        ],
+        keys: &[],
+        v: &[(0xA, 0), (0xB, 1)],
+        check: Some(|e| {
+            // The top two rows of the 0 glyph, F0 and 90.
+            let expected = [[true, true, true, true], [true, false, false, true]];
+            for (row, bits) in expected.iter().enumerate() {
+                for (col, &on) in bits.iter().enumerate() {
+                    if e.screen.get(row, col) != on {
+                        return Err(format!("pixel ({}, {}) should be {}", col, row, on));
+                    }
+                }
+            }
+            Ok(())
+        }),
+    },
+    Case {
+        category: "clear",
+        opcodes: "00E0",
+        program: &[0x6000, 0xF029, 0xD005, 0x00E0, 0x00FD],
+        keys: &[],
+        v: &[],
+        check: Some(|e| match e.screen.hash() == super::Screen::new().hash() {
+            true => Ok(()),
+            false => Err(String::from("the display isn't blank")),
+        }),
+    },
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelfTestResult {
+    pub category: &'static str,
+    pub opcodes: &'static str,
+    // Why it failed, `None` if it passed.
+    pub failure: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SelfTestReport {
+    pub results: Vec<SelfTestResult>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.failure.is_none())
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for r in &self.results {
+            match &r.failure {
+                None => writeln!(f, "PASS  {:<18} {}", r.category, r.opcodes)?,
+                Some(why) => writeln!(f, "FAIL  {:<18} {}: {}", r.category, r.opcodes, why)?,
+            }
+        }
+        let passed = self.results.iter().filter(|r| r.failure.is_none()).count();
+        writeln!(f, "{} of {} passed", passed, self.results.len())
+    }
+}
+
+fn run_case(case: &Case) -> Result<(), String> {
+    let program: Vec<u8> = case
+        .program
+        .iter()
+        .flat_map(|op| op.to_be_bytes())
+        .collect();
+    let mut e = Chip8::new();
+    e.load(&program).map_err(|err| err.to_string())?;
+    for &key in case.keys {
+        e.inject_key(key, true);
+    }
+    e.keyboard.update([false; 16]);
+    let mut steps = 0;
+    while !e.has_exited() {
+        if steps == MAX_STEPS {
+            return Err(format!("still running at {:#05X}", e.pc));
+        }
+        e.step().map_err(|err| err.to_string())?;
+        steps += 1;
+    }
+    for &(x, value) in case.v {
+        let actual = e.registers.v[x];
+        if actual != value {
+            return Err(format!(
+                "V{:X} is {:02X}, expected {:02X}",
+                x, actual, value
+            ));
+        }
+    }
+    case.check.map_or(Ok(()), |check| check(&e))
+}
+
+// Runs every case on a fresh default machine.
+pub fn run_self_test() -> SelfTestReport {
+    let results = CASES
+        .iter()
+        .map(|case| SelfTestResult {
+            category: case.category,
+            opcodes: case.opcodes,
+            failure: run_case(case).err(),
+        })
+        .collect();
+    SelfTestReport { results }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn case(program: &'static [u16], v: &'static [(usize, u8)], check: Option<Check>) -> Case {
+        Case {
+            category: "test",
+            opcodes: "",
+            program,
+            keys: &[],
+            v,
+            check,
+        }
+    }
+
+    #[test]
+    fn every_case_passes_on_this_build() {
+        let report = run_self_test();
+        assert_eq!(report.results.len(), CASES.len());
+        for r in &report.results {
+            assert_eq!(r.failure, None, "{}", r.category);
+        }
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn wrong_registers_hangs_and_errors_fail() {
+        assert_eq!(
+            run_case(&case(&[0x6A12, 0x00FD], &[(0xA, 0x12)], None)),
+            Ok(())
+        );
+        assert_eq!(
+            run_case(&case(&[0x6A12, 0x00FD], &[(0xA, 0x13)], None)),
+            Err(String::from("VA is 12, expected 13"))
+        );
+        assert_eq!(
+            run_case(&case(&[0x1200], &[], None)),
+            Err(String::from("still running at 0x200"))
+        );
+        assert!(run_case(&case(&[0xF0FF], &[], None)).is_err());
+        let failing: Check = |_| Err(String::from("no"));
+        assert_eq!(
+            run_case(&case(&[0x00FD], &[], Some(failing))),
+            Err(String::from("no"))
+        );
+    }
+
+    #[test]
+    fn reports_count_the_passes() {
+        let report = SelfTestReport {
+            results: vec![
+                SelfTestResult {
+                    category: "good",
+                    opcodes: "1nnn",
+                    failure: None,
+                },
+                SelfTestResult {
+                    category: "bad",
+                    opcodes: "2nnn",
+                    failure: Some(String::from("broken")),
+                },
+            ],
+        };
+        assert!(!report.passed());
+        let text = report.to_string();
+        assert!(text.starts_with("PASS  good"));
+        assert!(text.contains("FAIL  bad                2nnn: broken\n"));
+        assert!(text.ends_with("1 of 2 passed\n"));
+    }
+}
@@ -0,0 +1,442 @@
+// Save states. A state starts with a header holding the time it was saved and a
+// thumbnail of the display, which `Thumbnail::read_from` reads on its own, followed
+// by the machine: variant, registers, timers, stack, memory and display. MegaChip's
+// color display isn't included. With the `serde` feature `Chip8` also serializes to
+// and from the same machine state through serde.
+use super::{Chip8, Chip8Error, Timers, Variant};
+use crate::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "std")]
+use std::io::Read;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 1;
+// Magic, version, time saved, columns and rows, before the thumbnail pixels.
+#[cfg(feature = "std")]
+const HEADER_LEN: usize = 17;
+
+// The display when a state was saved, one bool per pixel, row by row.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub cols: usize,
+    pub rows: usize,
+    pub pixels: Vec<bool>,
+    // Seconds since the Unix epoch.
+    pub saved_at: u64,
+}
+
+// The machine, as a save state holds it.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Snapshot {
+    variant: Variant,
+    v: [u8; 16],
+    i: u32,
+    pc: u16,
+    timers: Timers,
+    stack: Vec<u16>,
+    exited: bool,
+    memory: Vec<u8>,
+    cols: usize,
+    rows: usize,
+    pixels: Vec<bool>,
+}
+
+fn invalid(what: &str) -> Chip8Error {
+    Chip8Error::InvalidState(what.to_string())
+}
+
+fn read_array<const N: usize>(r: &mut &[u8]) -> Result<[u8; N], Chip8Error> {
+    let (buf, rest) = r.split_first_chunk().ok_or_else(|| invalid("truncated"))?;
+    *r = rest;
+    Ok(*buf)
+}
+
+fn read_vec(r: &mut &[u8], len: usize) -> Result<Vec<u8>, Chip8Error> {
+    let (buf, rest) = r
+        .split_at_checked(len)
+        .ok_or_else(|| invalid("truncated"))?;
+    *r = rest;
+    Ok(buf.to_vec())
+}
+
+// Seconds since the Unix epoch, 0 without std as there's no clock to ask.
+fn now() -> u64 {
+    #[cfg(feature = "std")]
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    #[cfg(not(feature = "std"))]
+    0
+}
+
+fn fnv(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+impl Thumbnail {
+    // Reads just the header from a file or stream, without reading the whole state.
+    #[cfg(feature = "std")]
+    pub fn read_from(r: &mut impl Read) -> Result<Self, Chip8Error> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        r.take(HEADER_LEN as u64).read_to_end(&mut header)?;
+        if let [.., c0, c1, r0, r1] = header[..] {
+            let cols = u16::from_le_bytes([c0, c1]) as usize;
+            let rows = u16::from_le_bytes([r0, r1]) as usize;
+            let packed = (cols * rows).div_ceil(8);
+            r.take(packed as u64).read_to_end(&mut header)?;
+        }
+        Self::parse(&mut header.as_slice())
+    }
+
+    // Reads just the header, leaving `r` at the start of the machine state.
+    pub fn parse(r: &mut &[u8]) -> Result<Self, Chip8Error> {
+        if &read_array::<4>(r)? != MAGIC {
+            return Err(invalid("not a save state"));
+        }
+        if read_array::<1>(r)?[0] != VERSION {
+            return Err(invalid("unsupported version"));
+        }
+        let saved_at = u64::from_le_bytes(read_array(r)?);
+        let cols = u16::from_le_bytes(read_array(r)?) as usize;
+        let rows = u16::from_le_bytes(read_array(r)?) as usize;
+        let packed = read_vec(r, (cols * rows).div_ceil(8))?;
+        let pixels = (0..cols * rows)
+            .map(|n| packed[n / 8] & (0x80 >> (n % 8)) != 0)
+            .collect();
+        Ok(Thumbnail {
+            cols,
+            rows,
+            pixels,
+            saved_at,
+        })
+    }
+}
+
+impl Chip8 {
+    pub fn save_state(&self) -> Vec<u8> {
+        let saved_at = now();
+        let mut out = Vec::with_capacity(self.memory.len() + 512);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&saved_at.to_le_bytes());
+        out.extend_from_slice(&(self.screen.cols as u16).to_le_bytes());
+        out.extend_from_slice(&(self.screen.rows as u16).to_le_bytes());
+        self.screen.pack_bits(&mut out);
+        self.encode_machine(&mut out);
+        out
+    }
+
+    // Everything after the header, which already holds the display.
+    fn encode_machine(&self, out: &mut Vec<u8>) {
+        self.encode_registers(out);
+        out.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.memory);
+    }
+
+    fn encode_registers(&self, out: &mut Vec<u8>) {
+        out.push(match self.variant {
+            Variant::Chip8 => 0,
+            Variant::MegaChip => 1,
+            Variant::Hires => 2,
+        });
+        out.extend_from_slice(&self.registers.v);
+        out.extend_from_slice(&self.registers.i.to_le_bytes());
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        out.push(self.timers.delay);
+        out.push(self.timers.sound);
+        out.push(self.stack.len() as u8);
+        for addr in &self.stack {
+            out.extend_from_slice(&addr.to_le_bytes());
+        }
+        out.push(self.exited as u8);
+    }
+
+    // FNV-1a over the machine state and display, leaving out when it was saved. Two
+    // runs that end in the same hash ended in the same state.
+    pub fn state_hash(&self) -> u64 {
+        let mut machine = Vec::with_capacity(self.memory.len() + 64);
+        self.encode_machine(&mut machine);
+        fnv(self.screen.hash(), &machine)
+    }
+
+    // As `state_hash` but leaving out memory, for comparing runs of different ROMs.
+    pub fn behavior_hash(&self) -> u64 {
+        let mut registers = Vec::with_capacity(64);
+        self.encode_registers(&mut registers);
+        fnv(self.screen.hash(), &registers)
+    }
+
+    // Restores a state from `save_state`. Nothing changes if it turns out to be invalid.
+    pub fn load_state(&mut self, mut data: &[u8]) -> Result<(), Chip8Error> {
+        let r = &mut data;
+        let thumbnail = Thumbnail::parse(r)?;
+        let variant = match read_array::<1>(r)?[0] {
+            0 => Variant::Chip8,
+            1 => Variant::MegaChip,
+            2 => Variant::Hires,
+            _ => return Err(invalid("unknown variant")),
+        };
+        let v = read_array::<16>(r)?;
+        let i = u32::from_le_bytes(read_array(r)?);
+        let pc = u16::from_le_bytes(read_array(r)?);
+        let [delay, sound, depth] = read_array(r)?;
+        let stack = (0..depth)
+            .map(|_| read_array(r).map(u16::from_le_bytes))
+            .collect::<Result<Vec<_>, _>>()?;
+        let exited = read_array::<1>(r)?[0] != 0;
+        let len = u32::from_le_bytes(read_array(r)?) as usize;
+        let memory = read_vec(r, len)?;
+        self.restore(Snapshot {
+            variant,
+            v,
+            i,
+            pc,
+            timers: Timers { sound, delay },
+            stack,
+            exited,
+            memory,
+            cols: thumbnail.cols,
+            rows: thumbnail.rows,
+            pixels: thumbnail.pixels,
+        })
+    }
+
+    #[cfg(feature = "serde")]
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            variant: self.variant,
+            v: self.registers.v,
+            i: self.registers.i,
+            pc: self.pc,
+            timers: self.timers,
+            stack: self.stack.clone(),
+            exited: self.exited,
+            memory: self.memory.clone(),
+            cols: self.screen.cols,
+            rows: self.screen.rows,
+            pixels: self.screen.pixels.clone(),
+        }
+    }
+
+    fn restore(&mut self, state: Snapshot) -> Result<(), Chip8Error> {
+        if state.stack.len() > super::STACK_DEPTH {
+            return Err(invalid("stack too deep"));
+        }
+        let (memory_size, cols, rows) = state.variant.layout();
+        // Machines built with another memory or display size only take their own states.
+        let (memory_size, (cols, rows)) = match state.variant == self.variant {
+            true => (self.memory.len(), self.display_size),
+            false => (memory_size, (cols, rows)),
+        };
+        if state.memory.len() != memory_size {
+            return Err(invalid("memory size doesn't match the machine"));
+        }
+        let size = (state.cols, state.rows);
+        if size != (cols, rows) && size != super::SCHIP_HIRES {
+            return Err(invalid("display size doesn't match the machine"));
+        }
+        if state.pixels.len() != state.cols * state.rows {
+            return Err(invalid("display size doesn't match its pixels"));
+        }
+
+        if state.variant != self.variant {
+            self.set_variant(state.variant);
+        }
+        self.registers.v = state.v;
+        self.registers.i = state.i;
+        self.pc = state.pc;
+        self.timers = state.timers;
+        self.max_stack_depth = self.max_stack_depth.max(state.stack.len());
+        self.stack = state.stack;
+        self.exited = state.exited;
+        self.reached_end = false;
+        self.memory = state.memory;
+        if size != (self.screen.cols, self.screen.rows) {
+            self.set_resolution(size.0, size.1);
+        }
+        self.screen.pixels = state.pixels;
+        self.history.clear();
+        self.reset_idle();
+        self.invalidate_screen();
+        Ok(())
+    }
+}
+
+// The machine state `save_state` holds, field by field rather than as bytes, for
+// storing it as JSON or any other serde format. Deserializing gives a default machine
+// in that state, with the memory size of the state.
+#[cfg(feature = "serde")]
+impl Serialize for Chip8 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.snapshot().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Chip8 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use de::Error;
+        let state = Snapshot::deserialize(deserializer)?;
+        let mut builder = Chip8::builder().variant(state.variant);
+        if state.memory.len() != state.variant.layout().0 {
+            builder = builder.memory_size(state.memory.len());
+        }
+        let mut e = builder.build().map_err(D::Error::custom)?;
+        e.restore(state).map_err(D::Error::custom)?;
+        Ok(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{machine, steps};
+    use super::*;
+
+    // Draws the "0" glyph at (0, 0) and sets a few registers.
+    fn drawn() -> Chip8 {
+        let mut e = machine(&[0x6A12, 0xD005, 0x2208, 0x0000, 0x1208]);
+        steps(&mut e, 3);
+        e
+    }
+
+    #[test]
+    fn the_header_holds_a_thumbnail_of_the_display() {
+        let e = drawn();
+        let state = e.save_state();
+        let thumbnail = Thumbnail::parse(&mut state.as_slice()).unwrap();
+        assert_eq!((thumbnail.cols, thumbnail.rows), (64, 32));
+        assert_eq!(thumbnail.pixels, e.screen().pixels());
+        #[cfg(feature = "std")]
+        assert!(thumbnail.saved_at.abs_diff(now()) < 5);
+        // No clock to ask.
+        #[cfg(not(feature = "std"))]
+        assert_eq!(thumbnail.saved_at, 0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn thumbnails_are_read_without_the_machine() {
+        let state = drawn().save_state();
+        let mut reader = std::io::Cursor::new(&state);
+        let thumbnail = Thumbnail::read_from(&mut reader).unwrap();
+        assert_eq!(thumbnail.pixels.iter().filter(|&&p| p).count(), 14);
+        // The header and 64 * 32 bits of pixels, nothing more.
+        assert_eq!(reader.position(), HEADER_LEN as u64 + 256);
+    }
+
+    #[test]
+    fn states_restore_the_machine() {
+        let mut e = drawn();
+        let state = e.save_state();
+        let hash = e.state_hash();
+        steps(&mut e, 1);
+        e.reset().unwrap();
+        e.load_state(&state).unwrap();
+        assert_eq!(e.state_hash(), hash);
+        assert_eq!((e.pc(), e.registers.v[0xA]), (0x208, 0x12));
+        assert_eq!(e.stack, [0x204]);
+    }
+
+    #[test]
+    fn invalid_states_change_nothing() {
+        let mut e = drawn();
+        let hash = e.state_hash();
+        let state = e.save_state();
+        let mut bad_magic = state.clone();
+        bad_magic[0] = b'X';
+        let mut bad_version = state.clone();
+        bad_version[4] = VERSION + 1;
+        let cases: [(&[u8], &str); 4] = [
+            (&bad_magic, "not a save state"),
+            (&bad_version, "unsupported version"),
+            (&state[..state.len() - 1], "truncated"),
+            (&[], "truncated"),
+        ];
+        for (data, why) in cases {
+            match e.load_state(data) {
+                Err(Chip8Error::InvalidState(message)) => assert_eq!(message, why),
+                _ => panic!("{} state loaded", why),
+            }
+            assert_eq!(e.state_hash(), hash);
+        }
+    }
+
+    #[test]
+    fn every_truncated_state_is_an_error() {
+        let mut e = drawn();
+        let state = e.save_state();
+        // The 17 byte header and 64 * 32 bits of pixels.
+        let thumbnail_len = 17 + 256;
+        for len in 0..state.len() {
+            assert!(e.load_state(&state[..len]).is_err(), "{} bytes", len);
+            let thumbnail = Thumbnail::parse(&mut &state[..len]);
+            assert_eq!(thumbnail.is_ok(), len >= thumbnail_len, "{} bytes", len);
+        }
+    }
+
+    #[cfg(all(feature = "serde", feature = "std"))]
+    #[test]
+    fn serde_round_trips_the_machine() {
+        let mut e = drawn();
+        e.registers.i = 0x2F0;
+        e.timers = Timers { sound: 3, delay: 9 };
+        let json = serde_json::to_string(&e).unwrap();
+        let mut copy: Chip8 = serde_json::from_str(&json).unwrap();
+        assert_eq!(copy.state_hash(), e.state_hash());
+        assert_eq!(
+            (copy.pc(), copy.index(), copy.stack()),
+            (0x208, 0x2F0, &[0x204][..])
+        );
+        assert_eq!(copy.timers(), e.timers());
+        assert_eq!(copy.screen().pixels(), e.screen().pixels());
+        // And both carry on the same way.
+        for e in [&mut e, &mut copy] {
+            e.run_frame().unwrap();
+        }
+        assert_eq!(copy.state_hash(), e.state_hash());
+    }
+
+    #[cfg(all(feature = "serde", feature = "std"))]
+    #[test]
+    fn serde_keeps_the_memory_size_and_checks_the_state() {
+        let mut e = Chip8::builder().memory_size(8192).build().unwrap();
+        e.memory[0x1FFF] = 0xAB;
+        let json = serde_json::to_value(&e).unwrap();
+        let copy: Chip8 = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(copy.memory().len(), 8192);
+        assert_eq!(copy.memory()[0x1FFF], 0xAB);
+
+        let mut deep = json.clone();
+        deep["stack"] = serde_json::json!(vec![0x200; 17]);
+        let mut torn = json.clone();
+        torn["pixels"].as_array_mut().unwrap().pop();
+        let mut missing = json;
+        missing.as_object_mut().unwrap().remove("pc");
+        for (bad, why) in [
+            (deep, "stack too deep"),
+            (torn, "display size doesn't match its pixels"),
+            (missing, "missing field `pc`"),
+        ] {
+            let err = serde_json::from_value::<Chip8>(bad).err().unwrap();
+            assert!(err.to_string().contains(why), "{}", err);
+        }
+    }
+
+    #[test]
+    fn states_only_load_into_a_matching_memory_size() {
+        let state = drawn().save_state();
+        let mut e = Chip8::builder().memory_size(8192).build().unwrap();
+        assert!(matches!(
+            e.load_state(&state),
+            Err(Chip8Error::InvalidState(m)) if m == "memory size doesn't match the machine"
+        ));
+    }
+}
@@ -0,0 +1,131 @@
+use super::{Chip8, Instruction, Timing};
+
+// Running totals since the machine was created. Rates come from diffing two snapshots.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub instructions: u64,
+    pub draws: u64,
+    pub frames: u64,
+    // Frames in which Dxyn or 00E0 touched the display.
+    pub dirty_frames: u64,
+}
+
+impl Chip8 {
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    // Instructions per second the configured timing and speed ask for. `None` under
+    // `Timing::Vip`, where it depends on what the program executes.
+    pub fn target_ips(&self) -> Option<u64> {
+        match self.timing {
+            Timing::Fixed => Some((self.ips() as f32 * self.speed) as u64),
+            Timing::Vip => None,
+        }
+    }
+
+    // VIP cycles the last frame's draws took, including the wait for the vertical
+    // blank. Always 0 under `Timing::Fixed`.
+    pub fn last_frame_draw_cycles(&self) -> u64 {
+        self.last_draw_cycles
+    }
+
+    // Instructions the last frame executed, which falls short of the budget when the
+    // program stops or, under `Timing::Vip`, waits for the vertical blank.
+    pub fn last_frame_instructions(&self) -> u64 {
+        self.last_frame_instructions
+    }
+
+    pub(super) fn count_instruction(&mut self, ins: &Instruction) {
+        self.stats.instructions += 1;
+        self.frame_instructions += 1;
+        if let Instruction::Drw(..) = ins {
+            self.stats.draws += 1;
+        }
+        if ins.touches_display() {
+            self.frame_dirty = true;
+        }
+    }
+
+    pub(super) fn count_frame(&mut self) {
+        self.stats.frames += 1;
+        self.last_draw_cycles = core::mem::take(&mut self.draw_cycles);
+        self.last_frame_instructions = core::mem::take(&mut self.frame_instructions);
+        if self.frame_dirty {
+            self.stats.dirty_frames += 1;
+            self.frame_dirty = false;
+        }
+    }
+
+    // A frame cut short by a stop isn't counted, but what it ran still is.
+    pub(super) fn count_stopped_frame(&mut self) {
+        self.last_frame_instructions = core::mem::take(&mut self.frame_instructions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::machine;
+    use super::*;
+
+    #[test]
+    fn counters_track_instructions_draws_and_dirty_frames() {
+        // Draws once, then loops adding to V0.
+        let mut e = machine(&[0xA000, 0xD005, 0x7001, 0x1204]);
+        e.set_cycles_per_frame(2);
+        for _ in 0..3 {
+            e.run_frame().unwrap();
+        }
+        assert_eq!(
+            e.stats(),
+            Stats {
+                instructions: 6,
+                draws: 1,
+                frames: 3,
+                dirty_frames: 1,
+            }
+        );
+        assert_eq!(e.last_frame_instructions(), 2);
+    }
+
+    #[test]
+    fn target_ips_follows_the_speed_and_timing() {
+        let mut e = machine(&[0x1200]);
+        e.set_ips(600);
+        assert_eq!(e.target_ips(), Some(600));
+        e.set_speed_multiplier(2.0);
+        assert_eq!(e.target_ips(), Some(1200));
+        e.set_timing(Timing::Vip);
+        assert_eq!(e.target_ips(), None);
+    }
+
+    #[test]
+    fn display_waits_cut_frames_short() {
+        // Under VIP timing each Dxyn waits for the next frame, so however many there
+        // are in a row, a frame runs at most one of them.
+        let mut e = machine(&[0xD001, 0xD001, 0xD001, 0xD001, 0x1200]);
+        e.set_timing(Timing::Vip);
+        let counts: Vec<_> = (0..5)
+            .map(|_| {
+                e.run_frame().unwrap();
+                e.last_frame_instructions()
+            })
+            .collect();
+        assert_eq!(counts, [1, 1, 1, 1, 2]);
+        assert_eq!(e.stats().draws, 5);
+    }
+
+    #[test]
+    fn stops_count_what_the_frame_ran() {
+        let mut e = machine(&[0x7001, 0x7001, 0x7001, 0x1200]);
+        e.set_cycles_per_frame(10);
+        e.add_breakpoint(0x204);
+        e.run_frame().unwrap();
+        assert_eq!(e.last_frame_instructions(), 2);
+        // Stopped frames run nothing.
+        e.run_frame().unwrap();
+        assert_eq!(e.last_frame_instructions(), 0);
+        e.reset().unwrap();
+        assert_eq!(e.last_frame_instructions(), 0);
+    }
+}
@@ -0,0 +1,21 @@
+// Shared by the unit tests: machines running small programs written as opcodes.
+use super::Chip8;
+use crate::prelude::*;
+
+pub(super) fn rom(program: &[u16]) -> Vec<u8> {
+    program.iter().flat_map(|op| op.to_be_bytes()).collect()
+}
+
+// A default machine with `program` loaded at 0x200.
+pub(super) fn machine(program: &[u16]) -> Chip8 {
+    let mut e = Chip8::new();
+    e.load(&rom(program)).unwrap();
+    e
+}
+
+// Executes `n` instructions, failing the test on any error.
+pub(super) fn steps(e: &mut Chip8, n: usize) {
+    for _ in 0..n {
+        e.step().unwrap();
+    }
+}
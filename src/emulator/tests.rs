@@ -0,0 +1,1358 @@
+// Instruction-level tests: small programs run on a default machine, checked against
+// registers, memory and the display.
+use super::test_util::{machine, rom, steps};
+use super::*;
+use alloc::rc::Rc;
+use core::cell::Cell;
+
+// Draws a 3-row sprite from 0xFFE, so its last row is past the end of memory.
+fn draw_past_the_end(policy: SpriteReadPolicy) -> (Chip8, Result<(), Chip8Error>) {
+    let mut e = machine(&[0xAFFE, 0xD003]);
+    e.set_sprite_read_policy(policy);
+    e.memory[0xFFE] = 0x80;
+    e.memory[0xFFF] = 0x80;
+    steps(&mut e, 1);
+    let result = e.step().map(|_| ());
+    (e, result)
+}
+
+#[test]
+fn sprite_reads_wrap_to_the_start_of_memory() {
+    let (e, result) = draw_past_the_end(SpriteReadPolicy::Wrap);
+    result.unwrap();
+    assert!(e.screen().get(0, 0) && e.screen().get(1, 0));
+    // The third row is the first byte of the font, the top of the "0" glyph.
+    assert_eq!(e.memory()[0], 0xF0);
+    assert!((0..4).all(|col| e.screen().get(2, col)));
+    assert!(!e.screen().get(2, 4));
+}
+
+#[test]
+fn sprite_reads_clamp_at_the_end_of_memory() {
+    let (e, result) = draw_past_the_end(SpriteReadPolicy::Clamp);
+    result.unwrap();
+    assert!(e.screen().get(0, 0) && e.screen().get(1, 0));
+    assert!((0..8).all(|col| !e.screen().get(2, col)));
+    assert_eq!(e.pc(), 0x204);
+}
+
+#[test]
+fn sprite_reads_past_the_end_can_be_an_error() {
+    let (e, result) = draw_past_the_end(SpriteReadPolicy::Error);
+    assert!(matches!(
+        result,
+        Err(Chip8Error::MemoryOutOfBounds {
+            pc: 0x202,
+            addr: 0x1000
+        })
+    ));
+    assert_eq!(e.screen().lit_pixels().count(), 0);
+}
+
+#[test]
+fn sprite_reads_inside_memory_ignore_the_policy() {
+    let mut e = machine(&[0xAFFD, 0xD003]);
+    e.set_sprite_read_policy(SpriteReadPolicy::Error);
+    e.memory[0xFFD..].copy_from_slice(&[0x80, 0x80, 0x80]);
+    steps(&mut e, 2);
+    assert_eq!(e.screen().lit_pixels().count(), 3);
+}
+
+// Runs `program` placed at `pc` through the fuzzing entry point.
+fn fuzz_program(program: &[u8], pc: u16) -> Result<(), Chip8Error> {
+    let mut memory = vec![0; 4096];
+    memory[pc as usize..pc as usize + program.len()].copy_from_slice(program);
+    fuzz_step(&memory, pc)
+}
+
+#[test]
+fn fuzz_edge_cases_end_in_errors_not_panics() {
+    // Fx33, Fx55 and Fx65 with I at the last byte of memory.
+    for op in [0x33, 0x55, 0x65] {
+        let program = [0xAF, 0xFF, 0xFF, op];
+        assert!(fuzz_program(&program, 0x200).is_err());
+    }
+    // An instruction straddling the end of memory.
+    assert!(fuzz_program(&[0x60], 0xFFF).is_err());
+    // Starting past the end of memory.
+    assert!(fuzz_step(&[], 0xFFFF).is_err());
+    // Odd addresses run like any other, a jump to itself runs out its steps.
+    assert!(fuzz_program(&[0x12, 0x01], 0x201).is_ok());
+    // Unbounded recursion.
+    assert!(matches!(
+        fuzz_program(&[0x22, 0x00], 0x200),
+        Err(Chip8Error::StackOverflow { .. })
+    ));
+    assert!(matches!(
+        fuzz_program(&[0x00, 0xEE], 0x200),
+        Err(Chip8Error::StackUnderflow { pc: 0x200 })
+    ));
+}
+
+#[test]
+fn fuzz_step_survives_random_memory() {
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+    let mut rng = StdRng::seed_from_u64(412);
+    let mut memory = vec![0; 4096];
+    for _ in 0..200 {
+        rng.fill_bytes(&mut memory);
+        let pc = (rng.next_u32() & 0xFFF) as u16;
+        let _ = fuzz_step(&memory, pc);
+    }
+    // Past 4KB it's a MegaChip machine, where pc can reach the end of its range.
+    let mut memory = vec![0; 0x10100];
+    for _ in 0..200 {
+        rng.fill_bytes(&mut memory);
+        let pc = 0xFF00 | (rng.next_u32() & 0xFF) as u16;
+        let _ = fuzz_step(&memory, pc);
+    }
+}
+
+// A MegaChip machine, with memory past 0xFFFF, running `program` from `pc`.
+fn high_machine(program: &[u16], pc: u16) -> Chip8 {
+    let mut e = Chip8::new();
+    e.set_variant(Variant::MegaChip);
+    let bytes = rom(program);
+    e.memory[pc as usize..pc as usize + bytes.len()].copy_from_slice(&bytes);
+    e.pc = pc;
+    e
+}
+
+#[test]
+fn pc_stops_at_the_top_of_its_range() {
+    let mut e = Chip8::new();
+    e.set_variant(Variant::MegaChip);
+    e.load(&[0x60, 0x00].repeat(0xFE00 / 2)).unwrap();
+    let err = loop {
+        if let Err(err) = e.step() {
+            break err;
+        }
+    };
+    assert!(matches!(
+        err,
+        Chip8Error::MemoryOutOfBounds {
+            pc: 0xFFFE,
+            addr: 0x10000
+        }
+    ));
+    assert_eq!(e.pc(), 0xFFFE);
+    // A jump is fine there, as it doesn't need the next address.
+    let mut e = high_machine(&[0x1200], 0xFFFE);
+    steps(&mut e, 1);
+    assert_eq!(e.pc(), 0x200);
+}
+
+#[test]
+fn skips_and_returns_stop_at_the_top_of_pcs_range() {
+    // Taken, a skip at 0xFFFC would land past 0xFFFF.
+    let mut e = high_machine(&[0x3000, 0x6000], 0xFFFC);
+    assert!(matches!(
+        e.step(),
+        Err(Chip8Error::MemoryOutOfBounds { pc: 0xFFFC, .. })
+    ));
+    assert_eq!(e.pc(), 0xFFFC);
+    // Not taken, it goes on to the next instruction.
+    let mut e = high_machine(&[0x4000, 0x1200], 0xFFFC);
+    steps(&mut e, 2);
+    assert_eq!(e.pc(), 0x200);
+    // A return to a call at 0xFFFE.
+    let mut e = high_machine(&[0x00EE], 0x200);
+    e.stack.push(0xFFFE);
+    assert!(matches!(
+        e.step(),
+        Err(Chip8Error::MemoryOutOfBounds { pc: 0x200, .. })
+    ));
+    assert_eq!(e.stack.len(), 1);
+}
+
+// A 128x64 machine that draws the font's "0" at (V0, V1).
+fn draw_on_large_screen(x: u8, y: u8) -> Chip8 {
+    let mut e = Chip8::with_screen(Screen::with_size(128, 64));
+    e.load(&rom(&[
+        0x6000 | x as u16,
+        0x6100 | y as u16,
+        0xA000,
+        0xD015,
+    ]))
+    .unwrap();
+    steps(&mut e, 4);
+    e
+}
+
+#[test]
+fn large_screens_draw_past_the_standard_size() {
+    let e = draw_on_large_screen(100, 50);
+    assert_eq!((e.screen().cols(), e.screen().rows()), (128, 64));
+    assert_eq!(e.screen().pixels().len(), 128 * 64);
+    let lit: Vec<_> = e.screen().lit_pixels().collect();
+    assert_eq!(lit.len(), 14);
+    assert!(lit
+        .iter()
+        .all(|&(row, col)| (50..55).contains(&row) && (100..104).contains(&col)));
+    assert!((100..104).all(|col| e.screen().get(50, col) && e.screen().get(54, col)));
+    assert_eq!(e.registers()[0xF], 0);
+}
+
+#[test]
+fn large_screens_wrap_at_their_own_edges() {
+    let e = draw_on_large_screen(126, 62);
+    // The top row, F0, runs from the last two columns into the first two.
+    assert!([126, 127, 0, 1].iter().all(|&col| e.screen().get(62, col)));
+    // The bottom row lands back at the top.
+    assert!([126, 127, 0, 1].iter().all(|&col| e.screen().get(2, col)));
+    assert!(!e.screen().get(62, 62) && !e.screen().get(30, 126));
+}
+
+#[test]
+fn large_screens_keep_their_size_across_resets_and_states() {
+    let mut e = draw_on_large_screen(100, 50);
+    let state = e.save_state();
+    e.reset().unwrap();
+    assert_eq!((e.screen().cols(), e.screen().rows()), (128, 64));
+    assert_eq!(e.screen().lit_pixels().count(), 0);
+    e.load_state(&state).unwrap();
+    assert!(e.screen().get(50, 100));
+}
+
+#[test]
+fn falling_off_the_rom_sets_reached_end() {
+    let mut e = machine(&[0x6001, 0x7001]);
+    e.set_halt_at_rom_end(true);
+    steps(&mut e, 2);
+    assert!(!e.reached_end());
+    assert!(e.step().unwrap().is_none());
+    assert!(e.reached_end());
+    // Nothing past the end ran, and frames stop there too.
+    assert_eq!((e.pc(), e.registers()[0]), (0x204, 2));
+    e.set_cycles_per_frame(10);
+    e.run_frame().unwrap();
+    assert_eq!(e.pc(), 0x204);
+
+    e.reset().unwrap();
+    assert!(!e.reached_end());
+}
+
+#[test]
+fn without_the_halt_zeroed_memory_runs() {
+    let mut e = machine(&[0x6001, 0x7001]);
+    steps(&mut e, 4);
+    assert!(!e.reached_end());
+    assert_eq!(e.pc(), 0x208);
+}
+
+#[test]
+fn peek_opcode_reads_without_executing() {
+    let mut e = machine(&[0x6A12, 0x1234]);
+    assert_eq!(e.peek_opcode(), 0x6A12);
+    assert_eq!(e.peek_opcode(), 0x6A12);
+    assert_eq!(e.registers()[0xA], 0);
+    assert_eq!(e.peek_opcode_at(0x202), 0x1234);
+    assert_eq!(
+        Instruction::decode(e.peek_opcode()),
+        Some(Instruction::LdByte(0xA, 0x12))
+    );
+    steps(&mut e, 1);
+    assert_eq!(e.peek_opcode(), 0x1234);
+}
+
+#[test]
+fn peek_opcode_past_the_end_of_memory_reads_zeros() {
+    let mut e = machine(&[0x00E0]);
+    e.memory[0xFFF] = 0xAB;
+    assert_eq!(e.peek_opcode_at(0xFFF), 0xAB00);
+    assert_eq!(e.peek_opcode_at(0x1000), 0);
+    assert_eq!(e.peek_opcode_at(u16::MAX), 0);
+}
+
+// The font's "0" and "1" side by side in the top left corner.
+fn draw_zero_and_one() -> Chip8 {
+    let mut e = machine(&[0xA000, 0xD115, 0x6008, 0xA005, 0xD015]);
+    steps(&mut e, 5);
+    e
+}
+
+#[test]
+fn standard_screen_snapshot() {
+    let e = draw_zero_and_one();
+    let text = e.screen().to_text();
+    let rows: Vec<_> = text.lines().collect();
+    assert_eq!(rows.len(), 32);
+    assert!(rows.iter().all(|row| row.len() == 64));
+    let corner: Vec<_> = rows[..6].iter().map(|row| &row[..16]).collect();
+    assert_eq!(
+        corner,
+        [
+            "####......#.....",
+            "#..#.....##.....",
+            "#..#......#.....",
+            "#..#......#.....",
+            "####.....###....",
+            "................",
+        ]
+    );
+    assert_eq!(e.screen().lit_pixels().count(), 14 + 8);
+    // Pinned, so the same frame hashes the same across versions.
+    assert_eq!(e.screen().hash(), 0xA6280ED1FBE351B1);
+}
+
+#[test]
+fn resizing_clears_and_changes_the_hash() {
+    let mut e = draw_zero_and_one();
+    let hash = e.screen().hash();
+    e.screen.clear();
+    assert_eq!((e.screen().cols(), e.screen().rows()), (64, 32));
+    assert_eq!(e.screen().hash(), Screen::new().hash());
+    assert_ne!(e.screen().hash(), hash);
+    e.screen.resize(128, 64);
+    assert_eq!(e.screen().pixels().len(), 128 * 64);
+    assert_eq!(e.screen().lit_pixels().count(), 0);
+    assert_ne!(e.screen().hash(), Screen::new().hash());
+}
+
+#[test]
+fn pixels_wrap_at_the_screen_edges() {
+    let mut screen = Screen::new();
+    assert_eq!(screen.set(32, 64, true), 0);
+    assert!(screen.get(0, 0));
+    // Setting a lit pixel turns it off and reports the collision.
+    assert_eq!(screen.set(0, 0, true), 1);
+    assert!(!screen.get(0, 0));
+}
+
+// Runs the key test `opcode` on V0 = `v0` with key `held` down, returning the PC
+// after it: 0x206 if it skipped.
+fn key_test(opcode: u16, policy: KeyIndexPolicy, v0: u8, held: Option<usize>) -> u16 {
+    let mut e = machine(&[0x6000 | v0 as u16, opcode, 0x6000]);
+    e.set_key_index_policy(policy);
+    if let Some(key) = held {
+        e.keyboard.keymap[key] = true;
+    }
+    steps(&mut e, 2);
+    e.pc()
+}
+
+#[test]
+fn out_of_range_keys_read_as_up_by_default() {
+    let held = Some(0xF);
+    assert_eq!(
+        key_test(0xE09E, KeyIndexPolicy::NotPressed, 0xFF, held),
+        0x204
+    );
+    assert_eq!(
+        key_test(0xE0A1, KeyIndexPolicy::NotPressed, 0xFF, held),
+        0x206
+    );
+    assert_eq!(KeyIndexPolicy::default(), KeyIndexPolicy::NotPressed);
+}
+
+#[test]
+fn out_of_range_keys_can_use_the_low_nibble() {
+    let held = Some(0xF);
+    assert_eq!(key_test(0xE09E, KeyIndexPolicy::Mask, 0xFF, held), 0x206);
+    assert_eq!(key_test(0xE0A1, KeyIndexPolicy::Mask, 0xFF, held), 0x204);
+    assert_eq!(key_test(0xE09E, KeyIndexPolicy::Mask, 0x1F, None), 0x204);
+}
+
+#[test]
+fn key_indexes_resolve_the_same_for_every_value() {
+    let mut e = Chip8::new();
+    for v in 0..=255u8 {
+        e.set_key_index_policy(KeyIndexPolicy::NotPressed);
+        assert_eq!(
+            e.resolve_key(v),
+            (v < 16).then_some(v as usize),
+            "{:02X}",
+            v
+        );
+        e.set_key_index_policy(KeyIndexPolicy::Mask);
+        assert_eq!(e.resolve_key(v), Some(v as usize & 0xF), "{:02X}", v);
+    }
+}
+
+#[test]
+fn ex9e_and_exa1_agree_on_indexes_past_f() {
+    // 0x10 and 0xF0 both name key 0 when masked and no key otherwise, so with key 0
+    // held Ex9E skips exactly when ExA1 doesn't.
+    for v0 in [0x10, 0xF0] {
+        for held in [Some(0), None] {
+            let masked_down = held.is_some();
+            let cases = [
+                (KeyIndexPolicy::NotPressed, false),
+                (KeyIndexPolicy::Mask, masked_down),
+            ];
+            for (policy, down) in cases {
+                let skp = key_test(0xE09E, policy, v0, held) == 0x206;
+                let sknp = key_test(0xE0A1, policy, v0, held) == 0x206;
+                assert_eq!(
+                    (skp, sknp),
+                    (down, !down),
+                    "{:?} {:02X} {:?}",
+                    policy,
+                    v0,
+                    held
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn in_range_keys_ignore_the_policy() {
+    for policy in [KeyIndexPolicy::NotPressed, KeyIndexPolicy::Mask] {
+        assert_eq!(key_test(0xE09E, policy, 0x5, Some(0x5)), 0x206);
+        assert_eq!(key_test(0xE09E, policy, 0x5, None), 0x204);
+        assert_eq!(key_test(0xE0A1, policy, 0x5, None), 0x206);
+    }
+}
+
+#[test]
+fn released_keys_read_up_until_the_host_reports_them_up() {
+    let mut e = machine(&[0x6005, 0xE09E, 0x1202, 0x1206]);
+    let mut held = [false; 16];
+    held[5] = true;
+    e.keyboard.update(held);
+    e.release_all_keys();
+    assert_eq!(e.keyboard.keymap, [false; 16]);
+    // Still down on the host, but left over from before the release.
+    e.keyboard.update(held);
+    steps(&mut e, 2);
+    assert_eq!(e.pc(), 0x204);
+    e.keyboard.update([false; 16]);
+    e.keyboard.update(held);
+    steps(&mut e, 2);
+    assert_eq!(e.pc(), 0x206);
+}
+
+fn hires(program: &[u16]) -> Chip8 {
+    let mut e = Chip8::new();
+    e.set_variant(Variant::Hires);
+    e.load(&rom(program)).unwrap();
+    e
+}
+
+#[test]
+fn hires_programs_jump_past_the_interpreter() {
+    let mut e = hires(&[0x1260]);
+    steps(&mut e, 1);
+    assert_eq!(e.pc(), 0x2C0);
+    // Only the opening jump, and only under the hires variant.
+    let mut e = machine(&[0x1260]);
+    steps(&mut e, 1);
+    assert_eq!(e.pc(), 0x260);
+    let mut e = hires(&[0x6000, 0x1260]);
+    steps(&mut e, 2);
+    assert_eq!(e.pc(), 0x260);
+}
+
+#[test]
+fn hires_draws_the_lower_half_of_a_square_display() {
+    let mut e = hires(&[0x6000, 0x6128, 0xD015, 0x00E0]);
+    assert_eq!((e.screen.cols(), e.screen.rows()), (64, 64));
+    steps(&mut e, 3);
+    assert!(e.screen.get(40, 0));
+    // Not wrapped back onto the top half as on a 64x32 display.
+    assert!(!e.screen.get(8, 0));
+    steps(&mut e, 1);
+    assert_eq!(e.screen.lit_pixels().count(), 0);
+}
+
+#[test]
+fn exit_stops_the_machine_on_the_00fd() {
+    let mut e = machine(&[0x6001, 0x00FD, 0x6002]);
+    steps(&mut e, 1);
+    assert!(!e.has_exited());
+    steps(&mut e, 1);
+    assert!(e.has_exited());
+    assert_eq!(e.pc(), 0x202);
+    // Further steps and frames run nothing.
+    steps(&mut e, 3);
+    e.run_frame().unwrap();
+    assert_eq!(e.pc(), 0x202);
+    assert_eq!(e.registers.v[0], 1);
+}
+
+#[test]
+fn reset_clears_the_exit() {
+    let mut e = machine(&[0x00FD]);
+    steps(&mut e, 1);
+    e.reset().unwrap();
+    assert!(!e.has_exited());
+    assert_eq!(e.pc(), 0x200);
+}
+
+// Instructions run and timer ticks over `frames` frames at `speed`, with DT started at 100.
+fn run_at_speed(speed: f32, frames: usize) -> (u64, u8) {
+    let mut e = machine(&[0x6064, 0xF015, 0x1204]);
+    steps(&mut e, 2);
+    e.set_speed_multiplier(speed);
+    let before = e.stats().instructions;
+    for _ in 0..frames {
+        e.run_frame().unwrap();
+    }
+    (e.stats().instructions - before, 100 - e.timers.delay)
+}
+
+#[test]
+fn speed_scales_instructions_and_timers_together() {
+    assert_eq!(run_at_speed(1.0, 10), (10, 10));
+    assert_eq!(run_at_speed(2.0, 10), (20, 20));
+    assert_eq!(run_at_speed(0.5, 10), (5, 5));
+    // Fractions carry over between frames.
+    assert_eq!(run_at_speed(1.5, 4), (6, 6));
+}
+
+#[test]
+fn speed_is_kept_within_its_limits() {
+    let mut e = Chip8::new();
+    e.set_speed_multiplier(100.0);
+    assert_eq!(e.speed_multiplier(), MAX_SPEED);
+    e.set_speed_multiplier(0.0);
+    assert_eq!(e.speed_multiplier(), MIN_SPEED);
+}
+
+#[cfg(feature = "std")]
+fn rom_file(name: &str, program: &[u16]) -> String {
+    let path =
+        std::env::temp_dir().join(format!("chip8-reload-{}-{}.ch8", name, std::process::id()));
+    std::fs::write(&path, rom(program)).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn reload_picks_up_the_rebuilt_rom() {
+    let path = rom_file("rebuilt", &[0x6001, 0x1202]);
+    let mut e = Chip8::new();
+    e.load_from_file(&path).unwrap();
+    steps(&mut e, 2);
+    rom_file("rebuilt", &[0x6002, 0x1202]);
+    e.reload().unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(e.memory[0x200..0x202], [0x60, 0x02]);
+    assert_eq!((e.pc(), e.registers.v[0]), (0x200, 0));
+    assert_eq!(e.rom_path(), Some(path.as_str()));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn failed_reloads_leave_the_machine_running() {
+    let path = rom_file("broken", &[0x6001, 0x1202]);
+    let mut e = Chip8::new();
+    e.load_from_file(&path).unwrap();
+    steps(&mut e, 1);
+    std::fs::write(&path, []).unwrap();
+    assert!(matches!(e.reload(), Err(Chip8Error::EmptyRom)));
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(e.reload(), Err(Chip8Error::RomNotFound(_))));
+    assert_eq!((e.pc(), e.registers.v[0]), (0x202, 1));
+    // Still the file to try again once it's rebuilt.
+    assert_eq!(e.rom_path(), Some(path.as_str()));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn programs_not_from_a_file_have_nothing_to_reload() {
+    let mut e = machine(&[0x1200]);
+    assert!(matches!(e.reload(), Err(Chip8Error::NoRomFile)));
+    assert_eq!(e.rom_path(), None);
+}
+
+// A machine running `program` 16 instructions a frame, sampling every `interval`,
+// with key 1 held from the `down_from`th sample on. Returns it and the sample count.
+fn sampled(program: &[u16], interval: u32, down_from: usize) -> (Chip8, Rc<Cell<usize>>) {
+    let mut e = machine(program);
+    e.set_cycles_per_frame(16);
+    let polls = Rc::new(Cell::new(0));
+    let counter = polls.clone();
+    let sampler = move || {
+        counter.set(counter.get() + 1);
+        let mut keys = [false; 16];
+        keys[1] = counter.get() > down_from;
+        keys
+    };
+    e.set_key_sampler(Some(Box::new(sampler)), interval);
+    (e, polls)
+}
+
+// Sets V2 once key 1 is seen down.
+const WAIT_FOR_1: [u16; 5] = [0x6101, 0xE19E, 0x1202, 0x6201, 0x1208];
+
+#[test]
+fn key_samplers_poll_every_interval_within_a_frame() {
+    let (mut e, polls) = sampled(&WAIT_FOR_1, 4, usize::MAX);
+    e.run_frame().unwrap();
+    assert_eq!(polls.get(), 4);
+    let (mut e, polls) = sampled(&WAIT_FOR_1, u32::MAX, usize::MAX);
+    e.run_frame().unwrap();
+    e.run_frame().unwrap();
+    assert_eq!(polls.get(), 2);
+}
+
+#[test]
+fn keys_pressed_mid_frame_are_seen_in_that_frame() {
+    let (mut e, _) = sampled(&WAIT_FOR_1, 4, 1);
+    e.run_frame().unwrap();
+    assert_eq!(e.registers.v[2], 1);
+    // Sampled once a frame, the second sample is a frame later.
+    let (mut e, _) = sampled(&WAIT_FOR_1, u32::MAX, 1);
+    e.run_frame().unwrap();
+    assert_eq!(e.registers.v[2], 0);
+    e.run_frame().unwrap();
+    assert_eq!(e.registers.v[2], 1);
+}
+
+// There is a single display plane, so 00E0 always clears all of it.
+#[test]
+fn clear_screen_clears_every_pixel() {
+    let mut e = draw_zero_and_one();
+    assert_eq!(e.screen.lit_pixels().count(), 22);
+    e.memory[0x20A..0x20C].copy_from_slice(&[0x00, 0xE0]);
+    steps(&mut e, 1);
+    assert_eq!(e.screen.lit_pixels().count(), 0);
+}
+
+#[test]
+fn there_is_no_plane_select() {
+    let mut e = machine(&[0xF101]);
+    assert!(matches!(
+        e.step(),
+        Err(Chip8Error::InvalidOpcode {
+            pc: 0x200,
+            opcode: 0xF101
+        })
+    ));
+}
+
+#[test]
+fn max_stack_depth_keeps_the_peak() {
+    // Three nested calls, all returning, then a loop.
+    let mut e = machine(&[0x2204, 0x1202, 0x2208, 0x00EE, 0x220C, 0x00EE, 0x00EE]);
+    steps(&mut e, 2);
+    assert_eq!((e.stack_depth(), e.max_stack_depth()), (2, 2));
+    steps(&mut e, 5);
+    assert_eq!(e.pc(), 0x202);
+    assert_eq!((e.stack_depth(), e.max_stack_depth()), (0, 3));
+    e.reset().unwrap();
+    assert_eq!(e.max_stack_depth(), 0);
+}
+
+const FG: [u8; 4] = [0xFF, 0x80, 0x00, 0xFF];
+const BG: [u8; 4] = [0x10, 0x20, 0x30, 0xFF];
+
+// Lit at row 0 column 1 and row 1 column 0.
+fn checker_corner() -> Screen {
+    let mut screen = Screen::new();
+    screen.set(0, 1, true);
+    screen.set(1, 0, true);
+    screen
+}
+
+// The RGBA of the pixel at (x, y) of a buffer `width` pixels wide.
+fn rgba_at(buf: &[u8], width: usize, x: usize, y: usize) -> [u8; 4] {
+    let p = (y * width + x) * 4;
+    buf[p..p + 4].try_into().unwrap()
+}
+
+#[test]
+fn rgba_rendering_colors_every_pixel() {
+    let screen = checker_corner();
+    let mut out = vec![0xAA; 10];
+    screen.render_to_rgba(FG, BG, &mut out);
+    assert_eq!(out.len(), 64 * 32 * 4);
+    assert_eq!(rgba_at(&out, 64, 0, 0), BG);
+    assert_eq!(rgba_at(&out, 64, 1, 0), FG);
+    assert_eq!(rgba_at(&out, 64, 0, 1), FG);
+    assert_eq!(rgba_at(&out, 64, 63, 31), BG);
+}
+
+#[test]
+fn scaled_rgba_rendering_draws_blocks() {
+    let screen = checker_corner();
+    let mut out = Vec::new();
+    screen.render_to_rgba_scaled(FG, BG, 4, &mut out);
+    assert_eq!(out.len(), 256 * 128 * 4);
+    for (x, y) in [(4, 0), (7, 3), (0, 4), (3, 7)] {
+        assert_eq!(rgba_at(&out, 256, x, y), FG, "({}, {})", x, y);
+    }
+    for (x, y) in [(3, 3), (8, 0), (4, 4), (0, 8)] {
+        assert_eq!(rgba_at(&out, 256, x, y), BG, "({}, {})", x, y);
+    }
+}
+
+#[test]
+fn rendering_into_a_buffer_needs_room_for_it() {
+    let screen = checker_corner();
+    let mut short = vec![0; 64 * 32 * 4 - 1];
+    assert!(!screen.render_into(&mut short, FG, BG));
+    assert!(short.iter().all(|&b| b == 0));
+    let mut buf = vec![0; 64 * 32 * 4 + 4];
+    assert!(screen.render_into(&mut buf, FG, BG));
+    assert_eq!(rgba_at(&buf, 64, 1, 0), FG);
+    // Bytes past the display are left alone.
+    assert_eq!(rgba_at(&buf, 64, 0, 32), [0; 4]);
+}
+
+#[test]
+fn rendering_into_a_buffer_gives_the_same_bytes_as_rgba_rendering() {
+    let screen = checker_corner();
+    let mut buf = vec![0; 64 * 32 * 4];
+    assert!(screen.render_into(&mut buf, FG, BG));
+    assert_eq!(buf[..8], [BG, FG].concat());
+    assert_eq!(buf[64 * 4..64 * 4 + 8], [FG, BG].concat());
+    let mut out = Vec::new();
+    screen.render_to_rgba(FG, BG, &mut out);
+    assert_eq!(buf, out);
+}
+
+#[test]
+fn packed_bits_run_row_by_row_high_bit_first() {
+    let mut packed = vec![0xAA];
+    checker_corner().pack_bits(&mut packed);
+    assert_eq!(packed.len(), 1 + 64 * 32 / 8);
+    assert_eq!(packed[1], 0b0100_0000);
+    assert_eq!(packed[1 + 8], 0b1000_0000);
+    assert_eq!(packed.iter().filter(|&&b| b != 0).count(), 3);
+
+    let mut hires = Screen::with_size(128, 64);
+    hires.set(63, 127, true);
+    let mut packed = Vec::new();
+    hires.pack_bits(&mut packed);
+    assert_eq!(packed.len(), 128 * 64 / 8);
+    assert_eq!(packed[packed.len() - 1], 0b0000_0001);
+}
+
+// VF after `opcode` with VF = 0x0C and V0 = 0x05.
+fn vf_after(opcode: u16, vf_reset: VfReset) -> u8 {
+    let mut e = machine(&[0x6F0C, 0x6005, opcode]);
+    e.set_vf_reset(vf_reset);
+    steps(&mut e, 3);
+    e.registers()[0xF]
+}
+
+#[test]
+fn logic_ops_into_vf_end_with_the_reset_by_default() {
+    assert_eq!(VfReset::default(), VfReset::Reset);
+    for opcode in [0x8FF1, 0x8FF2, 0x8FF3, 0x8F01, 0x8F02, 0x8F03, 0x8001] {
+        assert_eq!(vf_after(opcode, VfReset::Reset), 0, "{:04X}", opcode);
+    }
+}
+
+#[test]
+fn logic_ops_into_vf_keep_their_result_without_the_reset() {
+    let expected = [
+        (0x8FF1, 0x0C),
+        (0x8FF2, 0x0C),
+        (0x8FF3, 0x00),
+        (0x8F01, 0x0D),
+        (0x8F02, 0x04),
+        (0x8F03, 0x09),
+        // Other targets leave VF as it was.
+        (0x8001, 0x0C),
+    ];
+    for (opcode, vf) in expected {
+        assert_eq!(vf_after(opcode, VfReset::Keep), vf, "{:04X}", opcode);
+    }
+}
+
+// Brightness of the top left pixel over the frames after a sprite there is drawn and
+// then erased again.
+fn flicker_brightness(mode: FlickerMode) -> Vec<f32> {
+    let mut e = machine(&[0xA20A, 0xD011, 0xD011, 0x1206, 0x0000, 0x8000]);
+    e.set_flicker_mode(mode);
+    (0..6)
+        .map(|_| {
+            e.run_frame().unwrap();
+            e.screen().brightness(0, 0)
+        })
+        .collect()
+}
+
+#[test]
+fn accurate_flicker_darkens_cleared_pixels_straight_away() {
+    assert_eq!(FlickerMode::default(), FlickerMode::Accurate);
+    assert_eq!(
+        flicker_brightness(FlickerMode::Accurate),
+        [0.0, 1.0, 0.0, 0.0, 0.0, 0.0]
+    );
+}
+
+#[test]
+fn persistence_fades_cleared_pixels_out() {
+    let glow = flicker_brightness(FlickerMode::Persistence);
+    assert_eq!(glow[..2], [0.0, 1.0]);
+    // Erased in the third frame, then fading until it's gone.
+    assert!(glow[2] > 0.5 && glow[2] < 1.0);
+    assert!(glow[3] < glow[2] && glow[4] < glow[3] && glow[4] > 0.0);
+    let mut e = machine(&[0xA20A, 0xD011, 0xD011, 0x1206, 0x0000, 0x8000]);
+    e.set_flicker_mode(FlickerMode::Persistence);
+    for _ in 0..20 {
+        e.run_frame().unwrap();
+    }
+    assert_eq!(e.screen().brightness(0, 0), 0.0);
+}
+
+// (result, flag) of the 8xyN arithmetic ops for Vx = `vx` and Vy = `vy`.
+fn arithmetic(n: u16, vx: u8, vy: u8) -> (u8, u8) {
+    match n {
+        0x4 => {
+            let (sum, carry) = vx.overflowing_add(vy);
+            (sum, carry as u8)
+        }
+        0x5 => (vx.wrapping_sub(vy), (vx >= vy) as u8),
+        0x6 => (vx >> 1, vx & 1),
+        0x7 => (vy.wrapping_sub(vx), (vy >= vx) as u8),
+        0xE => (vx << 1, vx >> 7),
+        _ => unreachable!(),
+    }
+}
+
+const FLAG_OPERANDS: [(u8, u8); 6] = [
+    (0x10, 0x20),
+    (0xF0, 0x20),
+    (0x81, 0x81),
+    (0x00, 0x00),
+    (0xFF, 0x01),
+    (0x01, 0xFF),
+];
+
+#[test]
+fn arithmetic_into_vf_keeps_the_flag() {
+    for n in [0x4, 0x5, 0x6, 0x7, 0xE] {
+        for (vf, v0) in FLAG_OPERANDS {
+            // x = F with y = F, then with y = 0.
+            for (y, vy) in [(0xF, vf), (0x0, v0)] {
+                let opcode = 0x8F00 | y << 4 | n;
+                let mut e = machine(&[0x6F00 | vf as u16, 0x6000 | v0 as u16, opcode]);
+                steps(&mut e, 3);
+                let (_, flag) = arithmetic(n, vf, vy);
+                assert_eq!(e.registers()[0xF], flag, "{:04X} VF={:02X}", opcode, vf);
+                assert_eq!(e.registers()[0], v0, "{:04X}", opcode);
+            }
+        }
+    }
+}
+
+#[test]
+fn arithmetic_reading_vf_uses_its_value_before_the_flag() {
+    for n in [0x4, 0x5, 0x6, 0x7, 0xE] {
+        for (v0, vf) in FLAG_OPERANDS {
+            let opcode = 0x80F0 | n;
+            let mut e = machine(&[0x6000 | v0 as u16, 0x6F00 | vf as u16, opcode]);
+            steps(&mut e, 3);
+            let (result, flag) = arithmetic(n, v0, vf);
+            assert_eq!(
+                (e.registers()[0], e.registers()[0xF]),
+                (result, flag),
+                "{:04X} V0={:02X} VF={:02X}",
+                opcode,
+                v0,
+                vf
+            );
+        }
+    }
+}
+
+#[test]
+fn dxyn_reads_coordinates_from_vf_before_the_collision_flag() {
+    // 8x1 sprites at (VF, V1), (V0, VF) and (VF, VF), each drawn twice so the second
+    // draw collides. V0 = 8, V1 = 2, VF = 5.
+    for (opcode, col, row) in [(0xDF11, 5, 2), (0xD0F1, 8, 5), (0xDFF1, 5, 5)] {
+        let mut e = machine(&[
+            0x6008, 0x6102, 0xA20E, 0x6F05, opcode, 0x6F05, opcode, 0x8000,
+        ]);
+        steps(&mut e, 5);
+        assert!(e.screen().get(row, col), "{:04X}", opcode);
+        assert_eq!(e.screen().lit_pixels().count(), 1);
+        assert_eq!(e.registers()[0xF], 0, "{:04X}", opcode);
+        steps(&mut e, 2);
+        assert_eq!(e.screen().lit_pixels().count(), 0, "{:04X}", opcode);
+        assert_eq!(e.registers()[0xF], 1, "{:04X}", opcode);
+    }
+}
+
+// A WAIT_FOR_1 machine reading the keys as `polling` says, 16 instructions a frame,
+// from an input source that holds key 1 from its fourth poll on.
+fn polled(polling: InputPolling) -> (Chip8, Rc<Cell<usize>>) {
+    let mut e = machine(&WAIT_FOR_1);
+    e.set_cycles_per_frame(16);
+    e.set_input_polling(polling);
+    let polls = Rc::new(Cell::new(0));
+    let counter = polls.clone();
+    let source = move || {
+        counter.set(counter.get() + 1);
+        let mut keys = [false; 16];
+        keys[1] = counter.get() > 3;
+        keys
+    };
+    e.set_input_source(Some(Box::new(source)));
+    (e, polls)
+}
+
+#[test]
+fn per_frame_polling_sees_keys_a_frame_at_a_time() {
+    assert_eq!(InputPolling::default(), InputPolling::PerFrame);
+    let (mut e, polls) = polled(InputPolling::PerFrame);
+    let seen: Vec<_> = (0..4)
+        .map(|_| {
+            e.tick().unwrap();
+            e.registers()[2]
+        })
+        .collect();
+    assert_eq!(seen, [0, 0, 0, 1]);
+    assert_eq!(polls.get(), 4);
+}
+
+#[test]
+fn per_cycle_polling_sees_keys_within_the_frame() {
+    let (mut e, polls) = polled(InputPolling::PerCycle);
+    e.tick().unwrap();
+    // Down by the second pass over E19E, so the first frame already takes it.
+    assert_eq!(e.registers()[2], 1);
+    assert_eq!(polls.get(), 17);
+    // The source is kept for the next frame.
+    e.tick().unwrap();
+    assert_eq!(polls.get(), 34);
+}
+
+#[test]
+fn empty_roms_are_rejected_and_change_nothing() {
+    let mut e = Chip8::new();
+    assert!(matches!(e.load(&[]), Err(Chip8Error::EmptyRom)));
+    assert_eq!(Chip8Error::EmptyRom.to_string(), "The ROM is empty");
+
+    let mut e = machine(&[0x6005, 0x1202]);
+    steps(&mut e, 1);
+    assert!(matches!(e.load(&[]), Err(Chip8Error::EmptyRom)));
+    assert_eq!((e.pc(), e.registers()[0]), (0x202, 5));
+    assert_eq!(e.memory()[0x200..0x204], [0x60, 0x05, 0x12, 0x02]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn empty_rom_files_are_rejected() {
+    let path = std::env::temp_dir().join(format!("chip8-empty-{}.ch8", std::process::id()));
+    std::fs::write(&path, []).unwrap();
+    let mut e = Chip8::new();
+    let result = e.load_from_file(&path.to_string_lossy());
+    let _ = std::fs::remove_file(&path);
+    assert!(matches!(result, Err(Chip8Error::EmptyRom)));
+    assert_eq!(e.rom_path(), None);
+}
+
+// The sound timer after Fx18 with V0 = `value` and at least `min` frames to sound.
+fn sound_after_fx18(value: u8, min: Option<u8>) -> u8 {
+    let mut e = machine(&[0x6000 | value as u16, 0xF018]);
+    if let Some(min) = min {
+        e.set_min_sound_frames(min);
+    }
+    steps(&mut e, 2);
+    assert_eq!(e.is_sound_active(), e.timers().sound > 0);
+    e.timers().sound
+}
+
+#[test]
+fn single_frame_beeps_are_skipped_by_default() {
+    assert_eq!(sound_after_fx18(1, None), 0);
+    assert_eq!(sound_after_fx18(2, None), 2);
+    assert_eq!(sound_after_fx18(0xFF, None), 0xFF);
+}
+
+#[test]
+fn the_minimum_beep_length_is_configurable() {
+    for min in [0, 1] {
+        assert_eq!(sound_after_fx18(1, Some(min)), 1);
+    }
+    assert_eq!(sound_after_fx18(4, Some(5)), 0);
+    assert_eq!(sound_after_fx18(5, Some(5)), 5);
+}
+
+#[test]
+fn out_of_band_display_changes_invalidate_the_screen() {
+    let mut e = machine(&[0xA000, 0xD005, 0x1204]);
+    // A fresh machine has never been drawn.
+    assert!(e.take_screen_invalidated());
+    assert!(!e.take_screen_invalidated());
+    let state = e.save_state();
+    steps(&mut e, 3);
+    // Frames that ran are the host's usual cue to redraw.
+    assert!(!e.take_screen_invalidated());
+
+    e.load_state(&state).unwrap();
+    assert!(e.take_screen_invalidated());
+    e.reset().unwrap();
+    assert!(e.take_screen_invalidated());
+    e.set_variant(Variant::Hires);
+    assert!(e.take_screen_invalidated());
+    e.invalidate_screen();
+    assert!(e.take_screen_invalidated());
+    assert!(!e.take_screen_invalidated());
+}
+
+#[test]
+fn resolution_switches_invalidate_the_screen() {
+    let mut e = machine(&[0x00FF, 0x00FE]);
+    e.take_screen_invalidated();
+    steps(&mut e, 1);
+    assert!(e.take_screen_invalidated());
+    steps(&mut e, 1);
+    assert!(e.take_screen_invalidated());
+}
+
+// The lit pixels after drawing the top row of the 0 glyph at (8, 0), four pixels
+// wide, then running `opcode`.
+fn lit_after_00xx(opcode: u16) -> (Chip8, Vec<(usize, usize)>) {
+    let mut e = machine(&[0x6008, 0x6100, 0xA000, 0xD011, opcode]);
+    steps(&mut e, 5);
+    let lit = e.screen().lit_pixels().collect();
+    (e, lit)
+}
+
+#[test]
+fn schip_00xx_opcodes_run_their_own_handlers() {
+    let row = |r: usize, cols: core::ops::Range<usize>| cols.map(|c| (r, c)).collect::<Vec<_>>();
+    assert_eq!(lit_after_00xx(0x0123).1, row(0, 8..12));
+    assert_eq!(lit_after_00xx(0x00C2).1, row(2, 8..12));
+    assert_eq!(lit_after_00xx(0x00FB).1, row(0, 12..16));
+    assert_eq!(lit_after_00xx(0x00FC).1, row(0, 4..8));
+    let (e, lit) = lit_after_00xx(0x00FD);
+    assert!(e.has_exited());
+    assert_eq!(lit, row(0, 8..12));
+    let (e, lit) = lit_after_00xx(0x00FF);
+    assert_eq!((e.screen().cols(), lit.len()), (128, 0));
+    let (e, lit) = lit_after_00xx(0x00E0);
+    assert_eq!((e.screen().cols(), lit.len()), (64, 0));
+}
+
+#[test]
+fn the_grid_is_off_by_default_and_leaves_the_pixels_alone() {
+    let mut e = machine(&[0xA000, 0xD015]);
+    steps(&mut e, 2);
+    assert!(!e.screen().grid());
+    let (pixels, hash) = (e.screen().pixels().to_vec(), e.screen().hash());
+    e.take_screen_invalidated();
+    e.set_grid(true);
+    assert!(e.screen().grid());
+    assert!(e.take_screen_invalidated());
+    assert_eq!(e.screen().pixels(), &pixels[..]);
+    assert_eq!(e.screen().hash(), hash);
+    e.set_grid(false);
+    assert!(!e.screen().grid());
+    assert_eq!(e.screen().pixels(), &pixels[..]);
+}
+
+#[test]
+fn ips_sets_the_instructions_per_frame() {
+    let mut e = machine(&[0x7001, 0x1200]);
+    e.set_ips(600);
+    assert_eq!((e.cycles_per_frame(), e.ips()), (10, 600));
+    e.run_frame().unwrap();
+    assert_eq!(e.stats().instructions, 10);
+    // Rounded to the nearest whole number a frame.
+    e.set_ips(700);
+    assert_eq!((e.cycles_per_frame(), e.ips()), (12, 700));
+    e.set_ips(689);
+    assert_eq!(e.cycles_per_frame(), 11);
+}
+
+#[test]
+fn ips_follows_the_frame_rate() {
+    let mut e = Chip8::new();
+    assert_eq!(e.frame_rate(), 60);
+    e.set_ips(600);
+    e.set_frame_rate(30);
+    assert_eq!((e.cycles_per_frame(), e.ips()), (20, 600));
+    e.set_frame_rate(120);
+    assert_eq!(e.cycles_per_frame(), 5);
+    // Instructions per frame set directly stay put instead.
+    e.set_cycles_per_frame(8);
+    e.set_frame_rate(60);
+    assert_eq!((e.cycles_per_frame(), e.ips()), (8, 480));
+}
+
+// Waits for a key, draws its glyph at (0, 0), then draws a random digit into V2. Key A
+// is pressed in the third frame, through an input source rather than the host keyboard.
+fn run_windowless(seed: u64) -> Chip8 {
+    let mut e = machine(&[0xF00A, 0xF029, 0x6100, 0xD115, 0xC20F, 0x00FD]);
+    e.seed_rng(seed);
+    let mut polls = 0;
+    e.set_input_source(Some(Box::new(move || {
+        polls += 1;
+        core::array::from_fn(|k| k == 0xA && polls == 3)
+    })));
+    for _ in 0..20 {
+        e.tick().unwrap();
+    }
+    e
+}
+
+// Input, drawing and randomness all go through the core, so a whole program runs with
+// no window, in every feature set including `--no-default-features`.
+#[test]
+fn the_core_runs_without_a_window() {
+    let e = run_windowless(3);
+    assert!(e.has_exited());
+    assert_eq!(e.registers()[0], 0xA);
+    // The top row of A is F0.
+    let top: Vec<bool> = (0..8).map(|col| e.screen().get(0, col)).collect();
+    assert_eq!(top, [true, true, true, true, false, false, false, false]);
+    let mut rgba = Vec::new();
+    e.screen().render_to_rgba(FG, BG, &mut rgba);
+    assert_eq!(rgba[..4], FG);
+    assert_eq!(rgba[4 * 4..4 * 4 + 4], BG);
+    assert_eq!(run_windowless(3).registers()[2], e.registers()[2]);
+}
+
+#[test]
+fn accessors_follow_execution() {
+    let mut e = machine(&[
+        0x6A05, 0xA300, 0xFA15, 0xFA18, 0x2210, 0x120A, 0x0000, 0x0000, // 0x200
+        0x2214, 0x00EE, 0xFA55, 0x1216, // 0x210
+    ]);
+    assert_eq!((e.pc(), e.index(), e.stack()), (0x200, 0, &[][..]));
+    steps(&mut e, 4);
+    assert_eq!(e.registers()[0xA], 5);
+    assert_eq!(e.index(), 0x300);
+    assert_eq!(e.timers(), Timers { sound: 5, delay: 5 });
+    steps(&mut e, 2);
+    assert_eq!((e.pc(), e.stack()), (0x214, &[0x208, 0x210][..]));
+    steps(&mut e, 1);
+    assert_eq!(e.memory()[0x30A], 5);
+    assert_eq!(e.memory()[0x300..0x30A], [0; 10]);
+    e.run_frame().unwrap();
+    assert_eq!(e.timers(), Timers { sound: 4, delay: 4 });
+}
+
+#[test]
+fn step_reports_what_it_executed() {
+    let mut e = machine(&[0x6A05, 0xFA15, 0x7A01, 0x2208, 0x00EE]);
+    let infos: Vec<StepInfo> = (0..5).map(|_| e.step().unwrap().unwrap()).collect();
+    let pcs: Vec<u16> = infos.iter().map(|i| i.pc).collect();
+    assert_eq!(pcs, [0x200, 0x202, 0x204, 0x206, 0x208]);
+    let opcodes: Vec<u16> = infos.iter().map(|i| i.opcode).collect();
+    assert_eq!(opcodes, [0x6A05, 0xFA15, 0x7A01, 0x2208, 0x00EE]);
+    let mnemonics: Vec<&str> = infos.iter().map(|i| i.mnemonic).collect();
+    assert_eq!(mnemonics, ["LD", "LD", "ADD", "CALL", "RET"]);
+    // The VIP's costs, fetch included, even under the default fixed timing.
+    let fetch = infos[0].cycles - 6;
+    let costs: Vec<u32> = infos.iter().map(|i| i.cycles - fetch).collect();
+    assert_eq!(costs, [6, 10, 10, 26, 10]);
+    // Steps leave the timers alone.
+    assert_eq!(e.timers().delay, 5);
+}
+
+#[test]
+fn step_reports_nothing_when_nothing_ran() {
+    let mut e = machine(&[0x6001, 0x6002]);
+    e.add_breakpoint(0x202);
+    assert!(e.step().unwrap().is_some());
+    assert_eq!(e.step().unwrap(), None);
+    e.continue_execution();
+    e.halt();
+    assert_eq!(e.step().unwrap(), None);
+    e.resume();
+    assert_eq!(e.step().unwrap().map(|i| i.pc), Some(0x202));
+}
+
+#[test]
+fn the_framebuffer_and_lit_pixels_agree() {
+    // The 1 glyph, 20 60 20 20 70, drawn at (60, 30) so it wraps to the top.
+    let mut e = machine(&[0x603C, 0x611E, 0x6201, 0xF229, 0xD015]);
+    steps(&mut e, 5);
+    let fb = e.framebuffer();
+    assert_eq!(fb.len(), e.screen().cols() * e.screen().rows());
+    let from_fb: Vec<(usize, usize)> = (0..fb.len())
+        .filter(|&n| fb[n])
+        .map(|n| (n / 64, n % 64))
+        .collect();
+    let lit: Vec<(usize, usize)> = e.screen().lit_pixels().collect();
+    assert_eq!(lit, from_fb);
+    // Top row first, so the rows that wrapped come before the ones they continue.
+    let expected = [
+        (0, 62),
+        (1, 62),
+        (2, 61),
+        (2, 62),
+        (2, 63),
+        (30, 62),
+        (31, 61),
+        (31, 62),
+    ];
+    assert_eq!(lit, expected);
+}
+
+// Without std there's no thread generator, so unseeded machines start from seed 0 and
+// draw the same numbers every run.
+#[cfg(not(feature = "std"))]
+#[test]
+fn without_std_unseeded_draws_start_from_seed_0() {
+    let program = [0xC0FF, 0xC1FF, 0xC2FF, 0xC3FF];
+    let mut unseeded = machine(&program);
+    let mut seeded = machine(&program);
+    seeded.seed_rng(0);
+    steps(&mut unseeded, 4);
+    steps(&mut seeded, 4);
+    assert_eq!(unseeded.registers(), seeded.registers());
+}
+
+// Leaves registers, I, timers, the stack and the display all changed.
+fn dirtied_machine() -> Chip8 {
+    let mut e = machine(&[0x6A12, 0xA000, 0xD005, 0xA123, 0xFA15, 0x2300]);
+    steps(&mut e, 6);
+    assert_eq!(e.stack_depth(), 1);
+    e
+}
+
+#[test]
+fn loading_starts_from_a_clean_machine() {
+    let mut e = dirtied_machine();
+    e.load(&rom(&[0x00E0])).unwrap();
+    assert_eq!(e.registers(), &[0; 16]);
+    assert_eq!(e.index(), 0);
+    assert_eq!(e.pc(), 0x200);
+    assert_eq!(e.timers(), Timers::default());
+    assert_eq!(e.stack_depth(), 0);
+    assert_eq!(e.screen().lit_pixels().count(), 0);
+    // The old program is gone, not just overwritten where the new one is.
+    assert_eq!(e.memory()[0x202], 0);
+}
+
+#[test]
+fn loading_can_preserve_the_machine_state() {
+    let mut e = dirtied_machine();
+    e.set_preserve_state(true);
+    e.load(&rom(&[0x00E0])).unwrap();
+    assert_eq!(e.registers()[0xA], 0x12);
+    assert_eq!(e.index(), 0x123);
+    assert_eq!(e.stack_depth(), 1);
+    assert_ne!(e.screen().lit_pixels().count(), 0);
+    assert_eq!(e.peek_opcode_at(0x200), 0x00E0);
+}
+
+// Clears what `dirtied_machine` changed, apart from memory.
+fn assert_restarted(e: &Chip8) {
+    assert_eq!(e.registers(), &[0; 16]);
+    assert_eq!(e.index(), 0);
+    assert_eq!(e.pc(), 0x200);
+    assert_eq!(e.timers(), Timers::default());
+    assert_eq!(e.stack_depth(), 0);
+    assert_eq!(e.screen().lit_pixels().count(), 0);
+}
+
+#[test]
+fn reset_restarts_the_program_on_a_clean_machine() {
+    let mut e = dirtied_machine();
+    // As a program writing over itself and its data would.
+    e.memory[0x202] = 0xFF;
+    e.memory[0x300] = 0xAB;
+    e.reset().unwrap();
+    assert_restarted(&e);
+    assert_eq!(e.peek_opcode_at(0x202), 0xA000);
+    assert_eq!(e.memory()[0x300], 0);
+    // And runs exactly as it did the first time.
+    steps(&mut e, 6);
+    assert_eq!(e.state_hash(), dirtied_machine().state_hash());
+}
+
+#[test]
+fn soft_reset_keeps_memory_as_the_program_left_it() {
+    let mut e = dirtied_machine();
+    e.memory[0x202] = 0xFF;
+    e.memory[0x300] = 0xAB;
+    e.soft_reset();
+    assert_restarted(&e);
+    assert_eq!(e.peek_opcode_at(0x202), 0xFF00);
+    assert_eq!(e.memory()[0x300], 0xAB);
+}
+
+#[test]
+fn reset_ignores_preserve_state_and_needs_a_program() {
+    let mut e = dirtied_machine();
+    e.set_preserve_state(true);
+    e.reset().unwrap();
+    assert_restarted(&e);
+    // Still set for the next load.
+    steps(&mut e, 6);
+    e.load(&rom(&[0x00E0])).unwrap();
+    assert_eq!(e.registers()[0xA], 0x12);
+
+    let mut empty = Chip8::new();
+    let hash = empty.state_hash();
+    assert!(matches!(empty.reset(), Err(Chip8Error::EmptyRom)));
+    assert_eq!(empty.state_hash(), hash);
+}
+
+// Fx55 storing V0 = 0xAB at `addr` under `protection`.
+fn store_at(addr: u16, protection: LowMemoryProtection) -> (Chip8, Result<(), Chip8Error>) {
+    let mut e = machine(&[0x60AB, 0xA000 | addr, 0xF055]);
+    e.set_protect_low_memory(protection);
+    steps(&mut e, 2);
+    let result = e.step().map(|_| ());
+    (e, result)
+}
+
+#[test]
+fn writes_below_0x200_are_an_error_by_default() {
+    let (e, result) = store_at(0x1FF, LowMemoryProtection::default());
+    assert!(matches!(
+        result,
+        Err(Chip8Error::ProtectedWrite {
+            pc: 0x204,
+            addr: 0x1FF
+        })
+    ));
+    assert_eq!(e.memory()[0x1FF], 0);
+    let (e, result) = store_at(0x200, LowMemoryProtection::default());
+    result.unwrap();
+    assert_eq!(e.memory()[0x200], 0xAB);
+    // Fx33 is protected the same way.
+    let mut e = machine(&[0x60FF, 0xA1FF, 0xF033]);
+    steps(&mut e, 2);
+    assert!(e.step().is_err());
+}
+
+#[test]
+fn writes_below_0x200_can_be_skipped() {
+    let (e, result) = store_at(0x1FF, LowMemoryProtection::Warn);
+    result.unwrap();
+    assert_eq!(e.memory()[0x1FF], 0);
+    assert_eq!(e.pc(), 0x206);
+    let (e, _) = store_at(0x200, LowMemoryProtection::Warn);
+    assert_eq!(e.memory()[0x200], 0xAB);
+}
+
+#[test]
+fn writes_below_0x200_can_be_allowed() {
+    let (e, result) = store_at(0x1FF, LowMemoryProtection::Off);
+    result.unwrap();
+    assert_eq!(e.memory()[0x1FF], 0xAB);
+}
+
+#[test]
+fn low_memory_protection_defaults_to_the_variant() {
+    let expected = [
+        (Variant::Chip8, LowMemoryProtection::Error),
+        (Variant::Hires, LowMemoryProtection::Off),
+        (Variant::MegaChip, LowMemoryProtection::Off),
+    ];
+    for (variant, protection) in expected {
+        let mut e = Chip8::new();
+        e.set_variant(variant);
+        assert_eq!(e.quirks().low_memory, protection, "{:?}", variant);
+        e.load(&rom(&[0x60AB, 0xA1FF, 0xF055])).unwrap();
+        steps(&mut e, 2);
+        assert_eq!(e.step().is_ok(), protection == LowMemoryProtection::Off);
+    }
+    // Switching back turns it on again.
+    let mut e = Chip8::new();
+    e.set_variant(Variant::Hires);
+    e.set_variant(Variant::Chip8);
+    assert_eq!(e.quirks().low_memory, LowMemoryProtection::Error);
+}
+
+#[test]
+fn reads_below_0x200_stay_allowed() {
+    let mut e = machine(&[0xA000, 0xF065]);
+    steps(&mut e, 2);
+    assert_eq!(e.registers()[0], 0xF0);
+}
@@ -0,0 +1,177 @@
+use super::Instruction;
+
+// Machine cycles the VIP has per 60Hz frame: a 1.76MHz clock at 8 clocks per cycle.
+pub const VIP_CYCLES_PER_FRAME: i64 = 3668;
+// Every instruction pays for the interpreter's fetch and dispatch loop.
+const VIP_FETCH_CYCLES: u32 = 40;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Timing {
+    // A fixed number of instructions per frame, whatever they are.
+    #[default]
+    Fixed,
+    // Each instruction costs what it did on the COSMAC VIP interpreter and a frame
+    // holds VIP_CYCLES_PER_FRAME machine cycles, so heavy frames run fewer instructions.
+    // Dxyn also waits for the vertical blank, so at most one draw happens per frame.
+    Vip,
+}
+
+// Approximate machine cycles `ins` took on the VIP. The data dependent costs (sprite
+// alignment, BCD digits) are averaged rather than modelled exactly.
+pub fn vip_cycles(ins: &Instruction, v: &[u8; 16]) -> u32 {
+    let execute = match *ins {
+        Instruction::Cls => 3078,
+        Instruction::Ret | Instruction::Exit => 10,
+        // Machine code runs for however long it likes, and the SCHIP instructions
+        // don't exist on the VIP, so these only pay for the fetch.
+        Instruction::Sys(_)
+        | Instruction::ScrollDown(_)
+        | Instruction::ScrollRight
+        | Instruction::ScrollLeft
+        | Instruction::Lores
+        | Instruction::Hires => 0,
+        Instruction::Jp(_) => 12,
+        Instruction::Call(_) => 26,
+        Instruction::SeByte(..) | Instruction::SneByte(..) => 14,
+        Instruction::SeReg(..) | Instruction::SneReg(..) => 18,
+        Instruction::LdByte(..) => 6,
+        Instruction::AddByte(..) => 10,
+        Instruction::LdReg(..)
+        | Instruction::Or(..)
+        | Instruction::And(..)
+        | Instruction::Xor(..)
+        | Instruction::AddReg(..)
+        | Instruction::Sub(..)
+        | Instruction::Shr(..)
+        | Instruction::Subn(..)
+        | Instruction::Shl(..) => 44,
+        Instruction::LdI(_) => 12,
+        Instruction::JpV0(_) => 22,
+        Instruction::Rnd(..) => 36,
+        Instruction::Drw(x, _, n) => {
+            // Sprites not on a byte boundary get shifted across two bytes per row.
+            let per_row = if v[x] & 7 == 0 { 46 } else { 66 };
+            68 + per_row * n as u32
+        }
+        Instruction::Skp(_) | Instruction::Sknp(_) => 14,
+        Instruction::LdVxDt(_) | Instruction::LdDtVx(_) | Instruction::LdStVx(_) => 10,
+        Instruction::LdVxK(_) => 19,
+        Instruction::AddI(_) => 16,
+        Instruction::LdF(_) => 20,
+        Instruction::LdB(_) => 152,
+        Instruction::LdIVx(x) | Instruction::LdVxI(x) => 14 + 14 * (x as u32 + 1),
+    };
+    VIP_FETCH_CYCLES + execute
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::machine;
+    use super::super::Chip8;
+    use super::*;
+
+    fn vip_machine(program: &[u16]) -> Chip8 {
+        let mut e = machine(program);
+        e.set_timing(Timing::Vip);
+        e
+    }
+
+    // Instructions run in each of `frames` frames.
+    fn frame_instructions(e: &mut Chip8, frames: usize) -> Vec<u64> {
+        (0..frames)
+            .map(|_| {
+                e.run_frame().unwrap();
+                e.last_frame_instructions()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn costs_include_the_fetch() {
+        let v = [0; 16];
+        assert_eq!(vip_cycles(&Instruction::LdByte(0, 1), &v), 46);
+        assert_eq!(vip_cycles(&Instruction::Jp(0x200), &v), 52);
+        assert_eq!(vip_cycles(&Instruction::Cls, &v), 3118);
+        assert_eq!(vip_cycles(&Instruction::LdIVx(0xF), &v), 40 + 14 + 14 * 16);
+    }
+
+    #[test]
+    fn unaligned_sprites_cost_more() {
+        let mut v = [0; 16];
+        let aligned = vip_cycles(&Instruction::Drw(0, 1, 5), &v);
+        v[0] = 3;
+        let unaligned = vip_cycles(&Instruction::Drw(0, 1, 5), &v);
+        assert_eq!((aligned, unaligned), (40 + 68 + 46 * 5, 40 + 68 + 66 * 5));
+    }
+
+    #[test]
+    fn cheap_loops_run_until_the_frame_is_spent() {
+        // 46 + 52 cycles a pass, so 3668 cycles hold 37 passes and one more load.
+        let mut e = vip_machine(&[0x6001, 0x1200]);
+        assert_eq!(frame_instructions(&mut e, 3), [75, 75, 75]);
+    }
+
+    #[test]
+    fn draws_take_the_rest_of_the_frame() {
+        // The first draw waits out the whole first frame, after that every frame
+        // runs the jump and then waits to draw again.
+        let mut e = vip_machine(&[0xD005, 0x1200]);
+        assert_eq!(frame_instructions(&mut e, 4), [1, 2, 2, 2]);
+        assert_eq!(e.stats().draws, 4);
+
+        let mut e = machine(&[0xD005, 0x1200]);
+        e.set_cycles_per_frame(20);
+        assert_eq!(frame_instructions(&mut e, 2), [20, 20]);
+        assert_eq!(e.stats().draws, 20);
+    }
+
+    #[test]
+    fn expensive_instructions_leave_frames_short() {
+        // 3118 + 52 cycles a pass, so each frame only starts one or two passes.
+        let mut e = vip_machine(&[0x00E0, 0x1200]);
+        assert_eq!(frame_instructions(&mut e, 4), [3, 2, 2, 2]);
+    }
+
+    // Draw cycles reported for each of `frames` frames.
+    fn frame_draw_cycles(e: &mut Chip8, frames: usize) -> Vec<u64> {
+        (0..frames)
+            .map(|_| {
+                e.run_frame().unwrap();
+                e.last_frame_draw_cycles()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn draw_cycles_count_the_wait_and_the_sprite() {
+        // A 15 row aligned sprite costs 40 + 68 + 46 * 15 = 798 cycles. The first draw
+        // waits out the whole first frame, later ones the frame less the jump.
+        let mut e = vip_machine(&[0xD00F, 0x1200]);
+        assert_eq!(frame_draw_cycles(&mut e, 3), [3668 + 798, 3616, 3616]);
+        // Unaligned it costs 40 + 68 + 66 * 15 = 1098, after the 46 cycle load.
+        let mut e = vip_machine(&[0x6003, 0xD00F, 0x1202]);
+        assert_eq!(frame_draw_cycles(&mut e, 2), [3622 + 1098, 3616]);
+    }
+
+    #[test]
+    fn large_sprites_eat_into_the_next_frame() {
+        // After the draw, passes of 7001 and the jump cost 50 + 52 cycles. The 798
+        // cycles the sprite overran leave room for 28 passes and one more add, where
+        // a 1 row sprite's 154 leave room for 34 and one more.
+        let mut e = vip_machine(&[0xD00F, 0x7001, 0x1202]);
+        assert_eq!(frame_instructions(&mut e, 2), [1, 57]);
+        assert_eq!(e.last_frame_draw_cycles(), 0);
+        let mut e = vip_machine(&[0xD001, 0x7001, 0x1202]);
+        assert_eq!(frame_instructions(&mut e, 2), [1, 69]);
+    }
+
+    #[test]
+    fn fixed_timing_counts_no_draw_cycles() {
+        let mut e = machine(&[0xD00F, 0x1200]);
+        assert_eq!(frame_draw_cycles(&mut e, 2), [0, 0]);
+        let mut e = vip_machine(&[0xD00F, 0x1200]);
+        e.run_frame().unwrap();
+        e.reset().unwrap();
+        assert_eq!(e.last_frame_draw_cycles(), 0);
+    }
+}
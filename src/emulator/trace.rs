@@ -0,0 +1,111 @@
+use super::{Chip8, Chip8Error, Instruction};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// Lines written between flushes, so a crash loses at most this much of the trace.
+const FLUSH_EVERY: u32 = 1024;
+
+pub(super) struct Trace {
+    writer: BufWriter<File>,
+    pending: u32,
+}
+
+impl Chip8 {
+    // Appends `pc opcode mnemonic` for every executed instruction to `path`, until
+    // `stop_trace`. Replaces any trace already running.
+    pub fn trace_to_file(&mut self, path: &str) -> Result<(), Chip8Error> {
+        self.stop_trace();
+        let file = File::options().create(true).append(true).open(path)?;
+        self.trace = Some(Trace {
+            writer: BufWriter::new(file),
+            pending: 0,
+        });
+        Ok(())
+    }
+
+    pub fn stop_trace(&mut self) {
+        if let Some(mut trace) = self.trace.take() {
+            if let Err(e) = trace.writer.flush() {
+                eprintln!("Error writing the trace: {}", e);
+            }
+        }
+    }
+
+    pub(super) fn write_trace(&mut self, pc: u16, opcode: u16) {
+        let Some(trace) = self.trace.as_mut() else {
+            return;
+        };
+        let result = match Instruction::decode(opcode) {
+            Some(ins) => writeln!(trace.writer, "{:#05X} {:04X} {}", pc, opcode, ins),
+            None => writeln!(trace.writer, "{:#05X} {:04X} ???", pc, opcode),
+        };
+        trace.pending += 1;
+        let result = result.and_then(|_| {
+            if trace.pending < FLUSH_EVERY {
+                return Ok(());
+            }
+            trace.pending = 0;
+            trace.writer.flush()
+        });
+        // A failing trace shouldn't take the emulation down with it.
+        if let Err(e) = result {
+            eprintln!("Error writing the trace, stopping it: {}", e);
+            self.trace = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::{machine, steps};
+    use std::{env, fs};
+
+    fn trace_path(name: &str) -> String {
+        let path = env::temp_dir().join(format!("chip8-trace-{}-{}.txt", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn traced_instructions_are_written_in_order() {
+        let path = trace_path("order");
+        let mut e = machine(&[0x6A12, 0xA300, 0x1200]);
+        e.trace_to_file(&path).unwrap();
+        steps(&mut e, 4);
+        e.stop_trace();
+        // Untraced from here on.
+        steps(&mut e, 1);
+        let trace = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            trace.lines().collect::<Vec<_>>(),
+            [
+                "0x200 6A12 LD VA, 0x12",
+                "0x202 A300 LD I, 0x300",
+                "0x204 1200 JP 0x200",
+                "0x200 6A12 LD VA, 0x12",
+            ]
+        );
+    }
+
+    #[test]
+    fn traces_append_to_an_existing_file() {
+        let path = trace_path("append");
+        fs::write(&path, "earlier\n").unwrap();
+        let mut e = machine(&[0x00E0]);
+        e.trace_to_file(&path).unwrap();
+        steps(&mut e, 1);
+        // Dropping the machine flushes the trace too.
+        drop(e);
+        let trace = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(trace, "earlier\n0x200 00E0 CLS\n");
+    }
+
+    #[test]
+    fn unwritable_paths_fail_to_start() {
+        let mut e = machine(&[0x00E0]);
+        let dir = env::temp_dir().join("chip8-no-such-dir").join("trace.txt");
+        assert!(e.trace_to_file(&dir.to_string_lossy()).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+// Memory access heatmap drawn over the display, one cell per byte of the first 4K
+// in a 64x64 grid: blue for fetches, green for reads, red for writes. Brightness
+// follows the log of the count relative to the busiest address.
+use chip8::emulator::AccessHeatmap;
+use macroquad::prelude::*;
+
+const GRID: usize = 64;
+const CELL: f32 = 4.0;
+
+pub struct HeatmapView {
+    pub visible: bool,
+    pixels: Vec<u8>,
+}
+
+fn intensities(counts: &[u32]) -> impl Iterator<Item = u8> + '_ {
+    let max = counts.iter().take(GRID * GRID).max().copied().unwrap_or(0);
+    let scale = ((max as f32) + 1.0).ln().max(1.0);
+    counts
+        .iter()
+        .map(move |&c| (((c as f32) + 1.0).ln() / scale * 255.0) as u8)
+}
+
+impl HeatmapView {
+    pub fn new(visible: bool) -> Self {
+        HeatmapView {
+            visible,
+            pixels: Vec::with_capacity(GRID * GRID * 4),
+        }
+    }
+
+    pub fn draw(&mut self, heatmap: &AccessHeatmap) {
+        if !self.visible {
+            return;
+        }
+        self.pixels.clear();
+        let channels = intensities(&heatmap.writes)
+            .zip(intensities(&heatmap.reads))
+            .zip(intensities(&heatmap.fetches))
+            .take(GRID * GRID);
+        for ((r, g), b) in channels {
+            self.pixels.extend([r, g, b, 0xFF]);
+        }
+        self.pixels.resize(GRID * GRID * 4, 0);
+
+        let texture = Texture2D::from_rgba8(GRID as u16, GRID as u16, &self.pixels);
+        texture.set_filter(FilterMode::Nearest);
+        let size = GRID as f32 * CELL;
+        let x = screen_width() - size - 10.0;
+        draw_texture_ex(
+            &texture,
+            x,
+            10.0,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(vec2(size, size)),
+                ..Default::default()
+            },
+        );
+        draw_rectangle_lines(x, 10.0, size, size, 2.0, GRAY);
+        let legend = [
+            ("fetch", BLUE),
+            ("read", GREEN),
+            ("write", RED),
+            ("F11 resets", WHITE),
+        ];
+        let mut lx = x;
+        for (label, color) in legend {
+            draw_text(label, lx, size + 30.0, 20.0, color);
+            lx += measure_text(label, None, 20, 1.0).width + 12.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intensity_follows_the_log_of_the_count() {
+        let counts = [0, 1, 15, 255];
+        let levels: Vec<u8> = intensities(&counts).collect();
+        assert_eq!(levels[0], 0);
+        assert_eq!(levels[3], 255);
+        // log2(16) is half of log2(256).
+        assert_eq!(levels[2], 127);
+        assert!(levels[1] > 0 && levels[1] < levels[2]);
+    }
+
+    #[test]
+    fn untouched_memory_stays_dark() {
+        assert!(intensities(&[0; 8]).all(|level| level == 0));
+    }
+}
@@ -1,69 +1,452 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod runner;
+
+// The parts of the std prelude the core uses, so it reads the same built with or
+// without std.
+mod prelude {
+    pub use alloc::boxed::Box;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec::Vec;
+    pub use alloc::{format, vec};
+}
+
 #[allow(non_snake_case)]
 pub mod emulator {
-    use macroquad::input;
-    use macroquad::prelude::*;
+    mod audio;
+    #[cfg(feature = "audio")]
+    mod beeper;
+    #[cfg(feature = "macroquad")]
+    mod bindings;
+    mod budget;
+    mod builder;
+    #[cfg(feature = "std")]
+    mod clock;
+    mod debugger;
+    mod detect;
+    mod display;
+    #[cfg(feature = "std")]
+    mod dump;
+    mod error;
+    mod font;
+    #[cfg(feature = "macroquad")]
+    mod frontend;
+    mod heatmap;
+    mod hooks;
+    mod i_history;
+    mod idle;
+    mod input;
+    mod input_script;
+    mod instruction;
+    mod megachip;
+    mod palette;
+    mod profile;
+    mod quirks;
+    mod random;
+    mod replay;
+    mod rewind;
+    #[cfg(feature = "lua")]
+    mod script;
+    mod self_test;
+    mod state;
+    mod stats;
+    #[cfg(test)]
+    mod test_util;
+    #[cfg(test)]
+    mod tests;
+    mod timing;
+    #[cfg(feature = "std")]
+    mod trace;
+
+    pub use audio::AudioBackend;
+    #[cfg(feature = "audio")]
+    pub use beeper::Beeper;
+    #[cfg(feature = "macroquad")]
+    pub use bindings::KeyBindings;
+    pub use budget::RunStatus;
+    pub use builder::Chip8Builder;
+    #[cfg(feature = "std")]
+    pub use clock::{ClockSkew, FrameClock, TimingStats};
+    pub use debugger::{RegisterWatch, StopReason};
+    pub use detect::{detect_variant, Detection};
+    pub use display::DisplayBackend;
+    #[cfg(feature = "std")]
+    pub use dump::{DumpTarget, RegisterFormat};
+    pub use error::{Chip8Error, EmulatorError};
+    pub use font::{Font, CUSTOM_FONT, STANDARD_FONT};
+    #[cfg(feature = "macroquad")]
+    pub use frontend::{hex_from_keycode, keycode_from_hex, poll_host_keys, MacroquadDisplay};
+    pub use heatmap::AccessHeatmap;
+    pub use hooks::{
+        DeepStackHook, DrawHook, InstructionHook, KeyWaitHook, MisalignedJumpHook, OverflowHook,
+        PcHook, SoundHook,
+    };
+    pub use input::InputSource;
+    pub use input_script::{AssertionFailure, InputScript};
+    pub use instruction::{supported_opcodes, Instruction, OpcodeInfo};
+    pub use megachip::{BlendMode, DigitizedSound};
+    pub use palette::Palette;
+    pub use profile::{HotInstruction, HotRange, ProfileReport};
+    pub use quirks::QuirkConfig;
+    pub use random::RandomSource;
+    pub use replay::{behaviorally_equal, first_divergence, InputLog, ReplayOutcome};
+    #[cfg(feature = "lua")]
+    pub use script::Script;
+    pub use self_test::{run_self_test, SelfTestReport, SelfTestResult};
+    pub use state::Thumbnail;
+    pub use stats::Stats;
+    pub use timing::Timing;
+
+    use crate::prelude::*;
+    use alloc::collections::VecDeque;
+    use debugger::Debugger;
+    use hooks::Hooks;
+    use idle::IdleDetector;
+    use megachip::MegaChip;
+    #[cfg(feature = "std")]
     use std::fs::File;
-    use std::io;
-    use std::io::prelude::*;
-
-    fn nibbles(u: u16) -> (u16, u16, u16, u16) {
-        (
-            (u & 0xF000) >> 12,
-            (u & 0x0F00) >> 8,
-            (u & 0x00F0) >> 4,
-            u & 0x000F,
-        )
-    }
+    #[cfg(feature = "std")]
+    use std::io::{self, prelude::*};
+    #[cfg(feature = "std")]
+    use trace::Trace;
 
-    pub fn keycode_from_hex(x: u8) -> input::KeyCode {
-        match x {
-            0 => input::KeyCode::Key0,
-            1 => input::KeyCode::Key1,
-            2 => input::KeyCode::Key2,
-            3 => input::KeyCode::Key3,
-            4 => input::KeyCode::Key4,
-            5 => input::KeyCode::Key5,
-            6 => input::KeyCode::Key6,
-            7 => input::KeyCode::Key7,
-            8 => input::KeyCode::Key8,
-            9 => input::KeyCode::Key9,
-            10 => input::KeyCode::A,
-            11 => input::KeyCode::B,
-            12 => input::KeyCode::C,
-            13 => input::KeyCode::D,
-            14 => input::KeyCode::E,
-            15 => input::KeyCode::F,
-            _ => input::KeyCode::Z,
-        }
-    }
+    const HISTORY_LEN: usize = 16;
+    const STACK_DEPTH: usize = 16;
+    // Display columns and rows after SCHIP's 00FF.
+    const SCHIP_HIRES: (usize, usize) = (128, 64);
+    const FUZZ_STEPS: usize = 1024;
+    const DEFAULT_FRAME_RATE: u32 = 60;
+    // Where programs load and start unless configured otherwise.
+    const PROGRAM_START: u16 = 0x200;
+    pub const MIN_SPEED: f32 = 0.1;
+    pub const MAX_SPEED: f32 = 10.0;
 
-    #[derive(Default)]
-    struct Timer {
-        sound: u8,
-        delay: u8,
+    // Both count down at 60Hz. See `Chip8::timers`.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct Timers {
+        pub sound: u8,
+        pub delay: u8,
     }
 
     #[derive(Default)]
     struct Register {
         v: [u8; 16],
-        i: u16,
+        // 16 bits wide, except under MegaChip where 01nn loads 24 bit addresses.
+        i: u32,
+    }
+    // Called for the keypad state during a frame, see `Chip8::set_key_sampler`.
+    pub type KeySampler = Box<dyn InputSource>;
+
+    // When a frame reads the host keys. `PerFrame` is the default: every instruction in
+    // a frame sees the same keypad, so a run depends only on the per-frame input, which
+    // is what input scripts and replays record. `PerCycle` samples before every
+    // instruction, so a key pressed mid-frame is seen up to a frame sooner, at the cost
+    // of the result depending on exactly when in the frame it arrived. Only hosts whose
+    // input changes while a frame runs, such as `--threaded`, see a difference, since
+    // macroquad updates its key state between host frames.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum InputPolling {
+        #[default]
+        PerFrame,
+        PerCycle,
+    }
+
+    // What `step` executed.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct StepInfo {
+        pub pc: u16,
+        pub opcode: u16,
+        // "MEGA" for MegaChip's own instructions.
+        pub mnemonic: &'static str,
+        // Machine cycles it took on the VIP, see `Timing::Vip`, whatever the timing.
+        // 0 for MegaChip's own instructions.
+        pub cycles: u32,
+    }
+
+    // Entry point for fuzzing the interpreter: loads `memory` as the whole address
+    // space and runs a bounded number of instructions from `initial_pc`. Any input
+    // must end in `Ok` or a `Chip8Error`, never a panic.
+    pub fn fuzz_step(memory: &[u8], initial_pc: u16) -> Result<(), Chip8Error> {
+        let mut chip8 = Chip8::new();
+        // Past 4KB, a MegaChip machine with memory beyond the reach of pc.
+        if memory.len() > chip8.memory.len() {
+            chip8.set_variant(Variant::MegaChip);
+        }
+        let n = memory.len().min(chip8.memory.len());
+        chip8.memory[..n].copy_from_slice(&memory[..n]);
+        chip8.pc = initial_pc;
+        for _ in 0..FUZZ_STEPS {
+            chip8.step()?;
+        }
+        Ok(())
+    }
+
+    // `amount * speed` rounded down, with the fraction carried over to the next call so
+    // that nothing is lost over many frames.
+    fn scaled(amount: f32, speed: f32, carry: &mut f32) -> u32 {
+        let total = amount * speed + *carry;
+        // Truncating is rounding down here, as neither can be negative.
+        let whole = total as u32;
+        *carry = total - whole as f32;
+        whole
+    }
+
+    #[cfg(feature = "std")]
+    pub fn read_rom(file_name: &str) -> Result<Vec<u8>, Chip8Error> {
+        let mut f = File::open(file_name).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Chip8Error::RomNotFound(file_name.to_string()),
+            _ => Chip8Error::Io(e),
+        })?;
+        let mut buffer = Vec::new();
+
+        f.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    // How `Screen::draw` shows pixels that were just turned off. Programs that redraw
+    // sprites every frame flicker on real hardware, and some use that on purpose as
+    // extra shades, so `Accurate` is the default.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum FlickerMode {
+        // Off pixels are dark straight away.
+        #[default]
+        Accurate,
+        // Off pixels fade out over a few frames, like a phosphor display, which hides
+        // flicker.
+        Persistence,
     }
+
+    // Brightness kept by an unlit pixel from one frame to the next under persistence.
+    const AFTERGLOW_DECAY: f32 = 0.6;
+
+    #[derive(Clone)]
     pub struct Screen {
-        pixels: [bool; 2048],
+        pixels: Vec<bool>,
         cols: usize,
         rows: usize,
-        pixel_size: usize,
+        pixel_w: usize,
+        pixel_h: usize,
+        palette: Palette,
+        flicker: FlickerMode,
+        // Outline every pixel cell, to check sprite placement.
+        grid: bool,
+        // Per pixel brightness under persistence, 1.0 while lit.
+        afterglow: Vec<f32>,
     }
     impl Screen {
         pub fn new() -> Self {
+            Self::with_size(64, 32)
+        }
+
+        pub fn with_size(cols: usize, rows: usize) -> Self {
             Screen {
-                pixels: [false; 2048],
-                cols: 64,
-                rows: 32,
-                pixel_size: 24,
+                pixels: vec![false; cols * rows],
+                cols,
+                rows,
+                pixel_w: 24,
+                pixel_h: 24,
+                palette: Palette::default(),
+                flicker: FlickerMode::Accurate,
+                grid: false,
+                afterglow: vec![0.0; cols * rows],
+            }
+        }
+
+        pub fn cols(&self) -> usize {
+            self.cols
+        }
+
+        pub fn rows(&self) -> usize {
+            self.rows
+        }
+
+        pub fn get(&self, row: usize, col: usize) -> bool {
+            self.pixels[(row % self.rows) * self.cols + col % self.cols]
+        }
+
+        // Row by row, cols * rows of them.
+        pub fn pixels(&self) -> &[bool] {
+            &self.pixels
+        }
+
+        // (row, col) of every lit pixel, top row first.
+        pub fn lit_pixels(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+            let cols = self.cols;
+            self.pixels
+                .iter()
+                .enumerate()
+                .filter(|(_, &p)| p)
+                .map(move |(n, _)| (n / cols, n % cols))
+        }
+
+        // How bright to draw a pixel: 1.0 while lit, and under persistence fading
+        // towards 0.0 over the frames after it went off.
+        pub fn brightness(&self, row: usize, col: usize) -> f32 {
+            let n = (row % self.rows) * self.cols + col % self.cols;
+            match self.flicker {
+                _ if self.pixels[n] => 1.0,
+                FlickerMode::Accurate => 0.0,
+                FlickerMode::Persistence => self.afterglow[n],
             }
         }
 
+        pub fn clear(&mut self) {
+            self.pixels.fill(false);
+        }
+
+        // Switches resolution, which also clears the display.
+        pub fn resize(&mut self, cols: usize, rows: usize) {
+            self.cols = cols;
+            self.rows = rows;
+            self.pixels = vec![false; cols * rows];
+            self.afterglow = vec![0.0; cols * rows];
+        }
+
+        pub fn grid(&self) -> bool {
+            self.grid
+        }
+
+        // Only changes how `draw` shows the display, never the pixels.
+        pub fn set_grid(&mut self, grid: bool) {
+            self.grid = grid;
+        }
+
+        // Moves the picture down `n` rows, blank rows coming in at the top.
+        pub fn scroll_down(&mut self, n: usize) {
+            let n = n.min(self.rows) * self.cols;
+            let len = self.pixels.len();
+            self.pixels.copy_within(..len - n, n);
+            self.pixels[..n].fill(false);
+        }
+
+        // Moves the picture `n` columns left, or right if `n` is negative.
+        pub fn scroll_across(&mut self, n: isize) {
+            let shift = n.unsigned_abs().min(self.cols);
+            for row in self.pixels.chunks_mut(self.cols) {
+                if n > 0 {
+                    row.copy_within(shift.., 0);
+                    row[self.cols - shift..].fill(false);
+                } else {
+                    row.copy_within(..self.cols - shift, shift);
+                    row[..shift].fill(false);
+                }
+            }
+        }
+
+        pub fn flicker_mode(&self) -> FlickerMode {
+            self.flicker
+        }
+
+        pub fn set_flicker_mode(&mut self, mode: FlickerMode) {
+            self.flicker = mode;
+            self.afterglow.fill(0.0);
+        }
+
+        // Ages the afterglow by one emulated frame.
+        fn fade(&mut self) {
+            if self.flicker == FlickerMode::Accurate {
+                return;
+            }
+            for (glow, &on) in self.afterglow.iter_mut().zip(&self.pixels) {
+                *glow = if on { 1.0 } else { *glow * AFTERGLOW_DECAY };
+                if *glow < 0.05 {
+                    *glow = 0.0;
+                }
+            }
+        }
+
+        // FNV-1a over the dimensions and pixels, for comparing frames.
+        pub fn hash(&self) -> u64 {
+            let mut hash: u64 = 0xcbf29ce484222325;
+            let dims = [self.cols as u64, self.rows as u64];
+            let bytes = dims
+                .iter()
+                .flat_map(|d| d.to_le_bytes())
+                .chain(self.pixels.iter().map(|&p| p as u8));
+            for byte in bytes {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+            hash
+        }
+
+        // One line per row, `#` for lit pixels and `.` for dark ones.
+        pub fn to_text(&self) -> String {
+            let mut text = String::with_capacity((self.cols + 1) * self.rows);
+            for row in self.pixels.chunks(self.cols) {
+                text.extend(row.iter().map(|&p| if p { '#' } else { '.' }));
+                text.push('\n');
+            }
+            text
+        }
+
+        // Tightly packed RGBA, cols * rows * 4 bytes, for renderers other than
+        // macroquad's. Reuses `out`'s allocation.
+        pub fn render_to_rgba(&self, fg: [u8; 4], bg: [u8; 4], out: &mut Vec<u8>) {
+            self.render_to_rgba_scaled(fg, bg, 1, out);
+        }
+
+        // As `render_to_rgba` with every pixel drawn as a `scale` x `scale` block.
+        pub fn render_to_rgba_scaled(
+            &self,
+            fg: [u8; 4],
+            bg: [u8; 4],
+            scale: usize,
+            out: &mut Vec<u8>,
+        ) {
+            out.clear();
+            out.reserve(self.pixels.len() * scale * scale * 4);
+            for row in self.pixels.chunks(self.cols) {
+                for _ in 0..scale {
+                    for &p in row {
+                        let color = if p { fg } else { bg };
+                        for _ in 0..scale {
+                            out.extend_from_slice(&color);
+                        }
+                    }
+                }
+            }
+        }
+
+        // As `render_to_rgba` into a buffer the caller owns, such as a mapped texture.
+        // Returns false, leaving `buf` alone, if it is shorter than cols * rows * 4.
+        pub fn render_into(&self, buf: &mut [u8], fg: [u8; 4], bg: [u8; 4]) -> bool {
+            let Some(buf) = buf.get_mut(..self.pixels.len() * 4) else {
+                return false;
+            };
+            for (out, &p) in buf.chunks_exact_mut(4).zip(&self.pixels) {
+                out.copy_from_slice(if p { &fg } else { &bg });
+            }
+            true
+        }
+
+        // One bit per pixel, row by row, most significant bit first like sprite data.
+        // Every display width is a multiple of 8, so rows start on a byte. Appends to
+        // `out`, which save states write the screen with.
+        pub fn pack_bits(&self, out: &mut Vec<u8>) {
+            out.extend(self.pixels.chunks(8).map(|bits| {
+                bits.iter()
+                    .enumerate()
+                    .fold(0, |byte, (n, &p)| byte | (p as u8) << (7 - n))
+            }));
+        }
+
+        pub fn set_pixel_size(&mut self, pixel_w: usize, pixel_h: usize) {
+            self.pixel_w = pixel_w;
+            self.pixel_h = pixel_h;
+        }
+
+        pub fn palette(&self) -> Palette {
+            self.palette
+        }
+
+        pub fn set_palette(&mut self, palette: Palette) {
+            self.palette = palette;
+        }
+
         pub fn set(&mut self, row: usize, col: usize, val: bool) -> u8 {
             let mut ans = 0;
 
@@ -75,162 +458,981 @@ pub mod emulator {
             self.pixels[row_ * self.cols + col_] ^= val;
             ans
         }
-
-        pub fn draw(&self) {
-            for row in 0..self.rows {
-                for col in 0..self.cols {
-                    if self.pixels[row * self.cols + col] {
-                        draw_rectangle(
-                            (col * self.pixel_size) as f32,
-                            (row * self.pixel_size) as f32,
-                            (self.pixel_size) as f32,
-                            self.pixel_size as f32,
-                            WHITE,
-                        )
-                    }
-                }
-            }
+    }
+    impl Default for Screen {
+        fn default() -> Self {
+            Self::new()
         }
     }
     pub struct Keyboard {
         pub keymap: [bool; 16],
+        // Keys held down by something other than the host keyboard, e.g. a script.
+        injected: [bool; 16],
+        // Host keys ignored until the host reports them up, see `release_all_keys`.
+        held_over: [bool; 16],
     }
     impl Keyboard {
         fn new() -> Self {
             Keyboard {
                 keymap: [false; 16],
+                injected: [false; 16],
+                held_over: [false; 16],
+            }
+        }
+
+        fn update(&mut self, host: [bool; 16]) {
+            for (i, host) in host.into_iter().enumerate() {
+                if !host {
+                    self.held_over[i] = false;
+                }
+                self.keymap[i] = (host && !self.held_over[i]) || self.injected[i];
+            }
+        }
+    }
+    // How Dxyn reads sprite rows that run past the end of memory. The VIP only decodes
+    // 12 address bits, so the default wraps back around to 0x000.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum SpriteReadPolicy {
+        #[default]
+        Wrap,
+        Clamp,
+        Error,
+    }
+
+    // How Ex9E/ExA1 treat a key index in Vx above 0xF, which only a buggy or corrupt ROM
+    // produces. `NotPressed` rejects it, so the key reads as up, `Mask` uses the low
+    // nibble like the VIP's keypad decoder. See `Chip8::resolve_key`.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum KeyIndexPolicy {
+        #[default]
+        NotPressed,
+        Mask,
+    }
+
+    // What Fx33/Fx55 do when I points below 0x200, into the interpreter and font area.
+    // Reads there stay allowed, Fx29 and Dxyn need them for the font. `set_variant`
+    // picks `Off` for MegaChip and Hires, whose programs write there.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum LowMemoryProtection {
+        #[default]
+        Error,
+        // Log a warning and skip the write.
+        Warn,
+        Off,
+    }
+
+    // Whether 8xy1, 8xy2 and 8xy3 reset VF after the logic op, as the COSMAC VIP does.
+    // CHIP-48 and SUPER-CHIP leave it alone.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum VfReset {
+        #[default]
+        Reset,
+        Keep,
+    }
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub enum Variant {
+        #[default]
+        Chip8,
+        // MEGA-CHIP8, see `megachip`. Memory grows to 16MB.
+        MegaChip,
+        // The VIP's two-page 64x64 interpreter. Its programs open with a 1260 jump.
+        Hires,
+    }
+
+    impl Variant {
+        pub const ALL: [Variant; 3] = [Variant::Chip8, Variant::Hires, Variant::MegaChip];
+
+        pub fn name(self) -> &'static str {
+            match self {
+                Variant::Chip8 => "chip8",
+                Variant::Hires => "hires",
+                Variant::MegaChip => "megachip",
+            }
+        }
+
+        pub fn from_name(name: &str) -> Option<Self> {
+            Self::ALL.into_iter().find(|v| v.name() == name)
+        }
+
+        // Where the interpreter area is the program's own, writes to it are expected.
+        fn low_memory_protection(self) -> LowMemoryProtection {
+            match self {
+                Variant::Chip8 => LowMemoryProtection::Error,
+                Variant::MegaChip | Variant::Hires => LowMemoryProtection::Off,
+            }
+        }
+
+        // Memory size and display columns and rows.
+        fn layout(self) -> (usize, usize, usize) {
+            match self {
+                Variant::Chip8 => (4096, 64, 32),
+                Variant::MegaChip => (megachip::MEMORY_SIZE, 64, 32),
+                Variant::Hires => (4096, 64, 64),
             }
         }
     }
+
     pub struct Chip8 {
         registers: Register,
-        timers: Timer,
+        timers: Timers,
         screen: Screen,
-        memory: [u8; 4096],
+        // What resets bring the display back to, after SCHIP's 00FF changed it.
+        display_size: (usize, usize),
+        memory: Vec<u8>,
         stack: Vec<u16>,
+        max_stack_depth: usize,
         pc: u16,
+        start_pc: u16,
         pub keyboard: Keyboard,
+        debugger: Debugger,
+        hooks: Hooks,
+        history: VecDeque<(u16, u16)>,
+        #[cfg(feature = "std")]
+        trace: Option<Trace>,
+        quirks: QuirkConfig,
+        timing: Timing,
+        cycles_per_frame: u32,
+        // Set by `set_ips`, which `cycles_per_frame` is then derived from.
+        ips: Option<u32>,
+        frame_rate: u32,
+        // Fx18 values below this don't sound at all, see `set_min_sound_frames`.
+        min_sound_frames: u8,
+        // Where `load` puts the hex digit sprites, which Fx29 points into.
+        font_base: u16,
+        // Registered fonts by name, the built-in one first.
+        fonts: Vec<(String, Font)>,
+        active_font: usize,
+        speed: f32,
+        // Fractions of a cycle and of a timer tick owed by `speed`, see `scaled`.
+        cycle_carry: f32,
+        timer_carry: f32,
+        // Machine cycles left in the current frame under `Timing::Vip`, negative when
+        // the last instruction overran it.
+        vip_cycles: i64,
+        // VIP cycles spent in Dxyn, waiting included, this frame and last frame.
+        draw_cycles: u64,
+        last_draw_cycles: u64,
+        frame_instructions: u64,
+        last_frame_instructions: u64,
+        // The last program loaded, for `reset`.
+        program: Vec<u8>,
+        rom_end: u16,
+        halt_at_rom_end: bool,
+        // Jumps and calls to odd addresses fail instead of only running the hooks.
+        misaligned_jump_error: bool,
+        reached_end: bool,
+        exited: bool,
+        preserve_state: bool,
+        stats: Stats,
+        frame_dirty: bool,
+        // The display changed outside of emulation, see `invalidate_screen`.
+        screen_invalid: bool,
+        variant: Variant,
+        megachip: Option<MegaChip>,
+        heatmap: Option<Box<AccessHeatmap>>,
+        #[cfg(feature = "std")]
+        crash_dump: Option<DumpTarget>,
+        rom_path: Option<String>,
+        idle: Option<IdleDetector>,
+        // Set by `seed_rng` or `set_random_source`, see `random`.
+        rng: Option<Box<dyn RandomSource>>,
+        key_sampler: Option<(KeySampler, u32)>,
+        input_source: Option<Box<dyn InputSource>>,
+        audio: Option<Box<dyn AudioBackend>>,
+        input_polling: InputPolling,
+        profiler: Option<Box<profile::Profiler>>,
+        // Instructions left, during `run_with_budget`.
+        budget: Option<u64>,
+        i_history: Option<(VecDeque<(u16, u32)>, usize)>,
+        rewind: Option<rewind::Rewind>,
+        #[cfg(feature = "macroquad")]
+        bindings: KeyBindings,
+    }
+
+    impl Default for Chip8 {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 
     impl Chip8 {
         pub fn new() -> Self {
             Chip8 {
                 registers: Register::default(),
-                timers: Timer::default(),
+                timers: Timers::default(),
                 screen: Screen::new(),
-                memory: [0; 4096],
+                display_size: (64, 32),
+                memory: vec![0; 4096],
                 stack: Vec::new(),
-                pc: 0x200,
+                max_stack_depth: 0,
+                pc: PROGRAM_START,
+                start_pc: PROGRAM_START,
                 keyboard: Keyboard::new(),
+                debugger: Debugger::default(),
+                hooks: Hooks::default(),
+                history: VecDeque::with_capacity(HISTORY_LEN),
+                #[cfg(feature = "std")]
+                trace: None,
+                quirks: QuirkConfig::default(),
+                timing: Timing::default(),
+                cycles_per_frame: 1,
+                ips: None,
+                frame_rate: DEFAULT_FRAME_RATE,
+                font_base: 0,
+                fonts: vec![(String::from(font::STANDARD_FONT), Font::standard())],
+                active_font: 0,
+                min_sound_frames: 2,
+                speed: 1.0,
+                cycle_carry: 0.0,
+                timer_carry: 0.0,
+                vip_cycles: 0,
+                draw_cycles: 0,
+                last_draw_cycles: 0,
+                frame_instructions: 0,
+                last_frame_instructions: 0,
+                program: Vec::new(),
+                rom_end: PROGRAM_START,
+                halt_at_rom_end: false,
+                misaligned_jump_error: false,
+                reached_end: false,
+                exited: false,
+                preserve_state: false,
+                stats: Stats::default(),
+                frame_dirty: false,
+                screen_invalid: true,
+                variant: Variant::Chip8,
+                megachip: None,
+                heatmap: None,
+                #[cfg(feature = "std")]
+                crash_dump: None,
+                rom_path: None,
+                idle: None,
+                rng: None,
+                key_sampler: None,
+                input_source: None,
+                audio: None,
+                input_polling: InputPolling::PerFrame,
+                profiler: None,
+                budget: None,
+                i_history: None,
+                rewind: None,
+                #[cfg(feature = "macroquad")]
+                bindings: KeyBindings::default(),
             }
         }
 
-        fn load(&mut self, program: &[u8]) {
-            //                self.memory[addr] = program[addr - 0x200];
-            self.memory[0x200..0x200 + program.len()].copy_from_slice(program);
-            let v = [
-                0xF0, 0x90, 0x90, 0x90, 0xF0, 0x20, 0x60, 0x20, 0x20, 0x70, 0xF0, 0x10, 0xF0, 0x80,
-                0xF0, 0xF0, 0x10, 0xF0, 0x10, 0xF0, 0x90, 0x90, 0xF0, 0x10, 0x10, 0xF0, 0x80, 0xF0,
-                0x10, 0xF0, 0xF0, 0x80, 0xF0, 0x90, 0xF0, 0xF0, 0x10, 0x20, 0x40, 0x40, 0xF0, 0x90,
-                0xF0, 0x90, 0xF0, 0xF0, 0x90, 0xF0, 0x10, 0xF0, 0xF0, 0x90, 0xF0, 0x90, 0x90, 0xE0,
-                0x90, 0xE0, 0x90, 0xE0, 0xF0, 0x80, 0x80, 0x80, 0xF0, 0xE0, 0x90, 0x90, 0x90, 0xE0,
-                0xF0, 0x80, 0xF0, 0x80, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0x80,
-            ];
+        // A machine with a non-standard display, e.g. `Screen::with_size(128, 64)`.
+        pub fn with_screen(screen: Screen) -> Self {
+            Chip8 {
+                display_size: (screen.cols, screen.rows),
+                screen,
+                ..Self::new()
+            }
+        }
 
-            self.memory[0..80].copy_from_slice(&v);
+        pub fn load(&mut self, program: &[u8]) -> Result<(), Chip8Error> {
+            // Would otherwise run zeroed memory as a stream of 0000s.
+            if program.is_empty() {
+                return Err(Chip8Error::EmptyRom);
+            }
+            let start = self.start_pc as usize;
+            let max = self.memory.len().saturating_sub(start);
+            if program.len() > max {
+                return Err(Chip8Error::RomTooLarge {
+                    size: program.len(),
+                    max,
+                });
+            }
+            if !self.preserve_state {
+                self.reset_state();
+            }
+            self.rom_path = None;
+            self.program = program.to_vec();
+            self.memory[start..start + program.len()].copy_from_slice(program);
+            self.rom_end = u16::try_from(start + program.len()).unwrap_or(u16::MAX);
+            // The hires interpreter itself runs up to 0x2C0, where the program proper
+            // starts, so the opening jump is redirected there.
+            if self.variant == Variant::Hires && program.starts_with(&[0x12, 0x60]) {
+                self.memory[start..start + 2].copy_from_slice(&[0x12, 0xC0]);
+            }
+            self.write_font();
+            Ok(())
         }
 
-        pub fn load_from_file(&mut self, file_name: &str) -> Result<(), io::Error> {
-            let mut f = File::open(file_name)?;
-            let mut buffer = Vec::new();
+        // Restarts the loaded program on a clean machine, as loading it again would but
+        // without going back to the file, so memory the program changed is restored too.
+        // Fails with `EmptyRom` if nothing was loaded.
+        pub fn reset(&mut self) -> Result<(), Chip8Error> {
+            let program = core::mem::take(&mut self.program);
+            let rom_path = self.rom_path.take();
+            let preserve = core::mem::replace(&mut self.preserve_state, false);
+            let result = self.load(&program);
+            self.preserve_state = preserve;
+            self.rom_path = rom_path;
+            if result.is_err() {
+                self.program = program;
+            }
+            result
+        }
 
-            f.read_to_end(&mut buffer)?;
+        // Like the VIP's reset switch: registers, timers, stack and display are cleared
+        // and the program starts over, but memory is left as the program changed it.
+        pub fn soft_reset(&mut self) {
+            self.registers = Register::default();
+            self.timers = Timers::default();
+            self.stack.clear();
+            self.max_stack_depth = 0;
+            self.pc = self.start_pc;
+            self.screen.clear();
+            let (cols, rows) = self.display_size;
+            if (self.screen.cols, self.screen.rows) != (cols, rows) {
+                self.set_resolution(cols, rows);
+            }
+            self.history.clear();
+            if let Some((history, _)) = self.i_history.as_mut() {
+                history.clear();
+            }
+            self.vip_cycles = 0;
+            self.draw_cycles = 0;
+            self.last_draw_cycles = 0;
+            self.frame_instructions = 0;
+            self.last_frame_instructions = 0;
+            self.reached_end = false;
+            self.exited = false;
+            self.hooks.waiting_at = None;
+            self.reset_idle();
+            if self.megachip.is_some() {
+                self.megachip = Some(MegaChip::new());
+            }
+            self.invalidate_screen();
+        }
+
+        // Everything a previously loaded program could have left behind.
+        fn reset_state(&mut self) {
+            self.soft_reset();
+            self.memory.fill(0);
+            self.clear_rewind();
+        }
+
+        // Marks the whole display as changed, so a host that only redraws after frames
+        // that ran redraws it anyway. For changes made outside of emulation, e.g. loading
+        // a state or patching the screen while paused.
+        pub fn invalidate_screen(&mut self) {
+            self.screen_invalid = true;
+            self.frame_dirty = true;
+        }
 
-            self.load(&buffer);
+        // Whether the display was invalidated since the last call.
+        pub fn take_screen_invalidated(&mut self) -> bool {
+            core::mem::take(&mut self.screen_invalid)
+        }
+
+        // By default `load` starts the new program from a clean machine. With this set it
+        // only copies the program and font, keeping registers, timers, stack, PC, screen
+        // and the rest of memory as they are.
+        pub fn set_preserve_state(&mut self, preserve: bool) {
+            self.preserve_state = preserve;
+        }
 
+        #[cfg(feature = "std")]
+        pub fn load_from_file(&mut self, file_name: &str) -> Result<(), Chip8Error> {
+            let buffer = read_rom(file_name)?;
+            self.load(&buffer)?;
+            self.rom_path = Some(file_name.to_string());
             Ok(())
         }
 
-        pub fn run(&mut self) {
-            for i in 0..16 {
-                self.keyboard.keymap[i] = is_key_down(keycode_from_hex(i as u8));
+        // The file the running program was loaded from, if it came from one.
+        pub fn rom_path(&self) -> Option<&str> {
+            self.rom_path.as_deref()
+        }
+
+        // Reads the ROM file again and restarts it, for picking up a rebuilt program.
+        // If the file is gone or no longer valid, the machine is left as it was.
+        #[cfg(feature = "std")]
+        pub fn reload(&mut self) -> Result<(), Chip8Error> {
+            let path = self.rom_path.clone().ok_or(Chip8Error::NoRomFile)?;
+            self.load_from_file(&path)
+        }
+
+        pub fn screen(&self) -> &Screen {
+            &self.screen
+        }
+
+        // The display row by row, `screen().cols()` pixels wide and `rows()` high.
+        pub fn framebuffer(&self) -> &[bool] {
+            self.screen.pixels()
+        }
+
+        pub fn memory(&self) -> &[u8] {
+            &self.memory
+        }
+
+        // V0 to VF.
+        pub fn registers(&self) -> &[u8; 16] {
+            &self.registers.v
+        }
+
+        // I, which only exceeds 16 bits under MegaChip.
+        pub fn index(&self) -> u32 {
+            self.registers.i
+        }
+
+        // Return addresses, innermost last. Each is the address of its 2nnn.
+        pub fn stack(&self) -> &[u16] {
+            &self.stack
+        }
+
+        pub fn timers(&self) -> Timers {
+            self.timers
+        }
+
+        pub fn stack_depth(&self) -> usize {
+            self.stack.len()
+        }
+
+        // The deepest the stack has been since the program was loaded.
+        pub fn max_stack_depth(&self) -> usize {
+            self.max_stack_depth
+        }
+
+        pub fn is_sound_active(&self) -> bool {
+            self.timers.sound > 0
+        }
+
+        pub fn set_pixel_size(&mut self, pixel_w: usize, pixel_h: usize) {
+            self.screen.set_pixel_size(pixel_w, pixel_h);
+        }
+
+        pub fn set_palette(&mut self, palette: Palette) {
+            self.screen.set_palette(palette);
+        }
+
+        pub fn set_flicker_mode(&mut self, mode: FlickerMode) {
+            self.screen.set_flicker_mode(mode);
+        }
+
+        pub fn set_grid(&mut self, grid: bool) {
+            self.screen.set_grid(grid);
+            self.invalidate_screen();
+        }
+
+        // Call before `load`, switching variants clears memory and the display.
+        pub fn set_variant(&mut self, variant: Variant) {
+            self.variant = variant;
+            let (memory, cols, rows) = variant.layout();
+            self.memory = vec![0; memory];
+            self.megachip = (variant == Variant::MegaChip).then(MegaChip::new);
+            self.quirks.low_memory = variant.low_memory_protection();
+            self.screen.resize(cols, rows);
+            self.display_size = (cols, rows);
+            self.reset_heatmap();
+            self.invalidate_screen();
+        }
+
+        pub fn variant(&self) -> Variant {
+            self.variant
+        }
+
+        // Fx18 with a value below `frames` leaves the buzzer off. A single frame of sound
+        // is a click rather than a beep, and on the VIP the timer's first decrement could
+        // land before the buzzer engaged, so 1 was close to silent anyway. Programs that
+        // fire Fx18 with 1 in quick succession then stay quiet rather than crackling.
+        // Defaults to 2, 0 or 1 sounds every value. Programs can't read ST back, so this
+        // only changes what is heard.
+        pub fn set_min_sound_frames(&mut self, frames: u8) {
+            self.min_sound_frames = frames;
+        }
+
+        pub fn set_timing(&mut self, timing: Timing) {
+            self.timing = timing;
+            self.vip_cycles = 0;
+        }
+
+        // Instructions per frame under `Timing::Fixed`.
+        pub fn set_cycles_per_frame(&mut self, cycles: u32) {
+            self.cycles_per_frame = cycles;
+            self.ips = None;
+        }
+
+        pub fn cycles_per_frame(&self) -> u32 {
+            self.cycles_per_frame
+        }
+
+        // Instructions per second under `Timing::Fixed`, rounded to a whole number per
+        // frame, so 700 at 60fps runs 12 a frame. Followed if the frame rate changes.
+        pub fn set_ips(&mut self, ips: u32) {
+            self.ips = Some(ips);
+            self.cycles_per_frame = (ips + self.frame_rate / 2) / self.frame_rate;
+        }
+
+        pub fn ips(&self) -> u32 {
+            self.ips.unwrap_or(self.cycles_per_frame * self.frame_rate)
+        }
+
+        // How often the host calls `run_frame`, 60 by default. The timers count down once
+        // a frame whatever this is, so it only sets how `set_ips` and `target_ips`
+        // convert between frames and seconds.
+        pub fn set_frame_rate(&mut self, fps: u32) {
+            self.frame_rate = fps.max(1);
+            if let Some(ips) = self.ips {
+                self.set_ips(ips);
             }
+        }
+
+        pub fn frame_rate(&self) -> u32 {
+            self.frame_rate
+        }
+
+        // Scales both the instructions run per frame and the 60Hz timer rate, so 0.5 runs
+        // the program at half speed and 2.0 at double, with game logic staying in step.
+        pub fn set_speed_multiplier(&mut self, speed: f32) {
+            self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+            self.cycle_carry = 0.0;
+            self.timer_carry = 0.0;
+        }
+
+        pub fn speed_multiplier(&self) -> f32 {
+            self.speed
+        }
+
+        // Stop instead of executing the zeroed memory after the ROM when PC runs off its end.
+        pub fn set_halt_at_rom_end(&mut self, halt: bool) {
+            self.halt_at_rom_end = halt;
+        }
+
+        // Make 1nnn, 2nnn and Bnnn to an odd address an error. Legal, since the VIP
+        // fetches from any address, but nearly always a bug that then executes the
+        // halves of two instructions. See also `on_misaligned_jump`.
+        pub fn set_misaligned_jump_error(&mut self, error: bool) {
+            self.misaligned_jump_error = error;
+        }
+
+        pub fn reached_end(&self) -> bool {
+            self.reached_end
+        }
+
+        // Whether the program ran 00FD. Unlike `reached_end` this is the program's own
+        // decision to stop.
+        pub fn has_exited(&self) -> bool {
+            self.exited
+        }
 
-            self.screen.draw();
-            let ins = ((self.memory[self.pc as usize] as u16) << 8)
-                | (self.memory[self.pc as usize + 1]) as u16;
-            self.execute_instruction(ins);
+        fn is_stopped(&self) -> bool {
+            self.debugger.stop.is_some()
+                || self.reached_end
+                || self.exited
+                || self.budget == Some(0)
+        }
+
+        // Releases every host key, e.g. when the window loses focus and the host may
+        // never report the matching key up. A key only counts as pressed again once the
+        // host has seen it up.
+        pub fn release_all_keys(&mut self) {
+            self.keyboard.keymap = [false; 16];
+            self.keyboard.held_over = [true; 16];
+        }
+
+        // Holds `key` down (or releases it) on top of the host keyboard from the next tick on.
+        pub fn inject_key(&mut self, key: u8, down: bool) {
+            if let Some(k) = self.keyboard.injected.get_mut(key as usize) {
+                *k = down;
+            }
+        }
+
+        pub fn set_sprite_read_policy(&mut self, policy: SpriteReadPolicy) {
+            self.quirks.sprite_read = policy;
+        }
+
+        pub fn set_key_index_policy(&mut self, policy: KeyIndexPolicy) {
+            self.quirks.key_index = policy;
+        }
+
+        // Off for programs that legitimately modify themselves below 0x200.
+        pub fn set_protect_low_memory(&mut self, protection: LowMemoryProtection) {
+            self.quirks.low_memory = protection;
+        }
+
+        pub fn set_vf_reset(&mut self, vf_reset: VfReset) {
+            self.quirks.vf_reset = vf_reset;
+        }
+
+        // The last few executed instructions as (pc, opcode), oldest first.
+        pub fn recent_instructions(&self) -> &VecDeque<(u16, u16)> {
+            &self.history
+        }
+
+        pub fn input_polling(&self) -> InputPolling {
+            self.input_polling
+        }
+
+        // See `InputPolling`. Hosts with a key sampler read this to choose its interval.
+        pub fn set_input_polling(&mut self, polling: InputPolling) {
+            self.input_polling = polling;
+        }
+
+        // Makes `run_frame` refresh the keypad from `sampler` every `interval`
+        // instructions, starting with the first, rather than once per frame. For hosts
+        // whose input changes while a frame's batch of instructions runs.
+        pub fn set_key_sampler(&mut self, sampler: Option<KeySampler>, interval: u32) {
+            self.key_sampler = sampler.map(|s| (s, interval.max(1)));
+        }
+
+        fn sample_keys(&mut self, executed: u32) {
+            if let Some((sampler, interval)) = self.key_sampler.as_mut() {
+                if executed.is_multiple_of(*interval) {
+                    let keys = sampler.poll();
+                    self.keyboard.update(keys);
+                }
+            }
+        }
+
+        // Like `tick`, but leaves the keypad as the caller set it, or to the key sampler.
+        pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+            let mut executed = 0;
+            match self.timing {
+                Timing::Fixed => {
+                    let cycles = scaled(
+                        self.cycles_per_frame as f32,
+                        self.speed,
+                        &mut self.cycle_carry,
+                    );
+                    for _ in 0..cycles {
+                        if self.is_stopped() {
+                            self.count_stopped_frame();
+                            return Ok(());
+                        }
+                        self.sample_keys(executed);
+                        self.step()?;
+                        executed += 1;
+                    }
+                }
+                Timing::Vip => {
+                    self.vip_cycles += scaled(
+                        timing::VIP_CYCLES_PER_FRAME as f32,
+                        self.speed,
+                        &mut self.cycle_carry,
+                    ) as i64;
+                    while self.vip_cycles > 0 {
+                        if self.is_stopped() {
+                            self.count_stopped_frame();
+                            return Ok(());
+                        }
+                        self.sample_keys(executed);
+                        self.step()?;
+                        executed += 1;
+                    }
+                }
+            }
+
+            self.count_frame();
+            self.screen.fade();
+            let ticks = scaled(1.0, self.speed, &mut self.timer_carry);
+            let ticks = u8::try_from(ticks).unwrap_or(u8::MAX);
+            self.timers.delay = self.timers.delay.saturating_sub(ticks);
+            self.timers.sound = self.timers.sound.saturating_sub(ticks);
+            self.update_audio();
+            self.check_sound_hooks();
+            self.record_rewind();
+            Ok(())
+        }
+
+        // Executes one instruction, without polling input or touching the timers. `None`
+        // if nothing ran, because the machine is stopped or at a breakpoint.
+        pub fn step(&mut self) -> Result<Option<StepInfo>, Chip8Error> {
+            #[cfg(feature = "std")]
+            if self.crash_dump.is_some() {
+                return self.step_with_dump();
+            }
+            self.execute_step()
+        }
+
+        fn execute_step(&mut self) -> Result<Option<StepInfo>, Chip8Error> {
+            if self.is_stopped() {
+                return Ok(None);
+            }
+            if self.halt_at_rom_end && self.pc >= self.rom_end {
+                self.reached_end = true;
+                return Ok(None);
+            }
+            if self.check_breakpoint(self.pc) {
+                return Ok(None);
+            }
+            if let Some(left) = self.budget.as_mut() {
+                *left -= 1;
+            }
+            self.run_pc_hooks();
+            let pc = self.pc;
+            let opcode = self.fetch()?;
+            self.note_fetch(pc);
+            self.count_execution(pc);
+            self.check_idle(pc);
+            if self.history.len() == HISTORY_LEN {
+                self.history.pop_front();
+            }
+            self.history.push_back((pc, opcode));
+            #[cfg(feature = "std")]
+            if self.trace.is_some() {
+                self.write_trace(pc, opcode);
+            }
+            self.run_instruction_hooks(pc, opcode);
+            if self.execute_megachip(opcode)? {
+                self.stats.instructions += 1;
+                return Ok(Some(StepInfo {
+                    pc,
+                    opcode,
+                    mnemonic: "MEGA",
+                    cycles: 0,
+                }));
+            }
+            let ins =
+                Instruction::decode(opcode).ok_or(Chip8Error::InvalidOpcode { pc, opcode })?;
+            if ins.falls_through() {
+                self.check_next_pc(pc, 2)?;
+            }
+            self.note_idle_activity(&ins);
+            let cycles = timing::vip_cycles(&ins, &self.registers.v);
+            if self.timing == Timing::Vip {
+                let cost = cycles as i64;
+                if let Instruction::Drw(..) = ins {
+                    // The VIP waits for the vertical blank before drawing, so the rest
+                    // of the frame is lost and the draw itself comes out of the next.
+                    let wait = self.vip_cycles.max(0);
+                    self.draw_cycles += (wait + cost) as u64;
+                    self.vip_cycles -= wait;
+                }
+                self.vip_cycles -= cost;
+            }
+            self.execute(ins)?;
+            // A skip taken at 0xFFFC wraps pc. Skips change nothing else, so putting pc
+            // back leaves the machine at the fault.
+            if ins.is_skip() && self.pc < pc {
+                self.pc = pc;
+                return Err(Chip8Error::MemoryOutOfBounds {
+                    pc,
+                    addr: pc as usize + 4,
+                });
+            }
+            match ins {
+                Instruction::Drw(..) => self.run_draw_hooks(pc, opcode),
+                Instruction::LdStVx(_) => self.check_sound_hooks(),
+                _ => {}
+            }
+            self.count_instruction(&ins);
+            self.check_register_watches(pc, &ins);
+            Ok(Some(StepInfo {
+                pc,
+                opcode,
+                mnemonic: ins.mnemonic(),
+                cycles,
+            }))
+        }
 
-            if self.timers.delay > 0 {
-                self.timers.delay -= 1;
+        // pc is 16 bits whatever the memory size, so an instruction `len` bytes long at
+        // the top of that range has nowhere to go on to.
+        pub(super) fn check_next_pc(&self, pc: u16, len: u16) -> Result<(), Chip8Error> {
+            match pc.checked_add(len) {
+                Some(_) => Ok(()),
+                None => Err(Chip8Error::MemoryOutOfBounds {
+                    pc,
+                    addr: pc as usize + len as usize,
+                }),
             }
-            if self.timers.sound > 0 {
-                self.timers.sound -= 1;
+        }
+
+        fn fetch(&self) -> Result<u16, Chip8Error> {
+            let pc = self.pc as usize;
+            if pc + 1 >= self.memory.len() {
+                return Err(Chip8Error::MemoryOutOfBounds {
+                    pc: self.pc,
+                    addr: pc + 1,
+                });
             }
+            Ok(self.peek_opcode_at(self.pc))
+        }
+
+        pub fn pc(&self) -> u16 {
+            self.pc
+        }
+
+        // The opcode the next step will execute, without executing it.
+        pub fn peek_opcode(&self) -> u16 {
+            self.peek_opcode_at(self.pc)
+        }
+
+        // Bytes past the end of memory read as zero.
+        pub fn peek_opcode_at(&self, addr: u16) -> u16 {
+            let byte = |a: usize| self.memory.get(a).copied().unwrap_or(0) as u16;
+            (byte(addr as usize) << 8) | byte(addr as usize + 1)
         }
 
         fn op00E0(&mut self) {
-            self.screen.pixels = [false; 2048];
+            self.screen.clear();
+            self.pc += 2;
+        }
+        // Changes the display size while keeping its size on screen. Clears it, as
+        // resolution switches do on XO-CHIP interpreters.
+        fn set_resolution(&mut self, cols: usize, rows: usize) {
+            let (width, height) = (
+                self.screen.cols * self.screen.pixel_w,
+                self.screen.rows * self.screen.pixel_h,
+            );
+            self.screen.resize(cols, rows);
+            self.screen
+                .set_pixel_size((width / cols).max(1), (height / rows).max(1));
+            self.invalidate_screen();
+        }
+        fn op0nnn(&mut self) {
+            self.pc += 2;
+        }
+        fn op00Cn(&mut self, n: u8) {
+            self.screen.scroll_down(n as usize);
+            self.pc += 2;
+        }
+        fn op00FB(&mut self) {
+            self.screen.scroll_across(-4);
             self.pc += 2;
         }
-        fn op00EE(&mut self) {
-            self.pc = self.stack.pop().unwrap() + 2;
+        fn op00FC(&mut self) {
+            self.screen.scroll_across(4);
+            self.pc += 2;
+        }
+        fn op00FE(&mut self) {
+            let (_, cols, rows) = self.variant.layout();
+            self.set_resolution(cols, rows);
+            self.pc += 2;
+        }
+        fn op00FF(&mut self) {
+            self.set_resolution(SCHIP_HIRES.0, SCHIP_HIRES.1);
+            self.pc += 2;
         }
-        fn op1nnn(&mut self, nnn: u16) {
+        // PC stays on the 00FD.
+        fn op00FD(&mut self) {
+            self.exited = true;
+        }
+        fn op00EE(&mut self) -> Result<(), Chip8Error> {
+            let ret = *self
+                .stack
+                .last()
+                .ok_or(Chip8Error::StackUnderflow { pc: self.pc })?;
+            self.check_next_pc(ret, 2)
+                .map_err(|_| Chip8Error::MemoryOutOfBounds {
+                    pc: self.pc,
+                    addr: ret as usize + 2,
+                })?;
+            self.stack.pop();
+            self.pc = ret + 2;
+            Ok(())
+        }
+        #[inline]
+        fn check_jump_target(&mut self, target: u16) -> Result<(), Chip8Error> {
+            if target.is_multiple_of(2) {
+                return Ok(());
+            }
+            if self.misaligned_jump_error {
+                return Err(Chip8Error::MisalignedJump {
+                    pc: self.pc,
+                    target,
+                });
+            }
+            self.run_misaligned_jump_hooks(target);
+            Ok(())
+        }
+        fn op1nnn(&mut self, nnn: u16) -> Result<(), Chip8Error> {
+            self.check_jump_target(nnn)?;
             self.pc = nnn;
+            Ok(())
         }
-        fn op2nnn(&mut self, nnn: u16) {
+        fn op2nnn(&mut self, nnn: u16) -> Result<(), Chip8Error> {
+            self.check_jump_target(nnn)?;
+            if self.stack.len() == STACK_DEPTH {
+                return Err(Chip8Error::StackOverflow { pc: self.pc });
+            }
             self.stack.push(self.pc);
+            self.max_stack_depth = self.max_stack_depth.max(self.stack.len());
+            self.run_deep_stack_hooks();
             self.pc = nnn;
+            Ok(())
         }
+        #[inline]
         fn op3xkk(&mut self, x: usize, kk: u8) {
             self.pc += 2;
             if self.registers.v[x] == kk {
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             }
         }
+        #[inline]
         fn op4xkk(&mut self, x: usize, kk: u8) {
             self.pc += 2;
             if self.registers.v[x] != kk {
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             }
         }
+        #[inline]
         fn op5xy0(&mut self, x: usize, y: usize) {
             self.pc += 2;
             if self.registers.v[x] == self.registers.v[y] {
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             }
         }
+        #[inline]
         fn op6xkk(&mut self, x: usize, kk: u8) {
             self.registers.v[x] = kk;
             self.pc += 2;
         }
+        #[inline]
         fn op7xkk(&mut self, x: usize, kk: u8) {
-            self.registers.v[x] = self.registers.v[x].wrapping_add(kk);
+            let (sum, overflow) = self.registers.v[x].overflowing_add(kk);
+            if overflow {
+                self.run_overflow_hooks(0x7000 | (x as u16) << 8 | kk as u16);
+            }
+            self.registers.v[x] = sum;
             self.pc += 2;
         }
+        #[inline]
         fn op8xy0(&mut self, x: usize, y: usize) {
             self.registers.v[x] = self.registers.v[y];
             self.pc += 2;
         }
+        // With x == F the result lands in VF first, so under `VfReset::Reset` VF ends up
+        // 0 and under `VfReset::Keep` it holds the OR/AND/XOR result.
+        #[inline]
         fn op8xy1(&mut self, x: usize, y: usize) {
             self.registers.v[x] |= self.registers.v[y];
-            self.registers.v[0xf] = 0;
+            self.reset_vf();
             self.pc += 2;
         }
+        #[inline]
         fn op8xy2(&mut self, x: usize, y: usize) {
             self.registers.v[x] &= self.registers.v[y];
-            self.registers.v[0xf] = 0;
+            self.reset_vf();
             self.pc += 2
         }
+        #[inline]
         fn op8xy3(&mut self, x: usize, y: usize) {
             self.registers.v[x] ^= self.registers.v[y];
-            self.registers.v[0xf] = 0;
+            self.reset_vf();
             self.pc += 2;
         }
+        #[inline]
+        fn reset_vf(&mut self) {
+            if self.quirks.vf_reset == VfReset::Reset {
+                self.registers.v[0xf] = 0;
+            }
+        }
+        // 8xy4 to 8xyE read their operands before writing anything and set VF last, so
+        // with x == F VF ends up holding the flag rather than the result.
+        #[inline]
         fn op8xy4(&mut self, x: usize, y: usize) {
-            let val: u16 = (self.registers.v[x] as u16) + (self.registers.v[y] as u16);
-            self.registers.v[x] = self.registers.v[x].wrapping_add(self.registers.v[y]);
+            let xx = self.registers.v[x];
+            let yy = self.registers.v[y];
+            let val: u16 = (xx as u16) + (yy as u16);
+            if val > 255 {
+                self.run_overflow_hooks(0x8004 | (x as u16) << 8 | (y as u16) << 4);
+            }
+            self.registers.v[x] = xx.wrapping_add(yy);
 
             if val > 255 {
                 self.registers.v[0xf] = 1;
@@ -239,11 +1441,12 @@ pub mod emulator {
             }
             self.pc += 2;
         }
+        #[inline]
         fn op8xy5(&mut self, x: usize, y: usize) {
             let xx = self.registers.v[x];
             let yy = self.registers.v[y];
 
-            self.registers.v[x] = self.registers.v[x].wrapping_sub(self.registers.v[y]);
+            self.registers.v[x] = xx.wrapping_sub(yy);
 
             if xx < yy {
                 self.registers.v[0xf] = 0;
@@ -252,17 +1455,19 @@ pub mod emulator {
             }
             self.pc += 2;
         }
+        #[inline]
         fn op8xy6(&mut self, x: usize, _y: usize) {
             let xx = self.registers.v[x];
             self.registers.v[x] >>= 1;
             self.registers.v[0xf] = xx & 1;
             self.pc += 2;
         }
+        #[inline]
         fn op8xy7(&mut self, x: usize, y: usize) {
             let xx = self.registers.v[x];
             let yy = self.registers.v[y];
 
-            self.registers.v[x] = self.registers.v[y].wrapping_sub(self.registers.v[x]);
+            self.registers.v[x] = yy.wrapping_sub(xx);
 
             if yy < xx {
                 self.registers.v[15] = 0;
@@ -271,161 +1476,264 @@ pub mod emulator {
             }
             self.pc += 2;
         }
+        #[inline]
         fn op8xyE(&mut self, x: usize, _y: usize) {
             let xx = self.registers.v[x];
             self.registers.v[x] <<= 1;
             self.registers.v[15] = (xx & 0b10000000) >> 7;
             self.pc += 2;
         }
+        #[inline]
         fn op9xy0(&mut self, x: usize, y: usize) {
             self.pc += 2;
             if self.registers.v[x] != self.registers.v[y] {
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             }
         }
+        #[inline]
         fn opAnnn(&mut self, nnn: u16) {
-            self.registers.i = nnn;
+            self.set_i(nnn as u32);
             self.pc += 2;
         }
-        fn opBnnn(&mut self, nnn: u16) {
-            self.pc = nnn + (self.registers.v[0] as u16);
+        #[inline]
+        fn opBnnn(&mut self, nnn: u16) -> Result<(), Chip8Error> {
+            let target = nnn + (self.registers.v[0] as u16);
+            self.check_jump_target(target)?;
+            self.pc = target;
+            Ok(())
         }
         fn opCxkk(&mut self, x: usize, kk: u8) {
-            self.registers.v[x] = macroquad::rand::gen_range(0, 255) & kk;
+            let random = self.random_byte();
+            self.registers.v[x] = random & kk;
             self.pc += 2;
         }
-        fn opDxyn(&mut self, x: usize, y: usize, n: u8) {
+        fn opDxyn(&mut self, x: usize, y: usize, n: u8) -> Result<(), Chip8Error> {
+            let i = self.registers.i as usize;
+            let mut rows = n as usize;
+            if i + rows > self.memory.len() {
+                match self.quirks.sprite_read {
+                    SpriteReadPolicy::Wrap => {}
+                    SpriteReadPolicy::Clamp => rows = self.memory.len().saturating_sub(i),
+                    SpriteReadPolicy::Error => {
+                        return Err(Chip8Error::MemoryOutOfBounds {
+                            pc: self.pc,
+                            addr: i + rows - 1,
+                        })
+                    }
+                }
+            }
+
+            // Read the coordinates before VF is cleared, either may be VF.
+            let (vx, vy) = (self.registers.v[x] as usize, self.registers.v[y] as usize);
             self.registers.v[15] = 0;
 
-            for byte in 0..n {
+            for byte in 0..rows {
+                let addr = (i + byte) % self.memory.len();
+                self.note_reads(addr, 1);
+                let sprite = self.memory[addr];
                 for bit in 0..8 {
-                    let pixel =
-                        (self.memory[self.registers.i as usize + byte as usize] >> (7 - bit)) & 1;
-                    self.registers.v[0xf] |= self.screen.set(
-                        ((self.registers.v[y] as u16) + byte as u16) as usize,
-                        ((self.registers.v[x] as u16) + (bit as u16)) as usize,
-                        pixel == 1,
-                    );
+                    let pixel = (sprite >> (7 - bit)) & 1;
+                    self.registers.v[0xf] |= self.screen.set(vy + byte, vx + bit, pixel == 1);
                 }
             }
 
             self.pc += 2;
+            Ok(())
+        }
+        // The keypad key a register value names under the key index policy, `None` if
+        // it names none. Every instruction taking a key index from a register goes
+        // through here.
+        pub fn resolve_key(&self, v: u8) -> Option<usize> {
+            let key = match self.quirks.key_index {
+                KeyIndexPolicy::NotPressed => v,
+                KeyIndexPolicy::Mask => v & 0xF,
+            };
+            (key < 16).then_some(key as usize)
+        }
+        fn is_key_pressed(&self, v: u8) -> bool {
+            self.resolve_key(v)
+                .is_some_and(|key| self.keyboard.keymap[key])
         }
         fn opEx9E(&mut self, x: usize) {
             self.pc += 2;
 
-            if self.keyboard.keymap[self.registers.v[x] as usize] {
-                self.pc += 2;
+            if self.is_key_pressed(self.registers.v[x]) {
+                self.pc = self.pc.wrapping_add(2);
             }
         }
         fn opExA1(&mut self, x: usize) {
             self.pc += 2;
-            if !self.keyboard.keymap[self.registers.v[x] as usize] {
-                self.pc += 2;
+            if !self.is_key_pressed(self.registers.v[x]) {
+                self.pc = self.pc.wrapping_add(2);
             }
         }
+        #[inline]
         fn opFx07(&mut self, x: usize) {
             self.registers.v[x] = self.timers.delay;
             self.pc += 2;
         }
         fn opFx0A(&mut self, x: usize) {
             for i in 0..16 {
-                println!("{}", i);
                 if self.keyboard.keymap[i as usize] {
-                    println!("HIT {}", i);
                     self.registers.v[x] = i;
+                    self.hooks.waiting_at = None;
                     self.pc += 2;
                     return;
                 }
             }
+            self.run_key_wait_hooks(x);
         }
+        #[inline]
         fn opFx15(&mut self, x: usize) {
             self.timers.delay = self.registers.v[x];
             self.pc += 2;
         }
+        #[inline]
         fn opFx18(&mut self, x: usize) {
-            self.timers.sound = self.registers.v[x];
+            let value = self.registers.v[x];
+            self.timers.sound = if value < self.min_sound_frames {
+                0
+            } else {
+                value
+            };
             self.pc += 2;
         }
 
+        #[inline]
         fn opFx1E(&mut self, x: usize) {
-            self.registers.i += self.registers.v[x] as u16;
+            let mask = match self.variant {
+                Variant::Chip8 | Variant::Hires => 0xFFFF,
+                Variant::MegaChip => 0xFF_FFFF,
+            };
+            self.set_i((self.registers.i + self.registers.v[x] as u32) & mask);
             self.pc += 2;
         }
 
+        #[inline]
         fn opFx29(&mut self, x: usize) {
-            self.registers.i = (self.registers.v[x] as u16) * 5;
+            self.set_i(self.glyph_address(self.registers.v[x]));
             self.pc += 2;
         }
-        fn opFx33(&mut self, x: usize) {
-            let xx = self.registers.v[x];
-            self.memory[self.registers.i as usize] = xx / 100;
-            self.memory[self.registers.i as usize + 1] = (xx / 10) % 10;
-            self.memory[self.registers.i as usize + 2] = xx % 10;
+        fn check_i_range(&self, len: usize) -> Result<(), Chip8Error> {
+            let end = self.registers.i as usize + len;
+            if end > self.memory.len() {
+                return Err(Chip8Error::MemoryOutOfBounds {
+                    pc: self.pc,
+                    addr: end - 1,
+                });
+            }
+            Ok(())
+        }
+        // Whether a store at I may go ahead under the `low_memory` quirk.
+        fn check_write(&self) -> Result<bool, Chip8Error> {
+            let addr = self.registers.i as usize;
+            if addr >= 0x200 {
+                return Ok(true);
+            }
+            match self.quirks.low_memory {
+                LowMemoryProtection::Error => Err(Chip8Error::ProtectedWrite { pc: self.pc, addr }),
+                LowMemoryProtection::Warn => {
+                    // Skipped silently without std, there's nowhere to warn.
+                    #[cfg(feature = "std")]
+                    eprintln!(
+                        "Skipped write to protected address {:#05X} at {:#05X}",
+                        addr, self.pc
+                    );
+                    Ok(false)
+                }
+                LowMemoryProtection::Off => Ok(true),
+            }
+        }
+        fn opFx33(&mut self, x: usize) -> Result<(), Chip8Error> {
+            self.check_i_range(3)?;
+            if self.check_write()? {
+                self.note_writes(self.registers.i as usize, 3);
+                let xx = self.registers.v[x];
+                self.memory[self.registers.i as usize] = xx / 100;
+                self.memory[self.registers.i as usize + 1] = (xx / 10) % 10;
+                self.memory[self.registers.i as usize + 2] = xx % 10;
+            }
             self.pc += 2;
+            Ok(())
         }
-        fn opFx55(&mut self, x: usize) {
-            for i in 0..x + 1 {
-                self.memory[self.registers.i as usize + i] = self.registers.v[i];
+        fn opFx55(&mut self, x: usize) -> Result<(), Chip8Error> {
+            self.check_i_range(x + 1)?;
+            if self.check_write()? {
+                self.note_writes(self.registers.i as usize, x + 1);
+                for i in 0..x + 1 {
+                    self.memory[self.registers.i as usize + i] = self.registers.v[i];
+                }
             }
-            self.registers.i += x as u16 + 1;
+            self.set_i(self.registers.i + x as u32 + 1);
             self.pc += 2;
+            Ok(())
         }
-        fn opFx65(&mut self, x: usize) {
+        fn opFx65(&mut self, x: usize) -> Result<(), Chip8Error> {
+            self.check_i_range(x + 1)?;
+            self.note_reads(self.registers.i as usize, x + 1);
             for i in 0..x + 1 {
                 self.registers.v[i] = self.memory[self.registers.i as usize + i];
             }
-            self.registers.i += x as u16 + 1;
-            self.pc += 2;
-        }
-
-        pub fn execute_instruction(&mut self, ins: u16) {
-            let x = ((ins & 0x0F00) >> 8) as usize;
-            let y = ((ins & 0x00F0) >> 4) as usize;
-            let nnn = ins & 0x0FFF;
-            let kk = (ins & 0x00FF) as u8;
-            let n = (ins & 0x000F) as u8;
-
-            match nibbles(ins) {
-                (0x0, 0x0, 0xE, 0xE) => self.op00EE(),
-                (0x0, _, _, _) => self.op00E0(),
-                (0x1, _, _, _) => self.op1nnn(nnn),
-                (0x2, _, _, _) => self.op2nnn(nnn),
-                (0x3, _, _, _) => self.op3xkk(x, kk),
-                (0x4, _, _, _) => self.op4xkk(x, kk),
-                (0x5, _, _, _) => self.op5xy0(x, y),
-                (0x6, _, _, _) => self.op6xkk(x, kk),
-                (0x7, _, _, _) => self.op7xkk(x, kk),
-                (0x8, _, _, 0x0) => self.op8xy0(x, y),
-                (0x8, _, _, 0x1) => self.op8xy1(x, y),
-                (0x8, _, _, 0x2) => self.op8xy2(x, y),
-                (0x8, _, _, 0x3) => self.op8xy3(x, y),
-                (0x8, _, _, 0x4) => self.op8xy4(x, y),
-                (0x8, _, _, 0x5) => self.op8xy5(x, y),
-                (0x8, _, _, 0x6) => self.op8xy6(x, y),
-                (0x8, _, _, 0x7) => self.op8xy7(x, y),
-                (0x8, _, _, 0xE) => self.op8xyE(x, y),
-                (0x9, _, _, _) => self.op9xy0(x, y),
-                (0xA, _, _, _) => self.opAnnn(nnn),
-                (0xB, _, _, _) => self.opBnnn(nnn),
-                (0xC, _, _, _) => self.opCxkk(x, kk),
-                (0xD, _, _, _) => self.opDxyn(x, y, n),
-                (0xE, _, _, 0xE) => self.opEx9E(x),
-                (0xE, _, _, 0x1) => self.opExA1(x),
-                (0xF, _, 0x0, 0x7) => self.opFx07(x),
-                (0xF, _, 0x0, 0xA) => self.opFx0A(x),
-                (0xF, _, 0x1, 0x5) => self.opFx15(x),
-                (0xF, _, 0x1, 0x8) => self.opFx18(x),
-                (0xF, _, 0x1, 0xE) => self.opFx1E(x),
-                (0xF, _, 0x2, _) => self.opFx29(x),
-                (0xF, _, 0x3, _) => self.opFx33(x),
-                (0xF, _, 0x5, _) => self.opFx55(x),
-                (0xF, _, 0x6, _) => self.opFx65(x),
-
-                _ => {
-                    panic!("Invalid opcode {}", ins)
-                }
+            self.set_i(self.registers.i + x as u32 + 1);
+            self.pc += 2;
+            Ok(())
+        }
+
+        pub fn execute_instruction(&mut self, ins: u16) -> Result<(), Chip8Error> {
+            match Instruction::decode(ins) {
+                Some(decoded) => self.execute(decoded),
+                None => Err(Chip8Error::InvalidOpcode {
+                    pc: self.pc,
+                    opcode: ins,
+                }),
             }
         }
+
+        fn execute(&mut self, ins: Instruction) -> Result<(), Chip8Error> {
+            match ins {
+                Instruction::Sys(_) => self.op0nnn(),
+                Instruction::Ret => self.op00EE()?,
+                Instruction::Cls => self.op00E0(),
+                Instruction::ScrollDown(n) => self.op00Cn(n),
+                Instruction::ScrollRight => self.op00FB(),
+                Instruction::ScrollLeft => self.op00FC(),
+                Instruction::Exit => self.op00FD(),
+                Instruction::Lores => self.op00FE(),
+                Instruction::Hires => self.op00FF(),
+                Instruction::Jp(nnn) => self.op1nnn(nnn)?,
+                Instruction::Call(nnn) => self.op2nnn(nnn)?,
+                Instruction::SeByte(x, kk) => self.op3xkk(x, kk),
+                Instruction::SneByte(x, kk) => self.op4xkk(x, kk),
+                Instruction::SeReg(x, y) => self.op5xy0(x, y),
+                Instruction::LdByte(x, kk) => self.op6xkk(x, kk),
+                Instruction::AddByte(x, kk) => self.op7xkk(x, kk),
+                Instruction::LdReg(x, y) => self.op8xy0(x, y),
+                Instruction::Or(x, y) => self.op8xy1(x, y),
+                Instruction::And(x, y) => self.op8xy2(x, y),
+                Instruction::Xor(x, y) => self.op8xy3(x, y),
+                Instruction::AddReg(x, y) => self.op8xy4(x, y),
+                Instruction::Sub(x, y) => self.op8xy5(x, y),
+                Instruction::Shr(x, y) => self.op8xy6(x, y),
+                Instruction::Subn(x, y) => self.op8xy7(x, y),
+                Instruction::Shl(x, y) => self.op8xyE(x, y),
+                Instruction::SneReg(x, y) => self.op9xy0(x, y),
+                Instruction::LdI(nnn) => self.opAnnn(nnn),
+                Instruction::JpV0(nnn) => self.opBnnn(nnn)?,
+                Instruction::Rnd(x, kk) => self.opCxkk(x, kk),
+                Instruction::Drw(x, y, n) => self.opDxyn(x, y, n)?,
+                Instruction::Skp(x) => self.opEx9E(x),
+                Instruction::Sknp(x) => self.opExA1(x),
+                Instruction::LdVxDt(x) => self.opFx07(x),
+                Instruction::LdVxK(x) => self.opFx0A(x),
+                Instruction::LdDtVx(x) => self.opFx15(x),
+                Instruction::LdStVx(x) => self.opFx18(x),
+                Instruction::AddI(x) => self.opFx1E(x),
+                Instruction::LdF(x) => self.opFx29(x),
+                Instruction::LdB(x) => self.opFx33(x)?,
+                Instruction::LdIVx(x) => self.opFx55(x)?,
+                Instruction::LdVxI(x) => self.opFx65(x)?,
+            }
+            Ok(())
+        }
     }
 }
@@ -1,11 +1,133 @@
-use chip8::emulator::Chip8;
+mod audio_recorder;
+mod control;
+mod crt;
+mod heatmap_view;
+mod monitor;
+mod overlay;
+mod session;
+mod slot_picker;
+
+use audio_recorder::AudioRecorder;
+#[cfg(feature = "audio")]
+use chip8::emulator::Beeper;
+#[cfg(feature = "lua")]
+use chip8::emulator::Script;
+use chip8::emulator::{
+    self, Chip8, Chip8Error, ClockSkew, DumpTarget, FlickerMode, Font, FrameClock, InputLog,
+    InputPolling, InputScript, Instruction, KeyBindings, LowMemoryProtection, Palette, QuirkConfig,
+    Stats, Timing, Variant, CUSTOM_FONT,
+};
+use chip8::runner::{Command, Runner};
+use control::ControlServer;
+use crt::Crt;
+use heatmap_view::HeatmapView;
 use macroquad::prelude::*;
-use std::io;
-use std::{env, process::exit};
+use overlay::Overlay;
+use serde_json::{json, Value};
+use session::Session;
+use slot_picker::SlotPicker;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs, process::exit};
+
+const FRAME_TIME: f64 = 1.0 / 60.0;
+const MAX_AUTO_SKIP: u32 = 4;
+// macroquad doesn't report focus changes. A gap this long between frames (window
+// hidden, moved or throttled in the background) is the closest signal it gives.
+const FOCUS_STALL: f64 = 0.5;
+const HUD_INTERVAL: f64 = 0.25;
+const SPARKLINE_LEN: usize = 120;
+// Speed multipliers stepped through with +/-.
+const SPEEDS: [f32; 9] = [0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 3.0, 4.0, 8.0];
+// Where F8 records to without --record-audio.
+const DEFAULT_AUDIO_PATH: &str = "chip8.wav";
+// Where F4 and SIGUSR1 write snapshots, relative to the working directory.
+const SNAPSHOT_DIR: &str = "snapshots";
+// Where F12 dumps the machine state without --dump-state-on-exit.
+const DEFAULT_STATE_DUMP_PATH: &str = "chip8-state.json";
+// Unchanged passes over the same loop before a program counts as finished.
+const IDLE_ITERATIONS: u32 = 1000;
+// Frames between rewind states without --rewind-every, ten a second.
+const DEFAULT_REWIND_INTERVAL: u32 = 6;
+// Frames --compare runs without --headless.
+const COMPARE_FRAMES: u64 = 600;
+// Hot ranges listed by --profile and --profile-json.
+const PROFILE_RANGES: usize = 16;
+
+const EXIT_USAGE: i32 = 1;
+const EXIT_ROM_UNREADABLE: i32 = 2;
+const EXIT_ROM_INVALID: i32 = 3;
+const EXIT_EMULATION: i32 = 4;
+const EXIT_ASSERTION: i32 = 5;
+
+const USAGE: &str = "\
+Usage: chip8 [OPTIONS] ROM
 
-fn conf() -> Conf {
+Options:
+  --frameskip N       only draw every N+1th frame
+  --threaded          run the emulation on its own thread
+  --timing fixed|vip  instruction timing model
+  --ips N             instructions per second under fixed timing, 60 by default
+  --variant NAME      chip8, hires or megachip, guessed from the ROM by default
+  --palette NAME      classic, gameboy, amber or blue
+  --bindings FILE     keypad key bindings, lines like `5 = W`
+  --font FILE         hex digit font, 16 glyphs back to back
+  --persistence       fade pixels out instead of flickering, see README
+  --poll-every-cycle  read the keys before every instruction, see README
+  --min-sound N       ignore Fx18 values below N frames, 2 by default
+  --grid              outline every pixel, F2 toggles
+  --crt               scanlines and glow, F3 toggles
+  --visual-beep       flash a border while the sound timer runs
+  --heatmap           count memory accesses and show them (F10)
+  --rewind N          keep N recent states to rewind through, Backspace holds
+  --rewind-every N    frames between rewind states, 6 by default
+  --script FILE       run a Lua script alongside the ROM (lua feature)
+  --headless FRAMES   run FRAMES frames without a window, then exit
+  --control-port PORT accept JSON commands on localhost:PORT
+  --record-audio FILE record the beeper to a WAV file, F8 stops and restarts
+  --warn-overflow     report 7xkk and 8xy4 additions that wrap past 255
+  --warn-stack DEPTH  report calls that take the stack past DEPTH entries
+  --misaligned warn|error  report jumps and calls to odd addresses, or stop on them
+  --low-memory off|warn|error  what Fx33/Fx55 do below 0x200, by variant by default
+  --profile           print the hottest code on exit
+  --log-frames        print how many instructions each frame ran
+  --profile-json FILE write the hottest code on exit to FILE as JSON
+  --seed N            seed the random number generator, for reproducible runs
+  --record-input FILE record the keypad to FILE for --verify-replay
+  --verify-replay FILE replay a recorded input log headless and print the final state hash
+  --expect-hash HASH  with --verify-replay, fail unless the final state hash is HASH
+  --compare ROM       run ROM alongside the first headless and report where they differ
+  --clock-skew SKEW   distort frame times: jitter:MS for up to MS ms either way, or
+                      a comma separated list of frame times in ms, repeated
+  --manual-clock      only advance time on advance_time control commands
+  --dump-state-on-exit FILE  write the machine state to FILE as JSON on exit, F12 dumps it
+  --crash-dump FILE   on an emulation error, write the machine state to FILE (- for stderr)
+  --monitor           debug from a command line on stdin instead of a window
+  --input-script FILE drive a headless run with scripted input and assertions
+  --self-test         check the interpreter against built-in test programs
+  --help              show this message
+
+Exit codes:
+  0  clean exit
+  1  invalid arguments
+  2  ROM not found or unreadable
+  3  ROM invalid, empty or too large
+  4  emulation error
+  5  input script assertion failed or unfinished, or replay mismatch
+";
+
+fn conf(variant: Option<Variant>) -> Conf {
+    let title = match variant {
+        Some(v) => format!("Chip8 Emulator ({})", v.name()),
+        None => String::from("Chip8 Emulator"),
+    };
     Conf {
-        window_title: String::from("Chip8 Emulator"),
+        window_title: title,
         window_width: 64 * 24,
         window_height: 32 * 24,
         fullscreen: false,
@@ -13,33 +135,2441 @@ fn conf() -> Conf {
     }
 }
 
-#[macroquad::main(conf)]
-async fn main() {
-    let args: Vec<String> = env::args().collect();
+#[derive(Clone)]
+struct Options {
+    rom: Option<String>,
+    frameskip: u32,
+    threaded: bool,
+    timing: Timing,
+    // Guessed from each ROM when not given.
+    variant: Option<Variant>,
+    palette: Palette,
+    visual_beep: bool,
+    crt: bool,
+    grid: bool,
+    flicker: FlickerMode,
+    input_polling: InputPolling,
+    min_sound: Option<u8>,
+    ips: Option<u32>,
+    bindings: KeyBindings,
+    font: Option<Font>,
+    heatmap: bool,
+    rewind: usize,
+    rewind_interval: u32,
+    script: Option<String>,
+    headless: Option<u64>,
+    input_script: Option<String>,
+    monitor: bool,
+    crash_dump: Option<DumpTarget>,
+    dump_state: Option<String>,
+    clock_skew: Option<ClockSkew>,
+    manual_clock: bool,
+    control_port: Option<u16>,
+    record_audio: Option<String>,
+    warn_overflow: bool,
+    // Some(true) to stop on a jump to an odd address, Some(false) to warn.
+    misaligned: Option<bool>,
+    low_memory: Option<LowMemoryProtection>,
+    warn_stack: Option<usize>,
+    profile: bool,
+    log_frames: bool,
+    profile_json: Option<String>,
+    seed: Option<u64>,
+    record_input: Option<String>,
+    verify_replay: Option<String>,
+    compare: Option<String>,
+    expect_hash: Option<u64>,
+    help: bool,
+    self_test: bool,
+}
+
+fn write_state_json(path: &str, e: &Chip8) -> io::Result<()> {
+    fs::write(path, serde_json::to_string_pretty(&e.state_json())? + "\n")
+}
+
+// Writes `name`.state, a save state that loads like any save slot, plus the JSON state
+// dump and a diagnostic dump with the recent instructions, into `dir`. Returns the
+// path they share, without an extension.
+fn write_snapshot(dir: &Path, name: &str, e: &Chip8) -> io::Result<PathBuf> {
+    let base = dir.join(name);
+    fs::create_dir_all(dir)?;
+    fs::write(base.with_extension("state"), e.save_state())?;
+    write_state_json(&base.with_extension("json").to_string_lossy(), e)?;
+    fs::write(base.with_extension("txt"), e.diagnostic_dump("snapshot"))?;
+    Ok(base)
+}
+
+// `state.json` becomes `state-crash.json`.
+fn crash_path(path: &str) -> String {
+    let p = Path::new(path);
+    match (p.file_stem(), p.extension()) {
+        (Some(stem), Some(ext)) => p
+            .with_file_name(format!(
+                "{}-crash.{}",
+                stem.to_string_lossy(),
+                ext.to_string_lossy()
+            ))
+            .to_string_lossy()
+            .into_owned(),
+        _ => format!("{}-crash", path),
+    }
+}
+
+// `jitter:MS`, or frame times in ms separated by commas.
+fn parse_clock_skew(spec: &str) -> Result<ClockSkew, String> {
+    let invalid = || format!("Invalid clock skew: {}", spec);
+    let ms = |s: &str| {
+        s.trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|t| t.is_finite() && *t >= 0.0)
+            .map(|t| t / 1000.0)
+            .ok_or_else(invalid)
+    };
+    match spec.strip_prefix("jitter:") {
+        Some(d) => Ok(ClockSkew::Jitter(ms(d)?)),
+        None => Ok(ClockSkew::Sequence(
+            spec.split(',').map(ms).collect::<Result<_, _>>()?,
+        )),
+    }
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Options {
+            rom: None,
+            frameskip: 0,
+            threaded: false,
+            timing: Timing::Fixed,
+            variant: None,
+            palette: Palette::Classic,
+            visual_beep: false,
+            crt: false,
+            grid: false,
+            flicker: FlickerMode::Accurate,
+            input_polling: InputPolling::PerFrame,
+            min_sound: None,
+            ips: None,
+            bindings: KeyBindings::default(),
+            font: None,
+            heatmap: false,
+            rewind: 0,
+            rewind_interval: DEFAULT_REWIND_INTERVAL,
+            script: None,
+            headless: None,
+            input_script: None,
+            monitor: false,
+            crash_dump: None,
+            dump_state: None,
+            clock_skew: None,
+            manual_clock: false,
+            control_port: None,
+            record_audio: None,
+            warn_overflow: false,
+            misaligned: None,
+            low_memory: None,
+            warn_stack: None,
+            profile: false,
+            log_frames: false,
+            profile_json: None,
+            seed: None,
+            record_input: None,
+            verify_replay: None,
+            compare: None,
+            expect_hash: None,
+            help: false,
+            self_test: false,
+        };
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--frameskip" => {
+                    let n = args.next().ok_or("--frameskip expects a number")?;
+                    options.frameskip = n
+                        .parse()
+                        .map_err(|_| format!("Invalid frameskip value: {}", n))?;
+                }
+                "--threaded" => options.threaded = true,
+                "--timing" => {
+                    options.timing = match args.next().map(String::as_str) {
+                        Some("fixed") => Timing::Fixed,
+                        Some("vip") => Timing::Vip,
+                        _ => return Err(String::from("--timing expects fixed or vip")),
+                    }
+                }
+                "--variant" => {
+                    options.variant = Some(
+                        args.next()
+                            .and_then(|name| Variant::from_name(name))
+                            .ok_or("--variant expects chip8, hires or megachip")?,
+                    );
+                }
+                "--palette" => {
+                    options.palette = args
+                        .next()
+                        .and_then(|name| Palette::from_name(name))
+                        .ok_or("--palette expects classic, gameboy, amber or blue")?;
+                }
+                "--bindings" => {
+                    let path = args.next().ok_or("--bindings expects a file")?;
+                    let source =
+                        fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+                    options.bindings = KeyBindings::default()
+                        .parse(&source)
+                        .map_err(|e| format!("{}: {}", path, e))?;
+                }
+                "--font" => {
+                    let path = args.next().ok_or("--font expects a file")?;
+                    let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+                    options.font = Some(Font::new(data).map_err(|e| format!("{}: {}", path, e))?);
+                }
+                "--persistence" => options.flicker = FlickerMode::Persistence,
+                "--poll-every-cycle" => options.input_polling = InputPolling::PerCycle,
+                "--min-sound" => {
+                    let n = args.next().ok_or("--min-sound expects a frame count")?;
+                    options.min_sound = Some(
+                        n.parse()
+                            .map_err(|_| format!("Invalid frame count: {}", n))?,
+                    );
+                }
+                "--ips" => {
+                    let n = args.next().ok_or("--ips expects an instruction count")?;
+                    options.ips = Some(
+                        n.parse()
+                            .map_err(|_| format!("Invalid instruction count: {}", n))?,
+                    );
+                }
+                "--rewind" => {
+                    let n = args.next().ok_or("--rewind expects a state count")?;
+                    options.rewind = n
+                        .parse()
+                        .map_err(|_| format!("Invalid state count: {}", n))?;
+                }
+                "--rewind-every" => {
+                    let n = args.next().ok_or("--rewind-every expects a frame count")?;
+                    options.rewind_interval = n
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| format!("Invalid frame count: {}", n))?;
+                }
+                "--crt" => options.crt = true,
+                "--grid" => options.grid = true,
+                "--visual-beep" => options.visual_beep = true,
+                "--heatmap" => options.heatmap = true,
+                "--warn-overflow" => options.warn_overflow = true,
+                "--misaligned" => {
+                    options.misaligned = match args.next().map(String::as_str) {
+                        Some("warn") => Some(false),
+                        Some("error") => Some(true),
+                        _ => return Err(String::from("--misaligned expects warn or error")),
+                    };
+                }
+                "--low-memory" => {
+                    options.low_memory = Some(match args.next().map(String::as_str) {
+                        Some("off") => LowMemoryProtection::Off,
+                        Some("warn") => LowMemoryProtection::Warn,
+                        Some("error") => LowMemoryProtection::Error,
+                        _ => return Err(String::from("--low-memory expects off, warn or error")),
+                    });
+                }
+                "--warn-stack" => {
+                    let n = args.next().ok_or("--warn-stack expects a depth")?;
+                    options.warn_stack = Some(
+                        n.parse()
+                            .map_err(|_| format!("Invalid stack depth: {}", n))?,
+                    );
+                }
+                "--profile" => options.profile = true,
+                "--log-frames" => options.log_frames = true,
+                "--profile-json" => {
+                    let path = args.next().ok_or("--profile-json expects a file")?;
+                    options.profile_json = Some(path.clone());
+                }
+                "--script" => {
+                    let path = args.next().ok_or("--script expects a Lua file")?;
+                    options.script = Some(path.clone());
+                }
+                "--headless" => {
+                    let n = args.next().ok_or("--headless expects a frame count")?;
+                    options.headless = Some(
+                        n.parse()
+                            .map_err(|_| format!("Invalid frame count: {}", n))?,
+                    );
+                }
+                "--monitor" => options.monitor = true,
+                "--input-script" => {
+                    let path = args.next().ok_or("--input-script expects a file")?;
+                    options.input_script = Some(path.clone());
+                }
+                "--crash-dump" => {
+                    options.crash_dump = match args.next().map(String::as_str) {
+                        Some("-") => Some(DumpTarget::Stderr),
+                        Some(path) => Some(DumpTarget::File(path.to_string())),
+                        None => return Err(String::from("--crash-dump expects a file or -")),
+                    }
+                }
+                "--clock-skew" => {
+                    let skew = args
+                        .next()
+                        .ok_or("--clock-skew expects jitter:MS or frame times")?;
+                    options.clock_skew = Some(parse_clock_skew(skew)?);
+                }
+                "--manual-clock" => options.manual_clock = true,
+                "--dump-state-on-exit" => {
+                    let path = args.next().ok_or("--dump-state-on-exit expects a file")?;
+                    options.dump_state = Some(path.clone());
+                }
+                "--control-port" => {
+                    let port = args.next().ok_or("--control-port expects a port")?;
+                    options.control_port = Some(
+                        port.parse()
+                            .map_err(|_| format!("Invalid port: {}", port))?,
+                    );
+                }
+                "--record-audio" => {
+                    let path = args.next().ok_or("--record-audio expects a WAV file")?;
+                    options.record_audio = Some(path.clone());
+                }
+                "--seed" => {
+                    let n = args.next().ok_or("--seed expects a number")?;
+                    options.seed = Some(n.parse().map_err(|_| format!("Invalid seed: {}", n))?);
+                }
+                "--record-input" => {
+                    let path = args.next().ok_or("--record-input expects a file")?;
+                    options.record_input = Some(path.clone());
+                }
+                "--compare" => {
+                    let path = args.next().ok_or("--compare expects a ROM")?;
+                    options.compare = Some(path.clone());
+                }
+                "--verify-replay" => {
+                    let path = args.next().ok_or("--verify-replay expects an input log")?;
+                    options.verify_replay = Some(path.clone());
+                }
+                "--expect-hash" => {
+                    let hash = args.next().ok_or("--expect-hash expects a hash")?;
+                    let digits = hash.trim_start_matches("0x").trim_start_matches("0X");
+                    options.expect_hash = Some(
+                        u64::from_str_radix(digits, 16)
+                            .map_err(|_| format!("Invalid hash: {}", hash))?,
+                    );
+                }
+                "--help" | "-h" => options.help = true,
+                "--self-test" => options.self_test = true,
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ => options.rom = Some(arg.clone()),
+            }
+        }
+        if options.record_input.is_some() {
+            if options.threaded {
+                return Err(String::from(
+                    "--record-input can't be combined with --threaded",
+                ));
+            }
+            // A replay needs the seed, so recording always uses one.
+            options.seed = options.seed.or_else(|| Some(::rand::random()));
+        }
+        if options.profiling() && options.threaded {
+            return Err(String::from("--profile can't be combined with --threaded"));
+        }
+        if options.log_frames && options.threaded {
+            return Err(String::from(
+                "--log-frames can't be combined with --threaded",
+            ));
+        }
+        if options.crt && options.threaded {
+            return Err(String::from("--crt can't be combined with --threaded"));
+        }
+        if options.monitor && options.threaded {
+            return Err(String::from("--monitor can't be combined with --threaded"));
+        }
+        if options.dump_state.is_some() && options.threaded {
+            return Err(String::from(
+                "--dump-state-on-exit can't be combined with --threaded",
+            ));
+        }
+        if options.clock_skew.is_some() && options.threaded {
+            return Err(String::from(
+                "--clock-skew can't be combined with --threaded",
+            ));
+        }
+        if options.manual_clock && options.control_port.is_none() {
+            return Err(String::from("--manual-clock needs --control-port"));
+        }
+        if options.control_port.is_some() && options.threaded {
+            return Err(String::from(
+                "--control-port can't be combined with --threaded",
+            ));
+        }
+        if options.script.is_some() {
+            if !cfg!(feature = "lua") {
+                return Err(String::from("--script needs a build with the lua feature"));
+            }
+            if options.threaded {
+                return Err(String::from("--script can't be combined with --threaded"));
+            }
+        }
+        Ok(options)
+    }
+
+    // These options with the variant decided for the ROM at `path`: the one given with
+    // --variant, or else a guess from the ROM.
+    fn for_rom(&self, path: &str) -> Result<Options, Chip8Error> {
+        let mut options = self.clone();
+        if options.variant.is_none() {
+            let guess = emulator::detect_variant(&emulator::read_rom(path)?, path);
+            println!("Variant: {} ({})", guess.variant.name(), guess.reason);
+            options.variant = Some(guess.variant);
+        }
+        Ok(options)
+    }
+
+    // Writes the --dump-state-on-exit dump, or its -crash sibling after an error.
+    fn dump_state(&self, e: &Chip8, crashed: bool) {
+        let Some(path) = &self.dump_state else {
+            return;
+        };
+        let path = if crashed {
+            crash_path(path)
+        } else {
+            path.clone()
+        };
+        if let Err(err) = write_state_json(&path, e) {
+            eprintln!("Error writing the state dump {}: {}", path, err);
+        }
+    }
+
+    fn profiling(&self) -> bool {
+        self.profile || self.profile_json.is_some()
+    }
+
+    fn log_frame(&self, e: &Chip8) {
+        if self.log_frames {
+            println!("Frame instructions: {}", e.last_frame_instructions());
+        }
+    }
+
+    // Prints or writes the hotspot report --profile and --profile-json asked for.
+    fn report_profile(&self, e: &Chip8) {
+        let Some(report) = e.profile_report(PROFILE_RANGES) else {
+            return;
+        };
+        if self.profile {
+            print!("{}", report);
+        }
+        if let Some(path) = &self.profile_json {
+            if let Err(err) = fs::write(path, report.to_json().to_string()) {
+                eprintln!("Error writing the profile {}: {}", path, err);
+            }
+        }
+    }
+
+    fn configure_and_load(&self, e: &mut Chip8, path: &str) -> Result<(), Chip8Error> {
+        self.configure(e);
+        e.load_from_file(path)
+    }
 
-    if args.len() < 2 {
-        eprintln!("ROM file not specified in the arguements");
-        exit(1);
+    fn configure(&self, e: &mut Chip8) {
+        e.set_variant(self.variant.unwrap_or_default());
+        if let Some(protection) = self.low_memory {
+            e.set_protect_low_memory(protection);
+        }
+        // Largest square pixels that fit the display into the window.
+        let (cols, rows) = (e.screen().cols(), e.screen().rows());
+        let size = (64 * 24 / cols).min(32 * 24 / rows);
+        e.set_pixel_size(size, size);
+        e.set_timing(self.timing);
+        e.set_palette(self.palette);
+        e.set_flicker_mode(self.flicker);
+        e.set_grid(self.grid);
+        e.set_input_polling(self.input_polling);
+        if let Some(frames) = self.min_sound {
+            e.set_min_sound_frames(frames);
+        }
+        if let Some(ips) = self.ips {
+            e.set_ips(ips);
+        }
+        e.set_bindings(self.bindings);
+        if let Some(font) = &self.font {
+            e.register_font(CUSTOM_FONT, font.clone());
+            // Registered just above.
+            let _ = e.set_font(CUSTOM_FONT);
+        }
+        e.set_heatmap_enabled(self.heatmap);
+        e.set_rewind(self.rewind, self.rewind_interval);
+        e.set_crash_dump(self.crash_dump.clone());
+        e.set_idle_detection(Some(IDLE_ITERATIONS));
+        e.set_profiling(self.profiling());
+        if let Some(seed) = self.seed {
+            e.seed_rng(seed);
+        }
+        if self.warn_overflow {
+            e.on_overflow(Box::new(|pc, opcode| {
+                eprintln!(
+                    "Overflow at {:#05X}: {:04X} {}",
+                    pc,
+                    opcode,
+                    disassemble(opcode)
+                )
+            }));
+        }
+        match self.misaligned {
+            Some(true) => e.set_misaligned_jump_error(true),
+            Some(false) => e.on_misaligned_jump(Box::new(|pc, target| {
+                eprintln!("Jump to odd address {:#05X} at {:#05X}", target, pc)
+            })),
+            None => {}
+        }
+        if let Some(threshold) = self.warn_stack {
+            e.on_deep_stack(
+                threshold,
+                Box::new(|pc, depth| eprintln!("Stack depth {} at {:#05X}", depth, pc)),
+            );
+        }
+    }
+}
+
+// Decides which emulated frames get presented. Presenting only happens every
+// `skip + 1`th frame, and up to MAX_AUTO_SKIP frames in a row are also dropped
+// while the emulation is falling behind.
+struct FrameSkip {
+    skip: u32,
+    skipped: u32,
+}
+
+impl FrameSkip {
+    fn should_present(&mut self, behind: bool) -> bool {
+        if self.skipped < self.skip || (behind && self.skipped < MAX_AUTO_SKIP) {
+            self.skipped += 1;
+            return false;
+        }
+        self.skipped = 0;
+        true
     }
+}
+
+// Rendered and emulated frames per second, reported once a second.
+#[derive(Default)]
+struct FrameRate {
+    elapsed: f64,
+    rendered: u32,
+    emulated: u32,
+}
+
+impl FrameRate {
+    fn update(&mut self, dt: f64, emulated: u32, rendered: bool) {
+        self.elapsed += dt;
+        self.emulated += emulated;
+        self.rendered += rendered as u32;
+        if self.elapsed >= 1.0 {
+            println!(
+                "Framerate : {} rendered / {} emulated",
+                self.rendered, self.emulated
+            );
+            *self = FrameRate::default();
+        }
+    }
+}
+
+// Performance overlay, toggled with F9. The text is only reformatted every
+// HUD_INTERVAL seconds, into the same strings.
+#[derive(Default)]
+struct Hud {
+    visible: bool,
+    elapsed: f64,
+    last: Stats,
+    frame_times: VecDeque<f32>,
+    lines: [String; 5],
+}
+
+impl Hud {
+    // `speed` is the configured multiplier, shown next to the one measured from frames.
+    fn update(
+        &mut self,
+        dt: f64,
+        stats: Stats,
+        target_ips: Option<u64>,
+        speed: f32,
+        quirks: &QuirkConfig,
+    ) {
+        if self.frame_times.len() == SPARKLINE_LEN {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(dt as f32);
+        self.elapsed += dt;
+        if self.elapsed < HUD_INTERVAL {
+            return;
+        }
+
+        // Counters restart when a ROM is reset or opened.
+        let rate = |now: u64, before: u64| now.saturating_sub(before) as f64 / self.elapsed;
+        let ips = rate(stats.instructions, self.last.instructions);
+        let draws = rate(stats.draws, self.last.draws);
+        let frames = stats.frames.saturating_sub(self.last.frames);
+        let dirty = stats.dirty_frames.saturating_sub(self.last.dirty_frames);
+        let frame_ms =
+            self.frame_times.iter().sum::<f32>() * 1000.0 / self.frame_times.len() as f32;
 
+        for line in &mut self.lines {
+            line.clear();
+        }
+        let _ = match target_ips {
+            Some(target) => write!(self.lines[0], "IPS {:.0} / {}", ips, target),
+            None => write!(self.lines[0], "IPS {:.0} (VIP timing)", ips),
+        };
+        let _ = write!(self.lines[1], "Frame {:.1} ms", frame_ms);
+        let _ = write!(
+            self.lines[2],
+            "Draws/s {:.0}  dirty {:.0}%",
+            draws,
+            dirty as f64 * 100.0 / frames.max(1) as f64
+        );
+        let _ = write!(
+            self.lines[3],
+            "Speed {:.2}x  measured {:.2}x",
+            speed,
+            rate(stats.frames, self.last.frames) / 60.0
+        );
+        let _ = write!(self.lines[4], "{}", quirks);
+
+        self.last = stats;
+        self.elapsed = 0.0;
+    }
+
+    fn draw(&self) {
+        if !self.visible {
+            return;
+        }
+        draw_rectangle(10.0, 10.0, 620.0, 200.0, Color::new(0.0, 0.0, 0.0, 0.7));
+        for (i, line) in self.lines.iter().enumerate() {
+            draw_text(line, 20.0, 35.0 + i as f32 * 24.0, 24.0, GREEN);
+        }
+        // One bar per frame, full height at 50ms.
+        for (i, t) in self.frame_times.iter().enumerate() {
+            let h = (t * 1000.0 / 50.0).min(1.0) * 40.0;
+            draw_rectangle(20.0 + i as f32 * 2.0, 200.0 - h, 2.0, h, GREEN);
+        }
+    }
+}
+
+// Moves `steps` entries through SPEEDS from `speed`, stopping at either end. A speed
+// between two entries counts as the one above it.
+fn next_speed(speed: f32, steps: isize) -> f32 {
+    let current = SPEEDS
+        .iter()
+        .position(|&s| s >= speed)
+        .unwrap_or(SPEEDS.len() - 1);
+    SPEEDS[current.saturating_add_signed(steps).min(SPEEDS.len() - 1)]
+}
+
+fn speed_toast(speed: f32) -> String {
+    format!("Speed {:.0}%", speed * 100.0)
+}
+
+fn exit_code(e: &Chip8Error) -> i32 {
+    match e {
+        Chip8Error::RomNotFound(_) | Chip8Error::Io(_) => EXIT_ROM_UNREADABLE,
+        Chip8Error::RomTooLarge { .. } | Chip8Error::EmptyRom => EXIT_ROM_INVALID,
+        Chip8Error::Script(_) => EXIT_USAGE,
+        _ => EXIT_EMULATION,
+    }
+}
+
+fn disassemble(opcode: u16) -> String {
+    match Instruction::decode(opcode) {
+        Some(ins) => ins.to_string(),
+        None => String::from("???"),
+    }
+}
+
+// `e` along with the address and disassembly of the instruction it happened at.
+// `last` is the most recently fetched (pc, opcode).
+fn runtime_message(e: &Chip8Error, last: Option<&(u16, u16)>) -> String {
+    let Some(pc) = e.address() else {
+        return e.to_string();
+    };
+    match last {
+        Some(&(at, opcode)) if at == pc => format!(
+            "{} (faulting instruction {:#05X}: {:04X} {})",
+            e,
+            pc,
+            opcode,
+            disassemble(opcode)
+        ),
+        _ => format!("{} (faulting address {:#05X})", e, pc),
+    }
+}
+
+// Border shown instead of (or along with) the buzzer. Drawn straight to the window,
+// so it never ends up in the emulator's own output.
+fn draw_visual_beep(palette: Palette) {
+    draw_rectangle_lines(
+        0.0,
+        0.0,
+        screen_width(),
+        screen_height(),
+        12.0,
+        palette.foreground(),
+    );
+}
+
+enum Fault {
+    Load {
+        message: String,
+        code: i32,
+    },
+    Runtime {
+        message: String,
+        history: Vec<String>,
+    },
+}
+
+impl Fault {
+    fn load(e: &Chip8Error) -> Self {
+        Fault::Load {
+            message: e.to_string(),
+            code: exit_code(e),
+        }
+    }
+
+    fn runtime<'a>(e: &Chip8Error, history: impl IntoIterator<Item = &'a (u16, u16)>) -> Self {
+        let history: Vec<_> = history.into_iter().collect();
+        let message = runtime_message(e, history.last().copied());
+        let history = history
+            .into_iter()
+            .map(|&(pc, opcode)| format!("{:#05X}  {:04X}  {}", pc, opcode, disassemble(opcode)))
+            .collect();
+        Fault::Runtime { message, history }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            Fault::Load { message, .. } | Fault::Runtime { message, .. } => message,
+        }
+    }
+
+    // What the process exits with if the user quits while this is shown.
+    fn exit_code(&self) -> i32 {
+        match self {
+            Fault::Load { code, .. } => *code,
+            Fault::Runtime { .. } => EXIT_EMULATION,
+        }
+    }
+
+    fn draw(&self, prompt: Option<&str>) {
+        let mut lines = Vec::new();
+        match self {
+            Fault::Load { message, .. } => {
+                lines.push(message.clone());
+                lines.push(String::new());
+                lines.push(String::from(
+                    "Drop a ROM here, press Ctrl+O to open one or Esc to quit",
+                ));
+            }
+            Fault::Runtime { message, history } => {
+                lines.push(format!("Emulation error: {}", message));
+                lines.push(String::new());
+                lines.push(String::from("Recent instructions:"));
+                lines.extend(history.iter().map(|l| format!("  {}", l)));
+                lines.push(String::new());
+                lines.push(String::from(
+                    "Press R to reset, drop a ROM here, press Ctrl+O to open one or Esc to quit",
+                ));
+            }
+        }
+        if let Some(path) = prompt {
+            lines.push(format!("Open ROM: {}_", path));
+        }
+
+        clear_background(BLACK);
+        for (i, line) in lines.iter().enumerate() {
+            draw_text(line, 20.0, 40.0 + i as f32 * 30.0, 30.0, WHITE);
+        }
+    }
+}
+
+enum Machine {
+    Local(Box<Chip8>),
+    // Emulation runs on its own thread, see `chip8::runner`.
+    Threaded(Runner),
+}
+
+fn open_rom(path: &str, options: &Options) -> Result<Machine, Chip8Error> {
+    let options = &options.for_rom(path)?;
+    if options.threaded {
+        let options = options.clone();
+        let runner = Runner::spawn(emulator::read_rom(path)?, move |e| options.configure(e))?;
+        return Ok(Machine::Threaded(runner));
+    }
     let mut e = Chip8::new();
-    let res = e.load_from_file(&args[1]);
+    options.configure_and_load(&mut e, path)?;
+    Ok(Machine::Local(Box::new(e)))
+}
+
+// The first of the files dropped on the window that has a path. Web builds only get
+// the bytes, which `open` has no use for.
+fn dropped_rom(files: Vec<DroppedFile>) -> Option<String> {
+    files
+        .into_iter()
+        .find_map(|f| f.path)
+        .map(|p| p.to_string_lossy().into_owned())
+}
 
-    if let Err(e) = res {
-        match e.kind() {
-            io::ErrorKind::NotFound => {
-                eprintln!("No such file exists");
+// Reads a ROM path typed into the window. Returns the path once Enter is pressed.
+fn read_prompt(prompt: &mut String) -> Option<String> {
+    while let Some(c) = get_char_pressed() {
+        if !c.is_control() {
+            prompt.push(c);
+        }
+    }
+    if is_key_pressed(KeyCode::Backspace) {
+        prompt.pop();
+    }
+    if is_key_pressed(KeyCode::Enter) {
+        return Some(prompt.trim().to_string());
+    }
+    None
+}
+
+struct App {
+    options: Options,
+    rom_path: Option<String>,
+    machine: Option<Machine>,
+    fault: Option<Fault>,
+    prompt: Option<String>,
+    target: RenderTarget,
+    camera: Camera2D,
+    frameskip: FrameSkip,
+    framerate: FrameRate,
+    hud: Hud,
+    heatmap_view: HeatmapView,
+    crt: Crt,
+    overlay: Overlay,
+    slot_picker: SlotPicker,
+    control: Option<ControlServer>,
+    speed: f32,
+    input_log: Option<InputLog>,
+    audio: Option<AudioRecorder>,
+    clock: FrameClock,
+    // Set by SIGUSR1, see `take_snapshot`.
+    snapshot_requested: Arc<AtomicBool>,
+    #[cfg(feature = "lua")]
+    script: Option<Script>,
+    session: Session,
+}
+
+impl App {
+    fn new(options: Options) -> Self {
+        let (width, height) = (screen_width(), screen_height());
+        let target = render_target(width as u32, height as u32);
+        target.texture.set_filter(FilterMode::Nearest);
+        let camera = Camera2D {
+            target: vec2(width / 2.0, height / 2.0),
+            zoom: vec2(2.0 / width, 2.0 / height),
+            render_target: Some(target.clone()),
+            ..Default::default()
+        };
+
+        let mut app = App {
+            rom_path: options.rom.clone(),
+            machine: None,
+            fault: None,
+            prompt: None,
+            target,
+            camera,
+            frameskip: FrameSkip {
+                skip: options.frameskip,
+                skipped: 0,
+            },
+            framerate: FrameRate::default(),
+            hud: Hud::default(),
+            heatmap_view: HeatmapView::new(options.heatmap),
+            crt: Crt::new(options.crt),
+            overlay: Overlay::default(),
+            slot_picker: SlotPicker::new(),
+            control: None,
+            speed: 1.0,
+            input_log: None,
+            audio: None,
+            clock: FrameClock::new(FRAME_TIME),
+            snapshot_requested: Arc::new(AtomicBool::new(false)),
+            #[cfg(feature = "lua")]
+            script: None,
+            session: Session::default(),
+            options,
+        };
+        app.clock.set_skew(app.options.clock_skew.clone());
+        app.clock.set_manual(app.options.manual_clock);
+        #[cfg(unix)]
+        if let Err(e) = signal_hook::flag::register(
+            signal_hook::consts::SIGUSR1,
+            Arc::clone(&app.snapshot_requested),
+        ) {
+            eprintln!("Can't listen for SIGUSR1: {}", e);
+        }
+        if let Some(port) = app.options.control_port {
+            match ControlServer::bind(port) {
+                Ok(server) => app.control = Some(server),
+                Err(e) => eprintln!("Can't listen on port {}: {}", port, e),
+            }
+        }
+        if app.options.record_audio.is_some() {
+            app.toggle_audio_recording();
+        }
+        match app.rom_path.clone() {
+            Some(path) => app.open(path),
+            None => {
+                app.fault = Some(Fault::Load {
+                    message: String::from("ROM file not specified in the arguments"),
+                    code: EXIT_USAGE,
+                })
             }
-            _ => {
-                eprintln!("Error reading the file");
+        }
+        app
+    }
+
+    fn open(&mut self, path: String) {
+        match open_rom(&path, &self.options) {
+            Ok(mut m) => {
+                let quirks = match &m {
+                    Machine::Local(e) => *e.quirks(),
+                    Machine::Threaded(runner) => runner.frame().quirks,
+                };
+                println!("Quirks: {}", quirks);
+                if let Machine::Local(e) = &mut m {
+                    e.set_heatmap_enabled(self.heatmap_view.visible || self.options.heatmap);
+                }
+                self.machine = Some(m);
+                self.set_speed(self.speed);
+                // Only the last ROM opened is recorded.
+                if self.options.record_input.is_some() {
+                    self.input_log = Some(InputLog::new(self.options.seed));
+                }
+                self.rom_path = Some(path);
+                self.fault = None;
+                self.overlay.paused = false;
+            }
+            Err(e) => self.fault = Some(Fault::load(&e)),
+        }
+        #[cfg(feature = "lua")]
+        if let Err(e) = self.attach_script() {
+            self.fault = Some(Fault::load(&e));
+        }
+    }
+
+    // Loads a fresh copy of the --script for the machine that was just opened.
+    #[cfg(feature = "lua")]
+    fn attach_script(&mut self) -> Result<(), Chip8Error> {
+        self.script = None;
+        if let (Some(path), Some(Machine::Local(e))) = (&self.options.script, self.machine.as_mut())
+        {
+            let script = Script::load(path)?;
+            script.attach(e);
+            self.script = Some(script);
+        }
+        Ok(())
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.overlay.paused = paused;
+        if let Some(Machine::Threaded(runner)) = &self.machine {
+            runner.send(Command::Pause(paused));
+        }
+    }
+
+    // Restarts the running program from the copy in memory, without reading the file.
+    fn reset(&mut self) {
+        match self.machine.as_mut() {
+            Some(Machine::Local(e)) => {
+                // Only fails if nothing was loaded, which a running machine always has.
+                let _ = e.reset();
+            }
+            Some(Machine::Threaded(runner)) => runner.send(Command::Reset),
+            None => return,
+        }
+        self.overlay.paused = false;
+        self.overlay.toast("Reset");
+    }
+
+    // Picks up a rebuilt ROM. A missing or broken file is reported and the current
+    // program keeps running.
+    fn reload(&mut self) {
+        let Some(path) = self.rom_path.clone() else {
+            return;
+        };
+        let result = match self.machine.as_mut() {
+            Some(Machine::Local(e)) => e.reload(),
+            _ => emulator::read_rom(&path).map(|_| self.open(path.clone())),
+        };
+        match result {
+            Ok(()) if self.fault.is_none() => self.overlay.toast(format!("Reloaded {}", path)),
+            Ok(()) => {}
+            Err(e) => self.overlay.toast(format!("Reload failed: {}", e)),
+        }
+    }
+
+    // Shown while F6 is held, see `slot_picker`.
+    fn update_slot_picker(&mut self) {
+        let (Some(rom), Some(Machine::Local(e))) = (self.rom_path.clone(), self.machine.as_mut())
+        else {
+            if is_key_pressed(KeyCode::F6) {
+                self.overlay.toast("Save states need a local machine");
+            }
+            return;
+        };
+        let palette = e.screen().palette();
+        if is_key_pressed(KeyCode::F6) {
+            self.slot_picker.refresh(&rom, palette);
+        }
+        self.slot_picker.visible = is_key_down(KeyCode::F6);
+        if !self.slot_picker.visible {
+            return;
+        }
+
+        let moves = [
+            (KeyCode::Left, -1, 0),
+            (KeyCode::Right, 1, 0),
+            (KeyCode::Up, 0, -1),
+            (KeyCode::Down, 0, 1),
+        ];
+        for (key, dx, dy) in moves {
+            if is_key_pressed(key) {
+                self.slot_picker.move_selection(dx, dy);
+            }
+        }
+        let slot = self.slot_picker.selected();
+        if is_key_pressed(KeyCode::Enter) {
+            self.load_slot(slot);
+        } else if is_key_pressed(KeyCode::S) {
+            self.save_slot(slot);
+        } else if is_key_pressed(KeyCode::Delete) {
+            let message = match fs::remove_file(slot_picker::slot_path(&rom, slot)) {
+                Ok(()) => format!("Cleared slot {}", slot),
+                Err(err) => format!("Can't clear slot {}: {}", slot, err),
+            };
+            self.slot_picker.refresh(&rom, palette);
+            self.overlay.toast(message);
+        }
+    }
+
+    // F5 saves to the slot selected in the slot picker, so it can be picked once and
+    // reused.
+    fn save_slot(&mut self, slot: usize) {
+        let (Some(rom), Some(Machine::Local(e))) = (&self.rom_path, self.machine.as_ref()) else {
+            self.overlay.toast("Save states need a local machine");
+            return;
+        };
+        let message = slot_picker::save_slot(rom, slot, e);
+        self.slot_picker.refresh(rom, e.screen().palette());
+        self.overlay.toast(message);
+    }
+
+    fn load_slot(&mut self, slot: usize) {
+        let (Some(rom), Some(Machine::Local(e))) = (&self.rom_path, self.machine.as_mut()) else {
+            self.overlay.toast("Save states need a local machine");
+            return;
+        };
+        let message = slot_picker::load_slot(rom, slot, e);
+        self.overlay.toast(message);
+    }
+
+    // Restarts the ROM under the next variant, for when the guess was wrong.
+    fn cycle_variant(&mut self) {
+        let Some(path) = self.rom_path.clone() else {
+            return;
+        };
+        let current = match self.machine.as_ref() {
+            Some(Machine::Local(e)) => e.variant(),
+            _ => self.options.variant.unwrap_or_default(),
+        };
+        let n = Variant::ALL.iter().position(|&v| v == current).unwrap_or(0);
+        let next = Variant::ALL[(n + 1) % Variant::ALL.len()];
+        self.options.variant = Some(next);
+        self.open(path);
+        self.overlay.toast(format!("Variant: {}", next.name()));
+    }
+
+    // Kept across ROM loads.
+    fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+        match self.machine.as_mut() {
+            Some(Machine::Local(e)) => e.set_speed_multiplier(speed),
+            Some(Machine::Threaded(runner)) => runner.send(Command::Speed(speed as f64)),
+            None => {}
+        }
+    }
+
+    // Every press shows the new speed, even when it's already at the end of SPEEDS.
+    fn change_speed(&mut self, steps: isize) {
+        let speed = next_speed(self.speed, steps);
+        self.set_speed(speed);
+        self.overlay.toast(speed_toast(speed));
+    }
+
+    // See `write_snapshot`. It runs between host frames, so on an instruction boundary.
+    fn take_snapshot(&mut self) {
+        let Some(Machine::Local(e)) = self.machine.as_ref() else {
+            self.overlay.toast("Snapshots need a local machine");
+            return;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let rom = self
+            .rom_path
+            .as_deref()
+            .and_then(|p| Path::new(p).file_stem())
+            .map_or(String::from("chip8"), |s| s.to_string_lossy().into_owned());
+        let name = format!("{}-{}-{:03}", rom, now.as_secs(), now.subsec_millis());
+        let message = match write_snapshot(Path::new(SNAPSHOT_DIR), &name, e) {
+            Ok(base) => format!("Snapshot saved to {}", base.display()),
+            Err(err) => format!("Can't save the snapshot {}: {}", name, err),
+        };
+        self.overlay.toast(message);
+    }
+
+    // The effect is applied as the render target is drawn, which the threaded
+    // frontend doesn't use.
+    fn toggle_crt(&mut self) {
+        if matches!(self.machine, Some(Machine::Threaded(_))) {
+            self.overlay.toast("The CRT effect needs a local machine");
+            return;
+        }
+        let message = if self.crt.toggle() {
+            "CRT effect on"
+        } else {
+            "CRT effect off"
+        };
+        self.overlay.toast(message);
+    }
+
+    fn toggle_grid(&mut self) {
+        self.options.grid = !self.options.grid;
+        if let Some(Machine::Local(e)) = self.machine.as_mut() {
+            e.set_grid(self.options.grid);
+        }
+    }
+
+    fn dump_state(&mut self) {
+        let Some(Machine::Local(e)) = self.machine.as_ref() else {
+            self.overlay.toast("State dumps need a local machine");
+            return;
+        };
+        let path = self
+            .options
+            .dump_state
+            .as_deref()
+            .unwrap_or(DEFAULT_STATE_DUMP_PATH);
+        let message = match write_state_json(path, e) {
+            Ok(()) => format!("State dumped to {}", path),
+            Err(err) => format!("Can't dump the state to {}: {}", path, err),
+        };
+        self.overlay.toast(message);
+    }
+
+    fn toggle_audio_recording(&mut self) {
+        if let Some(recorder) = self.audio.take() {
+            self.overlay.recording = false;
+            match recorder.finish() {
+                Ok(()) => self.overlay.toast("Audio recording saved"),
+                Err(e) => self
+                    .overlay
+                    .toast(format!("Error saving the recording: {}", e)),
+            }
+            return;
+        }
+        let path = self
+            .options
+            .record_audio
+            .as_deref()
+            .unwrap_or(DEFAULT_AUDIO_PATH);
+        match AudioRecorder::create(path) {
+            Ok(recorder) => {
+                self.audio = Some(recorder);
+                self.overlay.recording = true;
+                self.overlay.toast(format!("Recording audio to {}", path));
+            }
+            Err(e) => self
+                .overlay
+                .toast(format!("Can't record to {}: {}", path, e)),
+        }
+    }
+
+    // Whether the beeper should sound now, never while paused or faulted.
+    fn sound_playing(&self) -> bool {
+        match self.machine.as_ref() {
+            _ if self.fault.is_some() || self.overlay.paused => false,
+            Some(Machine::Local(e)) => e.is_sound_active(),
+            Some(Machine::Threaded(runner)) => runner.frame().sound,
+            None => false,
+        }
+    }
+
+    // Samples the sound timer once per host frame, so the recording keeps wall-clock
+    // time whether the machine is running, paused or faulted.
+    fn record_audio(&mut self, dt: f64) {
+        let sound = self.sound_playing();
+        let Some(recorder) = self.audio.as_mut() else {
+            return;
+        };
+        if let Err(e) = recorder.record(dt, sound) {
+            self.audio = None;
+            self.overlay.recording = false;
+            self.overlay
+                .toast(format!("Error writing the recording, stopped: {}", e));
+        }
+    }
+
+    // Counting only runs while the view is up, unless --heatmap asked for it.
+    fn toggle_heatmap(&mut self) {
+        let Some(Machine::Local(e)) = self.machine.as_mut() else {
+            self.overlay.toast("The heatmap needs a local machine");
+            return;
+        };
+        self.heatmap_view.visible = !self.heatmap_view.visible;
+        e.set_heatmap_enabled(self.heatmap_view.visible || self.options.heatmap);
+    }
+
+    fn poll_control(&mut self) {
+        let Some(requests) = self.control.as_mut().map(ControlServer::poll) else {
+            return;
+        };
+        for request in requests {
+            let response = match request.command {
+                Ok(command) => self.control_command(&command),
+                Err(e) => control::error(e),
+            };
+            if let Some(control) = self.control.as_mut() {
+                control.reply(request.client, response);
             }
         }
-        exit(1);
     }
 
+    fn control_command(&mut self, command: &Value) -> Value {
+        let cmd = command["cmd"].as_str().unwrap_or_default();
+        let number = |name: &str| command[name].as_u64().and_then(|n| usize::try_from(n).ok());
+        match cmd {
+            "pause" | "resume" => {
+                self.set_paused(cmd == "pause");
+                return control::ok(json!({}));
+            }
+            // Under --manual-clock, the time the next host frames emulate.
+            "advance_time" => {
+                let Some(seconds) = command["seconds"].as_f64() else {
+                    return control::error("advance_time expects seconds");
+                };
+                if !self.clock.is_manual() {
+                    return control::error("advance_time needs --manual-clock");
+                }
+                self.clock.advance_time(seconds);
+                return control::ok(json!({}));
+            }
+            "reset" => {
+                if self.machine.is_none() {
+                    return control::error("No ROM running");
+                }
+                self.reset();
+                return control::ok(json!({}));
+            }
+            "load_rom" => {
+                let Some(path) = command["path"].as_str() else {
+                    return control::error("load_rom expects a path");
+                };
+                self.open(path.to_string());
+                return match &self.fault {
+                    Some(f) => control::error(f.message()),
+                    None => control::ok(json!({})),
+                };
+            }
+            _ => {}
+        }
+
+        let Some(Machine::Local(e)) = self.machine.as_mut() else {
+            return control::error("No ROM running");
+        };
+        match cmd {
+            "key" => {
+                let Some(key) = number("key").filter(|&k| k < 16) else {
+                    return control::error("key expects a key from 0 to 15");
+                };
+                e.inject_key(key as u8, command["down"].as_bool().unwrap_or(true));
+                control::ok(json!({}))
+            }
+            // Runs frames, even while paused.
+            "step" => {
+                let n = match control::step_frames(command) {
+                    Ok(n) => n,
+                    Err(e) => return control::error(e),
+                };
+                for _ in 0..n {
+                    if let Err(err) = e.tick() {
+                        let message = runtime_message(&err, e.recent_instructions().back());
+                        self.fault = Some(Fault::runtime(&err, e.recent_instructions()));
+                        return control::error(message);
+                    }
+                }
+                control::ok(json!({ "frames": n }))
+            }
+            "screenshot" => {
+                let screen = e.screen();
+                let text = screen.to_text();
+                control::ok(json!({
+                    "width": screen.cols(),
+                    "height": screen.rows(),
+                    "rows": text.lines().collect::<Vec<_>>(),
+                }))
+            }
+            "dump_state" => control::ok(e.state_json()),
+            "read_mem" => {
+                let (Some(addr), Some(len)) = (number("addr"), number("len")) else {
+                    return control::error("read_mem expects addr and len");
+                };
+                match e.memory().get(addr..addr.saturating_add(len)) {
+                    Some(data) => control::ok(json!({ "data": data })),
+                    None => control::error("read_mem out of bounds"),
+                }
+            }
+            _ => control::error(format!("Unknown command {}", cmd)),
+        }
+    }
+
+    fn request_quit(&mut self) {
+        self.session.request_quit();
+    }
+
+    // Handles one host frame. Returns whether the run is over.
+    fn update(&mut self) -> bool {
+        self.update_frame();
+        self.session.is_done()
+    }
+
+    fn update_frame(&mut self) {
+        let dt = get_frame_time() as f64;
+        self.overlay.update(dt as f32);
+        self.poll_control();
+        self.open_dropped();
+        if is_key_pressed(KeyCode::F8) {
+            self.toggle_audio_recording();
+        }
+        self.record_audio(dt);
+        let playing = self.sound_playing();
+        self.session.play(playing);
+        if self.fault.is_some() {
+            self.update_fault();
+            self.overlay.draw();
+            return;
+        }
+        if is_key_pressed(KeyCode::Escape) {
+            self.request_quit();
+            return;
+        }
+        if is_key_pressed(KeyCode::F1) {
+            self.reset();
+        }
+        let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+        if is_key_pressed(KeyCode::F5) {
+            match shift {
+                true => self.reload(),
+                false => self.save_slot(self.slot_picker.selected()),
+            }
+        }
+        self.update_slot_picker();
+        if is_key_pressed(KeyCode::F7) {
+            match shift {
+                true => self.cycle_variant(),
+                false => self.load_slot(self.slot_picker.selected()),
+            }
+        }
+        if is_key_pressed(KeyCode::F9) {
+            self.hud.visible = !self.hud.visible;
+        }
+        if is_key_pressed(KeyCode::F10) {
+            self.toggle_heatmap();
+        }
+        if is_key_pressed(KeyCode::F11) {
+            if let Some(Machine::Local(e)) = self.machine.as_mut() {
+                e.reset_heatmap();
+            }
+        }
+        if is_key_pressed(KeyCode::F4) || self.snapshot_requested.swap(false, Ordering::Relaxed) {
+            self.take_snapshot();
+        }
+        if is_key_pressed(KeyCode::F2) {
+            self.toggle_grid();
+        }
+        if is_key_pressed(KeyCode::F3) {
+            self.toggle_crt();
+        }
+        if is_key_pressed(KeyCode::F12) {
+            self.dump_state();
+        }
+        if is_key_pressed(KeyCode::Equal) || is_key_pressed(KeyCode::KpAdd) {
+            self.change_speed(1);
+        }
+        if is_key_pressed(KeyCode::Minus) || is_key_pressed(KeyCode::KpSubtract) {
+            self.change_speed(-1);
+        }
+        if is_key_pressed(KeyCode::P) {
+            self.set_paused(!self.overlay.paused);
+        }
+
+        match self.machine.as_mut() {
+            Some(Machine::Threaded(runner)) => {
+                if dt > FOCUS_STALL {
+                    runner.send(Command::ReleaseKeys);
+                }
+                runner.set_keys(self.options.bindings.poll());
+                if let Some(f) = runner.take_fault() {
+                    self.fault = Some(Fault::runtime(&f.error, &f.history));
+                }
+                let mut frame = runner.frame();
+                frame.screen.set_grid(self.options.grid);
+                if frame.exited {
+                    self.session.request_quit();
+                }
+                self.overlay.idle = frame.idle;
+                clear_background(frame.screen.palette().background());
+                frame.screen.draw();
+                if self.options.visual_beep && frame.sound {
+                    draw_visual_beep(frame.screen.palette());
+                }
+                // The runner has whatever speed was last sent to it.
+                self.hud
+                    .update(dt, frame.stats, frame.target_ips, self.speed, &frame.quirks);
+            }
+            Some(Machine::Local(e)) => {
+                if dt > FOCUS_STALL {
+                    e.release_all_keys();
+                }
+                // Held to rewind, one state per host frame, instead of running.
+                let rewinding = is_key_down(KeyCode::Backspace) && e.rewind();
+                let ticks = if self.overlay.paused || rewinding {
+                    0
+                } else {
+                    self.clock.advance(dt)
+                };
+                // Only when actually advanced, the count is left over while paused.
+                if ticks > 0 && self.clock.last_dropped() > 0 {
+                    let dropped = self.clock.last_dropped();
+                    self.overlay
+                        .toast(format!("Fell behind, dropped {} frames", dropped));
+                }
+                for _ in 0..ticks {
+                    let result = e.tick();
+                    self.options.log_frame(e);
+                    if let Some(log) = self.input_log.as_mut() {
+                        log.record(e.keyboard.keymap);
+                    }
+                    if let Err(err) = result {
+                        self.fault = Some(Fault::runtime(&err, e.recent_instructions()));
+                        break;
+                    }
+                    #[cfg(feature = "lua")]
+                    if let Some(script) = &self.script {
+                        script.on_frame(e);
+                    }
+                }
+
+                if e.has_exited() {
+                    self.session.request_quit();
+                }
+                self.overlay.idle = e.is_idle();
+
+                // A state loaded while paused still has to show up.
+                let invalidated = e.take_screen_invalidated();
+                let present = invalidated
+                    || ticks > 0 && self.frameskip.should_present(self.clock.is_behind());
+                if present {
+                    set_camera(&self.camera);
+                    clear_background(e.screen().palette().background());
+                    e.render();
+                    set_default_camera();
+                }
+                self.framerate.update(dt, ticks, present);
+                self.hud.update(
+                    dt,
+                    e.stats(),
+                    e.target_ips(),
+                    e.speed_multiplier(),
+                    e.quirks(),
+                );
+
+                self.crt.draw(&self.target.texture);
+                if self.options.visual_beep && e.is_sound_active() {
+                    draw_visual_beep(e.screen().palette());
+                }
+                if let Some(heatmap) = e.heatmap() {
+                    self.heatmap_view.draw(heatmap);
+                }
+            }
+            None => {}
+        }
+        self.hud.draw();
+        self.slot_picker.draw();
+        self.overlay.draw();
+    }
+
+    // A ROM dropped on the window replaces whatever is running or shown.
+    fn open_dropped(&mut self) {
+        if let Some(path) = dropped_rom(get_dropped_files()) {
+            self.prompt = None;
+            self.open(path.clone());
+            if self.fault.is_none() {
+                self.overlay.toast(format!("Loaded {}", path));
+            }
+        }
+    }
+
+    fn update_fault(&mut self) {
+        let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+        if let Some(p) = self.prompt.as_mut() {
+            if is_key_pressed(KeyCode::Escape) {
+                self.prompt = None;
+            } else if let Some(path) = read_prompt(p) {
+                self.prompt = None;
+                self.open(path.clone());
+                if self.fault.is_none() {
+                    self.overlay.toast(format!("Loaded {}", path));
+                }
+            }
+        } else if ctrl && is_key_pressed(KeyCode::O) {
+            self.prompt = Some(String::new());
+        } else if is_key_pressed(KeyCode::Escape) {
+            self.request_quit();
+        } else if let (Some(Fault::Runtime { .. }), true, Some(path)) = (
+            &self.fault,
+            is_key_pressed(KeyCode::R),
+            self.rom_path.clone(),
+        ) {
+            self.open(path);
+            self.overlay.toast("Reset");
+        }
+
+        if let Some(f) = &self.fault {
+            f.draw(self.prompt.as_deref());
+        }
+    }
+
+    // Stops the emulation thread, if any, and flushes buffered output. Returns the
+    // exit code, non-zero when quitting from an error screen.
+    fn shutdown(mut self) -> i32 {
+        if let (Some(log), Some(path), Some(Machine::Local(e))) =
+            (&self.input_log, &self.options.record_input, &self.machine)
+        {
+            match fs::write(path, log.to_string()) {
+                Ok(()) => println!(
+                    "Recorded {} frames to {}, final state hash {:#018X}",
+                    log.frames(),
+                    path,
+                    e.state_hash()
+                ),
+                Err(err) => eprintln!("Error writing the input log {}: {}", path, err),
+            }
+        }
+        if let Some(Machine::Local(e)) = &self.machine {
+            self.options.report_profile(e);
+            self.options.dump_state(e, false);
+        }
+        self.machine = None;
+        self.session.shutdown();
+        if let Some(Err(e)) = self.audio.take().map(AudioRecorder::finish) {
+            eprintln!("Error saving the audio recording: {}", e);
+        }
+        let t = self.clock.timing_stats();
+        if t.host_frames > 0 {
+            println!(
+                "Frame time {:.1}/{:.1}/{:.1} ms (min/mean/max), speed {:.3}x, {} frames dropped, {} caught up",
+                t.min_frame * 1000.0,
+                t.mean_frame() * 1000.0,
+                t.max_frame * 1000.0,
+                t.speed_ratio(),
+                t.dropped_frames,
+                t.extra_frames
+            );
+        }
+        let _ = io::stdout().flush();
+        self.fault.as_ref().map_or(0, Fault::exit_code)
+    }
+}
+
+// Runs up to --headless frames with no window, stopping early if the program halts or
+// goes idle.
+// Input comes from the --input-script, if any, and the run also ends once it's done.
+fn run_headless(options: &Options) -> i32 {
+    let Some(path) = &options.rom else {
+        eprintln!("ROM file not specified in the arguments");
+        return EXIT_USAGE;
+    };
+    let mut e = Chip8::new();
+    if let Err(err) = options
+        .for_rom(path)
+        .and_then(|o| o.configure_and_load(&mut e, path))
+    {
+        eprintln!("{}", err);
+        return exit_code(&err);
+    }
+    #[cfg(feature = "lua")]
+    let script = match options.script.as_deref().map(Script::load).transpose() {
+        Ok(script) => script,
+        Err(err) => {
+            eprintln!("{}", err);
+            return exit_code(&err);
+        }
+    };
+    #[cfg(feature = "lua")]
+    if let Some(script) = &script {
+        script.attach(&mut e);
+    }
+
+    let mut input = match options.input_script.as_deref().map(InputScript::load) {
+        Some(Ok(input)) => Some(input),
+        Some(Err(err)) => {
+            eprintln!("{}", err);
+            return exit_code(&err);
+        }
+        None => None,
+    };
+
+    for frame in 0..options.headless.unwrap_or(u64::MAX) {
+        if let Some(input) = &mut input {
+            if let Err(failure) = input.run_due(&mut e) {
+                eprintln!("{}\n{}", failure, e.screen().to_text());
+                return EXIT_ASSERTION;
+            }
+            if input.is_done() {
+                break;
+            }
+        }
+        if let Err(err) = e.run_frame() {
+            let message = runtime_message(&err, e.recent_instructions().back());
+            eprintln!("Emulation error: {}", message);
+            options.report_profile(&e);
+            options.dump_state(&e, true);
+            return EXIT_EMULATION;
+        }
+        options.log_frame(&e);
+        #[cfg(feature = "lua")]
+        if let Some(script) = &script {
+            script.on_frame(&mut e);
+        }
+        if let Some(input) = &mut input {
+            input.end_frame();
+        }
+        if e.reached_end() || e.has_exited() || e.stop_reason().is_some() {
+            break;
+        }
+        // Still waiting on an input script means more input is coming.
+        if let Some(pc) = e.idle_pc().filter(|_| input.is_none()) {
+            println!("Halted at {:#05X} after {} frames", pc, frame + 1);
+            break;
+        }
+    }
+    options.report_profile(&e);
+    options.dump_state(&e, false);
+    // Assertions still pending are checked against the final screen.
+    if let Some(input) = &mut input {
+        if let Err(failure) = input.run_due(&mut e) {
+            eprintln!("{}\n{}", failure, e.screen().to_text());
+            return EXIT_ASSERTION;
+        }
+        if !input.is_done() {
+            eprintln!("Input script unfinished at frame {}", input.frame());
+            return EXIT_ASSERTION;
+        }
+    }
+    0
+}
+
+fn run_monitor(options: &Options) -> i32 {
+    let Some(path) = &options.rom else {
+        eprintln!("ROM file not specified in the arguments");
+        return EXIT_USAGE;
+    };
+    let mut e = Chip8::new();
+    if let Err(err) = options
+        .for_rom(path)
+        .and_then(|o| o.configure_and_load(&mut e, path))
+    {
+        eprintln!("{}", err);
+        return exit_code(&err);
+    }
+    monitor::run(&mut e)
+}
+
+// Replays the --verify-replay log against the ROM and checks the final state hash.
+fn run_replay(options: &Options, log_path: &str) -> i32 {
+    let Some(path) = &options.rom else {
+        eprintln!("ROM file not specified in the arguments");
+        return EXIT_USAGE;
+    };
+    let result = fs::read_to_string(log_path)
+        .map_err(|e| Chip8Error::Script(format!("{}: {}", log_path, e)))
+        .and_then(|source| InputLog::parse(&source))
+        .and_then(|log| Ok((log, options.for_rom(path)?, emulator::read_rom(path)?)));
+    let (log, options, rom) = match result {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("{}", err);
+            return exit_code(&err);
+        }
+    };
+    let Some(seed) = options.seed.or(log.seed) else {
+        eprintln!("The input log has no seed, pass one with --seed");
+        return EXIT_USAGE;
+    };
+
+    let mut e = Chip8::new();
+    options.configure(&mut e);
+    let expected = options.expect_hash.unwrap_or_default();
+    let max_frames = options.headless.unwrap_or(u64::MAX);
+    match e.verify_replay(&rom, &log, seed, expected, max_frames) {
+        Ok(outcome) => {
+            println!(
+                "Replayed {} frames, final state hash {:#018X}",
+                outcome.frames, outcome.hash
+            );
+            if options.expect_hash.is_some() && !outcome.matches {
+                eprintln!("Expected {:#018X}", expected);
+                return EXIT_ASSERTION;
+            }
+            0
+        }
+        Err(err) => {
+            let message = runtime_message(&err, e.recent_instructions().back());
+            eprintln!("Emulation error: {}", message);
+            EXIT_EMULATION
+        }
+    }
+}
+
+// Runs the ROM and the --compare ROM side by side, see `emulator::first_divergence`.
+fn run_compare(options: &Options, other: &str) -> i32 {
+    let Some(path) = &options.rom else {
+        eprintln!("ROM file not specified in the arguments");
+        return EXIT_USAGE;
+    };
+    let frames = options.headless.unwrap_or(COMPARE_FRAMES);
+    let seed = options.seed.unwrap_or_default();
+    let result = options.for_rom(path).and_then(|options| {
+        let (a, b) = (emulator::read_rom(path)?, emulator::read_rom(other)?);
+        emulator::first_divergence(&a, &b, seed, frames, |e| options.configure(e))
+    });
+    match result {
+        Ok(None) => {
+            println!("Equal for {} frames", frames);
+            0
+        }
+        Ok(Some(frame)) => {
+            println!("Diverged after frame {}", frame);
+            EXIT_ASSERTION
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            exit_code(&err)
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            exit(EXIT_USAGE);
+        }
+    };
+    if options.help {
+        print!("{}", USAGE);
+        return;
+    }
+    if options.self_test {
+        let report = emulator::run_self_test();
+        print!("{}", report);
+        exit(if report.passed() { 0 } else { EXIT_ASSERTION });
+    }
+    if let Some(log) = &options.verify_replay {
+        exit(run_replay(&options, log));
+    }
+    if let Some(other) = &options.compare {
+        exit(run_compare(&options, other));
+    }
+    if options.monitor {
+        exit(run_monitor(&options));
+    }
+    if options.headless.is_some() || options.input_script.is_some() {
+        exit(run_headless(&options));
+    }
+    // Only for the title, `open_rom` guesses again and logs why.
+    let variant = options.variant.or_else(|| {
+        let path = options.rom.as_deref()?;
+        let rom = emulator::read_rom(path).ok()?;
+        Some(emulator::detect_variant(&rom, path).variant)
+    });
+    macroquad::Window::from_config(conf(variant), run(options));
+}
+
+async fn run(options: Options) {
+    let mut app = App::new(options);
+    // The window plays the beeper itself, once per host frame like the recorder, so
+    // it also follows --threaded machines and stops while paused.
+    #[cfg(feature = "audio")]
+    match Beeper::new().await {
+        Ok(beeper) => app.session.set_beeper(Box::new(beeper)),
+        Err(e) => eprintln!("Sound is unavailable: {}", e),
+    }
+    prevent_quit();
+
     loop {
-        println!("Framerate : {}", get_fps());
-        e.run();
+        if is_quit_requested() {
+            app.request_quit();
+        }
+        if app.update() {
+            break;
+        }
         next_frame().await;
     }
+    exit(app.shutdown());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_fault_keeps_the_message_and_exit_code() {
+        let fault = Fault::load(&Chip8Error::RomNotFound(String::from("pong.ch8")));
+        assert_eq!(fault.message(), "ROM not found: pong.ch8");
+        assert_eq!(fault.exit_code(), EXIT_ROM_UNREADABLE);
+
+        let fault = Fault::load(&Chip8Error::EmptyRom);
+        assert_eq!(fault.exit_code(), EXIT_ROM_INVALID);
+    }
+
+    #[test]
+    fn runtime_fault_names_the_faulting_instruction() {
+        let history = [(0x200, 0x6005), (0x202, 0x00EE)];
+        let fault = Fault::runtime(&Chip8Error::StackUnderflow { pc: 0x202 }, &history);
+        assert_eq!(
+            fault.message(),
+            format!(
+                "Return with an empty stack at 0x202 (faulting instruction 0x202: 00EE {})",
+                disassemble(0x00EE)
+            )
+        );
+        assert_eq!(fault.exit_code(), EXIT_EMULATION);
+        let Fault::Runtime { history, .. } = fault else {
+            panic!("expected a runtime fault");
+        };
+        assert_eq!(
+            history,
+            [
+                format!("0x200  6005  {}", disassemble(0x6005)),
+                format!("0x202  00EE  {}", disassemble(0x00EE)),
+            ]
+        );
+    }
+
+    #[test]
+    fn runtime_fault_elsewhere_names_only_the_address() {
+        let fault = Fault::runtime(&Chip8Error::StackOverflow { pc: 0x204 }, &[(0x200, 0x2200)]);
+        assert_eq!(
+            fault.message(),
+            "Call with a full stack at 0x204 (faulting address 0x204)"
+        );
+    }
+
+    // Which of `frames` emulated frames get presented.
+    fn presented(skip: u32, behind: impl Fn(usize) -> bool, frames: usize) -> Vec<usize> {
+        let mut frameskip = FrameSkip { skip, skipped: 0 };
+        (0..frames)
+            .filter(|&frame| frameskip.should_present(behind(frame)))
+            .collect()
+    }
+
+    #[test]
+    fn frameskip_presents_every_n_plus_oneth_frame() {
+        assert_eq!(presented(0, |_| false, 4), [0, 1, 2, 3]);
+        assert_eq!(presented(2, |_| false, 9), [2, 5, 8]);
+    }
+
+    #[test]
+    fn frameskip_drops_a_bounded_run_of_frames_while_behind() {
+        let frames = presented(0, |_| true, 2 * (MAX_AUTO_SKIP as usize + 1));
+        assert_eq!(
+            frames,
+            [MAX_AUTO_SKIP as usize, 2 * MAX_AUTO_SKIP as usize + 1]
+        );
+        // Caught up again, every frame is presented.
+        let frames = presented(0, |frame| frame == 0, 4);
+        assert_eq!(frames, [1, 2, 3]);
+    }
+
+    // Writes `program` to a ROM file of its own in the temp directory.
+    fn rom_file(name: &str, program: &[u16]) -> String {
+        let path = env::temp_dir().join(format!("chip8-{}-{}.ch8", name, std::process::id()));
+        let bytes: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+        fs::write(&path, bytes).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    // Runs `program` headless for up to `frames` frames, returning the exit code and
+    // how many frames it ran.
+    fn run_headless_rom(name: &str, program: &[u16], frames: u64) -> (i32, u64) {
+        let rom = rom_file(name, program);
+        let dump = format!("{}.json", rom);
+        let args: Vec<String> = ["chip8", &rom, "--headless", &frames.to_string()]
+            .into_iter()
+            .chain(["--dump-state-on-exit", &dump])
+            .map(String::from)
+            .collect();
+        let code = run_headless(&Options::parse(&args).unwrap());
+        let state: Value = serde_json::from_str(&fs::read_to_string(&dump).unwrap()).unwrap();
+        let _ = (fs::remove_file(&rom), fs::remove_file(&dump));
+        (code, state["frames"].as_u64().unwrap())
+    }
+
+    #[test]
+    fn headless_runs_end_when_the_program_exits() {
+        let (code, frames) = run_headless_rom("exit", &[0x7001, 0x00FD], 1000);
+        // One instruction a frame, so the second frame runs the 00FD.
+        assert_eq!((code, frames), (0, 2));
+    }
+
+    #[test]
+    fn headless_runs_stop_after_their_frame_count() {
+        let (code, frames) = run_headless_rom("count", &[0x7001, 0x1200], 5);
+        assert_eq!((code, frames), (0, 5));
+    }
+
+    #[test]
+    fn hud_reports_rates_over_its_interval() {
+        let mut hud = Hud::default();
+        let stats = Stats {
+            instructions: 300,
+            draws: 15,
+            frames: 15,
+            dirty_frames: 5,
+        };
+        let quirks = QuirkConfig::default();
+        hud.update(HUD_INTERVAL / 2.0, stats, Some(1000), 2.0, &quirks);
+        assert!(hud.lines[0].is_empty());
+        hud.update(HUD_INTERVAL / 2.0, stats, Some(1000), 2.0, &quirks);
+        assert_eq!(
+            hud.lines[..4],
+            [
+                "IPS 1200 / 1000",
+                "Frame 125.0 ms",
+                "Draws/s 60  dirty 33%",
+                "Speed 2.00x  measured 1.00x",
+            ]
+        );
+        assert_eq!(hud.lines[4], quirks.to_string());
+        // Rates restart from the last report.
+        hud.update(HUD_INTERVAL, stats, None, 0.5, &quirks);
+        assert_eq!(hud.lines[0], "IPS 0 (VIP timing)");
+        assert_eq!(hud.lines[3], "Speed 0.50x  measured 0.00x");
+    }
+
+    #[test]
+    fn hud_keeps_the_last_frame_times() {
+        let mut hud = Hud::default();
+        for _ in 0..SPARKLINE_LEN + 10 {
+            hud.update(0.01, Stats::default(), None, 1.0, &QuirkConfig::default());
+        }
+        assert_eq!(hud.frame_times.len(), SPARKLINE_LEN);
+    }
+
+    // Whether the visual beep shows after each of `frames` frames of `program`, one
+    // instruction a frame.
+    fn visual_beep_frames(program: &[u16], frames: usize) -> Vec<bool> {
+        let mut e = Chip8::new();
+        let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+        e.load(&rom).unwrap();
+        (0..frames)
+            .map(|_| {
+                e.run_frame().unwrap();
+                e.is_sound_active()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn visual_beep_follows_the_sound_timer() {
+        // ST = 3 in the second frame, counting down at the end of each.
+        let shown = visual_beep_frames(&[0x6003, 0xF018, 0x1204], 5);
+        assert_eq!(shown, [false, true, true, false, false]);
+    }
+
+    #[test]
+    fn visual_beep_skips_sounds_too_short_to_hear() {
+        let shown = visual_beep_frames(&[0x6001, 0xF018, 0x1204], 4);
+        assert_eq!(shown, [false; 4]);
+    }
+
+    #[test]
+    fn hires_variant_gives_a_square_display() {
+        let args: Vec<String> = ["chip8", "game.ch8", "--variant", "hires"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let options = Options::parse(&args).unwrap();
+        assert_eq!(options.variant, Some(Variant::Hires));
+        let mut e = Chip8::new();
+        options.configure(&mut e);
+        assert_eq!((e.screen().cols(), e.screen().rows()), (64, 64));
+    }
+
+    #[test]
+    fn headless_runs_stop_once_the_program_idles() {
+        let (code, frames) = run_headless_rom("idle", &[0x6001, 0x1202], 5000);
+        assert_eq!(code, 0);
+        // One instruction a frame, idle after IDLE_ITERATIONS passes over the jump.
+        assert!(frames > IDLE_ITERATIONS as u64 && frames < IDLE_ITERATIONS as u64 + 10);
+    }
+
+    #[test]
+    fn the_variant_flag_overrides_the_guess() {
+        let rom = rom_file("guess", &[0x0011, 0x1202]);
+        let args = |extra: &[&str]| -> Vec<String> {
+            ["chip8", &rom]
+                .iter()
+                .chain(extra)
+                .map(|s| s.to_string())
+                .collect()
+        };
+        let guessed = Options::parse(&args(&[])).unwrap().for_rom(&rom).unwrap();
+        assert_eq!(guessed.variant, Some(Variant::MegaChip));
+        let given = Options::parse(&args(&["--variant", "chip8"]))
+            .unwrap()
+            .for_rom(&rom)
+            .unwrap();
+        let _ = fs::remove_file(&rom);
+        assert_eq!(given.variant, Some(Variant::Chip8));
+    }
+
+    #[test]
+    fn clock_skew_specs_parse_to_seconds() {
+        assert_eq!(parse_clock_skew("jitter:5"), Ok(ClockSkew::Jitter(0.005)));
+        assert_eq!(
+            parse_clock_skew("16, 50,0"),
+            Ok(ClockSkew::Sequence(vec![0.016, 0.05, 0.0]))
+        );
+        for spec in ["x", "16,-1", "jitter:", "jitter:-2", "16,,33"] {
+            assert_eq!(
+                parse_clock_skew(spec),
+                Err(format!("Invalid clock skew: {}", spec))
+            );
+        }
+    }
+
+    #[test]
+    fn crash_dumps_go_next_to_the_configured_path() {
+        assert_eq!(crash_path("out/state.json"), "out/state-crash.json");
+        assert_eq!(crash_path("state"), "state-crash");
+    }
+
+    #[test]
+    fn headless_errors_write_the_crash_dump() {
+        let rom = rom_file("crash", &[0x6A12, 0x00EE]);
+        let dump = format!("{}.json", rom);
+        let crash = crash_path(&dump);
+        let args: Vec<String> = [
+            "chip8",
+            &rom,
+            "--headless",
+            "10",
+            "--dump-state-on-exit",
+            &dump,
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        let code = run_headless(&Options::parse(&args).unwrap());
+        let written = fs::read_to_string(&crash);
+        let clean = fs::metadata(&dump).is_ok();
+        let _ = (
+            fs::remove_file(&rom),
+            fs::remove_file(&crash),
+            fs::remove_file(&dump),
+        );
+        assert_eq!(code, EXIT_EMULATION);
+        assert!(!clean);
+        let state: Value = serde_json::from_str(&written.unwrap()).unwrap();
+        assert_eq!(state["pc"], "0x202");
+        assert_eq!(state["v"][0xA], "12");
+    }
+
+    #[test]
+    fn snapshots_taken_mid_run_restore_in_a_fresh_machine() {
+        let program: [u16; 6] = [0xA300, 0x6005, 0xF015, 0x7101, 0xD015, 0x1206];
+        let mut e = Chip8::new();
+        let bytes: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+        e.load(&bytes).unwrap();
+        for _ in 0..4 {
+            e.run_frame().unwrap();
+        }
+        let dir = env::temp_dir().join(format!("chip8-snapshots-{}", std::process::id()));
+        let base = write_snapshot(&dir, "mid-run", &e).unwrap();
+        // The run carries on past the snapshot.
+        e.run_frame().unwrap();
+        let state = fs::read(base.with_extension("state")).unwrap();
+        let json = fs::read_to_string(base.with_extension("json")).unwrap();
+        let dump = fs::read_to_string(base.with_extension("txt")).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut restored = Chip8::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.pc(), 0x208);
+        assert_eq!(restored.registers()[1], 1);
+        assert_eq!(restored.timers().delay, 3);
+        let dumped: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(dumped["pc"], "0x208");
+        assert!(dump.starts_with("Chip8 diagnostic dump\nCause: snapshot\n"));
+        // Both go on to the same frame.
+        restored.run_frame().unwrap();
+        assert_eq!(restored.save_state(), e.save_state());
+    }
+
+    #[test]
+    fn bindings_files_apply_to_the_machine() {
+        let path = env::temp_dir().join(format!("chip8-bindings-{}.txt", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        fs::write(&path, "5 = Up\n8 = Down\n").unwrap();
+        let options = Options::parse(&[
+            String::from("chip8"),
+            String::from("--bindings"),
+            path.clone(),
+        ]);
+        fs::write(&path, "5 = Jump\n").unwrap();
+        let bad = Options::parse(&[
+            String::from("chip8"),
+            String::from("--bindings"),
+            path.clone(),
+        ]);
+        let _ = fs::remove_file(&path);
+
+        let mut e = Chip8::new();
+        options.unwrap().configure(&mut e);
+        assert_eq!(e.bindings().keys[0x5], KeyCode::Up);
+        assert_eq!(e.bindings().keys[0x8], KeyCode::Down);
+        assert_eq!(e.bindings().keys[0x0], KeyBindings::default().keys[0x0]);
+        assert_eq!(
+            bad.err().unwrap(),
+            format!(
+                "{}: Invalid key bindings: line 1: unknown key: 5 = Jump",
+                path
+            )
+        );
+    }
+
+    #[test]
+    fn crt_is_off_unless_asked_for() {
+        let parse = |extra: &[&str]| {
+            let args: Vec<String> = ["chip8"]
+                .iter()
+                .chain(extra)
+                .map(|a| a.to_string())
+                .collect();
+            Options::parse(&args)
+        };
+        assert!(!parse(&[]).unwrap().crt);
+        assert!(parse(&["--crt"]).unwrap().crt);
+        assert_eq!(
+            parse(&["--crt", "--threaded"]).err().unwrap(),
+            "--crt can't be combined with --threaded"
+        );
+    }
+
+    #[test]
+    fn compare_reports_whether_the_roms_diverge() {
+        let original = rom_file("compare-a", &[0x6001, 0x7101, 0x1202, 0xAB00]);
+        let data = rom_file("compare-b", &[0x6001, 0x7101, 0x1202, 0xCD00]);
+        let code = rom_file("compare-c", &[0x6001, 0x7102, 0x1202, 0xAB00]);
+        let compare = |other: &str| {
+            let args = ["chip8", &original, "--compare", other, "--headless", "50"];
+            let options = Options::parse(&args.map(String::from)).unwrap();
+            run_compare(&options, options.compare.as_deref().unwrap())
+        };
+        assert_eq!(compare(&data), 0);
+        assert_eq!(compare(&code), EXIT_ASSERTION);
+        for path in [original, data, code] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn misaligned_flag_warns_or_stops() {
+        let parse = |extra: &[&str]| {
+            let args: Vec<String> = ["chip8"]
+                .iter()
+                .chain(extra)
+                .map(|a| a.to_string())
+                .collect();
+            Options::parse(&args)
+        };
+        assert_eq!(parse(&[]).unwrap().misaligned, None);
+        assert_eq!(
+            parse(&["--misaligned", "warn"]).unwrap().misaligned,
+            Some(false)
+        );
+        assert_eq!(
+            parse(&["--misaligned", "error"]).unwrap().misaligned,
+            Some(true)
+        );
+        assert!(parse(&["--misaligned", "stop"]).is_err());
+
+        let mut e = Chip8::new();
+        parse(&["--misaligned", "error"]).unwrap().configure(&mut e);
+        e.load(&[0x12, 0x03]).unwrap();
+        assert!(matches!(
+            e.step(),
+            Err(emulator::Chip8Error::MisalignedJump { target: 0x203, .. })
+        ));
+    }
+
+    #[test]
+    fn self_test_flag_is_parsed() {
+        let args = [String::from("chip8")];
+        assert!(!Options::parse(&args).unwrap().self_test);
+        let args = [String::from("chip8"), String::from("--self-test")];
+        assert!(Options::parse(&args).unwrap().self_test);
+        assert!(emulator::run_self_test().passed());
+    }
+
+    #[test]
+    fn ips_flag_sets_the_instructions_per_frame() {
+        let mut e = Chip8::new();
+        let args = ["chip8", "--ips", "600"].map(String::from);
+        Options::parse(&args).unwrap().configure(&mut e);
+        assert_eq!((e.cycles_per_frame(), e.ips()), (10, 600));
+        let args = ["chip8", "--ips", "fast"].map(String::from);
+        assert_eq!(
+            Options::parse(&args).err().unwrap(),
+            "Invalid instruction count: fast"
+        );
+    }
+
+    #[test]
+    fn rewind_flags_set_the_depth_and_interval() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = ["chip8"]
+                .iter()
+                .chain(args)
+                .map(|a| a.to_string())
+                .collect();
+            Options::parse(&args)
+        };
+        let options = parse(&[]).unwrap();
+        assert_eq!((options.rewind, options.rewind_interval), (0, 6));
+        let options = parse(&["--rewind", "4", "--rewind-every", "1"]).unwrap();
+        let mut e = Chip8::new();
+        options.configure(&mut e);
+        e.load(&[0x70, 0x01]).unwrap();
+        for _ in 0..6 {
+            e.run_frame().unwrap();
+        }
+        assert_eq!(e.rewind_len(), 4);
+        assert_eq!(
+            parse(&["--rewind-every", "0"]).err().unwrap(),
+            "Invalid frame count: 0"
+        );
+        assert_eq!(
+            parse(&["--rewind", "lots"]).err().unwrap(),
+            "Invalid state count: lots"
+        );
+    }
+
+    #[test]
+    fn plus_and_minus_step_through_the_speeds() {
+        let mut speed = 1.0;
+        let mut toasts = Vec::new();
+        for steps in [1, 1, -1, -1, -1] {
+            speed = next_speed(speed, steps);
+            toasts.push(speed_toast(speed));
+        }
+        assert_eq!(
+            toasts,
+            [
+                "Speed 150%",
+                "Speed 200%",
+                "Speed 150%",
+                "Speed 100%",
+                "Speed 75%"
+            ]
+        );
+        // Both ends hold, and a speed off the list steps from the entry above it.
+        assert_eq!(next_speed(0.25, -1), 0.25);
+        assert_eq!(next_speed(8.0, 1), 8.0);
+        assert_eq!(next_speed(20.0, 1), 8.0);
+        assert_eq!(next_speed(1.2, 1), 2.0);
+        assert_eq!(next_speed(1.2, -1), 1.0);
+        // The HUD shows it the same as a running machine would report it.
+        let mut e = Chip8::new();
+        e.set_speed_multiplier(next_speed(1.0, 1));
+        let mut hud = Hud::default();
+        let quirks = QuirkConfig::default();
+        hud.update(
+            HUD_INTERVAL,
+            Stats::default(),
+            None,
+            e.speed_multiplier(),
+            &quirks,
+        );
+        assert!(hud.lines[3].starts_with("Speed 1.50x"));
+    }
+
+    #[test]
+    fn low_memory_flag_overrides_the_variant() {
+        let protection = |extra: &[&str]| {
+            let args: Vec<String> = ["chip8"]
+                .iter()
+                .chain(extra)
+                .map(|a| a.to_string())
+                .collect();
+            let mut e = Chip8::new();
+            Options::parse(&args).map(|o| {
+                o.configure(&mut e);
+                e.quirks().low_memory
+            })
+        };
+        assert_eq!(protection(&[]), Ok(LowMemoryProtection::Error));
+        assert_eq!(
+            protection(&["--variant", "megachip"]),
+            Ok(LowMemoryProtection::Off)
+        );
+        assert_eq!(
+            protection(&["--variant", "megachip", "--low-memory", "error"]),
+            Ok(LowMemoryProtection::Error)
+        );
+        assert_eq!(
+            protection(&["--low-memory", "warn"]),
+            Ok(LowMemoryProtection::Warn)
+        );
+        assert_eq!(
+            protection(&["--low-memory", "off"]),
+            Ok(LowMemoryProtection::Off)
+        );
+        assert!(protection(&["--low-memory", "on"]).is_err());
+    }
+
+    #[test]
+    fn the_first_dropped_file_with_a_path_opens() {
+        let file = |path: Option<&str>| DroppedFile {
+            path: path.map(PathBuf::from),
+            bytes: Some(vec![0x12, 0x00]),
+        };
+        assert_eq!(dropped_rom(Vec::new()), None);
+        assert_eq!(dropped_rom(vec![file(None)]), None);
+        assert_eq!(
+            dropped_rom(vec![
+                file(None),
+                file(Some("pong.ch8")),
+                file(Some("tetris.ch8"))
+            ]),
+            Some(String::from("pong.ch8"))
+        );
+    }
+
+    #[test]
+    fn grid_flag_turns_on_the_overlay() {
+        let mut e = Chip8::new();
+        let args = [String::from("chip8")];
+        Options::parse(&args).unwrap().configure(&mut e);
+        assert!(!e.screen().grid());
+        let args = [String::from("chip8"), String::from("--grid")];
+        Options::parse(&args).unwrap().configure(&mut e);
+        assert!(e.screen().grid());
+    }
+
+    #[test]
+    fn poll_every_cycle_sets_the_input_polling() {
+        let mut e = Chip8::new();
+        let args = [String::from("chip8")];
+        Options::parse(&args).unwrap().configure(&mut e);
+        assert_eq!(e.input_polling(), InputPolling::PerFrame);
+        let args = [String::from("chip8"), String::from("--poll-every-cycle")];
+        Options::parse(&args).unwrap().configure(&mut e);
+        assert_eq!(e.input_polling(), InputPolling::PerCycle);
+    }
+
+    #[test]
+    fn headless_runs_of_empty_roms_exit_as_invalid() {
+        let rom = rom_file("empty", &[]);
+        let args: Vec<String> = ["chip8", &rom, "--headless", "10"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let code = run_headless(&Options::parse(&args).unwrap());
+        let _ = fs::remove_file(&rom);
+        assert_eq!(code, EXIT_ROM_INVALID);
+    }
+
+    #[test]
+    fn min_sound_sets_the_shortest_beep() {
+        let sound = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            let mut e = Chip8::new();
+            Options::parse(&args).unwrap().configure(&mut e);
+            e.load(&[0x60, 0x03, 0xF0, 0x18]).unwrap();
+            e.step().unwrap();
+            e.step().unwrap();
+            e.timers().sound
+        };
+        assert_eq!(sound(&["chip8"]), 3);
+        assert_eq!(sound(&["chip8", "--min-sound", "4"]), 0);
+        let args = ["chip8", "--min-sound", "x"].map(String::from);
+        assert_eq!(
+            Options::parse(&args).err().unwrap(),
+            "Invalid frame count: x"
+        );
+    }
+
+    #[test]
+    fn log_frames_needs_a_local_machine() {
+        let args = ["chip8", "--log-frames"].map(String::from);
+        assert!(Options::parse(&args).unwrap().log_frames);
+        let args = ["chip8", "--log-frames", "--threaded"].map(String::from);
+        assert_eq!(
+            Options::parse(&args).err().unwrap(),
+            "--log-frames can't be combined with --threaded"
+        );
+    }
+
+    #[test]
+    fn manual_clock_needs_the_control_port() {
+        let args = ["chip8", "--manual-clock"].map(String::from);
+        assert_eq!(
+            Options::parse(&args).err().unwrap(),
+            "--manual-clock needs --control-port"
+        );
+        let args = ["chip8", "--manual-clock", "--control-port", "0"].map(String::from);
+        assert!(Options::parse(&args).unwrap().manual_clock);
+    }
+
+    #[test]
+    fn font_files_become_the_active_font() {
+        let path = env::temp_dir().join(format!("chip8-font-{}.bin", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        fs::write(&path, [0xAA; 80]).unwrap();
+        let options = Options::parse(&["chip8", "--font", &path].map(String::from));
+        fs::write(&path, [0xAA; 70]).unwrap();
+        let bad = Options::parse(&["chip8", "--font", &path].map(String::from));
+        let _ = fs::remove_file(&path);
+
+        let mut e = Chip8::new();
+        options.unwrap().configure(&mut e);
+        assert_eq!(e.font_name(), "custom");
+        assert_eq!(e.memory()[..80], [0xAA; 80]);
+        assert_eq!(
+            bad.err().unwrap(),
+            format!("{}: Invalid font: 70 bytes don't make 16 glyphs", path)
+        );
+    }
+
+    #[test]
+    fn undecodable_opcodes_disassemble_as_unknown() {
+        assert_eq!(disassemble(0xFFFF), "???");
+    }
 }
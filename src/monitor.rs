@@ -0,0 +1,368 @@
+// A gdb-like monitor for debugging without a window, see `--monitor`. Commands come
+// one per line on stdin. The program starts stopped, `continue` runs it in real time
+// until a breakpoint or any new line of input, and the rest inspect the machine.
+use crate::{disassemble, runtime_message, EXIT_EMULATION};
+use chip8::emulator::{Chip8, Chip8Error, RegisterFormat, StopReason};
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HELP: &str = "\
+step [N]          run N instructions, 1 by default
+frame [N]         run N frames, 1 by default
+continue          run until a breakpoint, an error or the next line of input
+break ADDR        stop before the instruction at ADDR (hex)
+delete ADDR       remove the breakpoint at ADDR
+reg               show the registers, timers and stack
+format FORMAT     hex, dec or bin values in reg
+mem ADDR [LEN]    show LEN bytes of memory from ADDR, 64 by default
+screen            show the display as text
+quit";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Step(u32),
+    Frame(u32),
+    Continue,
+    Break(u16),
+    Delete(u16),
+    Reg,
+    Format(RegisterFormat),
+    Mem { addr: usize, len: usize },
+    Screen,
+    Help,
+    Quit,
+}
+
+fn hex(word: &str) -> Option<usize> {
+    let digits = word.trim_start_matches("0x").trim_start_matches("0X");
+    usize::from_str_radix(digits, 16).ok()
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<_> = line.split_whitespace().collect();
+        let address = |word: Option<&&str>| {
+            word.and_then(|w| hex(w))
+                .and_then(|a| u16::try_from(a).ok())
+                .ok_or_else(|| format!("{} expects a hex address", words[0]))
+        };
+        let command = match words.first().copied() {
+            Some("step" | "s") => Command::Step(match words.get(1) {
+                Some(n) => n.parse().map_err(|_| format!("Invalid count: {}", n))?,
+                None => 1,
+            }),
+            Some("frame" | "f") => Command::Frame(match words.get(1) {
+                Some(n) => n.parse().map_err(|_| format!("Invalid count: {}", n))?,
+                None => 1,
+            }),
+            Some("continue" | "c") => Command::Continue,
+            Some("break" | "b") => Command::Break(address(words.get(1))?),
+            Some("delete" | "d") => Command::Delete(address(words.get(1))?),
+            Some("reg" | "r") => Command::Reg,
+            Some("format") => Command::Format(match words.get(1).copied() {
+                Some("hex") => RegisterFormat::Hex,
+                Some("dec") => RegisterFormat::Decimal,
+                Some("bin") => RegisterFormat::Binary,
+                _ => return Err(String::from("format expects hex, dec or bin")),
+            }),
+            Some("mem" | "m") => Command::Mem {
+                addr: address(words.get(1))? as usize,
+                len: match words.get(2) {
+                    Some(n) => n.parse().map_err(|_| format!("Invalid length: {}", n))?,
+                    None => 64,
+                },
+            },
+            Some("screen") => Command::Screen,
+            Some("help" | "h") => Command::Help,
+            Some("quit" | "q") => Command::Quit,
+            Some(other) => return Err(format!("Unknown command {}, try help", other)),
+            None => return Err(String::new()),
+        };
+        Ok(command)
+    }
+}
+
+// Lines of stdin, read on their own thread so a running program can be interrupted.
+fn read_stdin() -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+fn where_stopped(e: &Chip8) -> String {
+    let opcode = e.peek_opcode();
+    format!("{:#05X}  {:04X}  {}", e.pc(), opcode, disassemble(opcode))
+}
+
+fn print_memory(e: &Chip8, addr: usize, len: usize, out: &mut impl Write) -> io::Result<()> {
+    let end = addr.saturating_add(len).min(e.memory().len());
+    let Some(bytes) = e.memory().get(addr..end) else {
+        return writeln!(out, "Out of bounds");
+    };
+    for (n, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<_> = line.iter().map(|b| format!("{:02X}", b)).collect();
+        writeln!(out, "{:#05X}  {}", addr + n * 16, hex.join(" "))?;
+    }
+    Ok(())
+}
+
+fn describe_stop(e: &Chip8) -> String {
+    match e.stop_reason() {
+        Some(StopReason::Breakpoint { .. }) => format!("Breakpoint at {}", where_stopped(e)),
+        Some(StopReason::RegisterWatch {
+            register, value, ..
+        }) => format!(
+            "V{:X} set to {:02X}, stopped at {}",
+            register,
+            value,
+            where_stopped(e)
+        ),
+        _ if e.has_exited() => String::from("Program exited"),
+        _ => format!("Stopped at {}", where_stopped(e)),
+    }
+}
+
+// Carries out every command but `continue` and `quit`, which `run` handles, writing
+// what it shows to `out`.
+fn execute(
+    e: &mut Chip8,
+    command: Command,
+    format: &mut RegisterFormat,
+    out: &mut impl Write,
+) -> Result<(), Chip8Error> {
+    let mut result = Ok(());
+    let _ = match command {
+        Command::Step(n) => {
+            result = (0..n).try_for_each(|_| e.step_instruction().map(drop));
+            writeln!(out, "{}", where_stopped(e))
+        }
+        Command::Frame(n) => {
+            // Only the first frame steps off a breakpoint, the rest stop at one.
+            result = (0..n).try_for_each(|k| match k {
+                0 => e.step_frame(),
+                _ if e.stop_reason().is_some() => Ok(()),
+                _ => e.run_frame(),
+            });
+            writeln!(out, "{}", where_stopped(e))
+        }
+        Command::Break(addr) => {
+            e.add_breakpoint(addr);
+            Ok(())
+        }
+        Command::Delete(addr) if !e.remove_breakpoint(addr) => {
+            writeln!(out, "No breakpoint at {:#05X}", addr)
+        }
+        Command::Reg => write!(out, "{}", e.state_summary(*format)),
+        Command::Format(f) => {
+            *format = f;
+            Ok(())
+        }
+        Command::Mem { addr, len } => print_memory(e, addr, len, out),
+        Command::Screen => write!(out, "{}", e.screen().to_text()),
+        Command::Help => writeln!(out, "{}", HELP),
+        Command::Delete(_) | Command::Continue | Command::Quit => Ok(()),
+    };
+    result
+}
+
+// Runs the monitor until `quit` or the end of stdin. Returns the exit code.
+pub fn run(e: &mut Chip8) -> i32 {
+    let input = read_stdin();
+    let mut failed = false;
+    let mut format = RegisterFormat::Hex;
+    // A line that interrupted `continue`, run next.
+    let mut pending = None;
+    println!("{}", where_stopped(e));
+    loop {
+        let line = match pending.take() {
+            Some(line) => line,
+            None => {
+                print!("(chip8) ");
+                let _ = io::stdout().flush();
+                let Ok(line) = input.recv() else {
+                    break;
+                };
+                line
+            }
+        };
+        let command = match Command::parse(&line) {
+            Ok(command) => command,
+            Err(message) => {
+                if !message.is_empty() {
+                    println!("{}", message);
+                }
+                continue;
+            }
+        };
+        let result = match command {
+            Command::Continue => run_until_stopped(e, &input).map(|line| pending = line),
+            Command::Quit => break,
+            command => execute(e, command, &mut format, &mut io::stdout()),
+        };
+        if let Err(err) = result {
+            println!(
+                "Emulation error: {}",
+                runtime_message(&err, e.recent_instructions().back())
+            );
+            failed = true;
+        }
+    }
+    if failed {
+        EXIT_EMULATION
+    } else {
+        0
+    }
+}
+
+// Runs frames at 60 per second, so timers keep their pace, until the program stops or
+// a line of input arrives, which is returned. Steps off a breakpoint at PC first.
+fn run_until_stopped(
+    e: &mut Chip8,
+    input: &Receiver<String>,
+) -> Result<Option<String>, Chip8Error> {
+    let frame = Duration::from_secs_f64(1.0 / 60.0);
+    let mut next = Instant::now();
+    e.step_instruction()?;
+    loop {
+        if e.stop_reason().is_some() || e.has_exited() || e.reached_end() {
+            println!("{}", describe_stop(e));
+            return Ok(None);
+        }
+        match input.try_recv() {
+            Ok(line) => {
+                println!("Interrupted at {}", where_stopped(e));
+                return Ok(Some(line));
+            }
+            Err(TryRecvError::Disconnected) => {
+                println!("Interrupted at {}", where_stopped(e));
+                return Ok(None);
+            }
+            Err(TryRecvError::Empty) => {}
+        }
+        e.run_frame()?;
+        next += frame;
+        if let Some(wait) = next.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parses and executes `lines` in order, returning what they printed.
+    fn monitor(e: &mut Chip8, lines: &[&str]) -> String {
+        let mut format = RegisterFormat::Hex;
+        let mut out = Vec::new();
+        for line in lines {
+            execute(e, Command::parse(line).unwrap(), &mut format, &mut out).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    fn machine(program: &[u8]) -> Chip8 {
+        let mut e = Chip8::new();
+        e.load(program).unwrap();
+        e
+    }
+
+    #[test]
+    fn commands_parse_with_their_arguments() {
+        let cases = [
+            ("step", Command::Step(1)),
+            ("s 5", Command::Step(5)),
+            ("f 2", Command::Frame(2)),
+            ("c", Command::Continue),
+            ("break 0x20A", Command::Break(0x20A)),
+            ("b 2ff", Command::Break(0x2FF)),
+            ("delete 20A", Command::Delete(0x20A)),
+            ("reg", Command::Reg),
+            ("format bin", Command::Format(RegisterFormat::Binary)),
+            (
+                "mem 300",
+                Command::Mem {
+                    addr: 0x300,
+                    len: 64,
+                },
+            ),
+            (
+                "m 0x300 4",
+                Command::Mem {
+                    addr: 0x300,
+                    len: 4,
+                },
+            ),
+            ("screen", Command::Screen),
+            ("q", Command::Quit),
+        ];
+        for (line, command) in cases {
+            assert_eq!(Command::parse(line), Ok(command), "{}", line);
+        }
+    }
+
+    #[test]
+    fn bad_commands_say_why() {
+        let cases = [
+            ("step x", "Invalid count: x"),
+            ("break", "break expects a hex address"),
+            ("break 10000", "break expects a hex address"),
+            ("mem 300 y", "Invalid length: y"),
+            ("format oct", "format expects hex, dec or bin"),
+            ("jump", "Unknown command jump, try help"),
+            ("", ""),
+        ];
+        for (line, why) in cases {
+            assert_eq!(Command::parse(line), Err(String::from(why)), "{}", line);
+        }
+    }
+
+    #[test]
+    fn step_and_break_drive_the_debugger() {
+        let mut e = machine(&[0x60, 0x01, 0x70, 0x01, 0x70, 0x01, 0x12, 0x02]);
+        let out = monitor(&mut e, &["step 2"]);
+        assert_eq!(out, "0x204  7001  ADD V0, 0x01\n");
+        assert_eq!(e.registers()[0], 2);
+
+        monitor(&mut e, &["break 202"]);
+        assert_eq!(e.breakpoints(), [0x202]);
+        // Runs 0x204 and 0x206, then stops before 0x202.
+        monitor(&mut e, &["frame 5"]);
+        assert_eq!(e.stop_reason(), Some(StopReason::Breakpoint { pc: 0x202 }));
+        assert_eq!(e.registers()[0], 3);
+        // Stepping runs the instruction at the breakpoint.
+        monitor(&mut e, &["step"]);
+        assert_eq!(e.registers()[0], 4);
+
+        assert_eq!(monitor(&mut e, &["delete 202"]), "");
+        assert_eq!(monitor(&mut e, &["delete 202"]), "No breakpoint at 0x202\n");
+        assert!(e.breakpoints().is_empty());
+    }
+
+    #[test]
+    fn inspection_commands_print_the_machine() {
+        let mut e = machine(&[0x6A, 0x0C, 0xA2, 0x08, 0xD0, 0x01, 0x12, 0x06, 0xF0]);
+        monitor(&mut e, &["step 3"]);
+        let reg = monitor(&mut e, &["reg"]);
+        assert!(reg.starts_with("PC 0x206  I 0x208"), "{}", reg);
+        assert!(reg.contains("VA 0C"));
+        let reg = monitor(&mut e, &["format dec", "reg"]);
+        assert!(reg.contains("VA  12"));
+        assert_eq!(
+            monitor(&mut e, &["mem 200 9"]),
+            "0x200  6A 0C A2 08 D0 01 12 06 F0\n"
+        );
+        assert_eq!(monitor(&mut e, &["mem FFFF"]), "Out of bounds\n");
+        let screen = monitor(&mut e, &["screen"]);
+        assert!(screen.starts_with("####...."), "{}", screen);
+    }
+}
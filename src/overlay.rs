@@ -0,0 +1,136 @@
+// Transient notifications ("toasts") and persistent status badges drawn over the
+// emulator output.
+use macroquad::prelude::*;
+use std::collections::VecDeque;
+
+const TOAST_TIME: f32 = 2.0;
+// Toasts fade out over their last FADE_TIME seconds.
+const FADE_TIME: f32 = 0.5;
+const MAX_TOASTS: usize = 4;
+const FONT_SIZE: f32 = 28.0;
+
+struct Toast {
+    text: String,
+    remaining: f32,
+}
+
+#[derive(Default)]
+pub struct Overlay {
+    toasts: VecDeque<Toast>,
+    pub paused: bool,
+    pub recording: bool,
+    pub idle: bool,
+}
+
+impl Overlay {
+    pub fn toast(&mut self, text: impl Into<String>) {
+        self.toast_for(text, TOAST_TIME);
+    }
+
+    // Only the newest MAX_TOASTS are kept.
+    pub fn toast_for(&mut self, text: impl Into<String>, seconds: f32) {
+        if self.toasts.len() == MAX_TOASTS {
+            self.toasts.pop_front();
+        }
+        self.toasts.push_back(Toast {
+            text: text.into(),
+            remaining: seconds,
+        });
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for toast in self.toasts.iter_mut() {
+            toast.remaining -= dt;
+        }
+        self.toasts.retain(|t| t.remaining > 0.0);
+    }
+
+    pub fn draw(&self) {
+        let (width, height) = (screen_width(), screen_height());
+
+        // Newest at the bottom left, older ones stacked above.
+        for (i, toast) in self.toasts.iter().rev().enumerate() {
+            let alpha = (toast.remaining / FADE_TIME).min(1.0);
+            let size = measure_text(&toast.text, None, FONT_SIZE as u16, 1.0);
+            let y = height - 20.0 - (i as f32 + 1.0) * (FONT_SIZE + 12.0);
+            draw_rectangle(
+                10.0,
+                y,
+                size.width + 20.0,
+                FONT_SIZE + 8.0,
+                Color::new(0.0, 0.0, 0.0, 0.7 * alpha),
+            );
+            draw_text(
+                &toast.text,
+                20.0,
+                y + FONT_SIZE - 2.0,
+                FONT_SIZE,
+                Color::new(1.0, 1.0, 1.0, alpha),
+            );
+        }
+
+        if self.paused {
+            let size = measure_text("PAUSED", None, 64, 1.0);
+            let (x, y) = ((width - size.width) / 2.0, (height + size.height) / 2.0);
+            draw_rectangle(
+                x - 20.0,
+                y - size.height - 20.0,
+                size.width + 40.0,
+                size.height + 40.0,
+                Color::new(0.0, 0.0, 0.0, 0.7),
+            );
+            draw_text("PAUSED", x, y, 64.0, WHITE);
+        }
+
+        if self.idle {
+            draw_text(
+                "program idle",
+                width - 160.0,
+                height - 16.0,
+                20.0,
+                Color::new(1.0, 1.0, 1.0, 0.5),
+            );
+        }
+
+        if self.recording {
+            draw_circle(width - 90.0, 30.0, 8.0, RED);
+            draw_text("REC", width - 75.0, 38.0, FONT_SIZE, RED);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(overlay: &Overlay) -> Vec<&str> {
+        overlay.toasts.iter().map(|t| t.text.as_str()).collect()
+    }
+
+    #[test]
+    fn toasts_expire_after_their_time() {
+        let mut overlay = Overlay::default();
+        overlay.toast("Saved");
+        overlay.toast_for("Quick", 0.5);
+        overlay.update(0.25);
+        assert_eq!(texts(&overlay), ["Saved", "Quick"]);
+        overlay.update(0.25);
+        assert_eq!(texts(&overlay), ["Saved"]);
+        overlay.update(TOAST_TIME);
+        assert!(texts(&overlay).is_empty());
+    }
+
+    #[test]
+    fn only_the_newest_toasts_are_kept() {
+        let mut overlay = Overlay::default();
+        for n in 0..MAX_TOASTS + 2 {
+            overlay.toast(format!("Toast {}", n));
+        }
+        assert_eq!(overlay.toasts.len(), MAX_TOASTS);
+        assert_eq!(texts(&overlay)[0], "Toast 2");
+        assert_eq!(
+            texts(&overlay)[MAX_TOASTS - 1],
+            format!("Toast {}", MAX_TOASTS + 1)
+        );
+    }
+}
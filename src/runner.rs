@@ -0,0 +1,349 @@
+// Runs a Chip8 on its own thread, paced by a sleep-based 60Hz tick so that rendering
+// hiccups on the frontend do not disturb emulation. The frontend talks to it through
+// commands and reads back complete frames.
+use crate::emulator::{Chip8, Chip8Error, InputPolling, QuirkConfig, Screen, Stats};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const FRAME_TIME: f64 = 1.0 / 60.0;
+// Stalls longer than this are not caught up, the schedule restarts from now.
+const MAX_LAG: Duration = Duration::from_millis(250);
+
+pub enum Command {
+    Pause(bool),
+    Reset,
+    Speed(f64),
+    // See `Chip8::release_all_keys`.
+    ReleaseKeys,
+    Quit,
+}
+
+#[derive(Clone)]
+pub struct Frame {
+    pub screen: Screen,
+    pub sound: bool,
+    pub count: u64,
+    pub stats: Stats,
+    pub target_ips: Option<u64>,
+    pub quirks: QuirkConfig,
+    // The program ran 00FD.
+    pub exited: bool,
+    // See `Chip8::is_idle`.
+    pub idle: bool,
+}
+
+pub struct Fault {
+    pub error: Chip8Error,
+    pub history: Vec<(u16, u16)>,
+}
+
+// The keypad as the frontend last reported it, plus every key pressed since the
+// emulation last sampled it, so taps between two samples aren't lost.
+#[derive(Default)]
+struct Keys {
+    down: AtomicU16,
+    pressed: AtomicU16,
+}
+
+impl Keys {
+    fn set(&self, keys: [bool; 16]) {
+        let mask = keys
+            .iter()
+            .enumerate()
+            .fold(0, |m, (n, &down)| m | (down as u16) << n);
+        self.down.store(mask, Ordering::Relaxed);
+        self.pressed.fetch_or(mask, Ordering::Relaxed);
+    }
+
+    fn sample(&self) -> [bool; 16] {
+        let mask = self.down.load(Ordering::Relaxed) | self.pressed.swap(0, Ordering::Relaxed);
+        std::array::from_fn(|n| mask & 1 << n != 0)
+    }
+}
+
+struct Shared {
+    frame: Frame,
+    fault: Option<Fault>,
+}
+
+pub struct Runner {
+    commands: Sender<Command>,
+    shared: Arc<Mutex<Shared>>,
+    keys: Arc<Keys>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Runner {
+    // `configure` is applied to the machine on boot and again on every reset.
+    pub fn spawn<F>(rom: Vec<u8>, configure: F) -> Result<Self, Chip8Error>
+    where
+        F: Fn(&mut Chip8) + Send + 'static,
+    {
+        let chip8 = boot(&rom, &configure)?;
+        let shared = Arc::new(Mutex::new(Shared {
+            frame: Frame {
+                screen: chip8.screen().clone(),
+                sound: false,
+                count: 0,
+                stats: chip8.stats(),
+                target_ips: chip8.target_ips(),
+                quirks: *chip8.quirks(),
+                exited: false,
+                idle: false,
+            },
+            fault: None,
+        }));
+        let (commands, receiver) = mpsc::channel();
+        let keys = Arc::new(Keys::default());
+
+        let thread_shared = Arc::clone(&shared);
+        let thread_keys = Arc::clone(&keys);
+        let thread =
+            thread::spawn(move || emulate(rom, configure, receiver, thread_shared, thread_keys));
+
+        Ok(Runner {
+            commands,
+            shared,
+            keys,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn send(&self, command: Command) {
+        // The thread only goes away after Quit or a panic, either way there's no one to tell.
+        let _ = self.commands.send(command);
+    }
+
+    // The emulation samples these once a frame, or before every instruction under
+    // `InputPolling::PerCycle`.
+    pub fn set_keys(&self, keys: [bool; 16]) {
+        self.keys.set(keys);
+    }
+
+    // The most recently completed frame.
+    pub fn frame(&self) -> Frame {
+        self.shared.lock().unwrap().frame.clone()
+    }
+
+    pub fn take_fault(&self) -> Option<Fault> {
+        self.shared.lock().unwrap().fault.take()
+    }
+}
+
+impl Drop for Runner {
+    fn drop(&mut self) {
+        self.send(Command::Quit);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn boot(rom: &[u8], configure: &impl Fn(&mut Chip8)) -> Result<Chip8, Chip8Error> {
+    let mut chip8 = Chip8::new();
+    configure(&mut chip8);
+    chip8.load(rom)?;
+    Ok(chip8)
+}
+
+fn emulate(
+    rom: Vec<u8>,
+    configure: impl Fn(&mut Chip8),
+    commands: Receiver<Command>,
+    shared: Arc<Mutex<Shared>>,
+    keys: Arc<Keys>,
+) {
+    let boot = |rom: &[u8]| {
+        // The ROM was already validated by `spawn`.
+        let mut chip8 = boot(rom, &configure).unwrap();
+        let keys = Arc::clone(&keys);
+        // An interval no frame reaches samples only before its first instruction.
+        let interval = match chip8.input_polling() {
+            InputPolling::PerFrame => u32::MAX,
+            InputPolling::PerCycle => 1,
+        };
+        chip8.set_key_sampler(Some(Box::new(move || keys.sample())), interval);
+        chip8
+    };
+    let mut chip8 = boot(&rom);
+    let mut paused = false;
+    let mut speed = 1.0;
+    let mut count = 0;
+    let mut deadline = Instant::now();
+
+    loop {
+        loop {
+            match commands.try_recv() {
+                Ok(Command::Pause(p)) => paused = p,
+                Ok(Command::Reset) => {
+                    chip8 = boot(&rom);
+                    paused = false;
+                }
+                Ok(Command::Speed(s)) => speed = s,
+                Ok(Command::ReleaseKeys) => chip8.release_all_keys(),
+                Ok(Command::Quit) | Err(TryRecvError::Disconnected) => return,
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        if !paused {
+            let result = chip8.run_frame();
+            count += 1;
+
+            let mut shared = shared.lock().unwrap();
+            // Copying the whole screen under the lock keeps the frontend from
+            // ever seeing a half drawn frame.
+            shared.frame.screen.clone_from(chip8.screen());
+            shared.frame.sound = chip8.is_sound_active();
+            shared.frame.count = count;
+            shared.frame.stats = chip8.stats();
+            shared.frame.target_ips = chip8.target_ips();
+            shared.frame.quirks = *chip8.quirks();
+            shared.frame.exited = chip8.has_exited();
+            shared.frame.idle = chip8.is_idle();
+            if let Err(error) = result {
+                let history = chip8.recent_instructions().iter().copied().collect();
+                shared.fault = Some(Fault { error, history });
+                paused = true;
+            }
+        }
+
+        deadline += Duration::from_secs_f64(FRAME_TIME / speed);
+        let now = Instant::now();
+        if deadline > now {
+            thread::sleep(deadline - now);
+        } else if now - deadline > MAX_LAG {
+            deadline = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    // 0x202 loops on itself forever.
+    const LOOP: [u8; 4] = [0x60, 0x05, 0x12, 0x02];
+
+    // Polls the runner until `done`, failing the test after a few seconds.
+    fn wait_for(runner: &Runner, done: impl Fn(&Frame) -> bool) -> Frame {
+        let start = Instant::now();
+        loop {
+            let frame = runner.frame();
+            if done(&frame) {
+                return frame;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "runner timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn keys_tapped_between_samples_are_kept_once() {
+        let keys = Keys::default();
+        let mut down = [false; 16];
+        down[3] = true;
+        keys.set(down);
+        keys.set([false; 16]);
+        assert!(keys.sample()[3]);
+        assert!(!keys.sample()[3]);
+        keys.set(down);
+        assert!(keys.sample()[3] && keys.sample()[3]);
+    }
+
+    #[test]
+    fn frames_are_published_as_they_run() {
+        let runner = Runner::spawn(LOOP.to_vec(), |_| {}).unwrap();
+        runner.send(Command::Speed(20.0));
+        let frame = wait_for(&runner, |f| f.count >= 3);
+        assert!(!frame.exited);
+        assert!(runner.take_fault().is_none());
+    }
+
+    #[test]
+    fn paused_runner_runs_no_frames() {
+        let runner = Runner::spawn(LOOP.to_vec(), |_| {}).unwrap();
+        runner.send(Command::Speed(20.0));
+        wait_for(&runner, |f| f.count >= 1);
+        runner.send(Command::Pause(true));
+        // Lets a frame already under way finish.
+        thread::sleep(Duration::from_millis(20));
+        let count = runner.frame().count;
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(runner.frame().count, count);
+        runner.send(Command::Pause(false));
+        wait_for(&runner, |f| f.count > count);
+    }
+
+    #[test]
+    fn faults_stop_the_runner_and_carry_the_history() {
+        let runner = Runner::spawn(vec![0x00, 0xEE], |_| {}).unwrap();
+        let start = Instant::now();
+        let fault = loop {
+            if let Some(fault) = runner.take_fault() {
+                break fault;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "runner timed out");
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert!(matches!(
+            fault.error,
+            Chip8Error::StackUnderflow { pc: 0x200 }
+        ));
+        assert_eq!(fault.history.last(), Some(&(0x200, 0x00EE)));
+    }
+
+    #[test]
+    fn reset_boots_the_machine_again() {
+        let boots = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&boots);
+        let runner = Runner::spawn(LOOP.to_vec(), move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        })
+        .unwrap();
+        runner.send(Command::Speed(20.0));
+        wait_for(&runner, |f| f.count >= 1);
+        // Once by `spawn` to check the ROM and once on the thread.
+        assert_eq!(boots.load(Ordering::Relaxed), 2);
+        runner.send(Command::Reset);
+        let count = runner.frame().count;
+        wait_for(&runner, |f| f.count > count + 1);
+        assert_eq!(boots.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn released_keys_stay_up_until_the_host_lets_go() {
+        // Draws the "0" glyph once key 0 is seen down.
+        let rom = [0x60, 0x00, 0xE0, 0x9E, 0x12, 0x02, 0xD0, 0x05, 0x12, 0x08];
+        let runner = Runner::spawn(rom.to_vec(), |_| {}).unwrap();
+        runner.send(Command::Speed(20.0));
+        runner.send(Command::Pause(true));
+        thread::sleep(Duration::from_millis(20));
+        let mut down = [false; 16];
+        down[0] = true;
+        runner.set_keys(down);
+        runner.send(Command::ReleaseKeys);
+        runner.send(Command::Pause(false));
+        let count = runner.frame().count;
+        let frame = wait_for(&runner, |f| f.count > count + 3);
+        assert!(frame.screen.lit_pixels().next().is_none());
+
+        runner.set_keys([false; 16]);
+        let count = runner.frame().count;
+        wait_for(&runner, |f| f.count > count + 1);
+        runner.set_keys(down);
+        wait_for(&runner, |f| f.screen.get(0, 0));
+    }
+
+    #[test]
+    fn invalid_roms_fail_to_spawn() {
+        assert!(matches!(
+            Runner::spawn(Vec::new(), |_| {}),
+            Err(Chip8Error::EmptyRom)
+        ));
+    }
+}
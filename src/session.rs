@@ -0,0 +1,84 @@
+// How a windowed run ends. Esc, the window's close button and a program that halts
+// all request a quit, which `App::update` reports back to the loop in `run` at the end
+// of that frame. The beeper lives here too, so nothing is left sounding on the way out.
+use chip8::emulator::AudioBackend;
+
+#[derive(Default)]
+pub struct Session {
+    quit: bool,
+    beeper: Option<Box<dyn AudioBackend>>,
+}
+
+impl Session {
+    #[cfg(any(feature = "audio", test))]
+    pub fn set_beeper(&mut self, beeper: Box<dyn AudioBackend>) {
+        self.beeper = Some(beeper);
+    }
+
+    pub fn request_quit(&mut self) {
+        self.quit = true;
+    }
+
+    // Plays or stops the beeper for this frame, silent once a quit is requested.
+    pub fn play(&mut self, playing: bool) {
+        if let Some(beeper) = self.beeper.as_mut() {
+            beeper.set_playing(playing && !self.quit);
+        }
+    }
+
+    // What `App::update` returns: whether the run is over.
+    pub fn is_done(&self) -> bool {
+        self.quit
+    }
+
+    pub fn shutdown(&mut self) {
+        if let Some(mut beeper) = self.beeper.take() {
+            beeper.set_playing(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Logs every call it gets.
+    struct Log(Rc<RefCell<Vec<bool>>>);
+
+    impl AudioBackend for Log {
+        fn set_playing(&mut self, playing: bool) {
+            self.0.borrow_mut().push(playing);
+        }
+    }
+
+    fn session() -> (Session, Rc<RefCell<Vec<bool>>>) {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut session = Session::default();
+        session.set_beeper(Box::new(Log(calls.clone())));
+        (session, calls)
+    }
+
+    #[test]
+    fn a_quit_request_ends_the_run_that_frame() {
+        let (mut session, calls) = session();
+        session.play(true);
+        assert!(!session.is_done());
+        session.request_quit();
+        session.play(true);
+        assert!(session.is_done());
+        assert_eq!(*calls.borrow(), [true, false]);
+    }
+
+    #[test]
+    fn shutdown_stops_the_beeper() {
+        let (mut session, calls) = session();
+        session.play(true);
+        session.shutdown();
+        assert_eq!(*calls.borrow(), [true, false]);
+        // It's gone, later frames don't reach it.
+        session.play(true);
+        assert_eq!(calls.borrow().len(), 2);
+    }
+}
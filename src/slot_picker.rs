@@ -0,0 +1,271 @@
+// Save-state slot picker, shown while F6 is held: a grid of the SLOTS slots with a
+// thumbnail of each saved display and how long ago it was saved. Slots live next
+// to the ROM as `rom.state0` to `rom.state9`, and only their headers are read here.
+// The slot selected here is also the one F5 saves to and F7 loads from.
+use chip8::emulator::{Chip8, Chip8Error, Palette, Thumbnail};
+use macroquad::prelude::*;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SLOTS: usize = 10;
+const COLUMNS: usize = 5;
+const CELL_W: f32 = 192.0;
+const CELL_H: f32 = 96.0;
+const GAP: f32 = 16.0;
+const LABEL_H: f32 = 28.0;
+
+enum Slot {
+    Empty,
+    Corrupt,
+    Saved { texture: Texture2D, saved_at: u64 },
+}
+
+pub struct SlotPicker {
+    pub visible: bool,
+    selected: usize,
+    slots: Vec<Slot>,
+}
+
+pub fn slot_path(rom: &str, slot: usize) -> String {
+    format!("{}.state{}", rom, slot)
+}
+
+// Saves the whole machine to `slot` next to `rom`, returning what to tell the user.
+pub fn save_slot(rom: &str, slot: usize, e: &Chip8) -> String {
+    match fs::write(slot_path(rom, slot), e.save_state()) {
+        Ok(()) => format!("Saved slot {}", slot),
+        Err(err) => format!("Can't save slot {}: {}", slot, err),
+    }
+}
+
+// Restores `slot`, leaving the machine as it was if the slot is empty or invalid.
+pub fn load_slot(rom: &str, slot: usize, e: &mut Chip8) -> String {
+    match fs::read(slot_path(rom, slot))
+        .map_err(Chip8Error::from)
+        .and_then(|data| e.load_state(&data))
+    {
+        Ok(()) => format!("Loaded slot {}", slot),
+        Err(err) => format!("Can't load slot {}: {}", slot, err),
+    }
+}
+
+fn read_slot(path: &str, palette: Palette) -> Slot {
+    let thumbnail = match File::open(path) {
+        Ok(f) => Thumbnail::read_from(&mut BufReader::new(f)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Slot::Empty,
+        Err(_) => return Slot::Corrupt,
+    };
+    let Ok(t) = thumbnail else {
+        return Slot::Corrupt;
+    };
+    if t.cols == 0 || t.rows == 0 || t.cols > u16::MAX as usize || t.rows > u16::MAX as usize {
+        return Slot::Corrupt;
+    }
+    let (on, off) = (rgba8(palette.foreground()), rgba8(palette.background()));
+    let rgba: Vec<u8> = t
+        .pixels
+        .iter()
+        .flat_map(|&p| if p { on } else { off })
+        .collect();
+    let texture = Texture2D::from_rgba8(t.cols as u16, t.rows as u16, &rgba);
+    texture.set_filter(FilterMode::Nearest);
+    Slot::Saved {
+        texture,
+        saved_at: t.saved_at,
+    }
+}
+
+fn rgba8(c: Color) -> [u8; 4] {
+    [c.r, c.g, c.b, c.a].map(|v| (v * 255.0) as u8)
+}
+
+fn age(saved_at: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    match now.saturating_sub(saved_at) {
+        s if s < 60 => format!("{}s ago", s),
+        s if s < 3600 => format!("{}m ago", s / 60),
+        s if s < 86400 => format!("{}h ago", s / 3600),
+        s => format!("{}d ago", s / 86400),
+    }
+}
+
+impl SlotPicker {
+    pub fn new() -> Self {
+        SlotPicker {
+            visible: false,
+            selected: 0,
+            slots: (0..SLOTS).map(|_| Slot::Empty).collect(),
+        }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    // Re-reads every slot's header.
+    pub fn refresh(&mut self, rom: &str, palette: Palette) {
+        self.slots = (0..SLOTS)
+            .map(|n| read_slot(&slot_path(rom, n), palette))
+            .collect();
+    }
+
+    pub fn move_selection(&mut self, dx: isize, dy: isize) {
+        let col = (self.selected % COLUMNS) as isize + dx;
+        let row = (self.selected / COLUMNS) as isize + dy;
+        let rows = SLOTS.div_ceil(COLUMNS) as isize;
+        let col = col.rem_euclid(COLUMNS as isize) as usize;
+        let row = row.rem_euclid(rows) as usize;
+        self.selected = (row * COLUMNS + col).min(SLOTS - 1);
+    }
+
+    pub fn draw(&self) {
+        if !self.visible {
+            return;
+        }
+        let rows = SLOTS.div_ceil(COLUMNS);
+        let width = COLUMNS as f32 * (CELL_W + GAP) + GAP;
+        let height = rows as f32 * (CELL_H + LABEL_H + GAP) + GAP + 40.0;
+        let x0 = (screen_width() - width) / 2.0;
+        let y0 = (screen_height() - height) / 2.0;
+        draw_rectangle(x0, y0, width, height, Color::new(0.0, 0.0, 0.0, 0.85));
+        draw_text(
+            "Enter load   S save   Delete clear",
+            x0 + GAP,
+            y0 + 28.0,
+            24.0,
+            WHITE,
+        );
+
+        for (n, slot) in self.slots.iter().enumerate() {
+            let x = x0 + GAP + (n % COLUMNS) as f32 * (CELL_W + GAP);
+            let y = y0 + 40.0 + GAP + (n / COLUMNS) as f32 * (CELL_H + LABEL_H + GAP);
+            let label = match slot {
+                Slot::Empty => format!("{}  empty", n),
+                Slot::Corrupt => format!("{}  corrupt", n),
+                Slot::Saved { texture, saved_at } => {
+                    // Keep the display's aspect ratio inside the cell.
+                    let scale = (CELL_W / texture.width()).min(CELL_H / texture.height());
+                    let size = vec2(texture.width() * scale, texture.height() * scale);
+                    draw_texture_ex(
+                        texture,
+                        x + (CELL_W - size.x) / 2.0,
+                        y + (CELL_H - size.y) / 2.0,
+                        WHITE,
+                        DrawTextureParams {
+                            dest_size: Some(size),
+                            ..Default::default()
+                        },
+                    );
+                    format!("{}  {}", n, age(*saved_at))
+                }
+            };
+            let color = if n == self.selected { YELLOW } else { GRAY };
+            draw_rectangle_lines(x, y, CELL_W, CELL_H, 2.0, color);
+            draw_text(&label, x, y + CELL_H + 22.0, 22.0, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn selection_wraps_around_the_grid() {
+        let mut picker = SlotPicker::new();
+        picker.move_selection(-1, 0);
+        assert_eq!(picker.selected(), 4);
+        picker.move_selection(0, 1);
+        assert_eq!(picker.selected(), 9);
+        picker.move_selection(1, 0);
+        assert_eq!(picker.selected(), 5);
+        picker.move_selection(0, 1);
+        assert_eq!(picker.selected(), 0);
+    }
+
+    #[test]
+    fn slots_sit_next_to_the_rom() {
+        assert_eq!(slot_path("games/pong.ch8", 3), "games/pong.ch8.state3");
+    }
+
+    #[test]
+    fn ages_use_the_largest_whole_unit() {
+        let now = now();
+        assert_eq!(age(now), "0s ago");
+        assert_eq!(age(now - 150), "2m ago");
+        assert_eq!(age(now - 3 * 3600 - 5), "3h ago");
+        assert_eq!(age(now - 2 * 86400), "2d ago");
+        // Clocks that went backwards don't underflow.
+        assert_eq!(age(now + 100), "0s ago");
+    }
+
+    #[test]
+    fn slots_restore_what_was_saved_in_them() {
+        let rom = env::temp_dir().join(format!("chip8-slot-io-{}.ch8", std::process::id()));
+        let rom = rom.to_string_lossy();
+        let mut e = Chip8::new();
+        e.load(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02]).unwrap();
+        e.step().unwrap();
+        assert_eq!(save_slot(&rom, 2, &e), "Saved slot 2");
+        let saved = e.state_hash();
+        for _ in 0..5 {
+            e.step().unwrap();
+        }
+        assert_eq!(save_slot(&rom, 3, &e), "Saved slot 3");
+        let later = e.state_hash();
+        assert_eq!(load_slot(&rom, 2, &mut e), "Loaded slot 2");
+        assert_eq!((e.state_hash(), e.registers()[0]), (saved, 1));
+        assert_eq!(load_slot(&rom, 3, &mut e), "Loaded slot 3");
+        assert_eq!(e.state_hash(), later);
+        for slot in [2, 3] {
+            fs::remove_file(slot_path(&rom, slot)).unwrap();
+        }
+    }
+
+    #[test]
+    fn empty_and_corrupt_slots_leave_the_machine_alone() {
+        let rom = env::temp_dir().join(format!("chip8-slot-bad-{}.ch8", std::process::id()));
+        let rom = rom.to_string_lossy();
+        let _ = fs::remove_file(slot_path(&rom, 0));
+        fs::write(slot_path(&rom, 1), b"not a state").unwrap();
+        let mut e = Chip8::new();
+        e.load(&[0x60, 0x07]).unwrap();
+        e.step().unwrap();
+        let hash = e.state_hash();
+        assert!(load_slot(&rom, 0, &mut e).starts_with("Can't load slot 0: "));
+        assert_eq!(
+            load_slot(&rom, 1, &mut e),
+            "Can't load slot 1: Invalid save state: not a save state"
+        );
+        assert_eq!(e.state_hash(), hash);
+        fs::remove_file(slot_path(&rom, 1)).unwrap();
+        let missing_dir = format!("{}-missing/rom.ch8", rom);
+        assert!(save_slot(&missing_dir, 4, &e).starts_with("Can't save slot 4: "));
+    }
+
+    #[test]
+    fn missing_and_corrupt_slots_are_told_apart() {
+        let rom = env::temp_dir().join(format!("chip8-slots-{}.ch8", std::process::id()));
+        let rom = rom.to_string_lossy();
+        let (empty, corrupt) = (slot_path(&rom, 0), slot_path(&rom, 1));
+        let _ = fs::remove_file(&empty);
+        fs::write(&corrupt, b"not a state").unwrap();
+        assert!(matches!(read_slot(&empty, Palette::default()), Slot::Empty));
+        assert!(matches!(
+            read_slot(&corrupt, Palette::default()),
+            Slot::Corrupt
+        ));
+        let _ = fs::remove_file(&corrupt);
+    }
+}
@@ -0,0 +1,93 @@
+// Runs the binary headless and checks the exit code and message of every way a run
+// can end, as documented under `--help`.
+#![cfg(feature = "macroquad")]
+
+use assert_cmd::Command;
+use predicates::str::contains;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+// Writes `program` as a ROM named `name` into `dir`.
+fn rom(dir: &TempDir, name: &str, program: &[u16]) -> PathBuf {
+    let path = dir.path().join(name);
+    let bytes: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+    std::fs::write(&path, bytes).unwrap();
+    path
+}
+
+fn headless(rom: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("chip8").unwrap();
+    cmd.arg(rom).args(["--headless", "600"]);
+    cmd
+}
+
+#[test]
+fn clean_exit_is_0() {
+    let dir = TempDir::new().unwrap();
+    let rom = rom(&dir, "exit.ch8", &[0x6001, 0x00FD]);
+    headless(&rom).assert().code(0);
+}
+
+#[test]
+fn usage_errors_are_1() {
+    Command::cargo_bin("chip8")
+        .unwrap()
+        .arg("--no-such-option")
+        .assert()
+        .code(1)
+        .stderr(contains("Unknown option --no-such-option"));
+    Command::cargo_bin("chip8")
+        .unwrap()
+        .args(["--headless", "10"])
+        .assert()
+        .code(1)
+        .stderr(contains("ROM file not specified"));
+}
+
+#[test]
+fn missing_rom_is_2() {
+    let dir = TempDir::new().unwrap();
+    headless(&dir.path().join("missing.ch8"))
+        .assert()
+        .code(2)
+        .stderr(contains("ROM not found"));
+}
+
+#[test]
+fn invalid_rom_is_3() {
+    let dir = TempDir::new().unwrap();
+    let large = rom(&dir, "large.ch8", &[0x1200; 2000]);
+    headless(&large)
+        .assert()
+        .code(3)
+        .stderr(contains("ROM too large: 4000 bytes, max 3584"));
+    let empty = rom(&dir, "empty.ch8", &[]);
+    headless(&empty)
+        .assert()
+        .code(3)
+        .stderr(contains("The ROM is empty"));
+}
+
+#[test]
+fn emulation_error_is_4_and_names_the_instruction() {
+    let dir = TempDir::new().unwrap();
+    let rom = rom(&dir, "crash.ch8", &[0x6001, 0x00EE]);
+    headless(&rom).assert().code(4).stderr(contains(
+        "Return with an empty stack at 0x202 (faulting instruction 0x202: 00EE RET)",
+    ));
+}
+
+#[test]
+fn failed_assertion_is_5() {
+    let dir = TempDir::new().unwrap();
+    let rom = rom(&dir, "blank.ch8", &[0x1200]);
+    let script = dir.path().join("input.txt");
+    std::fs::write(&script, "wait 2; assert_pixel 0 0 on\n").unwrap();
+    Command::cargo_bin("chip8")
+        .unwrap()
+        .arg(&rom)
+        .arg("--input-script")
+        .arg(&script)
+        .assert()
+        .code(5);
+}